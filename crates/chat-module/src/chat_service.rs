@@ -1,17 +1,21 @@
 //! 聊天服务实现
 
-use crate::{ChatError, ChatMessageType, ChatServiceTrait, Result};
+use crate::{
+    ChatError, ChatMessageType, ChatServiceTrait, ContentType, Result, UsernameCache,
+    OFFLINE_REPLAY_METADATA_KEY,
+};
 use async_trait::async_trait;
 use network_service::{
-    BroadcastOptions, MessageId, MessageType, NetworkMessage, NetworkServiceTrait, NodeId,
+    now_millis, BroadcastOptions, MessageId, MessageType, NetworkError, NetworkEvent,
+    NetworkMessage, NetworkServiceTrait, NodeId, SeenMessages, ShutdownComponent,
 };
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
-use std::collections::{HashMap, HashSet};
+use std::collections::{HashMap, HashSet, VecDeque};
 use std::sync::Arc;
-use std::time::{SystemTime, UNIX_EPOCH};
-use tokio::sync::RwLock;
-use tracing::info;
+use std::time::Duration;
+use tokio::sync::{broadcast, oneshot, Mutex, RwLock};
+use tracing::{info, warn};
 use uuid::Uuid;
 
 /// 聊天用户信息
@@ -21,6 +25,43 @@ pub struct ChatUser {
     pub username: String,
     pub joined_rooms: HashSet<String>,
     pub last_active: u64,
+    /// 是否为访客（无固定用户名，离开后完全清理）
+    #[serde(default)]
+    pub is_guest: bool,
+    /// 用户设置的在线状态
+    #[serde(default)]
+    pub status: UserStatus,
+}
+
+/// 用户在线状态
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default, Serialize, Deserialize)]
+pub enum UserStatus {
+    /// 在线
+    #[default]
+    Online,
+    /// 离开
+    Away,
+    /// 忙碌
+    Busy,
+    /// 离线（隐身）
+    Offline,
+}
+
+/// 用户在线状态列表中的一项
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct UserPresence {
+    pub user_id: NodeId,
+    pub username: String,
+    pub status: UserStatus,
+    pub last_active: u64,
+    /// 用户所在节点是否保持连接，断开后保留成员身份期间为false，与用户设置的状态无关
+    #[serde(default = "default_connected")]
+    pub connected: bool,
+}
+
+/// 旧版本的状态列表不携带连接状态，视为已连接
+fn default_connected() -> bool {
+    true
 }
 
 impl ChatUser {
@@ -29,21 +70,40 @@ impl ChatUser {
             user_id,
             username,
             joined_rooms: HashSet::new(),
-            last_active: current_timestamp(),
+            last_active: now_millis(),
+            is_guest: false,
+            status: UserStatus::default(),
+        }
+    }
+
+    pub fn new_guest(user_id: NodeId, guest_name: String) -> Self {
+        Self {
+            is_guest: true,
+            ..Self::new(user_id, guest_name)
         }
     }
 
     pub fn join_room(&mut self, room_id: String) {
         self.joined_rooms.insert(room_id);
-        self.last_active = current_timestamp();
+        self.last_active = now_millis();
     }
 
     pub fn leave_room(&mut self, room_id: &str) {
         self.joined_rooms.remove(room_id);
-        self.last_active = current_timestamp();
+        self.last_active = now_millis();
     }
 }
 
+/// 聊天室加入策略
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum JoinPolicy {
+    /// 任何人可直接加入
+    #[default]
+    Open,
+    /// 需要聊天室所有者审批
+    ApprovalRequired,
+}
+
 /// 聊天室信息
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ChatRoom {
@@ -52,6 +112,33 @@ pub struct ChatRoom {
     pub members: HashSet<NodeId>,
     pub created_at: u64,
     pub message_count: u64,
+    /// 是否允许访客加入（旧版本快照不携带该字段，按允许处理）
+    #[serde(default = "default_allow_guests")]
+    pub allow_guests: bool,
+    /// 聊天室所有者（创建者）
+    pub owner: Option<NodeId>,
+    /// 加入策略（旧版本快照不携带该字段，按开放加入处理）
+    #[serde(default)]
+    pub join_policy: JoinPolicy,
+    /// 等待审批的用户（用户ID -> 用户名），不属于成员
+    #[serde(default)]
+    pub pending_members: HashMap<NodeId, String>,
+    /// 允许发送的消息内容类型
+    #[serde(default = "default_allowed_content_types")]
+    pub allowed_content_types: HashSet<ContentType>,
+    /// 成员断开时保留其成员身份，无法投递的消息在其重新连接后补发
+    #[serde(default)]
+    pub offline_delivery: bool,
+}
+
+/// 聊天室默认只允许文本消息
+fn default_allowed_content_types() -> HashSet<ContentType> {
+    HashSet::from([ContentType::Text])
+}
+
+/// 聊天室默认允许访客加入
+fn default_allow_guests() -> bool {
+    true
 }
 
 impl ChatRoom {
@@ -60,8 +147,14 @@ impl ChatRoom {
             room_id,
             room_name,
             members: HashSet::new(),
-            created_at: current_timestamp(),
+            created_at: now_millis(),
             message_count: 0,
+            allow_guests: default_allow_guests(),
+            owner: None,
+            join_policy: JoinPolicy::Open,
+            pending_members: HashMap::new(),
+            allowed_content_types: default_allowed_content_types(),
+            offline_delivery: false,
         }
     }
 
@@ -77,8 +170,10 @@ impl ChatRoom {
         self.members.contains(user_id)
     }
 
-    pub fn increment_message_count(&mut self) {
+    /// 增加消息计数，返回的新计数即该消息在聊天室内的序号
+    pub fn increment_message_count(&mut self) -> u64 {
         self.message_count += 1;
+        self.message_count
     }
 }
 
@@ -92,34 +187,238 @@ pub struct ChatMessageRecord {
     pub content: String,
     pub timestamp: u64,
     pub message_type: String,
+    /// 聊天室内单调递增的消息序号，从 1 开始
+    #[serde(default)]
+    pub room_seq: u64,
+}
+
+/// 聊天服务状态快照，用于向备用服务器移交
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ChatStateSnapshot {
+    pub users: HashMap<NodeId, ChatUser>,
+    pub rooms: HashMap<String, ChatRoom>,
+    pub message_history: Vec<ChatMessageRecord>,
+}
+
+/// 聊天室消息的投递报告
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SendReport {
+    pub message_id: Uuid,
+    /// 成功投递的成员数量
+    pub delivered: usize,
+    /// 投递失败的成员
+    pub failed: Vec<NodeId>,
+}
+
+/// 加入聊天室的结果，客户端可据此直接渲染聊天室
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct JoinResult {
+    pub room_id: String,
+    /// 聊天室是否由本次加入创建
+    pub created: bool,
+    /// 当前成员的用户名（已排序）
+    pub members: Vec<String>,
+    /// 该聊天室可查询的历史消息数量
+    pub history_available: usize,
+    /// 是否在等待所有者审批（此时尚未成为成员）
+    pub pending_approval: bool,
+}
+
+/// 访客离开后在历史记录中显示的名称
+const GUEST_DISPLAY_NAME: &str = "访客";
+
+/// 每种访客名称格式的最多尝试次数
+const GUEST_NAME_ATTEMPTS: usize = 32;
+
+/// 等待备用服务器确认状态移交的超时时间
+const HANDOFF_ACK_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// 检查断开成员是否超过保留时间的间隔
+const DEPARTED_SWEEP_INTERVAL: Duration = Duration::from_secs(60);
+
+/// 时钟函数，返回当前时间戳（毫秒），可在测试中替换
+pub type Clock = Arc<dyn Fn() -> u64 + Send + Sync>;
+
+/// 聊天服务配置
+#[derive(Debug, Clone)]
+pub struct ChatServiceConfig {
+    /// 单条消息内容的最大字符数
+    pub max_content_len: usize,
+    /// 消息历史最大条数
+    pub max_history_len: usize,
+    /// 消息历史最长保留时间，`None` 表示不按时间清理
+    ///
+    /// 与条数上限同时生效，先达到的限制优先。
+    pub max_history_age: Option<Duration>,
+    /// 系统中聊天室的最大数量，达到上限后不再自动创建新聊天室
+    pub max_rooms: usize,
+    /// 用户名映射的最大数量，超出时淘汰最久未使用的映射
+    pub max_username_mappings: usize,
+    /// 加入时用户名已属于其他节点的处理策略
+    pub duplicate_username_policy: DuplicateUsernamePolicy,
+    /// 消息内容中控制字符的处理策略（换行和制表符始终保留）
+    pub control_chars: ControlCharPolicy,
+    /// 联邦服务器节点
+    ///
+    /// 聊天室成员分布在多台服务器上时，聊天室消息会转发给同样有该聊天室成员的服务器。
+    pub federation_peers: Vec<NodeId>,
+    /// 受信任的状态移交来源（主服务器）
+    ///
+    /// 只接受这些节点发来的状态移交，其他节点发来的快照会被拒绝，避免任意节点覆盖本服务器的状态。
+    pub handoff_sources: Vec<NodeId>,
+    /// 每个离线成员最多保存的待补发消息数，超出时丢弃最早的消息
+    pub max_offline_messages: usize,
+    /// 断开后保留成员身份的最长时间，超时后移出所有聊天室并丢弃待补发的消息；
+    /// `None` 表示一直保留
+    pub offline_member_ttl: Option<Duration>,
+}
+
+/// 消息内容中控制字符的处理策略
+///
+/// 客户端直接在终端打印消息内容，ANSI 转义序列等控制字符会破坏其他用户的终端显示。
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ControlCharPolicy {
+    /// 删除控制字符，ANSI 转义序列整体删除
+    #[default]
+    Strip,
+    /// 将控制字符转义为可见文本（例如 `\u{1b}`）
+    Escape,
+}
+
+impl ControlCharPolicy {
+    /// 按策略清理消息内容
+    pub fn sanitize(self, content: &str) -> String {
+        let mut sanitized = String::with_capacity(content.len());
+        let mut chars = content.chars().peekable();
+        while let Some(c) = chars.next() {
+            if !c.is_control() || c == '\n' || c == '\t' {
+                sanitized.push(c);
+                continue;
+            }
+            match self {
+                ControlCharPolicy::Strip => {
+                    // CSI 序列：ESC [ 参数 ... 结束字节（0x40-0x7E）
+                    if c == '\u{1b}' && chars.peek() == Some(&'[') {
+                        chars.next();
+                        for next in chars.by_ref() {
+                            if ('\u{40}'..='\u{7e}').contains(&next) {
+                                break;
+                            }
+                        }
+                    }
+                }
+                ControlCharPolicy::Escape => sanitized.extend(c.escape_default()),
+            }
+        }
+        sanitized
+    }
+}
+
+/// 用户名已属于其他节点时的处理策略
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum DuplicateUsernamePolicy {
+    /// 一律拒绝
+    Reject,
+    /// 原节点是观察到已断开的网络节点时（例如用户以新节点重连），把身份和已加入的
+    /// 聊天室转移给新节点；原节点仍在线、是本地用户或从未在网络上出现过时拒绝。
+    ///
+    /// 依赖 `attach` 订阅的网络事件判断节点是否已断开。
+    #[default]
+    ReclaimIfDisconnected,
+}
+
+impl Default for ChatServiceConfig {
+    fn default() -> Self {
+        Self {
+            max_content_len: 4096,
+            max_history_len: 1000,
+            max_history_age: None,
+            max_rooms: 1000,
+            max_username_mappings: 10_000,
+            duplicate_username_policy: DuplicateUsernamePolicy::default(),
+            control_chars: ControlCharPolicy::default(),
+            federation_peers: Vec::new(),
+            handoff_sources: Vec::new(),
+            max_offline_messages: 100,
+            offline_member_ttl: Some(Duration::from_secs(24 * 3600)),
+        }
+    }
 }
 
 /// 聊天服务实现
 pub struct ChatService<N: NetworkServiceTrait> {
     /// 网络服务
     network_service: N,
+    /// 配置
+    config: ChatServiceConfig,
     /// 用户管理
+    ///
+    /// 用户的 `joined_rooms` 与聊天室的 `members` 必须在同一临界区内修改，
+    /// 需要同时持有时先锁 `users` 再锁 `rooms`。
     users: Arc<RwLock<HashMap<NodeId, ChatUser>>>,
     /// 聊天室管理
     rooms: Arc<RwLock<HashMap<String, ChatRoom>>>,
-    /// 消息历史（最近1000条）
+    /// 消息历史（按条数和保留时间裁剪）
     message_history: Arc<RwLock<Vec<ChatMessageRecord>>>,
     /// 用户名到用户ID的映射
-    username_to_user_id: Arc<RwLock<HashMap<String, NodeId>>>,
+    username_to_user_id: Arc<RwLock<UsernameCache>>,
+    /// 时钟
+    clock: Clock,
+    /// 历史清理任务
+    pruning_handle: Arc<Mutex<Option<tokio::task::JoinHandle<()>>>>,
+    /// 网络事件订阅任务，服务销毁时取消
+    event_task: Arc<Mutex<Option<tokio::task::JoinHandle<()>>>>,
+    /// 等待确认的状态移交
+    pending_handoffs: Arc<Mutex<HashMap<Uuid, oneshot::Sender<()>>>>,
+    /// 已知对端节点的协议版本
+    peer_versions: Arc<RwLock<HashMap<NodeId, u32>>>,
+    /// 联邦服务器上有成员的聊天室（服务器ID -> 聊天室ID）
+    federation_rooms: Arc<RwLock<HashMap<NodeId, HashSet<String>>>>,
+    /// 最近一次同步给联邦服务器的聊天室列表，同时串行化同步消息的发送
+    synced_rooms: Arc<Mutex<Option<Vec<String>>>>,
+    /// 已转发过的聊天室消息，按消息ID去重
+    relayed_messages: SeenMessages,
+    /// 等待补发给离线成员的消息（按发送顺序）
+    offline_messages: Arc<RwLock<HashMap<NodeId, VecDeque<NetworkMessage>>>>,
+    /// 已断开但仍保留用户身份的网络节点（节点ID -> 断开时间），其用户名可被新节点认领
+    departed_nodes: Arc<RwLock<HashMap<NodeId, u64>>>,
 }
 
 impl<N: NetworkServiceTrait> ChatService<N> {
     /// 创建新的聊天服务
     pub fn new(network_service: N) -> Self {
+        Self::with_config(network_service, ChatServiceConfig::default())
+    }
+
+    /// 使用指定配置创建聊天服务
+    pub fn with_config(network_service: N, config: ChatServiceConfig) -> Self {
+        let username_cache = UsernameCache::new(config.max_username_mappings);
         Self {
             network_service,
+            config,
             users: Arc::new(RwLock::new(HashMap::new())),
             rooms: Arc::new(RwLock::new(HashMap::new())),
             message_history: Arc::new(RwLock::new(Vec::new())),
-            username_to_user_id: Arc::new(RwLock::new(HashMap::new())),
+            username_to_user_id: Arc::new(RwLock::new(username_cache)),
+            clock: Arc::new(now_millis),
+            pruning_handle: Arc::new(Mutex::new(None)),
+            event_task: Arc::new(Mutex::new(None)),
+            pending_handoffs: Arc::new(Mutex::new(HashMap::new())),
+            peer_versions: Arc::new(RwLock::new(HashMap::new())),
+            federation_rooms: Arc::new(RwLock::new(HashMap::new())),
+            synced_rooms: Arc::new(Mutex::new(None)),
+            relayed_messages: SeenMessages::default(),
+            offline_messages: Arc::new(RwLock::new(HashMap::new())),
+            departed_nodes: Arc::new(RwLock::new(HashMap::new())),
         }
     }
 
+    /// 替换时钟（用于测试或外部时间源）
+    pub fn with_clock(mut self, clock: Clock) -> Self {
+        self.clock = clock;
+        self
+    }
+
     /// 验证聊天室名称
     fn validate_room_name(room_id: &str) -> Result<()> {
         if room_id.is_empty() || room_id.len() > 50 {
@@ -128,6 +427,22 @@ impl<N: NetworkServiceTrait> ChatService<N> {
         Ok(())
     }
 
+    /// 验证消息内容：非空且不超过长度上限
+    fn validate_content(&self, content: &str) -> Result<()> {
+        if content.trim().is_empty() {
+            return Err(ChatError::EmptyMessage);
+        }
+
+        let len = content.chars().count();
+        if len > self.config.max_content_len {
+            return Err(ChatError::MessageTooLong {
+                len,
+                max: self.config.max_content_len,
+            });
+        }
+        Ok(())
+    }
+
     /// 验证用户名
     fn validate_username(username: &str) -> Result<()> {
         if username.is_empty() || username.len() > 30 {
@@ -148,13 +463,68 @@ impl<N: NetworkServiceTrait> ChatService<N> {
         rooms.get(room_id).cloned()
     }
 
-    /// 创建聊天室（如果不存在）
-    async fn ensure_room_exists(&self, room_id: &str) -> Result<()> {
+    /// 创建聊天室（如果不存在），创建者成为所有者；返回是否新建
+    async fn ensure_room_exists(&self, room_id: &str, creator: &NodeId) -> Result<bool> {
         let mut rooms = self.rooms.write().await;
         if !rooms.contains_key(room_id) {
-            let room = ChatRoom::new(room_id.to_string(), room_id.to_string());
+            if rooms.len() >= self.config.max_rooms {
+                warn!(
+                    "聊天室数量已达上限 {}，拒绝创建 {}",
+                    self.config.max_rooms, room_id
+                );
+                return Err(ChatError::TooManyRooms(self.config.max_rooms));
+            }
+            let mut room = ChatRoom::new(room_id.to_string(), room_id.to_string());
+            room.owner = Some(creator.clone());
             rooms.insert(room_id.to_string(), room);
             info!("创建新聊天室: {}", room_id);
+            return Ok(true);
+        }
+        Ok(false)
+    }
+
+    /// 汇总加入聊天室后的状态
+    async fn join_result(
+        &self,
+        room_id: String,
+        created: bool,
+        pending_approval: bool,
+    ) -> Result<JoinResult> {
+        let mut members = self.list_room_members(room_id.clone()).await?;
+        members.sort();
+        let history_available = self
+            .message_history
+            .read()
+            .await
+            .iter()
+            .filter(|record| record.room_id == room_id)
+            .count();
+
+        Ok(JoinResult {
+            room_id,
+            created,
+            members,
+            history_available,
+            pending_approval,
+        })
+    }
+
+    /// 确认对端支持该消息，版本未知的对端按 [`crate::UNVERSIONED_PEER_PROTOCOL_VERSION`] 处理
+    async fn ensure_peer_supports(&self, peer: &NodeId, message: &ChatMessageType) -> Result<()> {
+        let peer_version = self
+            .peer_versions
+            .read()
+            .await
+            .get(peer)
+            .copied()
+            .unwrap_or(crate::UNVERSIONED_PEER_PROTOCOL_VERSION);
+        let required = message.min_protocol_version();
+        if peer_version < required {
+            return Err(ChatError::UnsupportedOperation {
+                operation: message.name().to_string(),
+                required,
+                peer_version,
+            });
         }
         Ok(())
     }
@@ -164,146 +534,437 @@ impl<N: NetworkServiceTrait> ChatService<N> {
         let mut history = self.message_history.write().await;
         history.push(message);
 
-        // 保持最近1000条消息
-        if history.len() > 1000 {
-            history.remove(0);
+        // 保持最近的若干条消息
+        let max_len = self.config.max_history_len;
+        if history.len() > max_len {
+            let excess = history.len() - max_len;
+            history.drain(..excess);
         }
     }
 
-    /// 广播聊天消息到聊天室成员
-    async fn broadcast_to_room(
-        &self,
-        room_id: &str,
-        message: NetworkMessage,
-        exclude_user: Option<NodeId>,
-    ) -> Result<Uuid> {
-        let room = self
-            .get_room(room_id)
-            .await
-            .ok_or_else(|| ChatError::RoomNotFound(room_id.to_string()))?;
+    /// 移除早于保留时间的历史记录，返回移除数量
+    fn prune_expired(history: &mut Vec<ChatMessageRecord>, now: u64, max_age: Duration) -> usize {
+        let cutoff = now.saturating_sub(max_age.as_millis() as u64);
+        let before = history.len();
+        history.retain(|record| record.timestamp >= cutoff);
+        before - history.len()
+    }
 
-        let mut exclude_nodes = Vec::new();
-        if let Some(user_id) = exclude_user {
-            exclude_nodes.push(user_id);
-        }
+    /// 按保留时间清理消息历史，返回移除数量
+    pub async fn prune_history(&self) -> usize {
+        let Some(max_age) = self.config.max_history_age else {
+            return 0;
+        };
+        let mut history = self.message_history.write().await;
+        Self::prune_expired(&mut history, (self.clock)(), max_age)
+    }
 
-        let options = BroadcastOptions {
-            exclude_nodes,
-            wait_for_response: false,
-            timeout_ms: Some(5000),
-            retry_count: 0,
+    /// 启动后台历史清理任务
+    pub async fn start_history_pruning(&self, interval: Duration) -> Result<()> {
+        let Some(max_age) = self.config.max_history_age else {
+            return Err(ChatError::InternalError("未配置历史保留时间".to_string()));
         };
 
-        let message_id = self
-            .network_service
-            .broadcast(message, Some(options))
-            .await?;
-        info!(
-            "向聊天室 {} 广播消息 {} (成员数: {})",
-            room_id,
-            message_id,
-            room.members.len()
-        );
+        let mut handle_guard = self.pruning_handle.lock().await;
+        if handle_guard.is_some() {
+            return Ok(());
+        }
 
-        Ok(message_id)
+        let history = self.message_history.clone();
+        let clock = self.clock.clone();
+        let handle = tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(interval);
+            loop {
+                ticker.tick().await;
+                let removed = Self::prune_expired(&mut *history.write().await, clock(), max_age);
+                if removed > 0 {
+                    info!("清理过期消息历史 {} 条", removed);
+                }
+            }
+        });
+
+        *handle_guard = Some(handle);
+        Ok(())
     }
-}
 
-#[async_trait]
-impl<N: NetworkServiceTrait> ChatServiceTrait for ChatService<N> {
-    async fn join_room(&self, user_id: NodeId, username: String, room_id: String) -> Result<()> {
+    /// 用户名映射累计淘汰的数量
+    pub async fn username_evictions(&self) -> u64 {
+        self.username_to_user_id.read().await.evictions()
+    }
+
+    /// 停止后台历史清理任务
+    pub async fn stop_history_pruning(&self) {
+        if let Some(handle) = self.pruning_handle.lock().await.take() {
+            handle.abort();
+        }
+    }
+
+    /// 按用户名查找用户ID
+    ///
+    /// 映射只是有界缓存，其中被淘汰的用户回退到扫描用户表查找，并重新放回映射。
+    async fn lookup_username(&self, username: &str) -> Option<NodeId> {
+        if let Some(user_id) = self.username_to_user_id.write().await.get(username) {
+            return Some(user_id);
+        }
+        let user_id = self
+            .users
+            .read()
+            .await
+            .values()
+            .find(|user| user.username == username)
+            .map(|user| user.user_id.clone())?;
+        self.username_to_user_id
+            .write()
+            .await
+            .insert(username.to_string(), user_id.clone());
+        Some(user_id)
+    }
+
+    /// 分配一个唯一的访客名称并预留
+    ///
+    /// 四位数字的名称只有一万个，多次冲突后改用更长的随机后缀。
+    async fn allocate_guest_name(&self, user_id: &NodeId) -> Result<String> {
+        let users = self.users.read().await;
+        let mut username_map = self.username_to_user_id.write().await;
+        let short_names = (0..GUEST_NAME_ATTEMPTS)
+            .map(|_| format!("Guest-{:04}", Uuid::new_v4().as_u128() % 10000));
+        let long_names = (0..GUEST_NAME_ATTEMPTS)
+            .map(|_| format!("Guest-{}", &Uuid::new_v4().simple().to_string()[..8]));
+        let guest_name = short_names
+            .chain(long_names)
+            .find(|candidate| {
+                !username_map.contains_key(candidate)
+                    && !users.values().any(|user| &user.username == candidate)
+            })
+            .ok_or_else(|| ChatError::InternalError("无法分配访客名称".to_string()))?;
+        username_map.insert(guest_name.clone(), user_id.clone());
+        Ok(guest_name)
+    }
+
+    /// 以访客身份加入聊天室，返回自动分配的访客名称
+    ///
+    /// 已注册的正式用户不能以访客身份加入，否则会失去正式用户的权限。
+    pub async fn join_as_guest(&self, user_id: NodeId, room_id: String) -> Result<String> {
         Self::validate_room_name(&room_id)?;
-        Self::validate_username(&username)?;
 
-        info!("用户 {} ({}) 加入聊天室 {}", username, user_id, room_id);
+        if let Some(room) = self.get_room(&room_id).await {
+            if !room.allow_guests {
+                return Err(ChatError::GuestNotAllowed(room_id));
+            }
+        }
 
-        // 确保聊天室存在
-        self.ensure_room_exists(&room_id).await?;
+        // 已存在的访客沿用原有名称
+        let existing = self.get_user(&user_id).await;
+        let guest_name = match existing {
+            Some(user) if !user.is_guest => {
+                return Err(ChatError::AlreadyRegistered(user_id));
+            }
+            Some(user) => user.username,
+            None => {
+                let guest_name = self.allocate_guest_name(&user_id).await?;
+                let mut users = self.users.write().await;
+                users.insert(
+                    user_id.clone(),
+                    ChatUser::new_guest(user_id.clone(), guest_name.clone()),
+                );
+                guest_name
+            }
+        };
 
-        // 更新用户信息
+        info!("访客 {} ({}) 加入聊天室 {}", guest_name, user_id, room_id);
+
+        if let Err(e) = self
+            .join_room(user_id.clone(), guest_name.clone(), room_id)
+            .await
         {
-            let mut users = self.users.write().await;
-            let user = users
-                .entry(user_id.clone())
-                .or_insert_with(|| ChatUser::new(user_id.clone(), username.clone()));
-            user.join_room(room_id.clone());
+            if self
+                .get_user(&user_id)
+                .await
+                .is_some_and(|u| u.is_guest && u.joined_rooms.is_empty())
+            {
+                self.remove_user(&user_id).await;
+            }
+            return Err(e);
         }
 
-        // 更新用户名映射
-        {
-            let mut username_map = self.username_to_user_id.write().await;
-            username_map.insert(username.clone(), user_id.clone());
+        Ok(guest_name)
+    }
+
+    /// 设置聊天室是否允许访客加入
+    pub async fn set_room_allow_guests(&self, room_id: &str, allow_guests: bool) -> Result<()> {
+        let mut rooms = self.rooms.write().await;
+        let room = rooms
+            .get_mut(room_id)
+            .ok_or_else(|| ChatError::RoomNotFound(room_id.to_string()))?;
+        room.allow_guests = allow_guests;
+        Ok(())
+    }
+
+    /// 设置聊天室允许发送的消息内容类型
+    pub async fn set_room_allowed_content_types(
+        &self,
+        room_id: &str,
+        content_types: impl IntoIterator<Item = ContentType>,
+    ) -> Result<()> {
+        let mut rooms = self.rooms.write().await;
+        let room = rooms
+            .get_mut(room_id)
+            .ok_or_else(|| ChatError::RoomNotFound(room_id.to_string()))?;
+        room.allowed_content_types = content_types.into_iter().collect();
+        Ok(())
+    }
+
+    /// 设置聊天室是否为离线成员保存消息
+    pub async fn set_room_offline_delivery(&self, room_id: &str, enabled: bool) -> Result<()> {
+        let mut rooms = self.rooms.write().await;
+        let room = rooms
+            .get_mut(room_id)
+            .ok_or_else(|| ChatError::RoomNotFound(room_id.to_string()))?;
+        room.offline_delivery = enabled;
+        Ok(())
+    }
+
+    /// 等待补发给用户的消息数量
+    pub async fn offline_message_count(&self, user_id: &NodeId) -> usize {
+        self.offline_messages
+            .read()
+            .await
+            .get(user_id)
+            .map_or(0, VecDeque::len)
+    }
+
+    /// 为投递失败的成员保存消息，超出上限时丢弃最早的消息
+    async fn store_offline_message(&self, members: &[NodeId], message: &NetworkMessage) {
+        let limit = self.config.max_offline_messages;
+        if limit == 0 {
+            return;
         }
+        let mut offline = self.offline_messages.write().await;
+        for member in members {
+            let queue = offline.entry(member.clone()).or_default();
+            if queue.len() >= limit {
+                queue.pop_front();
+                warn!(
+                    "用户 {} 的离线消息已达上限 {}，丢弃最早的消息",
+                    member, limit
+                );
+            }
+            queue.push_back(message.clone());
+        }
+    }
 
-        // 更新聊天室成员
-        {
-            let mut rooms = self.rooms.write().await;
-            if let Some(room) = rooms.get_mut(&room_id) {
-                room.add_member(user_id.clone());
+    /// 按发送顺序补发用户离线期间的消息，返回补发的数量
+    ///
+    /// 补发失败时未送达的消息放回队列，等待下次重新连接。
+    pub async fn deliver_offline_messages(&self, user_id: &NodeId) -> Result<usize> {
+        let Some(mut queue) = self.offline_messages.write().await.remove(user_id) else {
+            return Ok(0);
+        };
+
+        let mut delivered = 0;
+        while let Some(message) = queue.pop_front() {
+            let replay = message
+                .clone()
+                .with_metadata(OFFLINE_REPLAY_METADATA_KEY.to_string(), "true".to_string());
+            if let Err(e) = self
+                .network_service
+                .unicast(user_id.clone(), replay, None)
+                .await
+            {
+                queue.push_front(message);
+                let mut offline = self.offline_messages.write().await;
+                let pending = offline.entry(user_id.clone()).or_default();
+                // 补发期间新保存的消息排在后面
+                queue.append(pending);
+                *pending = queue;
+                return Err(e.into());
             }
+            delivered += 1;
         }
 
-        // 广播用户加入消息
-        let join_message = ChatMessageType::UserJoin {
-            username: username.clone(),
-            room_id: room_id.clone(),
+        info!("向用户 {} 补发 {} 条离线消息", user_id, delivered);
+        Ok(delivered)
+    }
+
+    /// 移出断开超过保留时间的成员，其待补发的消息一并丢弃，返回移出的用户数
+    pub async fn prune_departed_members(&self) -> usize {
+        let Some(ttl) = self.config.offline_member_ttl else {
+            return 0;
         };
+        let cutoff = (self.clock)().saturating_sub(ttl.as_millis() as u64);
+        let expired: Vec<NodeId> = self
+            .departed_nodes
+            .read()
+            .await
+            .iter()
+            .filter(|(_, departed_at)| **departed_at < cutoff)
+            .map(|(node_id, _)| node_id.clone())
+            .collect();
+
+        let mut removed = 0;
+        for node_id in expired {
+            if let Some(user) = self.remove_user(&node_id).await {
+                info!(
+                    "用户 {} ({}) 断开超过 {:?}，移出 {} 个聊天室",
+                    user.username,
+                    node_id,
+                    ttl,
+                    user.joined_rooms.len()
+                );
+                removed += 1;
+            }
+        }
+        removed
+    }
 
-        let payload = serde_json::to_value(&join_message)?;
-        let network_msg = NetworkMessage::new(MessageType::chat(), user_id.clone(), payload);
+    /// 用户是否在开启离线投递的聊天室中，断开时应保留其成员身份
+    async fn keeps_membership_offline(&self, user_id: &NodeId) -> bool {
+        let Some(user) = self.get_user(user_id).await else {
+            return false;
+        };
+        let rooms = self.rooms.read().await;
+        user.joined_rooms
+            .iter()
+            .any(|room_id| rooms.get(room_id).is_some_and(|room| room.offline_delivery))
+    }
 
-        self.broadcast_to_room(&room_id, network_msg, Some(user_id))
-            .await?;
+    /// 处理用户名已属于其他节点的情况，按配置的策略拒绝或转移身份
+    async fn resolve_username_conflict(&self, user_id: &NodeId, username: &str) -> Result<()> {
+        let owner = match self.lookup_username(username).await {
+            Some(owner) if &owner != user_id => owner,
+            _ => return Ok(()),
+        };
+        // 映射已过期（原用户已被清理）时直接沿用
+        if self
+            .get_user(&owner)
+            .await
+            .is_none_or(|u| u.username != username)
+        {
+            return Ok(());
+        }
+
+        let taken = || ChatError::UsernameTaken(username.to_string(), owner.clone());
+        if self.config.duplicate_username_policy == DuplicateUsernamePolicy::Reject {
+            return Err(taken());
+        }
+        // 只有观察到断开的网络节点可以被认领，本地用户和未知节点一律拒绝
+        if !self.departed_nodes.read().await.contains_key(&owner) {
+            return Err(taken());
+        }
+        // 无法确认原节点是否在线时按在线处理
+        let connected = self
+            .network_service
+            .get_connected_nodes()
+            .await
+            .map_err(|_| taken())?;
+        if connected.contains(&owner) {
+            return Err(taken());
+        }
 
+        self.transfer_identity(&owner, user_id).await;
+        info!(
+            "用户名 {} 已从断开的节点 {} 转移到 {}",
+            username, owner, user_id
+        );
         Ok(())
     }
 
-    async fn leave_room(&self, user_id: NodeId, room_id: String) -> Result<()> {
-        info!("用户 {} 离开聊天室 {}", user_id, room_id);
-
-        let username = {
-            let users = self.users.read().await;
-            users
-                .get(&user_id)
-                .map(|u| u.username.clone())
-                .ok_or_else(|| ChatError::UserNotFound(user_id.clone()))?
+    /// 把用户身份、聊天室成员关系和所有权从旧节点转移到新节点
+    async fn transfer_identity(&self, from: &NodeId, to: &NodeId) {
+        self.departed_nodes.write().await.remove(from);
+        let mut users = self.users.write().await;
+        let Some(mut user) = users.remove(from) else {
+            return;
         };
 
-        // 检查用户是否在聊天室中
         {
-            let users = self.users.read().await;
-            let user = users
-                .get(&user_id)
-                .ok_or_else(|| ChatError::UserNotFound(user_id.clone()))?;
-            if !user.joined_rooms.contains(&room_id) {
-                return Err(ChatError::UserNotInRoom(user_id.clone(), room_id.clone()));
+            let mut rooms = self.rooms.write().await;
+            for room in rooms.values_mut() {
+                if room.remove_member(from) {
+                    room.add_member(to.clone());
+                }
+                if room.owner.as_ref() == Some(from) {
+                    room.owner = Some(to.clone());
+                }
             }
         }
 
-        // 更新用户信息
-        {
+        self.username_to_user_id
+            .write()
+            .await
+            .insert(user.username.clone(), to.clone());
+        user.user_id = to.clone();
+        user.last_active = now_millis();
+        users.insert(to.clone(), user);
+    }
+
+    /// 移除用户及其所有聊天室成员关系（用户离线时调用）
+    ///
+    /// 访客会被完全清理，其历史消息的署名也会被匿名化。
+    pub async fn remove_user(&self, user_id: &NodeId) -> Option<ChatUser> {
+        self.departed_nodes.write().await.remove(user_id);
+        self.offline_messages.write().await.remove(user_id);
+        let user = {
             let mut users = self.users.write().await;
-            if let Some(user) = users.get_mut(&user_id) {
-                user.leave_room(&room_id);
+            let user = users.remove(user_id)?;
+            let mut rooms = self.rooms.write().await;
+            for room_id in &user.joined_rooms {
+                if let Some(room) = rooms.get_mut(room_id) {
+                    room.remove_member(user_id);
+                }
+            }
+            user
+        };
+
+        {
+            let mut username_map = self.username_to_user_id.write().await;
+            if username_map.peek(&user.username) == Some(user_id) {
+                username_map.remove(&user.username);
             }
         }
 
-        // 更新聊天室成员
+        if user.is_guest {
+            let mut history = self.message_history.write().await;
+            for record in history.iter_mut().filter(|r| &r.sender_id == user_id) {
+                record.sender_name = GUEST_DISPLAY_NAME.to_string();
+            }
+            info!("访客 {} ({}) 已清理", user.username, user_id);
+        }
+        self.sync_room_membership_if_changed().await;
+
+        Some(user)
+    }
+
+    /// 将用户加入聊天室成员并广播加入消息
+    async fn admit_member(&self, user_id: NodeId, username: String, room_id: String) -> Result<()> {
+        // 在同一临界区内更新用户信息和聊天室成员
         {
+            let mut users = self.users.write().await;
             let mut rooms = self.rooms.write().await;
+            let user = users
+                .entry(user_id.clone())
+                .or_insert_with(|| ChatUser::new(user_id.clone(), username.clone()));
+            user.join_room(room_id.clone());
             if let Some(room) = rooms.get_mut(&room_id) {
-                room.remove_member(&user_id);
+                room.pending_members.remove(&user_id);
+                room.add_member(user_id.clone());
             }
         }
 
-        // 广播用户离开消息
-        let leave_message = ChatMessageType::UserLeave {
-            username,
+        // 更新用户名映射
+        {
+            let mut username_map = self.username_to_user_id.write().await;
+            username_map.insert(username.clone(), user_id.clone());
+        }
+        self.sync_room_membership_if_changed().await;
+
+        // 广播用户加入消息
+        let join_message = ChatMessageType::UserJoin {
+            username: username.clone(),
             room_id: room_id.clone(),
         };
 
-        let payload = serde_json::to_value(&leave_message)?;
-        let network_msg = NetworkMessage::new(MessageType::chat(), user_id.clone(), payload);
+        let network_msg = join_message.to_network_message(MessageType::chat(), user_id.clone())?;
 
         self.broadcast_to_room(&room_id, network_msg, Some(user_id))
             .await?;
@@ -311,177 +972,1082 @@ impl<N: NetworkServiceTrait> ChatServiceTrait for ChatService<N> {
         Ok(())
     }
 
-    async fn send_message(
-        &self,
-        user_id: NodeId,
-        room_id: String,
-        content: String,
-    ) -> Result<Uuid> {
-        if content.trim().is_empty() {
-            return Err(ChatError::EmptyMessage);
+    /// 校验操作者是否为聊天室所有者
+    async fn ensure_room_owner(&self, owner_id: &NodeId, room_id: &str) -> Result<ChatRoom> {
+        let room = self
+            .get_room(room_id)
+            .await
+            .ok_or_else(|| ChatError::RoomNotFound(room_id.to_string()))?;
+        if room.owner.as_ref() != Some(owner_id) {
+            return Err(ChatError::NotRoomOwner(
+                owner_id.clone(),
+                room_id.to_string(),
+            ));
         }
+        Ok(room)
+    }
 
-        let username = {
-            let users = self.users.read().await;
-            users
-                .get(&user_id)
-                .map(|u| u.username.clone())
-                .ok_or_else(|| ChatError::UserNotFound(user_id.clone()))?
-        };
-
-        // 检查用户是否在聊天室中
-        {
-            let users = self.users.read().await;
-            let user = users
-                .get(&user_id)
-                .ok_or_else(|| ChatError::UserNotFound(user_id.clone()))?;
-            if !user.joined_rooms.contains(&room_id) {
-                return Err(ChatError::UserNotInRoom(user_id.clone(), room_id.clone()));
-            }
+    /// 设置聊天室加入策略（仅所有者）
+    pub async fn set_join_policy(
+        &self,
+        owner_id: &NodeId,
+        room_id: &str,
+        join_policy: JoinPolicy,
+    ) -> Result<()> {
+        self.ensure_room_owner(owner_id, room_id).await?;
+        let mut rooms = self.rooms.write().await;
+        if let Some(room) = rooms.get_mut(room_id) {
+            room.join_policy = join_policy;
         }
+        Ok(())
+    }
+
+    /// 获取聊天室中等待审批的用户名
+    pub async fn list_pending_joins(&self, room_id: &str) -> Result<Vec<String>> {
+        let room = self
+            .get_room(room_id)
+            .await
+            .ok_or_else(|| ChatError::RoomNotFound(room_id.to_string()))?;
+        Ok(room.pending_members.values().cloned().collect())
+    }
+
+    /// 批准加入申请（仅所有者）
+    pub async fn approve_join(
+        &self,
+        owner_id: &NodeId,
+        room_id: &str,
+        user_id: &NodeId,
+    ) -> Result<()> {
+        let room = self.ensure_room_owner(owner_id, room_id).await?;
+        let username =
+            room.pending_members.get(user_id).cloned().ok_or_else(|| {
+                ChatError::JoinRequestNotFound(user_id.clone(), room_id.to_string())
+            })?;
 
         info!(
-            "用户 {} 在聊天室 {} 发送消息: {}",
-            username, room_id, content
+            "所有者 {} 批准 {} 加入聊天室 {}",
+            owner_id, username, room_id
         );
+        self.admit_member(user_id.clone(), username, room_id.to_string())
+            .await
+    }
 
-        // 创建聊天消息
-        let chat_message = ChatMessageType::TextMessage {
-            room_id: room_id.clone(),
-            content: content.clone(),
+    /// 拒绝加入申请（仅所有者）
+    pub async fn deny_join(
+        &self,
+        owner_id: &NodeId,
+        room_id: &str,
+        user_id: &NodeId,
+    ) -> Result<()> {
+        self.ensure_room_owner(owner_id, room_id).await?;
+        let denied_message = ChatMessageType::JoinDenied {
+            room_id: room_id.to_string(),
+        };
+        // 先确认申请者能收到拒绝通知，失败时保留申请
+        self.ensure_peer_supports(user_id, &denied_message).await?;
+        let removed = {
+            let mut rooms = self.rooms.write().await;
+            rooms
+                .get_mut(room_id)
+                .and_then(|room| room.pending_members.remove(user_id))
         };
+        let username = removed
+            .ok_or_else(|| ChatError::JoinRequestNotFound(user_id.clone(), room_id.to_string()))?;
+
+        info!(
+            "所有者 {} 拒绝 {} 加入聊天室 {}",
+            owner_id, username, room_id
+        );
 
-        let payload = serde_json::to_value(&chat_message)?;
-        let network_msg = NetworkMessage::new(MessageType::chat(), user_id.clone(), payload);
+        // 未加入任何聊天室的访客随之清理
+        if self
+            .get_user(user_id)
+            .await
+            .is_some_and(|u| u.is_guest && u.joined_rooms.is_empty())
+        {
+            self.remove_user(user_id).await;
+        }
 
-        let message_id = network_msg.id;
+        let network_msg =
+            denied_message.to_network_message(MessageType::chat(), owner_id.clone())?;
+        self.unicast_node(user_id.clone(), network_msg).await?;
 
-        // 添加到消息历史
-        let history_record = ChatMessageRecord {
-            message_id,
-            room_id: room_id.clone(),
-            sender_id: user_id.clone(),
-            sender_name: username,
-            content,
-            timestamp: current_timestamp(),
-            message_type: "text".to_string(),
+        Ok(())
+    }
+
+    /// 导出当前状态快照
+    pub async fn export_state(&self) -> ChatStateSnapshot {
+        ChatStateSnapshot {
+            users: self.users.read().await.clone(),
+            rooms: self.rooms.read().await.clone(),
+            message_history: self.message_history.read().await.clone(),
+        }
+    }
+
+    /// 用快照替换当前状态
+    pub async fn import_state(&self, snapshot: ChatStateSnapshot) {
+        let mut username_map = UsernameCache::new(self.config.max_username_mappings);
+        for user in snapshot.users.values() {
+            username_map.insert(user.username.clone(), user.user_id.clone());
+        }
+
+        *self.users.write().await = snapshot.users;
+        *self.rooms.write().await = snapshot.rooms;
+        *self.message_history.write().await = snapshot.message_history;
+        *self.username_to_user_id.write().await = username_map;
+    }
+
+    /// 本服务器有成员的聊天室（按聊天室ID排序）
+    async fn occupied_rooms(&self) -> Vec<String> {
+        let mut rooms: Vec<String> = self
+            .rooms
+            .read()
+            .await
+            .values()
+            .filter(|room| !room.members.is_empty())
+            .map(|room| room.room_id.clone())
+            .collect();
+        rooms.sort();
+        rooms
+    }
+
+    /// 向联邦服务器同步本服务器有成员的聊天室
+    ///
+    /// 对端据此决定是否向本服务器转发聊天室消息。本地成员关系变化时会自动同步，
+    /// 联邦服务器（重新）连接后也会同步一次。
+    pub async fn sync_room_membership(&self) -> Result<()> {
+        if self.config.federation_peers.is_empty() {
+            return Ok(());
+        }
+        let mut synced = self.synced_rooms.lock().await;
+        let rooms = self.occupied_rooms().await;
+        *synced = Some(rooms.clone());
+        self.send_membership_sync(rooms).await
+    }
+
+    /// 有成员的聊天室发生变化时向联邦服务器同步
+    async fn sync_room_membership_if_changed(&self) {
+        if self.config.federation_peers.is_empty() {
+            return;
+        }
+        let mut synced = self.synced_rooms.lock().await;
+        let rooms = self.occupied_rooms().await;
+        if synced.as_ref() == Some(&rooms) {
+            return;
+        }
+        *synced = Some(rooms.clone());
+        if let Err(e) = self.send_membership_sync(rooms).await {
+            warn!("同步聊天室成员关系失败: {}", e);
+        }
+    }
+
+    /// 向每个联邦服务器发送聊天室列表
+    async fn send_membership_sync(&self, rooms: Vec<String>) -> Result<()> {
+        let local_id = self.network_service.get_local_node_id().await?;
+        let sync_message = ChatMessageType::RoomMembershipSync { rooms };
+        for peer in &self.config.federation_peers {
+            let network_msg =
+                sync_message.to_network_message(MessageType::system(), local_id.clone())?;
+            if let Err(e) = self
+                .network_service
+                .unicast(peer.clone(), network_msg, None)
+                .await
+            {
+                warn!("向联邦服务器 {} 同步聊天室失败: {}", peer, e);
+            }
+        }
+        Ok(())
+    }
+
+    /// 将聊天室消息转发给同样有该聊天室成员的联邦服务器，`skip` 为上一跳服务器
+    async fn relay_to_servers(
+        &self,
+        room_id: &str,
+        message: &NetworkMessage,
+        sender_name: &str,
+        skip: Option<&NodeId>,
+    ) -> Result<()> {
+        self.relayed_messages.insert(message.id).await;
+
+        let targets: Vec<NodeId> = {
+            let federation_rooms = self.federation_rooms.read().await;
+            self.config
+                .federation_peers
+                .iter()
+                .filter(|peer| Some(*peer) != skip)
+                .filter(|peer| {
+                    federation_rooms
+                        .get(*peer)
+                        .is_some_and(|rooms| rooms.contains(room_id))
+                })
+                .cloned()
+                .collect()
+        };
+        if targets.is_empty() {
+            return Ok(());
+        }
+
+        let local_id = self.network_service.get_local_node_id().await?;
+        let relay_message = ChatMessageType::RoomRelay {
+            room_id: room_id.to_string(),
+            message: message.clone(),
+            sender_name: sender_name.to_string(),
         };
-        self.add_to_history(history_record).await;
+        for server in targets {
+            let network_msg =
+                relay_message.to_network_message(MessageType::system(), local_id.clone())?;
+            match self
+                .network_service
+                .unicast(server.clone(), network_msg, None)
+                .await
+            {
+                Ok(_) => info!(
+                    "聊天室 {} 消息 {} 已转发给服务器 {}",
+                    room_id, message.id, server
+                ),
+                Err(e) => warn!(
+                    "聊天室 {} 消息 {} 转发给服务器 {} 失败: {}",
+                    room_id, message.id, server, e
+                ),
+            }
+        }
+        Ok(())
+    }
 
-        // 更新聊天室消息计数
+    /// 单播给指定节点；节点不存在且目标是已知用户名时，提示调用方误把用户名当作节点ID
+    async fn unicast_node(&self, target: NodeId, message: NetworkMessage) -> Result<MessageId> {
+        match self
+            .network_service
+            .unicast(target.clone(), message, None)
+            .await
         {
-            let mut rooms = self.rooms.write().await;
-            if let Some(room) = rooms.get_mut(&room_id) {
-                room.increment_message_count();
+            Err(NetworkError::NodeNotFound(_)) if self.lookup_username(&target).await.is_some() => {
+                Err(ChatError::UsernameAsNodeId(target))
             }
+            result => Ok(result?),
         }
+    }
 
-        // 广播消息到聊天室
-        self.broadcast_to_room(&room_id, network_msg, Some(user_id))
+    /// 将状态移交给备用服务器
+    ///
+    /// 导出状态并通过system消息发送给备用服务器，收到确认后
+    /// 广播 `RoomServerChanged`，客户端据此切换到新服务器。
+    /// 备用服务器须已发送过携带协议版本的消息，否则按旧版本拒绝移交。
+    pub async fn handoff_to(&self, standby: NodeId) -> Result<()> {
+        let handoff_id = Uuid::new_v4();
+        let snapshot = self.export_state().await;
+        info!(
+            "向备用服务器 {} 移交状态: {} 个聊天室, {} 个用户",
+            standby,
+            snapshot.rooms.len(),
+            snapshot.users.len()
+        );
+
+        let handoff_message = ChatMessageType::StateHandoff {
+            handoff_id,
+            snapshot,
+        };
+        self.ensure_peer_supports(&standby, &handoff_message)
             .await?;
+        let local_id = self.network_service.get_local_node_id().await?;
+        let network_msg =
+            handoff_message.to_network_message(MessageType::system(), local_id.clone())?;
 
-        Ok(message_id)
+        let (ack_tx, ack_rx) = oneshot::channel();
+        self.pending_handoffs
+            .lock()
+            .await
+            .insert(handoff_id, ack_tx);
+
+        if let Err(e) = self.unicast_node(standby.clone(), network_msg).await {
+            self.pending_handoffs.lock().await.remove(&handoff_id);
+            return Err(e);
+        }
+
+        match tokio::time::timeout(HANDOFF_ACK_TIMEOUT, ack_rx).await {
+            Ok(Ok(())) => {}
+            Ok(Err(_)) | Err(_) => {
+                self.pending_handoffs.lock().await.remove(&handoff_id);
+                return Err(ChatError::HandoffFailed(format!(
+                    "等待 {} 确认超时",
+                    standby
+                )));
+            }
+        }
+
+        // 通知客户端切换到备用服务器
+        let changed_message = ChatMessageType::RoomServerChanged {
+            server: standby.clone(),
+        };
+        let network_msg = changed_message.to_network_message(MessageType::chat(), local_id)?;
+        self.network_service.broadcast(network_msg, None).await?;
+
+        info!("状态已移交给 {}", standby);
+        Ok(())
     }
 
-    async fn send_private_message(
+    /// 校验并记录聊天室消息，返回待发送的网络消息
+    async fn prepare_room_message(
         &self,
-        from_user: NodeId,
-        to_user: String,
+        user_id: &NodeId,
+        room_id: &str,
         content: String,
-    ) -> Result<Uuid> {
-        if content.trim().is_empty() {
-            return Err(ChatError::EmptyMessage);
-        }
+        content_type: ContentType,
+    ) -> Result<NetworkMessage> {
+        let content = self.sanitize_content(&content);
+        self.validate_content(&content)?;
 
-        // 查找目标用户ID
-        let target_user_id = {
-            let username_map = self.username_to_user_id.read().await;
-            username_map
-                .get(&to_user)
-                .cloned()
-                .ok_or_else(|| ChatError::UserNotFound(to_user.clone()))?
+        // 检查用户是否在聊天室中
+        let username = {
+            let users = self.users.read().await;
+            let user = users
+                .get(user_id)
+                .ok_or_else(|| ChatError::UserNotFound(user_id.clone()))?;
+            if !user.joined_rooms.contains(room_id) {
+                return Err(ChatError::UserNotInRoom(
+                    user_id.clone(),
+                    room_id.to_string(),
+                ));
+            }
+            user.username.clone()
         };
 
-        let from_username = {
-            let users = self.users.read().await;
-            users
-                .get(&from_user)
-                .map(|u| u.username.clone())
-                .ok_or_else(|| ChatError::UserNotFound(from_user.clone()))?
+        // 检查聊天室是否允许该内容类型
+        let allowed = self
+            .rooms
+            .read()
+            .await
+            .get(room_id)
+            .is_some_and(|room| room.allowed_content_types.contains(&content_type));
+        if !allowed {
+            return Err(ChatError::ContentTypeNotAllowed(
+                room_id.to_string(),
+                content_type.as_str().to_string(),
+            ));
+        }
+
+        info!(
+            "用户 {} 在聊天室 {} 发送{}消息: {}",
+            username,
+            room_id,
+            content_type.as_str(),
+            content
+        );
+
+        // 创建聊天消息
+        let chat_message = ChatMessageType::TextMessage {
+            room_id: room_id.to_string(),
+            content: content.clone(),
+            content_type,
         };
 
-        info!("用户 {} 向 {} 发送私聊消息", from_username, to_user);
+        let network_msg = chat_message.to_network_message(MessageType::chat(), user_id.clone())?;
 
-        // 创建私聊消息
-        let private_message = ChatMessageType::PrivateMessage {
-            target_user: to_user,
+        self.record_room_message(ChatMessageRecord {
+            message_id: network_msg.id,
+            room_id: room_id.to_string(),
+            sender_id: user_id.clone(),
+            sender_name: username,
             content,
-        };
+            timestamp: (self.clock)(),
+            message_type: content_type.as_str().to_string(),
+            room_seq: 0,
+        })
+        .await;
+
+        Ok(network_msg)
+    }
 
-        let payload = serde_json::to_value(&private_message)?;
-        let network_msg = NetworkMessage::new(MessageType::chat(), from_user.clone(), payload);
+    /// 分配聊天室内序号并把消息加入历史
+    ///
+    /// 两步在同一临界区内完成（先锁 `rooms` 再锁历史），历史中的序号因此严格递增，
+    /// 按序号增量拉取历史的客户端不会漏掉序号较小但写入较晚的消息。
+    async fn record_room_message(&self, mut record: ChatMessageRecord) {
+        let mut rooms = self.rooms.write().await;
+        // 更新聊天室消息计数，同时分配聊天室内序号
+        record.room_seq = rooms
+            .get_mut(&record.room_id)
+            .map_or(0, |room| room.increment_message_count());
+        self.add_to_history(record).await;
+    }
 
-        let message_id = network_msg.id;
+    /// 逐个投递消息给聊天室成员（发送者除外），返回投递报告
+    async fn deliver_to_room_reported(
+        &self,
+        room_id: &str,
+        message: NetworkMessage,
+        exclude_user: Option<&NodeId>,
+    ) -> Result<SendReport> {
+        let room = self
+            .get_room(room_id)
+            .await
+            .ok_or_else(|| ChatError::RoomNotFound(room_id.to_string()))?;
 
-        // 发送单播消息
-        let _sent_id = self
-            .network_service
-            .unicast(target_user_id, network_msg, None)
-            .await?;
+        let mut report = SendReport {
+            message_id: message.id,
+            delivered: 0,
+            failed: Vec::new(),
+        };
+        for member in room
+            .members
+            .iter()
+            .filter(|member| **member != message.sender && Some(*member) != exclude_user)
+        {
+            match self
+                .network_service
+                .unicast(member.clone(), message.clone(), None)
+                .await
+            {
+                Ok(_) => report.delivered += 1,
+                Err(e) => {
+                    warn!("消息 {} 投递给 {} 失败: {}", message.id, member, e);
+                    report.failed.push(member.clone());
+                }
+            }
+        }
+        report.failed.sort();
+        if room.offline_delivery && !report.failed.is_empty() {
+            self.store_offline_message(&report.failed, &message).await;
+        }
 
-        Ok(message_id)
+        info!(
+            "聊天室 {} 消息 {} 已投递 {}/{}",
+            room_id,
+            message.id,
+            report.delivered,
+            report.delivered + report.failed.len()
+        );
+        Ok(report)
     }
 
-    async fn list_rooms(&self) -> Result<Vec<String>> {
-        let rooms = self.rooms.read().await;
-        let room_list = rooms.keys().cloned().collect();
-        Ok(room_list)
+    /// 广播聊天消息到聊天室成员，并转发给有该聊天室成员的联邦服务器
+    async fn broadcast_to_room(
+        &self,
+        room_id: &str,
+        message: NetworkMessage,
+        exclude_user: Option<NodeId>,
+    ) -> Result<Uuid> {
+        let sender_name = self
+            .get_user(&message.sender)
+            .await
+            .map(|user| user.username)
+            .unwrap_or_default();
+        self.relay_to_servers(room_id, &message, &sender_name, None)
+            .await?;
+        self.deliver_to_local_members(room_id, message, exclude_user)
+            .await
     }
 
-    async fn list_room_members(&self, room_id: String) -> Result<Vec<String>> {
+    /// 广播聊天消息到本服务器上的聊天室成员
+    async fn deliver_to_local_members(
+        &self,
+        room_id: &str,
+        message: NetworkMessage,
+        exclude_user: Option<NodeId>,
+    ) -> Result<Uuid> {
         let room = self
-            .get_room(&room_id)
+            .get_room(room_id)
             .await
-            .ok_or_else(|| ChatError::RoomNotFound(room_id))?;
+            .ok_or_else(|| ChatError::RoomNotFound(room_id.to_string()))?;
 
-        let users = self.users.read().await;
-        let member_names: Vec<String> = room
+        // 逐个成员单播，才能知道需要为哪些离线成员保存消息
+        if room.offline_delivery {
+            let report = self
+                .deliver_to_room_reported(room_id, message, exclude_user.as_ref())
+                .await?;
+            return Ok(report.message_id);
+        }
+
+        let mut exclude_nodes = Vec::new();
+        if let Some(user_id) = exclude_user {
+            exclude_nodes.push(user_id);
+        }
+
+        // 接收者 = 聊天室成员 - 排除的用户 - 发送者，排除仅在本聊天室内生效
+        let recipients: Vec<NodeId> = room
             .members
             .iter()
-            .filter_map(|user_id| users.get(user_id).map(|u| u.username.clone()))
+            .filter(|member| !exclude_nodes.contains(member) && **member != message.sender)
+            .cloned()
             .collect();
 
-        Ok(member_names)
-    }
+        let options = BroadcastOptions {
+            exclude_nodes,
+            include_nodes: Some(recipients),
+            wait_for_response: false,
+            timeout_ms: Some(5000),
+            retry_count: 0,
+            include_self: false,
+            require_recipients: true,
+        };
 
-    async fn get_user_rooms(&self, user_id: NodeId) -> Result<Vec<String>> {
-        let users = self.users.read().await;
-        let user = users
-            .get(&user_id)
-            .ok_or_else(|| ChatError::UserNotFound(user_id))?;
+        let fallback_id = message.id;
+        let message_id = match self.network_service.broadcast(message, Some(options)).await {
+            Err(NetworkError::NoRecipients) => {
+                warn!("聊天室 {} 中没有其他成员，消息无人接收", room_id);
+                fallback_id
+            }
+            result => result?,
+        };
+        info!(
+            "向聊天室 {} 广播消息 {} (成员数: {})",
+            room_id,
+            message_id,
+            room.members.len()
+        );
 
-        let rooms: Vec<String> = user.joined_rooms.iter().cloned().collect();
-        Ok(rooms)
+        Ok(message_id)
     }
 }
 
-/// 获取当前时间戳
-fn current_timestamp() -> u64 {
-    SystemTime::now()
-        .duration_since(UNIX_EPOCH)
-        .unwrap()
-        .as_secs()
-}
+impl<N: NetworkServiceTrait + 'static> ChatService<N> {
+    /// 订阅网络事件，节点断开时自动将其从所有聊天室移除
+    ///
+    /// 在开启离线投递的聊天室中的用户断开时保留成员身份（用户设置的状态不变），
+    /// 重新连接后补发离线消息；断开超过 `offline_member_ttl` 的成员被定期移出。
+    /// 网络服务不支持事件订阅时返回false。重复调用不会创建新的订阅。
+    pub async fn attach(self: &Arc<Self>) -> bool {
+        let mut task_guard = self.event_task.lock().await;
+        if task_guard.is_some() {
+            return true;
+        }
+        let Some(mut events) = self.network_service.subscribe_events() else {
+            warn!("网络服务不支持事件订阅，无法自动清理断开的用户");
+            return false;
+        };
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use network_service::AnemoNetworkService;
+        // 只持有弱引用，服务销毁后任务自然结束
+        let service = Arc::downgrade(self);
+        let handle = tokio::spawn(async move {
+            let mut sweep = tokio::time::interval(DEPARTED_SWEEP_INTERVAL);
+            loop {
+                let event = tokio::select! {
+                    event = events.recv() => event,
+                    _ = sweep.tick() => {
+                        let Some(service) = service.upgrade() else {
+                            break;
+                        };
+                        service.prune_departed_members().await;
+                        continue;
+                    }
+                };
+                match event {
+                    Ok(NetworkEvent::NodeDisconnected { node_id, reason }) => {
+                        let Some(service) = service.upgrade() else {
+                            break;
+                        };
+                        if service.keeps_membership_offline(&node_id).await {
+                            info!("用户 {} 已断开，保留成员身份等待重新连接", node_id);
+                            let departed_at = (service.clock)();
+                            service
+                                .departed_nodes
+                                .write()
+                                .await
+                                .insert(node_id.clone(), departed_at);
+                        } else if let Some(user) = service.remove_user(&node_id).await {
+                            info!(
+                                "用户 {} ({}) 已断开，移出 {} 个聊天室: {}",
+                                user.username,
+                                node_id,
+                                user.joined_rooms.len(),
+                                reason
+                            );
+                        }
+                    }
+                    Ok(NetworkEvent::NodeConnected { node_id, .. }) => {
+                        info!("节点 {} 已连接", node_id);
+                        let Some(service) = service.upgrade() else {
+                            break;
+                        };
+                        service.departed_nodes.write().await.remove(&node_id);
+                        if service.config.federation_peers.contains(&node_id) {
+                            if let Err(e) = service.sync_room_membership().await {
+                                warn!("向联邦服务器 {} 同步聊天室失败: {}", node_id, e);
+                            }
+                        }
+                        if let Err(e) = service.deliver_offline_messages(&node_id).await {
+                            warn!("向用户 {} 补发离线消息失败: {}", node_id, e);
+                        }
+                    }
+                    Ok(_) => {}
+                    Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                        warn!("网络事件积压，跳过 {} 个事件", skipped);
+                    }
+                    Err(broadcast::error::RecvError::Closed) => break,
+                }
+            }
+        });
 
-    #[tokio::test]
-    async fn test_chat_service_creation() {
-        let network_service = AnemoNetworkService::new();
-        let chat_service = ChatService::new(network_service);
+        *task_guard = Some(handle);
+        true
+    }
+}
+
+impl<N: NetworkServiceTrait> Drop for ChatService<N> {
+    fn drop(&mut self) {
+        if let Ok(mut task) = self.event_task.try_lock() {
+            if let Some(handle) = task.take() {
+                handle.abort();
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl<N: NetworkServiceTrait> ChatServiceTrait for ChatService<N> {
+    fn sanitize_content(&self, content: &str) -> String {
+        self.config.control_chars.sanitize(content)
+    }
+
+    async fn join_room(
+        &self,
+        user_id: NodeId,
+        username: String,
+        room_id: String,
+    ) -> Result<JoinResult> {
+        Self::validate_room_name(&room_id)?;
+        Self::validate_username(&username)?;
+        self.resolve_username_conflict(&user_id, &username).await?;
+
+        info!("用户 {} ({}) 加入聊天室 {}", username, user_id, room_id);
+
+        // 访客只能加入允许访客的聊天室
+        if self.get_user(&user_id).await.is_some_and(|u| u.is_guest) {
+            if let Some(room) = self.get_room(&room_id).await {
+                if !room.allow_guests {
+                    return Err(ChatError::GuestNotAllowed(room_id));
+                }
+            }
+        }
+
+        // 确保聊天室存在
+        let created = self.ensure_room_exists(&room_id, &user_id).await?;
+
+        // 需要审批的聊天室：进入待审批状态并通知所有者
+        let owner_to_notify = {
+            let mut rooms = self.rooms.write().await;
+            match rooms.get_mut(&room_id) {
+                Some(room)
+                    if room.join_policy == JoinPolicy::ApprovalRequired
+                        && room.owner.as_ref() != Some(&user_id)
+                        && !room.has_member(&user_id) =>
+                {
+                    room.pending_members
+                        .insert(user_id.clone(), username.clone());
+                    room.owner.clone()
+                }
+                _ => None,
+            }
+        };
+
+        if let Some(owner) = owner_to_notify {
+            info!(
+                "用户 {} 加入聊天室 {} 需要所有者 {} 审批",
+                username, room_id, owner
+            );
+
+            let request_message = ChatMessageType::JoinRequest {
+                username,
+                room_id: room_id.clone(),
+            };
+            self.ensure_peer_supports(&owner, &request_message).await?;
+            let network_msg = request_message.to_network_message(MessageType::chat(), user_id)?;
+            self.unicast_node(owner, network_msg).await?;
+            return self.join_result(room_id, created, true).await;
+        }
+
+        self.admit_member(user_id, username, room_id.clone())
+            .await?;
+        self.join_result(room_id, created, false).await
+    }
+
+    async fn leave_room(&self, user_id: NodeId, room_id: String) -> Result<()> {
+        info!("用户 {} 离开聊天室 {}", user_id, room_id);
+
+        // 检查用户是否在聊天室中，并在同一临界区内更新用户信息和聊天室成员
+        let username = {
+            let mut users = self.users.write().await;
+            let user = users
+                .get_mut(&user_id)
+                .ok_or_else(|| ChatError::UserNotFound(user_id.clone()))?;
+            if !user.joined_rooms.contains(&room_id) {
+                return Err(ChatError::UserNotInRoom(user_id.clone(), room_id.clone()));
+            }
+            user.leave_room(&room_id);
+
+            let mut rooms = self.rooms.write().await;
+            if let Some(room) = rooms.get_mut(&room_id) {
+                room.remove_member(&user_id);
+            }
+            user.username.clone()
+        };
+
+        // 访客离开最后一个聊天室后完全清理
+        if self
+            .get_user(&user_id)
+            .await
+            .is_some_and(|u| u.is_guest && u.joined_rooms.is_empty())
+        {
+            self.remove_user(&user_id).await;
+        }
+
+        // 广播用户离开消息
+        let leave_message = ChatMessageType::UserLeave {
+            username,
+            room_id: room_id.clone(),
+        };
+
+        let network_msg = leave_message.to_network_message(MessageType::chat(), user_id.clone())?;
+
+        self.broadcast_to_room(&room_id, network_msg, Some(user_id))
+            .await?;
+        self.sync_room_membership_if_changed().await;
+
+        Ok(())
+    }
+
+    async fn leave_all_rooms(&self, user_id: NodeId) -> Result<Vec<String>> {
+        let mut room_ids: Vec<String> = self
+            .get_user(&user_id)
+            .await
+            .ok_or_else(|| ChatError::UserNotFound(user_id.clone()))?
+            .joined_rooms
+            .into_iter()
+            .collect();
+        room_ids.sort();
+
+        let mut left = Vec::new();
+        for room_id in room_ids {
+            match self.leave_room(user_id.clone(), room_id.clone()).await {
+                Ok(()) => left.push(room_id),
+                Err(e) => warn!("用户 {} 离开聊天室 {} 失败: {}", user_id, room_id, e),
+            }
+        }
+        Ok(left)
+    }
+
+    async fn send_message(
+        &self,
+        user_id: NodeId,
+        room_id: String,
+        content: String,
+        content_type: ContentType,
+    ) -> Result<Uuid> {
+        let network_msg = self
+            .prepare_room_message(&user_id, &room_id, content, content_type)
+            .await?;
+        let message_id = network_msg.id;
+
+        // 广播消息到聊天室
+        self.broadcast_to_room(&room_id, network_msg, Some(user_id))
+            .await?;
+
+        Ok(message_id)
+    }
+
+    async fn send_message_reported(
+        &self,
+        user_id: NodeId,
+        room_id: String,
+        content: String,
+        content_type: ContentType,
+    ) -> Result<SendReport> {
+        let network_msg = self
+            .prepare_room_message(&user_id, &room_id, content, content_type)
+            .await?;
+        self.deliver_to_room_reported(&room_id, network_msg, None)
+            .await
+    }
+
+    async fn send_private_message(
+        &self,
+        from_user: NodeId,
+        to_user: String,
+        content: String,
+    ) -> Result<Uuid> {
+        let content = self.sanitize_content(&content);
+        self.validate_content(&content)?;
+
+        // 查找目标用户ID
+        let target_user_id = self
+            .lookup_username(&to_user)
+            .await
+            .ok_or_else(|| ChatError::UserNotFound(to_user.clone()))?;
+
+        let from_username = {
+            let users = self.users.read().await;
+            users
+                .get(&from_user)
+                .map(|u| u.username.clone())
+                .ok_or_else(|| ChatError::UserNotFound(from_user.clone()))?
+        };
+
+        info!("用户 {} 向 {} 发送私聊消息", from_username, to_user);
+
+        // 创建私聊消息
+        let private_message = ChatMessageType::PrivateMessage {
+            target_user: to_user,
+            content,
+        };
+
+        let network_msg =
+            private_message.to_network_message(MessageType::chat(), from_user.clone())?;
+
+        let message_id = network_msg.id;
+
+        // 发送单播消息
+        let _sent_id = self.unicast_node(target_user_id, network_msg).await?;
+
+        Ok(message_id)
+    }
+
+    async fn list_rooms(&self) -> Result<Vec<String>> {
+        let rooms = self.rooms.read().await;
+        let room_list = rooms.keys().cloned().collect();
+        Ok(room_list)
+    }
+
+    async fn list_rooms_detailed(&self) -> Result<Vec<ChatRoom>> {
+        let rooms = self.rooms.read().await;
+        Ok(rooms.values().cloned().collect())
+    }
+
+    async fn list_room_members(&self, room_id: String) -> Result<Vec<String>> {
+        let room = self
+            .get_room(&room_id)
+            .await
+            .ok_or_else(|| ChatError::RoomNotFound(room_id))?;
+
+        let users = self.users.read().await;
+        let member_names: Vec<String> = room
+            .members
+            .iter()
+            .filter_map(|user_id| users.get(user_id).map(|u| u.username.clone()))
+            .collect();
+
+        Ok(member_names)
+    }
+
+    async fn get_room_history(
+        &self,
+        room_id: &str,
+        after_seq: Option<u64>,
+    ) -> Result<Vec<ChatMessageRecord>> {
+        if self.get_room(room_id).await.is_none() {
+            return Err(ChatError::RoomNotFound(room_id.to_string()));
+        }
+
+        let after_seq = after_seq.unwrap_or(0);
+        let mut records: Vec<ChatMessageRecord> = self
+            .message_history
+            .read()
+            .await
+            .iter()
+            .filter(|record| record.room_id == room_id && record.room_seq > after_seq)
+            .cloned()
+            .collect();
+        records.sort_by_key(|record| record.room_seq);
+        Ok(records)
+    }
+
+    async fn room_member_count(&self, room_id: &str) -> Result<usize> {
+        self.rooms
+            .read()
+            .await
+            .get(room_id)
+            .map(|room| room.members.len())
+            .ok_or_else(|| ChatError::RoomNotFound(room_id.to_string()))
+    }
+
+    async fn is_room_member(&self, room_id: &str, user_id: &NodeId) -> bool {
+        self.rooms
+            .read()
+            .await
+            .get(room_id)
+            .is_some_and(|room| room.members.contains(user_id))
+    }
+
+    async fn set_status(&self, user_id: NodeId, status: UserStatus) -> Result<()> {
+        let (username, rooms) = {
+            let mut users = self.users.write().await;
+            let user = users
+                .get_mut(&user_id)
+                .ok_or_else(|| ChatError::UserNotFound(user_id.clone()))?;
+            user.last_active = now_millis();
+            if user.status == status {
+                return Ok(());
+            }
+            user.status = status;
+            let mut rooms: Vec<String> = user.joined_rooms.iter().cloned().collect();
+            rooms.sort();
+            (user.username.clone(), rooms)
+        };
+
+        info!("用户 {} ({}) 状态变更为 {:?}", username, user_id, status);
+
+        // 通知用户所在的聊天室
+        for room_id in rooms {
+            let status_message = ChatMessageType::StatusChanged {
+                username: username.clone(),
+                status,
+            };
+            let network_msg =
+                status_message.to_network_message(MessageType::chat(), user_id.clone())?;
+            self.broadcast_to_room(&room_id, network_msg, Some(user_id.clone()))
+                .await?;
+        }
+        Ok(())
+    }
+
+    async fn list_users(&self) -> Result<Vec<UserPresence>> {
+        let departed = self.departed_nodes.read().await.clone();
+        let mut presence: Vec<UserPresence> = self
+            .users
+            .read()
+            .await
+            .values()
+            .map(|user| UserPresence {
+                user_id: user.user_id.clone(),
+                username: user.username.clone(),
+                status: user.status,
+                last_active: user.last_active,
+                connected: !departed.contains_key(&user.user_id),
+            })
+            .collect();
+        presence.sort_by(|a, b| a.username.cmp(&b.username));
+        Ok(presence)
+    }
+
+    async fn get_user_rooms(&self, user_id: NodeId) -> Result<Vec<String>> {
+        let users = self.users.read().await;
+        let user = users
+            .get(&user_id)
+            .ok_or_else(|| ChatError::UserNotFound(user_id))?;
+
+        let rooms: Vec<String> = user.joined_rooms.iter().cloned().collect();
+        Ok(rooms)
+    }
+
+    async fn accept_state_handoff(&self, from: NodeId, snapshot: ChatStateSnapshot) -> Result<()> {
+        if !self.config.handoff_sources.contains(&from) {
+            warn!("拒绝来自 {} 的状态移交：不是受信任的主服务器", from);
+            return Err(ChatError::UntrustedHandoffSource(from));
+        }
+        info!(
+            "从 {} 导入状态: {} 个聊天室, {} 个用户",
+            from,
+            snapshot.rooms.len(),
+            snapshot.users.len()
+        );
+        self.import_state(snapshot).await;
+        Ok(())
+    }
+
+    async fn apply_membership_sync(&self, from: NodeId, rooms: Vec<String>) -> Result<()> {
+        if !self.config.federation_peers.contains(&from) {
+            return Err(ChatError::UnknownFederationPeer(from));
+        }
+        info!("联邦服务器 {} 同步了 {} 个聊天室", from, rooms.len());
+        self.federation_rooms
+            .write()
+            .await
+            .insert(from, rooms.into_iter().collect());
+        Ok(())
+    }
+
+    async fn receive_room_relay(
+        &self,
+        from: NodeId,
+        room_id: String,
+        message: NetworkMessage,
+        sender_name: String,
+    ) -> Result<bool> {
+        if !self.config.federation_peers.contains(&from) {
+            return Err(ChatError::UnknownFederationPeer(from));
+        }
+        if !self.relayed_messages.insert(message.id).await {
+            info!("忽略重复转发的聊天室消息 {}", message.id);
+            return Ok(false);
+        }
+
+        self.relay_to_servers(&room_id, &message, &sender_name, Some(&from))
+            .await?;
+        if self.get_room(&room_id).await.is_none() {
+            return Ok(true);
+        }
+
+        // 其他服务器上的成员发送的文本消息同样记入本服务器的聊天室历史
+        if let Ok(ChatMessageType::TextMessage {
+            content,
+            content_type,
+            ..
+        }) = serde_json::from_value(message.payload.clone())
+        {
+            let sender_name = if sender_name.is_empty() {
+                message.sender.clone()
+            } else {
+                sender_name
+            };
+            self.record_room_message(ChatMessageRecord {
+                message_id: message.id,
+                room_id: room_id.clone(),
+                sender_id: message.sender.clone(),
+                sender_name,
+                content: self.sanitize_content(&content),
+                timestamp: message.timestamp,
+                message_type: content_type.as_str().to_string(),
+                room_seq: 0,
+            })
+            .await;
+        }
+        self.deliver_to_local_members(&room_id, message, None)
+            .await?;
+        Ok(true)
+    }
+
+    async fn confirm_state_handoff(&self, handoff_id: Uuid) -> Result<()> {
+        let ack_tx = self
+            .pending_handoffs
+            .lock()
+            .await
+            .remove(&handoff_id)
+            .ok_or_else(|| ChatError::HandoffFailed(format!("未知的移交: {}", handoff_id)))?;
+        // 等待方可能已超时放弃
+        let _ = ack_tx.send(());
+        Ok(())
+    }
+
+    async fn record_peer_version(&self, peer: NodeId, version: u32) {
+        self.peer_versions.write().await.insert(peer, version);
+    }
+}
+
+#[async_trait]
+impl<N: NetworkServiceTrait + 'static> ShutdownComponent for ChatService<N> {
+    fn name(&self) -> &str {
+        "chat"
+    }
+
+    /// 停止历史清理和网络事件订阅任务
+    async fn shutdown(&self) -> network_service::Result<()> {
+        self.stop_history_pruning().await;
+        if let Some(handle) = self.event_task.lock().await.take() {
+            handle.abort();
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use network_service::test_utils::MockNetworkService;
+    use network_service::AnemoNetworkService;
+
+    #[tokio::test]
+    async fn test_chat_service_creation() {
+        let network_service = AnemoNetworkService::new();
+        let chat_service = ChatService::new(network_service);
 
         let rooms = chat_service.list_rooms().await.unwrap();
         assert!(rooms.is_empty());
@@ -502,4 +2068,1527 @@ mod tests {
         // let user_rooms = chat_service.get_user_rooms(user_id).await.unwrap();
         // assert!(user_rooms.contains(&room_id));
     }
+
+    #[tokio::test]
+    async fn test_guest_join_and_leave() {
+        let chat_service = ChatService::new(MockNetworkService::new());
+        let room_id = "general".to_string();
+        let guest_id = "guest-node-1".to_string();
+
+        let guest_name = chat_service
+            .join_as_guest(guest_id.clone(), room_id.clone())
+            .await
+            .unwrap();
+        assert!(guest_name.starts_with("Guest-"));
+        assert!(chat_service.get_user(&guest_id).await.unwrap().is_guest);
+
+        let other_name = chat_service
+            .join_as_guest("guest-node-2".to_string(), room_id.clone())
+            .await
+            .unwrap();
+        assert_ne!(guest_name, other_name);
+
+        let members = chat_service
+            .list_room_members(room_id.clone())
+            .await
+            .unwrap();
+        assert!(members.contains(&guest_name));
+
+        chat_service
+            .leave_room(guest_id.clone(), room_id.clone())
+            .await
+            .unwrap();
+
+        assert!(chat_service.get_user(&guest_id).await.is_none());
+        assert!(!chat_service
+            .username_to_user_id
+            .read()
+            .await
+            .contains_key(&guest_name));
+        let members = chat_service.list_room_members(room_id).await.unwrap();
+        assert!(!members.contains(&guest_name));
+    }
+
+    #[tokio::test]
+    async fn test_registered_user_cannot_join_as_guest() {
+        let chat_service = ChatService::new(MockNetworkService::new());
+        let user_id = "user1".to_string();
+        chat_service
+            .join_room(user_id.clone(), "Alice".to_string(), "general".to_string())
+            .await
+            .unwrap();
+
+        let result = chat_service
+            .join_as_guest(user_id.clone(), "lobby".to_string())
+            .await;
+        assert!(matches!(result, Err(ChatError::AlreadyRegistered(id)) if id == user_id));
+
+        // 正式用户身份不变，也没有加入访客聊天室
+        let user = chat_service.get_user(&user_id).await.unwrap();
+        assert!(!user.is_guest);
+        assert_eq!(user.username, "Alice");
+        assert_eq!(
+            chat_service.get_user_rooms(user_id.clone()).await.unwrap(),
+            vec!["general".to_string()]
+        );
+
+        // 仍可加入不允许访客的聊天室
+        chat_service
+            .set_room_allow_guests("general", false)
+            .await
+            .unwrap();
+        chat_service
+            .leave_room(user_id.clone(), "general".to_string())
+            .await
+            .unwrap();
+        chat_service
+            .join_room(user_id, "Alice".to_string(), "general".to_string())
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_guest_names_fall_back_when_short_names_exhausted() {
+        let config = ChatServiceConfig {
+            max_username_mappings: 20_000,
+            ..Default::default()
+        };
+        let chat_service = ChatService::with_config(MockNetworkService::new(), config);
+        {
+            let mut username_map = chat_service.username_to_user_id.write().await;
+            for n in 0..10000 {
+                username_map.insert(format!("Guest-{:04}", n), format!("node-{}", n));
+            }
+        }
+
+        let guest_name = chat_service
+            .join_as_guest("guest-node".to_string(), "general".to_string())
+            .await
+            .unwrap();
+        assert!(guest_name.starts_with("Guest-"));
+        assert_eq!(guest_name.len(), "Guest-".len() + 8);
+    }
+
+    #[test]
+    fn test_room_without_newer_fields_uses_defaults() {
+        let mut value =
+            serde_json::to_value(ChatRoom::new("general".to_string(), "general".to_string()))
+                .unwrap();
+        let fields = value.as_object_mut().unwrap();
+        for field in ["allow_guests", "owner", "join_policy", "pending_members"] {
+            fields.remove(field);
+        }
+
+        let room: ChatRoom = serde_json::from_value(value).unwrap();
+        assert!(room.allow_guests);
+        assert_eq!(room.owner, None);
+        assert_eq!(room.join_policy, JoinPolicy::Open);
+        assert!(room.pending_members.is_empty());
+    }
+
+    /// 创建由 Alice 拥有、需要审批的聊天室，并让 Bob 提交加入申请
+    async fn setup_approval_room(
+        network: &MockNetworkService,
+    ) -> (ChatService<MockNetworkService>, NodeId, NodeId, String) {
+        let chat_service = ChatService::new(network.clone());
+        let owner = "alice-node".to_string();
+        let joiner = "bob-node".to_string();
+        let room_id = "private".to_string();
+        // 两个用户都通过携带协议版本的消息加入
+        for node in [&owner, &joiner] {
+            chat_service
+                .record_peer_version(node.clone(), crate::CHAT_PROTOCOL_VERSION)
+                .await;
+        }
+
+        chat_service
+            .join_room(owner.clone(), "Alice".to_string(), room_id.clone())
+            .await
+            .unwrap();
+        chat_service
+            .set_join_policy(&owner, &room_id, JoinPolicy::ApprovalRequired)
+            .await
+            .unwrap();
+        chat_service
+            .join_room(joiner.clone(), "Bob".to_string(), room_id.clone())
+            .await
+            .unwrap();
+
+        (chat_service, owner, joiner, room_id)
+    }
+
+    #[tokio::test]
+    async fn test_join_approval() {
+        let network = MockNetworkService::new();
+        let (chat_service, owner, joiner, room_id) = setup_approval_room(&network).await;
+
+        // 待审批用户不在成员列表中，所有者收到申请
+        let members = chat_service
+            .list_room_members(room_id.clone())
+            .await
+            .unwrap();
+        assert_eq!(members, vec!["Alice".to_string()]);
+        assert_eq!(
+            chat_service.list_pending_joins(&room_id).await.unwrap(),
+            vec!["Bob".to_string()]
+        );
+        {
+            let unicasts = network.unicasts.lock().unwrap();
+            assert_eq!(unicasts.len(), 1);
+            assert_eq!(unicasts[0].0, owner);
+        }
+
+        // 非所有者不能审批
+        assert!(matches!(
+            chat_service.approve_join(&joiner, &room_id, &joiner).await,
+            Err(ChatError::NotRoomOwner(_, _))
+        ));
+
+        chat_service
+            .approve_join(&owner, &room_id, &joiner)
+            .await
+            .unwrap();
+
+        let members = chat_service
+            .list_room_members(room_id.clone())
+            .await
+            .unwrap();
+        assert!(members.contains(&"Bob".to_string()));
+        assert!(chat_service
+            .list_pending_joins(&room_id)
+            .await
+            .unwrap()
+            .is_empty());
+        assert!(chat_service
+            .get_user_rooms(joiner)
+            .await
+            .unwrap()
+            .contains(&room_id));
+    }
+
+    #[tokio::test]
+    async fn test_join_denial() {
+        let network = MockNetworkService::new();
+        let (chat_service, owner, joiner, room_id) = setup_approval_room(&network).await;
+
+        chat_service
+            .deny_join(&owner, &room_id, &joiner)
+            .await
+            .unwrap();
+
+        let members = chat_service
+            .list_room_members(room_id.clone())
+            .await
+            .unwrap();
+        assert!(!members.contains(&"Bob".to_string()));
+        assert!(chat_service
+            .list_pending_joins(&room_id)
+            .await
+            .unwrap()
+            .is_empty());
+        {
+            let unicasts = network.unicasts.lock().unwrap();
+            assert_eq!(unicasts.last().unwrap().0, joiner);
+        }
+
+        // 已处理的申请不能再次审批
+        assert!(matches!(
+            chat_service.approve_join(&owner, &room_id, &joiner).await,
+            Err(ChatError::JoinRequestNotFound(_, _))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_message_too_long_rejected() {
+        let network = MockNetworkService::new();
+        let config = ChatServiceConfig {
+            max_content_len: 16,
+            ..Default::default()
+        };
+        let chat_service = ChatService::with_config(network.clone(), config);
+        let user_id = "user1".to_string();
+        let room_id = "general".to_string();
+
+        chat_service
+            .join_room(user_id.clone(), "Alice".to_string(), room_id.clone())
+            .await
+            .unwrap();
+        chat_service
+            .join_room("user2".to_string(), "Bob".to_string(), room_id.clone())
+            .await
+            .unwrap();
+        let broadcasts_before = network.broadcasts.lock().unwrap().len();
+
+        let content = "x".repeat(17);
+        let result = chat_service
+            .send_message(
+                user_id.clone(),
+                room_id.clone(),
+                content.clone(),
+                ContentType::Text,
+            )
+            .await;
+        assert!(matches!(
+            result,
+            Err(ChatError::MessageTooLong { len: 17, max: 16 })
+        ));
+
+        let result = chat_service
+            .send_private_message(user_id.clone(), "Bob".to_string(), content)
+            .await;
+        assert!(matches!(result, Err(ChatError::MessageTooLong { .. })));
+
+        // 拒绝发生在任何网络调用之前
+        assert_eq!(network.broadcasts.lock().unwrap().len(), broadcasts_before);
+        assert!(network.unicasts.lock().unwrap().is_empty());
+        assert!(chat_service.message_history.read().await.is_empty());
+
+        // 恰好等于上限的消息可以发送
+        chat_service
+            .send_message(user_id, room_id, "y".repeat(16), ContentType::Text)
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_max_rooms_limit() {
+        let config = ChatServiceConfig {
+            max_rooms: 2,
+            ..Default::default()
+        };
+        let chat_service = ChatService::with_config(MockNetworkService::new(), config);
+
+        for room_id in ["room-a", "room-b"] {
+            chat_service
+                .join_room(
+                    "user1".to_string(),
+                    "Alice".to_string(),
+                    room_id.to_string(),
+                )
+                .await
+                .unwrap();
+        }
+
+        let result = chat_service
+            .join_room(
+                "user1".to_string(),
+                "Alice".to_string(),
+                "room-c".to_string(),
+            )
+            .await;
+        assert!(matches!(result, Err(ChatError::TooManyRooms(2))));
+        assert!(chat_service.get_room("room-c").await.is_none());
+
+        // 已存在的聊天室仍可加入
+        chat_service
+            .join_room("user2".to_string(), "Bob".to_string(), "room-a".to_string())
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_room_broadcast_recipients_scoped_to_room() {
+        let network = MockNetworkService::new();
+        let chat_service = ChatService::new(network.clone());
+
+        // "shared" 同时在两个聊天室中
+        for (user_id, username, room_id) in [
+            ("user1", "Alice", "room-a"),
+            ("user2", "Bob", "room-a"),
+            ("shared", "Carol", "room-a"),
+            ("shared", "Carol", "room-b"),
+            ("user4", "Dave", "room-b"),
+        ] {
+            chat_service
+                .join_room(
+                    user_id.to_string(),
+                    username.to_string(),
+                    room_id.to_string(),
+                )
+                .await
+                .unwrap();
+        }
+
+        let last_recipients = || {
+            let options = network.broadcast_options.lock().unwrap();
+            let mut recipients = options.last().unwrap().include_nodes.clone().unwrap();
+            recipients.sort();
+            recipients
+        };
+
+        chat_service
+            .send_message(
+                "user1".to_string(),
+                "room-a".to_string(),
+                "hi".to_string(),
+                ContentType::Text,
+            )
+            .await
+            .unwrap();
+        assert_eq!(last_recipients(), vec!["shared", "user2"]);
+
+        chat_service
+            .send_message(
+                "user4".to_string(),
+                "room-b".to_string(),
+                "hi".to_string(),
+                ContentType::Text,
+            )
+            .await
+            .unwrap();
+        assert_eq!(last_recipients(), vec!["shared"]);
+    }
+
+    #[tokio::test]
+    async fn test_send_message_report_counts_failures() {
+        let network = MockNetworkService::new();
+        let chat_service = ChatService::new(network.clone());
+        let room_id = "general".to_string();
+
+        for (user_id, username) in [
+            ("user1", "Alice"),
+            ("user2", "Bob"),
+            ("user3", "Carol"),
+            ("user4", "Dave"),
+            ("user5", "Erin"),
+        ] {
+            chat_service
+                .join_room(user_id.to_string(), username.to_string(), room_id.clone())
+                .await
+                .unwrap();
+        }
+        network
+            .failing_targets
+            .lock()
+            .unwrap()
+            .insert("user3".to_string());
+
+        let report = chat_service
+            .send_message_reported(
+                "user1".to_string(),
+                room_id,
+                "hi".to_string(),
+                ContentType::Text,
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(report.delivered, 3);
+        assert_eq!(report.failed, vec!["user3".to_string()]);
+        assert_eq!(chat_service.message_history.read().await.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_disconnected_user_removed_from_rooms() {
+        let network = MockNetworkService::new();
+        let chat_service = Arc::new(ChatService::new(network.clone()));
+        assert!(chat_service.attach().await);
+
+        for room_id in ["room-a", "room-b"] {
+            chat_service
+                .join_room(
+                    "user1".to_string(),
+                    "Alice".to_string(),
+                    room_id.to_string(),
+                )
+                .await
+                .unwrap();
+        }
+        chat_service
+            .join_room("user2".to_string(), "Bob".to_string(), "room-a".to_string())
+            .await
+            .unwrap();
+
+        network
+            .event_bus
+            .publish(NetworkEvent::NodeDisconnected {
+                node_id: "user1".to_string(),
+                reason: "ConnectionLost".to_string(),
+            })
+            .await;
+
+        tokio::time::timeout(Duration::from_secs(1), async {
+            while chat_service.get_user(&"user1".to_string()).await.is_some() {
+                tokio::time::sleep(Duration::from_millis(5)).await;
+            }
+        })
+        .await
+        .expect("断开的用户应被自动移除");
+
+        assert_eq!(chat_service.room_member_count("room-a").await.unwrap(), 1);
+        assert_eq!(chat_service.room_member_count("room-b").await.unwrap(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_offline_member_receives_messages_on_reconnect() {
+        let network = MockNetworkService::new();
+        let config = ChatServiceConfig {
+            max_offline_messages: 2,
+            ..Default::default()
+        };
+        let chat_service = Arc::new(ChatService::with_config(network.clone(), config));
+        assert!(chat_service.attach().await);
+        let room_id = "general".to_string();
+        for (user_id, username) in [("user1", "Alice"), ("user2", "Bob")] {
+            chat_service
+                .join_room(user_id.to_string(), username.to_string(), room_id.clone())
+                .await
+                .unwrap();
+        }
+        chat_service
+            .set_room_offline_delivery(&room_id, true)
+            .await
+            .unwrap();
+        let bob = "user2".to_string();
+        chat_service
+            .set_status(bob.clone(), UserStatus::Busy)
+            .await
+            .unwrap();
+        let bob_presence = || async {
+            chat_service
+                .list_users()
+                .await
+                .unwrap()
+                .into_iter()
+                .find(|presence| presence.user_id == "user2")
+                .unwrap()
+        };
+
+        // Bob 断开后仍是成员，只标记为未连接，用户设置的状态不变
+        network.failing_targets.lock().unwrap().insert(bob.clone());
+        network
+            .event_bus
+            .publish(NetworkEvent::NodeDisconnected {
+                node_id: bob.clone(),
+                reason: "ConnectionLost".to_string(),
+            })
+            .await;
+        tokio::time::timeout(Duration::from_secs(1), async {
+            while bob_presence().await.connected {
+                tokio::time::sleep(Duration::from_millis(5)).await;
+            }
+        })
+        .await
+        .expect("断开的用户应标记为未连接");
+        assert!(chat_service.is_room_member(&room_id, &bob).await);
+        assert_eq!(bob_presence().await.status, UserStatus::Busy);
+
+        // 离线期间的消息按上限保存，最早的消息被丢弃
+        let mut sent = Vec::new();
+        for content in ["one", "two", "three"] {
+            sent.push(
+                chat_service
+                    .send_message(
+                        "user1".to_string(),
+                        room_id.clone(),
+                        content.to_string(),
+                        ContentType::Text,
+                    )
+                    .await
+                    .unwrap(),
+            );
+        }
+        assert_eq!(chat_service.offline_message_count(&bob).await, 2);
+
+        network.failing_targets.lock().unwrap().clear();
+        network.unicasts.lock().unwrap().clear();
+        network
+            .event_bus
+            .publish(NetworkEvent::NodeConnected {
+                node_id: bob.clone(),
+                connection: Default::default(),
+                metadata: HashMap::new(),
+            })
+            .await;
+        tokio::time::timeout(Duration::from_secs(1), async {
+            while chat_service.offline_message_count(&bob).await > 0 {
+                tokio::time::sleep(Duration::from_millis(5)).await;
+            }
+        })
+        .await
+        .expect("重新连接后应补发离线消息");
+
+        let replayed: Vec<Uuid> = network
+            .unicasts
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|(target, message)| {
+                *target == bob && message.get_metadata(OFFLINE_REPLAY_METADATA_KEY).is_some()
+            })
+            .map(|(_, message)| message.id)
+            .collect();
+        assert_eq!(replayed, sent[1..]);
+        let presence = bob_presence().await;
+        assert!(presence.connected);
+        assert_eq!(presence.status, UserStatus::Busy);
+    }
+
+    #[tokio::test]
+    async fn test_departed_member_expires_with_queue() {
+        use std::sync::atomic::{AtomicU64, Ordering};
+
+        let now = Arc::new(AtomicU64::new(1_000_000));
+        let clock_now = now.clone();
+        let network = MockNetworkService::new();
+        let config = ChatServiceConfig {
+            offline_member_ttl: Some(Duration::from_secs(3600)),
+            ..Default::default()
+        };
+        let chat_service = Arc::new(
+            ChatService::with_config(network.clone(), config)
+                .with_clock(Arc::new(move || clock_now.load(Ordering::SeqCst))),
+        );
+        assert!(chat_service.attach().await);
+        let room_id = "general".to_string();
+        for (user_id, username) in [("user1", "Alice"), ("user2", "Bob")] {
+            chat_service
+                .join_room(user_id.to_string(), username.to_string(), room_id.clone())
+                .await
+                .unwrap();
+        }
+        chat_service
+            .set_room_offline_delivery(&room_id, true)
+            .await
+            .unwrap();
+        let bob = "user2".to_string();
+
+        network.failing_targets.lock().unwrap().insert(bob.clone());
+        network
+            .event_bus
+            .publish(NetworkEvent::NodeDisconnected {
+                node_id: bob.clone(),
+                reason: "ConnectionLost".to_string(),
+            })
+            .await;
+        tokio::time::timeout(Duration::from_secs(1), async {
+            while !chat_service.departed_nodes.read().await.contains_key(&bob) {
+                tokio::time::sleep(Duration::from_millis(5)).await;
+            }
+        })
+        .await
+        .expect("断开的节点应被记录");
+        chat_service
+            .send_message(
+                "user1".to_string(),
+                room_id.clone(),
+                "hello".to_string(),
+                ContentType::Text,
+            )
+            .await
+            .unwrap();
+        assert_eq!(chat_service.offline_message_count(&bob).await, 1);
+
+        // 未超过保留时间时保留
+        now.fetch_add(3_600_000, Ordering::SeqCst);
+        assert_eq!(chat_service.prune_departed_members().await, 0);
+        assert!(chat_service.is_room_member(&room_id, &bob).await);
+
+        // 超过保留时间后移出聊天室并丢弃待补发的消息
+        now.fetch_add(1, Ordering::SeqCst);
+        assert_eq!(chat_service.prune_departed_members().await, 1);
+        assert!(!chat_service.is_room_member(&room_id, &bob).await);
+        assert!(chat_service.get_user(&bob).await.is_none());
+        assert_eq!(chat_service.offline_message_count(&bob).await, 0);
+    }
+
+    #[tokio::test]
+    async fn test_room_member_count() {
+        let chat_service = ChatService::new(MockNetworkService::new());
+        let room_id = "general".to_string();
+
+        for (user_id, username) in [("user1", "Alice"), ("user2", "Bob")] {
+            chat_service
+                .join_room(user_id.to_string(), username.to_string(), room_id.clone())
+                .await
+                .unwrap();
+        }
+        assert_eq!(chat_service.room_member_count(&room_id).await.unwrap(), 2);
+
+        chat_service
+            .leave_room("user1".to_string(), room_id.clone())
+            .await
+            .unwrap();
+        assert_eq!(chat_service.room_member_count(&room_id).await.unwrap(), 1);
+
+        assert!(matches!(
+            chat_service.room_member_count("missing").await,
+            Err(ChatError::RoomNotFound(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_reconnecting_user_reclaims_stale_username() {
+        let network_service = MockNetworkService::new();
+        let chat_service = Arc::new(ChatService::new(network_service.clone()));
+        assert!(chat_service.attach().await);
+
+        // 旧节点加入两个开启离线投递的聊天室后断开，身份被保留
+        for room_id in ["general", "random"] {
+            chat_service
+                .join_room(
+                    "old-node".to_string(),
+                    "Alice".to_string(),
+                    room_id.to_string(),
+                )
+                .await
+                .unwrap();
+            chat_service
+                .set_room_offline_delivery(room_id, true)
+                .await
+                .unwrap();
+        }
+        network_service
+            .event_bus
+            .publish(NetworkEvent::NodeDisconnected {
+                node_id: "old-node".to_string(),
+                reason: "ConnectionLost".to_string(),
+            })
+            .await;
+        tokio::time::timeout(Duration::from_secs(1), async {
+            while !chat_service
+                .departed_nodes
+                .read()
+                .await
+                .contains_key("old-node")
+            {
+                tokio::time::sleep(Duration::from_millis(5)).await;
+            }
+        })
+        .await
+        .expect("断开的节点应被记录");
+
+        // 同一用户以新节点重连
+        chat_service
+            .join_room(
+                "new-node".to_string(),
+                "Alice".to_string(),
+                "general".to_string(),
+            )
+            .await
+            .unwrap();
+
+        assert!(chat_service
+            .get_user(&"old-node".to_string())
+            .await
+            .is_none());
+        let user = chat_service
+            .get_user(&"new-node".to_string())
+            .await
+            .unwrap();
+        assert_eq!(user.username, "Alice");
+        assert_eq!(user.joined_rooms.len(), 2);
+        let random = chat_service.get_room("random").await.unwrap();
+        assert!(random.has_member(&"new-node".to_string()));
+        assert!(!random.has_member(&"old-node".to_string()));
+        assert_eq!(chat_service.room_member_count("general").await.unwrap(), 1);
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test_history_appended_in_room_seq_order() {
+        let chat_service = Arc::new(ChatService::new(MockNetworkService::new()));
+        chat_service
+            .join_room(
+                "user1".to_string(),
+                "Alice".to_string(),
+                "general".to_string(),
+            )
+            .await
+            .unwrap();
+
+        let sends: Vec<_> = (0..50)
+            .map(|i| {
+                let chat_service = chat_service.clone();
+                tokio::spawn(async move {
+                    chat_service
+                        .send_message(
+                            "user1".to_string(),
+                            "general".to_string(),
+                            format!("message {}", i),
+                            ContentType::Text,
+                        )
+                        .await
+                        .unwrap();
+                })
+            })
+            .collect();
+        for send in sends {
+            send.await.unwrap();
+        }
+
+        let seqs: Vec<u64> = chat_service
+            .message_history
+            .read()
+            .await
+            .iter()
+            .map(|record| record.room_seq)
+            .collect();
+        assert_eq!(seqs, (1..=50).collect::<Vec<u64>>());
+    }
+
+    #[tokio::test]
+    async fn test_membership_changes_synced_to_federation_peers() {
+        let network = MockNetworkService::new();
+        let config = ChatServiceConfig {
+            federation_peers: vec!["server-b".to_string()],
+            ..Default::default()
+        };
+        let chat_service = ChatService::with_config(network.clone(), config);
+        let synced_rooms = |network: &MockNetworkService| -> Vec<Vec<String>> {
+            network
+                .unicasts
+                .lock()
+                .unwrap()
+                .iter()
+                .filter_map(|(target, message)| {
+                    assert_eq!(target, "server-b");
+                    match serde_json::from_value(message.payload.clone()) {
+                        Ok(ChatMessageType::RoomMembershipSync { rooms }) => Some(rooms),
+                        _ => None,
+                    }
+                })
+                .collect()
+        };
+
+        for user_id in ["user1", "user2"] {
+            chat_service
+                .join_room(
+                    user_id.to_string(),
+                    user_id.to_string(),
+                    "general".to_string(),
+                )
+                .await
+                .unwrap();
+        }
+        chat_service
+            .leave_room("user1".to_string(), "general".to_string())
+            .await
+            .unwrap();
+        // 只有有成员的聊天室集合变化时才同步
+        assert_eq!(synced_rooms(&network), vec![vec!["general".to_string()]]);
+
+        chat_service
+            .remove_user(&"user2".to_string())
+            .await
+            .unwrap();
+        assert_eq!(
+            synced_rooms(&network),
+            vec![vec!["general".to_string()], Vec::new()]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_state_handoff_from_untrusted_source_rejected() {
+        let config = ChatServiceConfig {
+            handoff_sources: vec!["primary".to_string()],
+            ..Default::default()
+        };
+        let chat_service = ChatService::with_config(MockNetworkService::new(), config);
+        chat_service
+            .join_room(
+                "user1".to_string(),
+                "Alice".to_string(),
+                "general".to_string(),
+            )
+            .await
+            .unwrap();
+
+        // 攻击者发来的空快照不能覆盖现有状态
+        let other = ChatService::new(MockNetworkService::new());
+        let snapshot = other.export_state().await;
+        let result = chat_service
+            .accept_state_handoff("attacker".to_string(), snapshot)
+            .await;
+        assert!(matches!(
+            result,
+            Err(ChatError::UntrustedHandoffSource(node)) if node == "attacker"
+        ));
+        assert_eq!(
+            chat_service
+                .list_room_members("general".to_string())
+                .await
+                .unwrap(),
+            vec!["Alice".to_string()]
+        );
+        assert_eq!(
+            chat_service
+                .get_user_rooms("user1".to_string())
+                .await
+                .unwrap(),
+            vec!["general".to_string()]
+        );
+
+        // 受信任的主服务器可以移交状态
+        let snapshot = other.export_state().await;
+        chat_service
+            .accept_state_handoff("primary".to_string(), snapshot)
+            .await
+            .unwrap();
+        assert!(chat_service
+            .get_user_rooms("user1".to_string())
+            .await
+            .is_err());
+    }
+
+    #[tokio::test]
+    async fn test_username_used_as_node_id_reported() {
+        let network = MockNetworkService::new();
+        let chat_service = ChatService::new(network.clone());
+        chat_service
+            .join_room(
+                "user1".to_string(),
+                "Alice".to_string(),
+                "general".to_string(),
+            )
+            .await
+            .unwrap();
+        // 网络层找不到这些节点
+        network
+            .failing_targets
+            .lock()
+            .unwrap()
+            .extend(["Alice".to_string(), "server-9".to_string()]);
+        for target in ["Alice", "server-9"] {
+            chat_service
+                .record_peer_version(target.to_string(), crate::CHAT_PROTOCOL_VERSION)
+                .await;
+        }
+
+        let result = chat_service.handoff_to("Alice".to_string()).await;
+        assert!(matches!(result, Err(ChatError::UsernameAsNodeId(name)) if name == "Alice"));
+
+        // 不是用户名的目标仍报告网络层的错误
+        let result = chat_service.handoff_to("server-9".to_string()).await;
+        assert!(matches!(
+            result,
+            Err(ChatError::NetworkError(NetworkError::NodeNotFound(_)))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_evicted_username_still_owned() {
+        let network = MockNetworkService::new();
+        let config = ChatServiceConfig {
+            max_username_mappings: 2,
+            ..Default::default()
+        };
+        let chat_service = ChatService::with_config(network.clone(), config);
+        for (user_id, username) in [("user1", "Alice"), ("user2", "Bob"), ("user3", "Carol")] {
+            chat_service
+                .join_room(
+                    user_id.to_string(),
+                    username.to_string(),
+                    "general".to_string(),
+                )
+                .await
+                .unwrap();
+        }
+        assert_eq!(chat_service.username_evictions().await, 1);
+
+        // Alice 的映射已被淘汰，但她仍在线：用户名不能被占用，私聊仍能送达
+        let result = chat_service
+            .join_room(
+                "intruder".to_string(),
+                "Alice".to_string(),
+                "general".to_string(),
+            )
+            .await;
+        assert!(matches!(result, Err(ChatError::UsernameTaken(_, owner)) if owner == "user1"));
+
+        chat_service
+            .send_private_message("user2".to_string(), "Alice".to_string(), "hi".to_string())
+            .await
+            .unwrap();
+        let (target, _) = network.unicasts.lock().unwrap().last().cloned().unwrap();
+        assert_eq!(target, "user1");
+    }
+
+    #[tokio::test]
+    async fn test_local_user_username_not_reclaimed() {
+        let network_service = MockNetworkService::new();
+        let chat_service = Arc::new(ChatService::new(network_service));
+        assert!(chat_service.attach().await);
+
+        // 本地用户从未作为网络节点出现，不在线也不能被认领
+        chat_service
+            .join_room(
+                "mock-node".to_string(),
+                "Alice".to_string(),
+                "general".to_string(),
+            )
+            .await
+            .unwrap();
+        let result = chat_service
+            .join_room(
+                "new-node".to_string(),
+                "Alice".to_string(),
+                "general".to_string(),
+            )
+            .await;
+
+        assert!(matches!(result, Err(ChatError::UsernameTaken(_, owner)) if owner == "mock-node"));
+        assert!(chat_service
+            .get_user(&"mock-node".to_string())
+            .await
+            .is_some());
+    }
+
+    #[tokio::test]
+    async fn test_username_owned_by_connected_node_rejected() {
+        let network_service = MockNetworkService::new();
+        network_service
+            .connected_nodes
+            .lock()
+            .unwrap()
+            .push("node-a".to_string());
+        let chat_service = ChatService::new(network_service);
+
+        chat_service
+            .join_room(
+                "node-a".to_string(),
+                "Alice".to_string(),
+                "general".to_string(),
+            )
+            .await
+            .unwrap();
+        let result = chat_service
+            .join_room(
+                "node-b".to_string(),
+                "Alice".to_string(),
+                "general".to_string(),
+            )
+            .await;
+
+        assert!(matches!(result, Err(ChatError::UsernameTaken(..))));
+        assert!(chat_service.get_user(&"node-a".to_string()).await.is_some());
+    }
+
+    #[tokio::test]
+    async fn test_list_rooms_detailed() {
+        let chat_service = ChatService::new(MockNetworkService::new());
+
+        for (user_id, username, room_id) in [
+            ("user1", "Alice", "general"),
+            ("user2", "Bob", "general"),
+            ("user3", "Carol", "general"),
+            ("user1", "Alice", "random"),
+            ("user3", "Carol", "dev"),
+        ] {
+            chat_service
+                .join_room(
+                    user_id.to_string(),
+                    username.to_string(),
+                    room_id.to_string(),
+                )
+                .await
+                .unwrap();
+        }
+
+        let mut rooms: Vec<(String, usize)> = chat_service
+            .list_rooms_detailed()
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|room| (room.room_id, room.members.len()))
+            .collect();
+        rooms.sort();
+        assert_eq!(
+            rooms,
+            vec![
+                ("dev".to_string(), 1),
+                ("general".to_string(), 3),
+                ("random".to_string(), 1),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_join_result_new_vs_existing_room() {
+        let chat_service = ChatService::new(MockNetworkService::new());
+
+        let first = chat_service
+            .join_room(
+                "user1".to_string(),
+                "Alice".to_string(),
+                "general".to_string(),
+            )
+            .await
+            .unwrap();
+        assert!(first.created);
+        assert!(!first.pending_approval);
+        assert_eq!(first.members, vec!["Alice".to_string()]);
+        assert_eq!(first.history_available, 0);
+
+        chat_service
+            .send_message(
+                "user1".to_string(),
+                "general".to_string(),
+                "hello".to_string(),
+                ContentType::Text,
+            )
+            .await
+            .unwrap();
+
+        let second = chat_service
+            .join_room(
+                "user2".to_string(),
+                "Bob".to_string(),
+                "general".to_string(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(second.room_id, "general");
+        assert!(!second.created);
+        assert_eq!(second.members, vec!["Alice".to_string(), "Bob".to_string()]);
+        assert_eq!(second.history_available, 1);
+    }
+
+    #[tokio::test]
+    async fn test_record_timestamps_sub_second() {
+        let first = ChatRoom::new("room-a".to_string(), "Room A".to_string());
+        tokio::time::sleep(Duration::from_millis(5)).await;
+        let second = ChatRoom::new("room-b".to_string(), "Room B".to_string());
+
+        // 毫秒时间戳可以区分一秒内先后创建的记录
+        assert!(second.created_at > first.created_at);
+        assert!(second.created_at - first.created_at < 1000);
+    }
+
+    fn history_record(timestamp: u64) -> ChatMessageRecord {
+        ChatMessageRecord {
+            message_id: Uuid::new_v4(),
+            room_id: "general".to_string(),
+            sender_id: "user1".to_string(),
+            sender_name: "Alice".to_string(),
+            content: format!("sent at {}", timestamp),
+            timestamp,
+            message_type: "text".to_string(),
+            room_seq: 0,
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test_concurrent_join_leave_keeps_membership_coherent() {
+        let chat_service = Arc::new(ChatService::new(MockNetworkService::new()));
+        let user_id = "user1".to_string();
+        let rooms = ["room-a", "room-b"];
+
+        let mut tasks = Vec::new();
+        for worker in 0..8 {
+            let chat_service = chat_service.clone();
+            let user_id = user_id.clone();
+            tasks.push(tokio::spawn(async move {
+                for i in 0..50 {
+                    let room_id = rooms[(worker + i) % rooms.len()].to_string();
+                    if (worker + i) % 3 == 0 {
+                        let _ = chat_service.leave_room(user_id.clone(), room_id).await;
+                    } else {
+                        let _ = chat_service
+                            .join_room(user_id.clone(), "Alice".to_string(), room_id)
+                            .await;
+                    }
+                }
+            }));
+        }
+
+        // 操作进行期间两种视图始终一致
+        for _ in 0..50 {
+            {
+                let users = chat_service.users.read().await;
+                let all_rooms = chat_service.rooms.read().await;
+                let joined = users
+                    .get(&user_id)
+                    .map(|user| user.joined_rooms.clone())
+                    .unwrap_or_default();
+                for room_id in rooms {
+                    let member = all_rooms
+                        .get(room_id)
+                        .is_some_and(|room| room.has_member(&user_id));
+                    assert_eq!(joined.contains(room_id), member, "聊天室 {}", room_id);
+                }
+            }
+            tokio::task::yield_now().await;
+        }
+
+        for task in tasks {
+            task.await.unwrap();
+        }
+        let joined = chat_service.get_user_rooms(user_id.clone()).await.unwrap();
+        for room_id in rooms {
+            assert_eq!(
+                joined.contains(&room_id.to_string()),
+                chat_service.is_room_member(room_id, &user_id).await
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn test_status_broadcast_and_listed() {
+        let network = MockNetworkService::new();
+        let chat_service = ChatService::new(network.clone());
+        for (user_id, username) in [("user1", "Alice"), ("user2", "Bob")] {
+            chat_service
+                .join_room(
+                    user_id.to_string(),
+                    username.to_string(),
+                    "general".to_string(),
+                )
+                .await
+                .unwrap();
+        }
+        let broadcasts_before = network.broadcasts.lock().unwrap().len();
+
+        chat_service
+            .set_status("user1".to_string(), UserStatus::Away)
+            .await
+            .unwrap();
+
+        let broadcasts = network.broadcasts.lock().unwrap().clone();
+        assert_eq!(broadcasts.len(), broadcasts_before + 1);
+        match serde_json::from_value(broadcasts.last().unwrap().payload.clone()).unwrap() {
+            ChatMessageType::StatusChanged { username, status } => {
+                assert_eq!(username, "Alice");
+                assert_eq!(status, UserStatus::Away);
+            }
+            other => panic!("意外的消息: {:?}", other),
+        }
+
+        let presence = chat_service.list_users().await.unwrap();
+        let statuses: Vec<_> = presence
+            .iter()
+            .map(|p| (p.username.as_str(), p.status))
+            .collect();
+        assert_eq!(
+            statuses,
+            vec![("Alice", UserStatus::Away), ("Bob", UserStatus::Online)]
+        );
+
+        // 状态未变化时不重复广播
+        chat_service
+            .set_status("user1".to_string(), UserStatus::Away)
+            .await
+            .unwrap();
+        assert_eq!(
+            network.broadcasts.lock().unwrap().len(),
+            broadcasts_before + 1
+        );
+    }
+
+    #[tokio::test]
+    async fn test_control_characters_sanitized() {
+        let network = MockNetworkService::new();
+        let chat_service = ChatService::new(network.clone());
+        for (user_id, username) in [("user1", "Alice"), ("user2", "Bob")] {
+            chat_service
+                .join_room(
+                    user_id.to_string(),
+                    username.to_string(),
+                    "general".to_string(),
+                )
+                .await
+                .unwrap();
+        }
+
+        chat_service
+            .send_message(
+                "user1".to_string(),
+                "general".to_string(),
+                "\u{1b}[31mred\u{1b}[0m\u{7} alert\nnext\tline".to_string(),
+                ContentType::Text,
+            )
+            .await
+            .unwrap();
+
+        let expected = "red alert\nnext\tline";
+        assert_eq!(
+            chat_service.sanitize_content("\u{1b}[31mred\u{1b}[0m\u{7} alert\nnext\tline"),
+            expected
+        );
+        let history = chat_service
+            .get_room_history("general", None)
+            .await
+            .unwrap();
+        assert_eq!(history[0].content, expected);
+        let sent = network.broadcasts.lock().unwrap().last().cloned().unwrap();
+        match serde_json::from_value(sent.payload).unwrap() {
+            ChatMessageType::TextMessage { content, .. } => assert_eq!(content, expected),
+            other => panic!("意外的消息: {:?}", other),
+        }
+
+        // 只有控制字符的消息视为空消息
+        assert!(matches!(
+            chat_service
+                .send_private_message(
+                    "user1".to_string(),
+                    "Bob".to_string(),
+                    "\u{1b}[2J".to_string()
+                )
+                .await,
+            Err(ChatError::EmptyMessage)
+        ));
+
+        assert_eq!(
+            ControlCharPolicy::Escape.sanitize("a\u{1b}[1mb"),
+            "a\\u{1b}[1mb"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_room_history_after_seq() {
+        let network = MockNetworkService::new();
+        let chat_service = ChatService::new(network);
+        let user_id = "user1".to_string();
+        chat_service
+            .join_room(user_id.clone(), "Alice".to_string(), "general".to_string())
+            .await
+            .unwrap();
+
+        for content in ["one", "two"] {
+            chat_service
+                .send_message(
+                    user_id.clone(),
+                    "general".to_string(),
+                    content.to_string(),
+                    ContentType::Text,
+                )
+                .await
+                .unwrap();
+        }
+        let seen = chat_service
+            .get_room_history("general", None)
+            .await
+            .unwrap()
+            .last()
+            .unwrap()
+            .room_seq;
+        assert_eq!(seen, 2);
+
+        // 断线期间产生的新消息
+        chat_service
+            .leave_room(user_id.clone(), "general".to_string())
+            .await
+            .unwrap();
+        chat_service
+            .join_room(
+                "user2".to_string(),
+                "Bob".to_string(),
+                "general".to_string(),
+            )
+            .await
+            .unwrap();
+        chat_service
+            .send_message(
+                "user2".to_string(),
+                "general".to_string(),
+                "three".to_string(),
+                ContentType::Text,
+            )
+            .await
+            .unwrap();
+
+        // 重连后只获取记录序号之后的消息
+        chat_service
+            .join_room(user_id, "Alice".to_string(), "general".to_string())
+            .await
+            .unwrap();
+        let missed = chat_service
+            .get_room_history("general", Some(seen))
+            .await
+            .unwrap();
+        assert_eq!(missed.len(), 1);
+        assert_eq!(missed[0].content, "three");
+        assert_eq!(missed[0].room_seq, 3);
+
+        assert!(matches!(
+            chat_service.get_room_history("missing", None).await,
+            Err(ChatError::RoomNotFound(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_history_pruned_by_age() {
+        use std::sync::atomic::{AtomicU64, Ordering};
+
+        let now = Arc::new(AtomicU64::new(1_000_000_000));
+        let clock_now = now.clone();
+        let config = ChatServiceConfig {
+            max_history_len: 3,
+            max_history_age: Some(Duration::from_secs(24 * 3600)),
+            ..Default::default()
+        };
+        let chat_service = ChatService::with_config(MockNetworkService::new(), config)
+            .with_clock(Arc::new(move || clock_now.load(Ordering::SeqCst)));
+
+        let day = 24 * 3600 * 1000;
+        chat_service
+            .add_to_history(history_record(1_000_000_000 - day - 10_000))
+            .await;
+        chat_service
+            .add_to_history(history_record(1_000_000_000 - 60_000))
+            .await;
+        chat_service
+            .add_to_history(history_record(1_000_000_000))
+            .await;
+
+        assert_eq!(chat_service.prune_history().await, 1);
+        let timestamps: Vec<u64> = chat_service
+            .message_history
+            .read()
+            .await
+            .iter()
+            .map(|record| record.timestamp)
+            .collect();
+        assert_eq!(timestamps, vec![1_000_000_000 - 60_000, 1_000_000_000]);
+
+        // 时间推进后，剩余记录也会过期
+        now.store(1_000_000_000 + day + 1, Ordering::SeqCst);
+        assert_eq!(chat_service.prune_history().await, 2);
+
+        // 条数上限与保留时间同时生效
+        for offset in 0..5 {
+            chat_service
+                .add_to_history(history_record(1_000_000_000 + day + offset))
+                .await;
+        }
+        assert_eq!(chat_service.message_history.read().await.len(), 3);
+    }
+
+    #[tokio::test]
+    async fn test_guest_barred_from_room() {
+        let chat_service = ChatService::new(MockNetworkService::new());
+        let room_id = "members-only".to_string();
+
+        chat_service
+            .join_room("user1".to_string(), "Alice".to_string(), room_id.clone())
+            .await
+            .unwrap();
+        chat_service
+            .set_room_allow_guests(&room_id, false)
+            .await
+            .unwrap();
+
+        let result = chat_service
+            .join_as_guest("guest-node".to_string(), room_id)
+            .await;
+        assert!(matches!(result, Err(ChatError::GuestNotAllowed(_))));
+        assert!(chat_service
+            .get_user(&"guest-node".to_string())
+            .await
+            .is_none());
+    }
+
+    #[tokio::test]
+    async fn test_leave_all_rooms() {
+        let network = MockNetworkService::new();
+        let chat_service = ChatService::new(network.clone());
+        for room_id in ["room-a", "room-b", "room-c"] {
+            chat_service
+                .join_room(
+                    "user1".to_string(),
+                    "Alice".to_string(),
+                    room_id.to_string(),
+                )
+                .await
+                .unwrap();
+            chat_service
+                .join_room("user2".to_string(), "Bob".to_string(), room_id.to_string())
+                .await
+                .unwrap();
+        }
+        let broadcasts_before = network.broadcasts.lock().unwrap().len();
+
+        let left = chat_service
+            .leave_all_rooms("user1".to_string())
+            .await
+            .unwrap();
+        assert_eq!(left, vec!["room-a", "room-b", "room-c"]);
+
+        let mut leave_rooms: Vec<String> = network.broadcasts.lock().unwrap()[broadcasts_before..]
+            .iter()
+            .filter_map(
+                |message| match serde_json::from_value(message.payload.clone()).ok()? {
+                    ChatMessageType::UserLeave { room_id, .. } => Some(room_id),
+                    _ => None,
+                },
+            )
+            .collect();
+        leave_rooms.sort();
+        assert_eq!(leave_rooms, left);
+
+        assert!(chat_service
+            .get_user_rooms("user1".to_string())
+            .await
+            .unwrap()
+            .is_empty());
+        assert!(
+            !chat_service
+                .is_room_member("room-b", &"user1".to_string())
+                .await
+        );
+    }
+
+    #[tokio::test]
+    async fn test_room_allowed_content_types() {
+        let network = MockNetworkService::new();
+        let chat_service = ChatService::new(network.clone());
+
+        for room_id in ["text-only", "gallery"] {
+            chat_service
+                .join_room(
+                    "user1".to_string(),
+                    "Alice".to_string(),
+                    room_id.to_string(),
+                )
+                .await
+                .unwrap();
+            chat_service
+                .join_room("user2".to_string(), "Bob".to_string(), room_id.to_string())
+                .await
+                .unwrap();
+        }
+        chat_service
+            .set_room_allowed_content_types("gallery", [ContentType::Text, ContentType::Image])
+            .await
+            .unwrap();
+        let broadcasts_before = network.broadcasts.lock().unwrap().len();
+
+        // 默认只允许文本
+        let result = chat_service
+            .send_message(
+                "user1".to_string(),
+                "text-only".to_string(),
+                "cat.png".to_string(),
+                ContentType::Image,
+            )
+            .await;
+        assert!(matches!(
+            result,
+            Err(ChatError::ContentTypeNotAllowed(room, content_type))
+                if room == "text-only" && content_type == "image"
+        ));
+        assert_eq!(network.broadcasts.lock().unwrap().len(), broadcasts_before);
+
+        chat_service
+            .send_message(
+                "user1".to_string(),
+                "gallery".to_string(),
+                "cat.png".to_string(),
+                ContentType::Image,
+            )
+            .await
+            .unwrap();
+
+        // 内容类型随消息发送给接收者
+        let broadcasts = network.broadcasts.lock().unwrap();
+        let message = broadcasts.last().unwrap();
+        let chat_message: ChatMessageType =
+            serde_json::from_value(message.payload.clone()).unwrap();
+        assert!(matches!(
+            chat_message,
+            ChatMessageType::TextMessage {
+                content_type: ContentType::Image,
+                ..
+            }
+        ));
+    }
 }
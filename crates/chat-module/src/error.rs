@@ -17,6 +17,9 @@ pub enum ChatError {
     #[error("用户 {0} 未加入聊天室 {1}")]
     UserNotInRoom(String, String),
 
+    #[error("聊天室数量已达上限: {0}")]
+    TooManyRooms(usize),
+
     #[error("聊天室 {0} 已存在")]
     RoomAlreadyExists(String),
 
@@ -26,12 +29,52 @@ pub enum ChatError {
     #[error("消息为空")]
     EmptyMessage,
 
+    #[error("对端不支持操作 {operation}: 需要协议版本 {required}，对端版本 {peer_version}")]
+    UnsupportedOperation {
+        operation: String,
+        required: u32,
+        peer_version: u32,
+    },
+
+    #[error("状态移交失败: {0}")]
+    HandoffFailed(String),
+
+    #[error("消息过长: {len} 字符（上限 {max}）")]
+    MessageTooLong { len: usize, max: usize },
+
     #[error("无效的聊天室名称: {0}")]
     InvalidRoomName(String),
 
     #[error("无效的用户名: {0}")]
     InvalidUsername(String),
 
+    #[error("用户名 {0} 已被在线节点 {1} 使用")]
+    UsernameTaken(String, String),
+
+    #[error("聊天室 {0} 不允许发送 {1} 类型的消息")]
+    ContentTypeNotAllowed(String, String),
+
+    #[error("聊天室 {0} 不允许访客加入")]
+    GuestNotAllowed(String),
+
+    #[error("用户 {0} 已注册，不能以访客身份加入")]
+    AlreadyRegistered(String),
+
+    #[error("用户 {0} 不是聊天室 {1} 的所有者")]
+    NotRoomOwner(String, String),
+
+    #[error("用户 {0} 没有加入聊天室 {1} 的待审批请求")]
+    JoinRequestNotFound(String, String),
+
+    #[error("{0} 是用户名而不是节点ID，请先解析为节点ID")]
+    UsernameAsNodeId(String),
+
+    #[error("节点 {0} 不是联邦服务器")]
+    UnknownFederationPeer(String),
+
+    #[error("节点 {0} 不是受信任的状态移交来源")]
+    UntrustedHandoffSource(String),
+
     #[error("序列化错误: {0}")]
     SerializationError(#[from] serde_json::Error),
 
@@ -9,17 +9,52 @@
 pub mod chat_service;
 pub mod error;
 pub mod message_handler;
+pub mod username_cache;
 
-pub use chat_service::{ChatRoom, ChatService, ChatUser};
+pub use chat_service::{
+    ChatMessageRecord, ChatRoom, ChatService, ChatServiceConfig, ChatStateSnapshot, ChatUser,
+    Clock, ControlCharPolicy, DuplicateUsernamePolicy, JoinPolicy, JoinResult, SendReport,
+    UserPresence, UserStatus,
+};
 pub use error::{ChatError, Result};
 pub use message_handler::ChatMessageHandler;
+pub use username_cache::UsernameCache;
+
+/// 聊天消息的内容类型
+pub use network_service::ChatType as ContentType;
 
 use async_trait::async_trait;
-use network_service::NodeId;
+use network_service::{MessageType, NetworkMessage, NodeId};
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
 use uuid::Uuid;
 
+/// 当前聊天协议版本
+///
+/// - 1: 基础聊天功能（加入/离开、文本、私聊、列表查询）
+/// - 2: 加入审批、状态移交、服务器变更通知、请求响应
+pub const CHAT_PROTOCOL_VERSION: u32 = 2;
+
+/// 网络消息元数据中携带协议版本的键
+pub const PROTOCOL_VERSION_METADATA_KEY: &str = "chat_protocol_version";
+
+/// 网络消息元数据中标记离线补发消息的键
+pub const OFFLINE_REPLAY_METADATA_KEY: &str = "chat_offline_replay";
+
+/// 未声明协议版本的对端按该版本处理
+///
+/// 未携带版本的消息和从未收到过消息的对端都使用这一版本，
+/// 避免向无法解析新消息的对端发送新版本消息。
+pub const UNVERSIONED_PEER_PROTOCOL_VERSION: u32 = 1;
+
+/// 获取消息发送方的协议版本，未携带版本的消息视为 [`UNVERSIONED_PEER_PROTOCOL_VERSION`]
+pub fn peer_protocol_version(message: &NetworkMessage) -> u32 {
+    message
+        .get_metadata(PROTOCOL_VERSION_METADATA_KEY)
+        .and_then(|version| version.parse().ok())
+        .unwrap_or(UNVERSIONED_PEER_PROTOCOL_VERSION)
+}
+
 /// 聊天消息类型
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum ChatMessageType {
@@ -27,8 +62,13 @@ pub enum ChatMessageType {
     UserJoin { username: String, room_id: String },
     /// 用户离开聊天室
     UserLeave { username: String, room_id: String },
-    /// 文本消息
-    TextMessage { room_id: String, content: String },
+    /// 聊天室消息（旧版本客户端不携带内容类型，按文本处理）
+    TextMessage {
+        room_id: String,
+        content: String,
+        #[serde(default)]
+        content_type: ContentType,
+    },
     /// 私聊消息
     PrivateMessage {
         target_user: String,
@@ -38,6 +78,114 @@ pub enum ChatMessageType {
     ListRooms,
     /// 聊天室成员列表请求
     ListRoomMembers { room_id: String },
+    /// 加入申请（发送给需要审批的聊天室所有者）
+    JoinRequest { username: String, room_id: String },
+    /// 加入申请被拒绝（发送给申请者）
+    JoinDenied { room_id: String },
+    /// 状态移交（主服务器发送给备用服务器，使用system消息类型）
+    StateHandoff {
+        handoff_id: Uuid,
+        snapshot: ChatStateSnapshot,
+    },
+    /// 状态移交确认（备用服务器回复主服务器）
+    StateHandoffAck { handoff_id: Uuid },
+    /// 聊天室服务器变更，客户端应切换到新服务器
+    RoomServerChanged { server: NodeId },
+    /// 对请求的响应（回复给请求者）
+    Response { response: ChatResponseType },
+    /// 用户在线状态变更（广播给用户所在的聊天室）
+    StatusChanged {
+        username: String,
+        status: UserStatus,
+    },
+    /// 本服务器有成员的聊天室（联邦服务器之间同步，使用system消息类型）
+    RoomMembershipSync { rooms: Vec<String> },
+    /// 转发给联邦服务器的聊天室消息，`message` 保留原始消息ID用于去重
+    RoomRelay {
+        room_id: String,
+        message: NetworkMessage,
+        /// 发送者的用户名，接收方记录消息历史时使用
+        #[serde(default)]
+        sender_name: String,
+    },
+}
+
+impl ChatMessageType {
+    /// 处理该消息所需的最低协议版本
+    pub fn min_protocol_version(&self) -> u32 {
+        match self {
+            ChatMessageType::UserJoin { .. }
+            | ChatMessageType::UserLeave { .. }
+            | ChatMessageType::TextMessage { .. }
+            | ChatMessageType::PrivateMessage { .. }
+            | ChatMessageType::ListRooms
+            | ChatMessageType::ListRoomMembers { .. } => 1,
+            ChatMessageType::JoinRequest { .. }
+            | ChatMessageType::JoinDenied { .. }
+            | ChatMessageType::StateHandoff { .. }
+            | ChatMessageType::StateHandoffAck { .. }
+            | ChatMessageType::RoomServerChanged { .. }
+            | ChatMessageType::Response { .. }
+            | ChatMessageType::StatusChanged { .. }
+            | ChatMessageType::RoomMembershipSync { .. }
+            | ChatMessageType::RoomRelay { .. } => 2,
+        }
+    }
+
+    /// 是否为需要响应的请求消息
+    ///
+    /// 请求与响应的对应关系：
+    /// - `ListRooms` → `RoomList`
+    /// - `ListRoomMembers` → `MemberList`
+    /// - `UserJoin` → `Success`
+    /// - `TextMessage` → `MessageBroadcast`
+    ///
+    /// 处理失败时统一回复 `Error`。
+    pub fn expects_response(&self) -> bool {
+        matches!(
+            self,
+            ChatMessageType::UserJoin { .. }
+                | ChatMessageType::TextMessage { .. }
+                | ChatMessageType::ListRooms
+                | ChatMessageType::ListRoomMembers { .. }
+        )
+    }
+
+    /// 消息名称，用于日志和错误信息
+    pub fn name(&self) -> &'static str {
+        match self {
+            ChatMessageType::UserJoin { .. } => "UserJoin",
+            ChatMessageType::UserLeave { .. } => "UserLeave",
+            ChatMessageType::TextMessage { .. } => "TextMessage",
+            ChatMessageType::PrivateMessage { .. } => "PrivateMessage",
+            ChatMessageType::ListRooms => "ListRooms",
+            ChatMessageType::ListRoomMembers { .. } => "ListRoomMembers",
+            ChatMessageType::JoinRequest { .. } => "JoinRequest",
+            ChatMessageType::JoinDenied { .. } => "JoinDenied",
+            ChatMessageType::StateHandoff { .. } => "StateHandoff",
+            ChatMessageType::StateHandoffAck { .. } => "StateHandoffAck",
+            ChatMessageType::RoomServerChanged { .. } => "RoomServerChanged",
+            ChatMessageType::Response { .. } => "Response",
+            ChatMessageType::StatusChanged { .. } => "StatusChanged",
+            ChatMessageType::RoomMembershipSync { .. } => "RoomMembershipSync",
+            ChatMessageType::RoomRelay { .. } => "RoomRelay",
+        }
+    }
+
+    /// 封装为携带协议版本的网络消息
+    pub fn to_network_message(
+        &self,
+        message_type: MessageType,
+        sender: NodeId,
+    ) -> Result<NetworkMessage> {
+        let payload = serde_json::to_value(self)?;
+        Ok(
+            NetworkMessage::new(message_type, sender, payload).with_metadata(
+                PROTOCOL_VERSION_METADATA_KEY.to_string(),
+                CHAT_PROTOCOL_VERSION.to_string(),
+            ),
+        )
+    }
 }
 
 /// 聊天响应类型
@@ -61,15 +209,40 @@ pub enum ChatResponseType {
 /// 聊天服务trait
 #[async_trait]
 pub trait ChatServiceTrait: Send + Sync {
+    /// 按配置的策略清理消息内容中的控制字符，打印日志前也应先清理
+    fn sanitize_content(&self, content: &str) -> String;
+
     /// 用户加入聊天室
-    async fn join_room(&self, user_id: NodeId, username: String, room_id: String) -> Result<()>;
+    async fn join_room(
+        &self,
+        user_id: NodeId,
+        username: String,
+        room_id: String,
+    ) -> Result<JoinResult>;
 
     /// 用户离开聊天室
     async fn leave_room(&self, user_id: NodeId, room_id: String) -> Result<()>;
 
-    /// 发送聊天消息
-    async fn send_message(&self, user_id: NodeId, room_id: String, content: String)
-        -> Result<Uuid>;
+    /// 用户离开所有已加入的聊天室，返回已离开的聊天室（按名称排序）
+    async fn leave_all_rooms(&self, user_id: NodeId) -> Result<Vec<String>>;
+
+    /// 发送聊天消息，内容类型须为聊天室允许的类型
+    async fn send_message(
+        &self,
+        user_id: NodeId,
+        room_id: String,
+        content: String,
+        content_type: ContentType,
+    ) -> Result<Uuid>;
+
+    /// 发送聊天消息，返回每个成员的投递结果
+    async fn send_message_reported(
+        &self,
+        user_id: NodeId,
+        room_id: String,
+        content: String,
+        content_type: ContentType,
+    ) -> Result<SendReport>;
 
     /// 发送私聊消息
     async fn send_private_message(
@@ -82,9 +255,54 @@ pub trait ChatServiceTrait: Send + Sync {
     /// 获取聊天室列表
     async fn list_rooms(&self) -> Result<Vec<String>>;
 
+    /// 获取所有聊天室的完整信息（单次加锁，适合仪表盘等批量查询）
+    async fn list_rooms_detailed(&self) -> Result<Vec<ChatRoom>>;
+
     /// 获取聊天室成员列表
     async fn list_room_members(&self, room_id: String) -> Result<Vec<String>>;
 
+    /// 获取聊天室历史消息（按序号排列），`after_seq` 为已有的最后一条序号时只返回更新的消息
+    async fn get_room_history(
+        &self,
+        room_id: &str,
+        after_seq: Option<u64>,
+    ) -> Result<Vec<ChatMessageRecord>>;
+
+    /// 获取聊天室成员数量
+    async fn room_member_count(&self, room_id: &str) -> Result<usize>;
+
+    /// 用户是否为聊天室成员，聊天室不存在时返回false
+    async fn is_room_member(&self, room_id: &str, user_id: &NodeId) -> bool;
+
     /// 获取用户所在的聊天室
     async fn get_user_rooms(&self, user_id: NodeId) -> Result<Vec<String>>;
+
+    /// 设置用户在线状态，并通知用户所在的聊天室
+    async fn set_status(&self, user_id: NodeId, status: UserStatus) -> Result<()>;
+
+    /// 列出所有用户及其在线状态（按用户名排序）
+    async fn list_users(&self) -> Result<Vec<UserPresence>>;
+
+    /// 接收主服务器移交的状态（备用服务器）
+    async fn accept_state_handoff(&self, from: NodeId, snapshot: ChatStateSnapshot) -> Result<()>;
+
+    /// 记录联邦服务器同步的聊天室
+    async fn apply_membership_sync(&self, from: NodeId, rooms: Vec<String>) -> Result<()>;
+
+    /// 接收联邦服务器转发的聊天室消息，记入历史、投递给本地成员并继续转发
+    ///
+    /// 已处理过的消息返回 `false`。
+    async fn receive_room_relay(
+        &self,
+        from: NodeId,
+        room_id: String,
+        message: NetworkMessage,
+        sender_name: String,
+    ) -> Result<bool>;
+
+    /// 确认状态移交已完成（主服务器）
+    async fn confirm_state_handoff(&self, handoff_id: Uuid) -> Result<()>;
+
+    /// 记录对端节点的协议版本
+    async fn record_peer_version(&self, peer: NodeId, version: u32);
 }
@@ -1,10 +1,13 @@
 //! 聊天消息处理器
 
-use crate::{ChatError, ChatMessageType, ChatServiceTrait};
+use crate::{
+    peer_protocol_version, ChatError, ChatMessageType, ChatResponseType, ChatServiceTrait,
+    CHAT_PROTOCOL_VERSION,
+};
 use async_trait::async_trait;
-use network_service::{MessageHandler, NetworkMessage, NodeId};
+use network_service::{HandlerContext, MessageHandler, MessageType, NetworkMessage, NodeId};
 use std::sync::Arc;
-use tracing::{error, info, warn};
+use tracing::{debug, error, info, warn};
 
 /// 聊天消息处理器
 pub struct ChatMessageHandler<C: ChatServiceTrait> {
@@ -16,31 +19,82 @@ impl<C: ChatServiceTrait> ChatMessageHandler<C> {
     pub fn new(chat_service: Arc<C>) -> Self {
         Self { chat_service }
     }
+
+    /// 构造回复给请求者的响应消息
+    async fn response_message(
+        ctx: &HandlerContext,
+        response: ChatResponseType,
+    ) -> network_service::Result<NetworkMessage> {
+        ChatMessageType::Response { response }
+            .to_network_message(MessageType::chat(), ctx.local_node_id().await?)
+            .map_err(Self::to_network_error)
+    }
+
+    /// 将聊天错误转换为网络错误
+    fn to_network_error(chat_error: ChatError) -> network_service::NetworkError {
+        match chat_error {
+            ChatError::NetworkError(net_err) => net_err,
+            other_err => network_service::NetworkError::InternalError(other_err.to_string()),
+        }
+    }
 }
 
 #[async_trait]
 impl<C: ChatServiceTrait> MessageHandler for ChatMessageHandler<C> {
     async fn handle_message(
         &self,
+        ctx: &HandlerContext,
         from: NodeId,
         message: NetworkMessage,
     ) -> network_service::Result<Option<NetworkMessage>> {
         info!("处理来自 {} 的聊天消息", from);
 
+        let peer_version = peer_protocol_version(&message);
+        let response_required = message.response_required;
+        self.chat_service
+            .record_peer_version(from.clone(), peer_version)
+            .await;
+
         // 解析消息负载
         let chat_message: ChatMessageType = match serde_json::from_value(message.payload.clone()) {
             Ok(msg) => msg,
+            Err(e) if peer_version > CHAT_PROTOCOL_VERSION => {
+                // 较新版本的对端可能发送本地尚不支持的消息，忽略即可
+                info!(
+                    "忽略来自 {} 的未知消息（对端协议版本 {}，本地 {}）: {}",
+                    from, peer_version, CHAT_PROTOCOL_VERSION, e
+                );
+                return Ok(None);
+            }
             Err(e) => {
-                error!("无法解析聊天消息: {}", e);
+                error!("{}", message.parse_failure_summary(&from, &e));
                 return Err(network_service::NetworkError::SerializationError(e));
             }
         };
 
+        // 需要回复发送者的响应
+        let mut response = None;
+        let expects_response = chat_message.expects_response();
+
         // 根据消息类型处理
         let result = match chat_message {
             ChatMessageType::UserJoin { username, room_id } => {
                 info!("用户 {} 加入聊天室 {}", username, room_id);
-                self.chat_service.join_room(from, username, room_id).await
+                match self.chat_service.join_room(from, username, room_id).await {
+                    Ok(joined) => {
+                        let message = if joined.pending_approval {
+                            format!("已申请加入聊天室 {}，等待所有者审批", joined.room_id)
+                        } else {
+                            format!("已加入聊天室 {}", joined.room_id)
+                        };
+                        response = Some(
+                            Self::response_message(ctx, ChatResponseType::Success { message })
+                                .await?,
+                        );
+                        Ok(())
+                    }
+                    Err(e) => Err(e),
+                }
             }
 
             ChatMessageType::UserLeave {
@@ -51,11 +105,41 @@ impl<C: ChatServiceTrait> MessageHandler for ChatMessageHandler<C> {
                 self.chat_service.leave_room(from, room_id).await
             }
 
-            ChatMessageType::TextMessage { room_id, content } => {
-                info!("收到聊天室 {} 的消息: {}", room_id, content);
-                match self.chat_service.send_message(from, room_id, content).await {
-                    Ok(_message_id) => Ok(()),
-                    Err(e) => Err(e),
+            ChatMessageType::TextMessage {
+                room_id,
+                content,
+                content_type,
+            } => {
+                info!(
+                    "收到聊天室 {} 的消息: {}",
+                    room_id,
+                    self.chat_service.sanitize_content(&content)
+                );
+                // 以服务器记录的成员关系为准，拒绝非成员发言
+                if !self.chat_service.is_room_member(&room_id, &from).await {
+                    let error = ChatError::UserNotInRoom(from.clone(), room_id).to_string();
+                    warn!("拒绝非成员消息: {}", error);
+                    response =
+                        Some(Self::response_message(ctx, ChatResponseType::Error { error }).await?);
+                    Ok(())
+                } else {
+                    match self
+                        .chat_service
+                        .send_message(from, room_id, content, content_type)
+                        .await
+                    {
+                        Ok(message_id) => {
+                            response = Some(
+                                Self::response_message(
+                                    ctx,
+                                    ChatResponseType::MessageBroadcast { message_id },
+                                )
+                                .await?,
+                            );
+                            Ok(())
+                        }
+                        Err(e) => Err(e),
+                    }
                 }
             }
 
@@ -63,7 +147,11 @@ impl<C: ChatServiceTrait> MessageHandler for ChatMessageHandler<C> {
                 target_user,
                 content,
             } => {
-                info!("收到发给 {} 的私聊消息: {}", target_user, content);
+                info!(
+                    "收到发给 {} 的私聊消息: {}",
+                    target_user,
+                    self.chat_service.sanitize_content(&content)
+                );
                 match self
                     .chat_service
                     .send_private_message(from, target_user, content)
@@ -79,6 +167,10 @@ impl<C: ChatServiceTrait> MessageHandler for ChatMessageHandler<C> {
                 match self.chat_service.list_rooms().await {
                     Ok(rooms) => {
                         info!("返回 {} 个聊天室", rooms.len());
+                        response = Some(
+                            Self::response_message(ctx, ChatResponseType::RoomList { rooms })
+                                .await?,
+                        );
                         Ok(())
                     }
                     Err(e) => Err(e),
@@ -87,38 +179,140 @@ impl<C: ChatServiceTrait> MessageHandler for ChatMessageHandler<C> {
 
             ChatMessageType::ListRoomMembers { room_id } => {
                 info!("收到聊天室 {} 成员列表请求", room_id);
-                match self.chat_service.list_room_members(room_id).await {
+                match self.chat_service.list_room_members(room_id.clone()).await {
                     Ok(members) => {
                         info!("聊天室有 {} 个成员", members.len());
+                        response = Some(
+                            Self::response_message(
+                                ctx,
+                                ChatResponseType::MemberList { room_id, members },
+                            )
+                            .await?,
+                        );
+                        Ok(())
+                    }
+                    Err(e) => Err(e),
+                }
+            }
+
+            ChatMessageType::JoinRequest { username, room_id } => {
+                info!(
+                    "用户 {} ({}) 申请加入聊天室 {}，等待审批",
+                    username, from, room_id
+                );
+                Ok(())
+            }
+
+            ChatMessageType::JoinDenied { room_id } => {
+                info!("加入聊天室 {} 的申请被拒绝", room_id);
+                Ok(())
+            }
+
+            ChatMessageType::StateHandoff {
+                handoff_id,
+                snapshot,
+            } => {
+                info!("收到来自 {} 的状态移交: {}", from, handoff_id);
+                match self
+                    .chat_service
+                    .accept_state_handoff(from.clone(), snapshot)
+                    .await
+                {
+                    Ok(()) => {
+                        let ack = ChatMessageType::StateHandoffAck { handoff_id };
+                        response = Some(
+                            ack.to_network_message(
+                                MessageType::system(),
+                                ctx.local_node_id().await?,
+                            )
+                            .map_err(Self::to_network_error)?,
+                        );
                         Ok(())
                     }
                     Err(e) => Err(e),
                 }
             }
+
+            ChatMessageType::RoomMembershipSync { rooms } => {
+                self.chat_service.apply_membership_sync(from, rooms).await
+            }
+
+            ChatMessageType::RoomRelay {
+                room_id,
+                message,
+                sender_name,
+            } => self
+                .chat_service
+                .receive_room_relay(from, room_id, message, sender_name)
+                .await
+                .map(|_| ()),
+
+            ChatMessageType::StateHandoffAck { handoff_id } => {
+                info!("收到 {} 的状态移交确认: {}", from, handoff_id);
+                self.chat_service.confirm_state_handoff(handoff_id).await
+            }
+
+            ChatMessageType::RoomServerChanged { server } => {
+                info!("聊天室服务器已变更为 {}", server);
+                Ok(())
+            }
+
+            ChatMessageType::Response { response } => {
+                info!("收到来自 {} 的响应: {:?}", from, response);
+                Ok(())
+            }
+
+            ChatMessageType::StatusChanged { username, status } => {
+                info!("用户 {} 状态变更为 {:?}", username, status);
+                self.chat_service.set_status(from, status).await
+            }
         };
 
-        // 将聊天错误转换为网络错误
-        if let Err(chat_error) = result {
-            match chat_error {
-                ChatError::NetworkError(net_err) => return Err(net_err),
-                other_err => {
-                    warn!("聊天服务处理消息失败: {}", other_err);
-                    return Err(network_service::NetworkError::InternalError(
-                        other_err.to_string(),
-                    ));
+        // 请求消息的业务错误回复给请求者，其余错误转换为网络错误
+        match result {
+            Ok(()) => {}
+            Err(chat_error)
+                if expects_response && !matches!(chat_error, ChatError::NetworkError(_)) =>
+            {
+                warn!("聊天服务处理请求失败: {}", chat_error);
+                let error = chat_error.to_string();
+                response =
+                    Some(Self::response_message(ctx, ChatResponseType::Error { error }).await?);
+            }
+            Err(chat_error) => {
+                if !matches!(chat_error, ChatError::NetworkError(_)) {
+                    warn!("聊天服务处理消息失败: {}", chat_error);
                 }
+                return Err(Self::to_network_error(chat_error));
+            }
+        }
+
+        // 发送方没有要求回复的请求只处理不回复
+        if expects_response && !response_required {
+            if let Some(response_msg) = response.take() {
+                debug!("请求未要求回复，跳过响应 {}", response_msg.id);
+            }
+        }
+
+        // 旧版本对端无法解析响应消息，不再回复
+        if peer_version < 2 {
+            if let Some(response_msg) = response.take() {
+                info!(
+                    "对端协议版本 {} 不支持响应消息，跳过回复 {}",
+                    peer_version, response_msg.id
+                );
             }
         }
 
-        // 聊天消息通常不需要返回响应消息
-        Ok(None)
+        Ok(response)
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::{ChatService, ChatServiceTrait};
+    use crate::{ChatService, ChatServiceConfig, ChatServiceTrait, ContentType};
+    use network_service::test_utils::MockNetworkService;
     use network_service::{AnemoNetworkService, MessageType};
     use serde_json::json;
     use std::sync::Arc;
@@ -133,6 +327,7 @@ mod tests {
         let chat_msg = ChatMessageType::TextMessage {
             room_id: "general".to_string(),
             content: "Hello World".to_string(),
+            content_type: ContentType::Text,
         };
 
         let payload = serde_json::to_value(&chat_msg).unwrap();
@@ -143,4 +338,625 @@ mod tests {
         // let result = handler.handle_message("test-user".to_string(), network_msg).await;
         // assert!(result.is_ok());
     }
+
+    #[tokio::test]
+    async fn test_list_room_members_returns_response() {
+        let network = MockNetworkService::new();
+        let chat_service = Arc::new(ChatService::new(network.clone()));
+        let handler = ChatMessageHandler::new(chat_service.clone());
+
+        for (user_id, username) in [("user1", "Alice"), ("user2", "Bob")] {
+            chat_service
+                .join_room(
+                    user_id.to_string(),
+                    username.to_string(),
+                    "general".to_string(),
+                )
+                .await
+                .unwrap();
+        }
+
+        let request = ChatMessageType::ListRoomMembers {
+            room_id: "general".to_string(),
+        };
+        let network_msg = request
+            .to_network_message(MessageType::chat(), "client".to_string())
+            .unwrap()
+            .with_response_required();
+        let reply = handler
+            .handle_message(
+                &HandlerContext::new(network.clone()),
+                "client".to_string(),
+                network_msg,
+            )
+            .await
+            .unwrap()
+            .expect("成员列表请求应返回响应");
+
+        match serde_json::from_value(reply.payload).unwrap() {
+            ChatMessageType::Response {
+                response:
+                    ChatResponseType::MemberList {
+                        room_id,
+                        mut members,
+                    },
+            } => {
+                members.sort();
+                assert_eq!(room_id, "general");
+                assert_eq!(members, vec!["Alice".to_string(), "Bob".to_string()]);
+            }
+            other => panic!("意外的响应: {:?}", other),
+        }
+    }
+
+    /// 记录收到的回复
+    struct ReplyCollector {
+        replies: Arc<std::sync::Mutex<Vec<NetworkMessage>>>,
+    }
+
+    #[async_trait]
+    impl MessageHandler for ReplyCollector {
+        async fn handle_message(
+            &self,
+            _ctx: &HandlerContext,
+            _from: NodeId,
+            message: NetworkMessage,
+        ) -> network_service::Result<Option<NetworkMessage>> {
+            self.replies.lock().unwrap().push(message);
+            Ok(None)
+        }
+    }
+
+    #[tokio::test]
+    async fn test_list_room_members_over_loopback() {
+        use network_service::{NetworkServiceConfig, NetworkServiceTrait, UnicastOptions};
+
+        let loopback_config = |name: &str| NetworkServiceConfig {
+            bind_address: "127.0.0.1:0".parse().unwrap(),
+            server_name: name.to_string(),
+            ..Default::default()
+        };
+        let server = AnemoNetworkService::new();
+        let client = AnemoNetworkService::new();
+        let chat_service = Arc::new(ChatService::new(server.clone()));
+        server
+            .register_message_handler(
+                MessageType::chat(),
+                Box::new(ChatMessageHandler::new(chat_service.clone())),
+            )
+            .await
+            .unwrap();
+        let replies = Arc::new(std::sync::Mutex::new(Vec::new()));
+        client
+            .register_message_handler(
+                MessageType::chat(),
+                Box::new(ReplyCollector {
+                    replies: replies.clone(),
+                }),
+            )
+            .await
+            .unwrap();
+
+        server.start(loopback_config("list-server")).await.unwrap();
+        client.start(loopback_config("list-client")).await.unwrap();
+        let server_addr = server.effective_config().await.unwrap().local_addr;
+        client.connect(server_addr).await.unwrap();
+        let server_id = server.get_local_node_id().await.unwrap();
+        let client_id = client.get_local_node_id().await.unwrap();
+
+        for (user_id, username) in [("user1", "Alice"), ("user2", "Bob")] {
+            chat_service
+                .join_room(
+                    user_id.to_string(),
+                    username.to_string(),
+                    "general".to_string(),
+                )
+                .await
+                .unwrap();
+        }
+
+        // 响应随请求所在的连接返回，发送返回前已交给客户端的处理器
+        let request = ChatMessageType::ListRoomMembers {
+            room_id: "general".to_string(),
+        }
+        .to_network_message(MessageType::chat(), client_id)
+        .unwrap();
+        client
+            .unicast(
+                server_id,
+                request,
+                Some(UnicastOptions {
+                    wait_for_response: true,
+                    ..Default::default()
+                }),
+            )
+            .await
+            .unwrap();
+
+        let reply = replies.lock().unwrap().pop().expect("客户端应收到成员列表");
+        match serde_json::from_value(reply.payload).unwrap() {
+            ChatMessageType::Response {
+                response:
+                    ChatResponseType::MemberList {
+                        room_id,
+                        mut members,
+                    },
+            } => {
+                members.sort();
+                assert_eq!(room_id, "general");
+                assert_eq!(members, vec!["Alice".to_string(), "Bob".to_string()]);
+            }
+            other => panic!("意外的响应: {:?}", other),
+        }
+
+        client.stop().await.unwrap();
+        server.stop().await.unwrap();
+    }
+
+    /// 发送请求消息并解析返回的响应
+    async fn request(
+        handler: &ChatMessageHandler<ChatService<MockNetworkService>>,
+        network: &MockNetworkService,
+        from: &str,
+        request: ChatMessageType,
+    ) -> ChatResponseType {
+        let network_msg = request
+            .to_network_message(MessageType::chat(), from.to_string())
+            .unwrap()
+            .with_response_required();
+        let reply = handler
+            .handle_message(
+                &HandlerContext::new(network.clone()),
+                from.to_string(),
+                network_msg,
+            )
+            .await
+            .unwrap()
+            .expect("请求消息应返回响应");
+        match serde_json::from_value(reply.payload).unwrap() {
+            ChatMessageType::Response { response } => response,
+            other => panic!("意外的响应: {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_request_messages_return_typed_responses() {
+        let network = MockNetworkService::new();
+        let chat_service = Arc::new(ChatService::new(network.clone()));
+        let handler = ChatMessageHandler::new(chat_service.clone());
+
+        let joined = ChatMessageType::UserJoin {
+            username: "Alice".to_string(),
+            room_id: "general".to_string(),
+        };
+        assert!(matches!(
+            request(&handler, &network, "user1", joined).await,
+            ChatResponseType::Success { .. }
+        ));
+
+        match request(&handler, &network, "user1", ChatMessageType::ListRooms).await {
+            ChatResponseType::RoomList { rooms } => {
+                assert_eq!(rooms, vec!["general".to_string()]);
+            }
+            other => panic!("意外的响应: {:?}", other),
+        }
+
+        let post = ChatMessageType::TextMessage {
+            room_id: "general".to_string(),
+            content: "hi".to_string(),
+            content_type: ContentType::Text,
+        };
+        assert!(matches!(
+            request(&handler, &network, "user1", post).await,
+            ChatResponseType::MessageBroadcast { .. }
+        ));
+
+        // 业务错误以 Error 响应返回而不是处理失败
+        let invalid = ChatMessageType::UserJoin {
+            username: "Bob".to_string(),
+            room_id: String::new(),
+        };
+        assert!(matches!(
+            request(&handler, &network, "user2", invalid).await,
+            ChatResponseType::Error { .. }
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_request_without_response_required_gets_no_reply() {
+        let network = MockNetworkService::new();
+        let chat_service = Arc::new(ChatService::new(network.clone()));
+        let handler = ChatMessageHandler::new(chat_service.clone());
+        let ctx = HandlerContext::new(network.clone());
+
+        let join = ChatMessageType::UserJoin {
+            username: "Alice".to_string(),
+            room_id: "general".to_string(),
+        }
+        .to_network_message(MessageType::chat(), "user1".to_string())
+        .unwrap();
+        let reply = handler
+            .handle_message(&ctx, "user1".to_string(), join)
+            .await
+            .unwrap();
+        assert!(reply.is_none());
+        // 请求照常处理
+        assert!(
+            chat_service
+                .is_room_member("general", &"user1".to_string())
+                .await
+        );
+
+        // 业务错误也不回复
+        let invalid = ChatMessageType::UserJoin {
+            username: "Bob".to_string(),
+            room_id: String::new(),
+        }
+        .to_network_message(MessageType::chat(), "user2".to_string())
+        .unwrap();
+        let reply = handler
+            .handle_message(&ctx, "user2".to_string(), invalid)
+            .await
+            .unwrap();
+        assert!(reply.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_non_member_text_message_rejected() {
+        let network = MockNetworkService::new();
+        let chat_service = Arc::new(ChatService::new(network.clone()));
+        let handler = ChatMessageHandler::new(chat_service.clone());
+
+        chat_service
+            .join_room(
+                "user1".to_string(),
+                "Alice".to_string(),
+                "general".to_string(),
+            )
+            .await
+            .unwrap();
+        let broadcasts_before = network.broadcasts.lock().unwrap().len();
+
+        let post = ChatMessageType::TextMessage {
+            room_id: "general".to_string(),
+            content: "let me in".to_string(),
+            content_type: ContentType::Text,
+        };
+        let network_msg = post
+            .to_network_message(MessageType::chat(), "intruder".to_string())
+            .unwrap()
+            .with_response_required();
+        let reply = handler
+            .handle_message(
+                &HandlerContext::new(network.clone()),
+                "intruder".to_string(),
+                network_msg,
+            )
+            .await
+            .unwrap()
+            .expect("非成员发言应返回错误响应");
+
+        assert!(matches!(
+            serde_json::from_value(reply.payload).unwrap(),
+            ChatMessageType::Response {
+                response: ChatResponseType::Error { .. }
+            }
+        ));
+        // 消息没有被转发
+        assert_eq!(network.broadcasts.lock().unwrap().len(), broadcasts_before);
+    }
+
+    #[tokio::test]
+    async fn test_version_skewed_peers_degrade_gracefully() {
+        let network = MockNetworkService::new();
+        let chat_service = Arc::new(ChatService::new(network.clone()));
+        let handler = ChatMessageHandler::new(chat_service.clone());
+        let ctx = HandlerContext::new(network.clone());
+
+        chat_service
+            .join_room(
+                "user1".to_string(),
+                "Alice".to_string(),
+                "general".to_string(),
+            )
+            .await
+            .unwrap();
+
+        // 旧版本客户端（未携带版本）请求成员列表：正常处理但不回复其无法解析的响应
+        let request = ChatMessageType::ListRoomMembers {
+            room_id: "general".to_string(),
+        };
+        let old_request = NetworkMessage::new(
+            MessageType::chat(),
+            "old-client".to_string(),
+            serde_json::to_value(&request).unwrap(),
+        );
+        let reply = handler
+            .handle_message(&ctx, "old-client".to_string(), old_request)
+            .await
+            .unwrap();
+        assert!(reply.is_none());
+
+        // 向旧版本客户端发送需要新版本的消息时返回明确的错误
+        chat_service
+            .record_peer_version("user1".to_string(), CHAT_PROTOCOL_VERSION)
+            .await;
+        chat_service
+            .set_join_policy(
+                &"user1".to_string(),
+                "general",
+                crate::JoinPolicy::ApprovalRequired,
+            )
+            .await
+            .unwrap();
+        chat_service
+            .join_room(
+                "old-client".to_string(),
+                "Old".to_string(),
+                "general".to_string(),
+            )
+            .await
+            .unwrap();
+        let result = chat_service
+            .deny_join(&"user1".to_string(), "general", &"old-client".to_string())
+            .await;
+        assert!(matches!(
+            result,
+            Err(ChatError::UnsupportedOperation {
+                required: 2,
+                peer_version: 1,
+                ..
+            })
+        ));
+        // 拒绝失败时保留申请，所有者仍可审批
+        assert_eq!(
+            chat_service.list_pending_joins("general").await.unwrap(),
+            vec!["Old".to_string()]
+        );
+
+        // 较新版本的对端发送本地未知的消息：忽略而不是报错
+        let newer = NetworkMessage::new(
+            MessageType::chat(),
+            "new-client".to_string(),
+            json!({ "Reaction": { "message_id": "m1", "emoji": "👍" } }),
+        )
+        .with_metadata(
+            crate::PROTOCOL_VERSION_METADATA_KEY.to_string(),
+            (CHAT_PROTOCOL_VERSION + 1).to_string(),
+        );
+        let reply = handler
+            .handle_message(&ctx, "new-client".to_string(), newer)
+            .await
+            .unwrap();
+        assert!(reply.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_malformed_payload_log_includes_snippet() {
+        let network = MockNetworkService::new();
+        let handler = ChatMessageHandler::new(Arc::new(ChatService::new(network.clone())));
+
+        let malformed = NetworkMessage::new(
+            MessageType::chat(),
+            "client-7".to_string(),
+            json!({ "TextMessage": { "room": "general", "text": "x".repeat(500) } }),
+        );
+        let error =
+            serde_json::from_value::<ChatMessageType>(malformed.payload.clone()).unwrap_err();
+        let summary = malformed.parse_failure_summary("client-7", &error);
+
+        assert!(summary.contains("client-7"));
+        assert!(summary.contains(r#"{"TextMessage":{"room":"general""#));
+        assert!(summary.ends_with('…'));
+        assert!(summary.len() < 500);
+
+        let result = handler
+            .handle_message(
+                &HandlerContext::new(network),
+                "client-7".to_string(),
+                malformed,
+            )
+            .await;
+        assert!(matches!(
+            result,
+            Err(network_service::NetworkError::SerializationError(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_state_handoff_to_standby() {
+        let primary_net = MockNetworkService::new();
+        let standby_net = MockNetworkService::new();
+        let primary = Arc::new(ChatService::new(primary_net.clone()));
+        let standby = Arc::new(ChatService::with_config(
+            standby_net.clone(),
+            ChatServiceConfig {
+                handoff_sources: vec!["primary".to_string()],
+                ..Default::default()
+            },
+        ));
+        let primary_handler = ChatMessageHandler::new(primary.clone());
+        let standby_handler = ChatMessageHandler::new(standby.clone());
+
+        primary
+            .join_room(
+                "client-1".to_string(),
+                "Alice".to_string(),
+                "general".to_string(),
+            )
+            .await
+            .unwrap();
+
+        // 备用服务器此前发送过携带协议版本的消息
+        primary
+            .record_peer_version("standby".to_string(), CHAT_PROTOCOL_VERSION)
+            .await;
+        let handoff = {
+            let primary = primary.clone();
+            tokio::spawn(async move { primary.handoff_to("standby".to_string()).await })
+        };
+
+        // 等待主服务器发出状态移交消息
+        let (target, handoff_msg) = loop {
+            let sent = primary_net.unicasts.lock().unwrap().pop();
+            if let Some(sent) = sent {
+                break sent;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+        };
+        assert_eq!(target, "standby");
+        assert_eq!(handoff_msg.message_type, MessageType::system());
+
+        // 备用服务器导入状态并回复确认
+        let ack = standby_handler
+            .handle_message(
+                &HandlerContext::new(standby_net.clone()),
+                "primary".to_string(),
+                handoff_msg,
+            )
+            .await
+            .unwrap()
+            .expect("备用服务器应回复确认");
+
+        primary_handler
+            .handle_message(
+                &HandlerContext::new(primary_net.clone()),
+                "standby".to_string(),
+                ack,
+            )
+            .await
+            .unwrap();
+        handoff.await.unwrap().unwrap();
+
+        // 客户端在备用服务器上看到的聊天室状态保持不变
+        let members = standby
+            .list_room_members("general".to_string())
+            .await
+            .unwrap();
+        assert_eq!(members, vec!["Alice".to_string()]);
+        assert_eq!(
+            standby
+                .get_user_rooms("client-1".to_string())
+                .await
+                .unwrap(),
+            vec!["general".to_string()]
+        );
+
+        // 主服务器广播了服务器变更
+        let last_broadcast = primary_net
+            .broadcasts
+            .lock()
+            .unwrap()
+            .last()
+            .cloned()
+            .unwrap();
+        let changed: ChatMessageType = serde_json::from_value(last_broadcast.payload).unwrap();
+        assert!(matches!(
+            changed,
+            ChatMessageType::RoomServerChanged { server } if server == "standby"
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_room_message_relayed_across_servers_once() {
+        let federated = |peer: &str| ChatServiceConfig {
+            federation_peers: vec![peer.to_string()],
+            ..Default::default()
+        };
+        let net_a = MockNetworkService::new();
+        let net_b = MockNetworkService::new();
+        let server_a = Arc::new(ChatService::with_config(
+            net_a.clone(),
+            federated("server-b"),
+        ));
+        let server_b = Arc::new(ChatService::with_config(
+            net_b.clone(),
+            federated("server-a"),
+        ));
+        let handler_a = ChatMessageHandler::new(server_a.clone());
+        let handler_b = ChatMessageHandler::new(server_b.clone());
+
+        server_a
+            .join_room(
+                "client-a".to_string(),
+                "Alice".to_string(),
+                "general".to_string(),
+            )
+            .await
+            .unwrap();
+        server_b
+            .join_room(
+                "client-b".to_string(),
+                "Bob".to_string(),
+                "general".to_string(),
+            )
+            .await
+            .unwrap();
+
+        // 成员加入时两台服务器自动互相同步聊天室成员关系
+        let (_, sync_to_b) = net_a.unicasts.lock().unwrap().pop().unwrap();
+        let (_, sync_to_a) = net_b.unicasts.lock().unwrap().pop().unwrap();
+        assert!(net_a.unicasts.lock().unwrap().is_empty());
+        assert!(net_b.unicasts.lock().unwrap().is_empty());
+        handler_b
+            .handle_message(
+                &HandlerContext::new(net_b.clone()),
+                "server-a".to_string(),
+                sync_to_b,
+            )
+            .await
+            .unwrap();
+        handler_a
+            .handle_message(
+                &HandlerContext::new(net_a.clone()),
+                "server-b".to_string(),
+                sync_to_a,
+            )
+            .await
+            .unwrap();
+
+        // Alice 在服务器A上发言
+        let message_id = server_a
+            .send_message(
+                "client-a".to_string(),
+                "general".to_string(),
+                "hello from A".to_string(),
+                ContentType::Text,
+            )
+            .await
+            .unwrap();
+        let (target, relay) = net_a.unicasts.lock().unwrap().pop().unwrap();
+        assert_eq!(target, "server-b");
+
+        // 同一条转发重复到达，服务器B只投递一次
+        for _ in 0..2 {
+            handler_b
+                .handle_message(
+                    &HandlerContext::new(net_b.clone()),
+                    "server-a".to_string(),
+                    relay.clone(),
+                )
+                .await
+                .unwrap();
+        }
+        let delivered: Vec<_> = net_b
+            .broadcasts
+            .lock()
+            .unwrap()
+            .iter()
+            .zip(net_b.broadcast_options.lock().unwrap().iter())
+            .filter(|(message, _)| message.id == message_id)
+            .map(|(_, options)| options.include_nodes.clone())
+            .collect();
+        assert_eq!(delivered, vec![Some(vec!["client-b".to_string()])]);
+        // 不会再转发回来源服务器
+        assert!(net_b.unicasts.lock().unwrap().is_empty());
+
+        // 转发的消息记入服务器B的聊天室历史
+        let history = server_b.get_room_history("general", None).await.unwrap();
+        let relayed = history.last().unwrap();
+        assert_eq!(relayed.message_id, message_id);
+        assert_eq!(relayed.sender_name, "Alice");
+        assert_eq!(relayed.content, "hello from A");
+    }
 }
@@ -0,0 +1,126 @@
+//! 用户名到用户ID的有界映射
+//!
+//! 按最近使用顺序淘汰，防止恶意客户端用大量不同的用户名耗尽内存。
+//! 映射只是查找缓存，被淘汰的用户名仍由用户表保证唯一，查找时回退到扫描用户表。
+
+use network_service::NodeId;
+use std::collections::{BTreeMap, HashMap};
+
+/// 带LRU淘汰的用户名映射
+#[derive(Debug)]
+pub struct UsernameCache {
+    /// 容量上限
+    capacity: usize,
+    /// 用户名 -> (用户ID, 最近使用序号)
+    entries: HashMap<String, (NodeId, u64)>,
+    /// 最近使用序号 -> 用户名，序号最小的最久未使用
+    recency: BTreeMap<u64, String>,
+    /// 下一个使用序号
+    next_tick: u64,
+    /// 累计淘汰数量
+    evictions: u64,
+}
+
+impl UsernameCache {
+    /// 创建指定容量的映射
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity: capacity.max(1),
+            entries: HashMap::new(),
+            recency: BTreeMap::new(),
+            next_tick: 0,
+            evictions: 0,
+        }
+    }
+
+    fn tick(&mut self) -> u64 {
+        let tick = self.next_tick;
+        self.next_tick += 1;
+        tick
+    }
+
+    /// 查找用户ID并标记为最近使用
+    pub fn get(&mut self, username: &str) -> Option<NodeId> {
+        let tick = self.tick();
+        let (user_id, last_used) = self.entries.get_mut(username)?;
+        self.recency.remove(last_used);
+        *last_used = tick;
+        self.recency.insert(tick, username.to_string());
+        Some(user_id.clone())
+    }
+
+    /// 查找用户ID，不影响淘汰顺序
+    pub fn peek(&self, username: &str) -> Option<&NodeId> {
+        self.entries.get(username).map(|(user_id, _)| user_id)
+    }
+
+    /// 是否包含用户名
+    pub fn contains_key(&self, username: &str) -> bool {
+        self.entries.contains_key(username)
+    }
+
+    /// 插入映射，超出容量时淘汰最久未使用的用户名
+    pub fn insert(&mut self, username: String, user_id: NodeId) {
+        let tick = self.tick();
+        if let Some((_, last_used)) = self.entries.insert(username.clone(), (user_id, tick)) {
+            self.recency.remove(&last_used);
+        }
+        self.recency.insert(tick, username);
+
+        while self.entries.len() > self.capacity {
+            let Some((_, oldest)) = self.recency.pop_first() else {
+                break;
+            };
+            self.entries.remove(&oldest);
+            self.evictions += 1;
+        }
+    }
+
+    /// 移除映射
+    pub fn remove(&mut self, username: &str) -> Option<NodeId> {
+        let (user_id, last_used) = self.entries.remove(username)?;
+        self.recency.remove(&last_used);
+        Some(user_id)
+    }
+
+    /// 当前映射数量
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// 是否为空
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// 累计淘汰数量
+    pub fn evictions(&self) -> u64 {
+        self.evictions
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_oldest_mapping_evicted() {
+        let mut cache = UsernameCache::new(3);
+        for name in ["alice", "bob", "carol"] {
+            cache.insert(name.to_string(), format!("{}-id", name));
+        }
+
+        // 访问 alice 后，bob 成为最久未使用的映射
+        assert_eq!(cache.get("alice"), Some("alice-id".to_string()));
+        cache.insert("dave".to_string(), "dave-id".to_string());
+
+        assert_eq!(cache.len(), 3);
+        assert!(!cache.contains_key("bob"));
+        assert!(cache.contains_key("alice"));
+        assert_eq!(cache.evictions(), 1);
+
+        cache.insert("erin".to_string(), "erin-id".to_string());
+        assert!(!cache.contains_key("carol"));
+        assert_eq!(cache.evictions(), 2);
+    }
+}
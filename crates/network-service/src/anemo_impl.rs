@@ -1,25 +1,159 @@
 //! Anemo网络服务的具体实现
 
 use crate::{
-    BroadcastOptions, EventBus, EventHandler, MessageHandler, MessageId, MessageType,
-    NetworkMessage, NetworkServiceConfig, NetworkServiceTrait, NodeId, Result, UnicastOptions,
+    retry_with_backoff, AuthDecision, BackoffConfig, BroadcastOptions, ConnectError,
+    ConnectionAuthenticator, ConnectionMetadata, DeadLetter, DeadLetterConfig, DeadLetterQueue,
+    DisconnectReason, EventBus, EventHandler, HandlerContext, KeySource, MessageHandler, MessageId,
+    MessageType, NetworkMessage, NetworkService, NetworkServiceConfig, NetworkServiceTrait,
+    NetworkStats, NodeId, NodeOrder, NonceGenerator, OutboundWal, PeerState, PoolStats,
+    ReconnectLimiter, Result, ShutdownReport, StatsSnapshot, SystemMessageHandler,
+    SystemMessageType, UnicastOptions, WalEntry,
 };
-use anemo::codegen::Bytes;
-use anemo::{Network, PeerId, Request, Router};
+use anemo::codegen::{BoxFuture, Bytes, Context, Poll, Service};
+use anemo::types::PeerEvent;
+use anemo::{Network, PeerId, Request, Response, Router};
 use async_trait::async_trait;
+use futures::stream::{self, StreamExt};
 use once_cell::sync::Lazy;
 use serde_json;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+use std::convert::Infallible;
+use std::future::Future;
 use std::net::SocketAddr;
+use std::path::PathBuf;
 use std::sync::Arc;
-use std::time::{SystemTime, UNIX_EPOCH};
-use tokio::sync::RwLock;
-use tracing::{error, info, warn};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tokio::sync::{broadcast, Mutex, OnceCell, RwLock};
+use tracing::{debug, error, info, warn};
 
-/// 全局节点注册表 - 在实际应用中应该使用分布式注册中心
+/// 承载 `NetworkMessage` 的RPC路由，自定义路由器不能使用该路径
+pub const NETWORK_MESSAGE_ROUTE: &str = "/network/message";
+
+/// 查询对端节点信息（节点ID、服务名、协议版本等）的RPC路由，自定义路由器不能使用该路径
+pub const NODE_INFO_ROUTE: &str = "/network/node-info";
+
+/// 节点信息中节点ID的键
+const NODE_ID_KEY: &str = "node_id";
+
+/// 节点信息中公布的网络协议版本
+const NODE_PROTOCOL_VERSION: &str = "1";
+
+/// 节点信息中公布的消息编码格式
+const NODE_WIRE_FORMAT: &str = "json";
+
+/// 断开连接前发送 `Goodbye` 的超时时间（毫秒）
+const GOODBYE_TIMEOUT_MS: u64 = 1000;
+
+/// 查询对端节点信息的超时时间（毫秒）
+const NODE_INFO_TIMEOUT_MS: u64 = 1000;
+
+/// 入站消息等待连接通过认证的最长时间（毫秒）
+const PEER_AUTH_WAIT_MS: u64 = 5000;
+
+/// 本进程内运行的节点（节点ID → PeerId），只用于检查显式节点ID冲突和同名服务；
+/// 寻址使用各实例自己的节点表
 static GLOBAL_NODES: Lazy<Arc<RwLock<HashMap<NodeId, PeerId>>>> =
     Lazy::new(|| Arc::new(RwLock::new(HashMap::new())));
 
+/// 启动后实际生效的配置
+#[derive(Debug, Clone)]
+pub struct EffectiveConfig {
+    /// 启动配置：`bind_address` 为实际监听地址；私钥不对外暴露，`private_key`
+    /// 固定为 [`KeySource::Random`]，节点身份以 `peer_id` 为准
+    pub config: NetworkServiceConfig,
+    /// 实际监听地址（绑定端口0时为系统分配的端口）
+    pub local_addr: SocketAddr,
+    /// 本节点的PeerId
+    pub peer_id: PeerId,
+    /// 本地节点ID
+    pub node_id: NodeId,
+}
+
+impl EffectiveConfig {
+    fn new(
+        mut config: NetworkServiceConfig,
+        local_addr: SocketAddr,
+        peer_id: PeerId,
+        node_id: NodeId,
+    ) -> Self {
+        config.bind_address = local_addr;
+        config.private_key = KeySource::Random;
+        Self {
+            config,
+            local_addr,
+            peer_id,
+            node_id,
+        }
+    }
+}
+
+/// 探测单个服务器的结果
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ProbeResult {
+    /// 连接成功并收到Ping的回应，`rtt` 为连接加Ping往返的总耗时
+    Reachable { rtt: Duration },
+    /// 连接被拒绝、地址不可达或握手失败
+    Refused,
+    /// 在超时时间内没有完成探测
+    Timeout,
+    /// 其他失败（地址无效、服务未启动、对端没有回应Ping等）
+    Failed(String),
+}
+
+impl From<ConnectError> for ProbeResult {
+    fn from(error: ConnectError) -> Self {
+        match error {
+            ConnectError::Timeout(_) => ProbeResult::Timeout,
+            ConnectError::Refused(_)
+            | ConnectError::AddressUnreachable(_)
+            | ConnectError::TlsHandshakeFailed(_) => ProbeResult::Refused,
+            ConnectError::Other(detail) => ProbeResult::Failed(detail),
+        }
+    }
+}
+
+/// 构造自定义路由的回调，每次启动（包括轮换私钥后的重启）调用一次
+pub type RouterFactory = Arc<dyn Fn() -> CustomRoutes + Send + Sync>;
+
+/// 挂载单个自定义路由的回调
+type MountRoute = Box<dyn FnOnce(Router) -> Router + Send>;
+
+/// 自定义RPC路由，启动时与内置路由一起挂载到同一个路由器上
+///
+/// 先记录路径再挂载，启动时可以在挂载前检查路径冲突。
+#[derive(Default)]
+pub struct CustomRoutes {
+    routes: Vec<(String, MountRoute)>,
+}
+
+impl CustomRoutes {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// 在 `path` 上挂载RPC服务
+    pub fn route<S>(mut self, path: &str, service: S) -> Self
+    where
+        S: Service<Request<Bytes>, Response = Response<Bytes>, Error = Infallible>
+            + Clone
+            + Send
+            + 'static,
+        S::Future: Send + 'static,
+    {
+        let mount_path = path.to_string();
+        self.routes.push((
+            path.to_string(),
+            Box::new(move |router: Router| router.route(&mount_path, service)),
+        ));
+        self
+    }
+
+    /// 已记录的路由路径
+    pub fn paths(&self) -> impl Iterator<Item = &str> {
+        self.routes.iter().map(|(path, _)| path.as_str())
+    }
+}
+
 /// 基于Anemo的网络服务实现
 #[derive(Clone)]
 pub struct AnemoNetworkService {
@@ -27,293 +161,4854 @@ pub struct AnemoNetworkService {
     network: Arc<RwLock<Option<Network>>>,
     /// 事件总线
     event_bus: Arc<EventBus>,
-    /// 消息处理器
-    message_handlers: Arc<RwLock<HashMap<MessageType, Box<dyn MessageHandler>>>>,
+    /// 入站消息处理流程（处理器注册表、工作池、去重、重放检测等），与事件总线共享
+    inbound: NetworkService,
     /// 服务状态
     is_running: Arc<RwLock<bool>>,
     /// 本地节点ID
     local_node_id: Arc<RwLock<Option<NodeId>>>,
+    /// 本实例的节点表：已连接节点的节点ID → PeerId
+    nodes: Arc<RwLock<HashMap<NodeId, PeerId>>>,
+    /// 主动连接过的地址上的节点ID，断开后重连时用于标识节点
+    address_book: Arc<RwLock<HashMap<SocketAddr, NodeId>>>,
+    /// 本节点主动连接过的地址，只向这些地址上的对端出示认证元数据
+    dialed_addrs: Arc<RwLock<HashSet<SocketAddr>>>,
     /// 已知的服务器地址列表
     known_servers: Arc<RwLock<Vec<String>>>,
+    /// 启动时使用的配置
+    config: Arc<RwLock<Option<NetworkServiceConfig>>>,
+    /// 累计统计信息
+    stats: Arc<RwLock<NetworkStats>>,
+    /// 未能投递的消息
+    dead_letters: DeadLetterQueue,
+    /// 出站消息预写日志，`None` 表示不启用
+    wal: Option<OutboundWal>,
+    /// 出站消息的重放保护序号
+    nonces: NonceGenerator,
+    /// 自定义路由器，`None` 时使用空路由器
+    router_factory: Option<RouterFactory>,
+    /// 连接认证回调
+    authenticator: Arc<RwLock<Option<ConnectionAuthenticator>>>,
+    /// 对端握手元数据
+    peer_metadata: Arc<RwLock<HashMap<PeerId, HashMap<String, String>>>>,
+    /// 连接认证任务
+    auth_watcher: Arc<Mutex<Option<tokio::task::JoinHandle<()>>>>,
+    /// 各连接最近一次应用消息的时间
+    peer_activity: Arc<RwLock<HashMap<PeerId, Instant>>>,
+    /// 各连接的建立时间，只记录通过认证的连接
+    connected_at: Arc<RwLock<HashMap<PeerId, Instant>>>,
+    /// 已知服务器的连接，不会因空闲被断开
+    persistent_peers: Arc<RwLock<HashSet<PeerId>>>,
+    /// 正在断开的连接，断开完成前不再处理其消息
+    disconnecting: Arc<RwLock<HashSet<PeerId>>>,
+    /// 正在探测的地址，这些地址上新建的连接不认证、不登记到节点表，也不发布连接事件
+    probing_addrs: Arc<RwLock<HashSet<SocketAddr>>>,
+    /// 探测建立的连接，断开时不发布节点断开事件
+    probe_peers: Arc<RwLock<HashSet<PeerId>>>,
+    /// 连接通过认证、被拒绝或断开时通知等待认证结果的入站消息
+    peer_auth_changed: Arc<tokio::sync::Notify>,
+    /// 过期节点清理任务
+    stale_sweeper: Arc<Mutex<Option<tokio::task::JoinHandle<()>>>>,
+    /// 串行化预写日志重发，避免同一条消息被并发重发
+    wal_replay: Arc<Mutex<()>>,
+    /// 进行中的停止流程，并发调用 `stop` 时共享同一次拆除
+    stopping: Arc<Mutex<Option<Arc<OnceCell<ShutdownReport>>>>>,
 }
 
 impl AnemoNetworkService {
     /// 创建新的网络服务实例
     pub fn new() -> Self {
+        let event_bus = EventBus::new(NetworkServiceConfig::default().event_bus_capacity);
         Self {
             network: Arc::new(RwLock::new(None)),
-            event_bus: Arc::new(EventBus::new(1000)),
-            message_handlers: Arc::new(RwLock::new(HashMap::new())),
+            inbound: NetworkService::with_event_bus(event_bus.clone()),
+            event_bus: Arc::new(event_bus),
             is_running: Arc::new(RwLock::new(false)),
             local_node_id: Arc::new(RwLock::new(None)),
+            nodes: Arc::new(RwLock::new(HashMap::new())),
+            address_book: Arc::new(RwLock::new(HashMap::new())),
+            dialed_addrs: Arc::new(RwLock::new(HashSet::new())),
             known_servers: Arc::new(RwLock::new(Vec::new())),
+            config: Arc::new(RwLock::new(None)),
+            stats: Arc::new(RwLock::new(NetworkStats::default())),
+            dead_letters: DeadLetterQueue::default(),
+            wal: None,
+            nonces: NonceGenerator::starting_at(crate::now_nanos()),
+            wal_replay: Arc::new(Mutex::new(())),
+            router_factory: None,
+            authenticator: Arc::new(RwLock::new(None)),
+            peer_metadata: Arc::new(RwLock::new(HashMap::new())),
+            auth_watcher: Arc::new(Mutex::new(None)),
+            peer_activity: Arc::new(RwLock::new(HashMap::new())),
+            connected_at: Arc::new(RwLock::new(HashMap::new())),
+            persistent_peers: Arc::new(RwLock::new(HashSet::new())),
+            disconnecting: Arc::new(RwLock::new(HashSet::new())),
+            probing_addrs: Arc::new(RwLock::new(HashSet::new())),
+            probe_peers: Arc::new(RwLock::new(HashSet::new())),
+            peer_auth_changed: Arc::new(tokio::sync::Notify::new()),
+            stale_sweeper: Arc::new(Mutex::new(None)),
+            stopping: Arc::new(Mutex::new(None)),
         }
     }
 
-    /// 添加已知的服务器地址
-    pub async fn add_known_server(&self, server_addr: String) {
-        let mut servers = self.known_servers.write().await;
-        if !servers.contains(&server_addr) {
-            servers.push(server_addr.clone());
-            info!("添加已知服务器: {}", server_addr);
-        }
+    /// 设置连接认证回调，新建立的连接被拒绝时会断开
+    pub async fn set_connection_authenticator(&self, authenticator: ConnectionAuthenticator) {
+        *self.authenticator.write().await = Some(authenticator);
     }
 
-    /// 将PeerId转换为NodeId
-    fn peer_id_to_node_id(peer_id: PeerId) -> NodeId {
-        peer_id.to_string()
+    /// 记录对端的握手元数据，供认证回调使用
+    pub async fn record_peer_metadata(&self, peer_id: PeerId, metadata: HashMap<String, String>) {
+        self.peer_metadata.write().await.insert(peer_id, metadata);
     }
 
-    /// 将NodeId转换为PeerId
-    fn node_id_to_peer_id(node_id: &NodeId) -> Result<PeerId> {
-        // 从全局节点表中查找
-        let global_nodes = GLOBAL_NODES
-            .try_read()
-            .map_err(|_| crate::NetworkError::config_error("无法获取节点表"))?;
-
-        global_nodes
-            .get(node_id)
+    /// 对连接进行认证，未设置回调时接受所有连接
+    pub async fn authorize_peer(&self, peer_id: PeerId) -> AuthDecision {
+        let Some(authenticator) = self.authenticator.read().await.clone() else {
+            return AuthDecision::Accept;
+        };
+        let metadata = self
+            .peer_metadata
+            .read()
+            .await
+            .get(&peer_id)
             .cloned()
-            .ok_or_else(|| crate::NetworkError::node_not_found(node_id.clone()))
+            .unwrap_or_default();
+        authenticator(peer_id, metadata).await
     }
 
-    /// 连接到已知的服务器（延迟执行）
-    pub async fn connect_to_known_servers_delayed(&self) {
-        // 等待一段时间让网络服务完全启动
-        tokio::time::sleep(tokio::time::Duration::from_secs(1)).await;
+    /// 认证连接，拒绝时断开；接受时发布节点连接事件
+    ///
+    /// 认证前先查询对端公布的节点信息，认证回调可以使用其中的附加元数据（例如令牌）。
+    async fn enforce_peer_auth(&self, network: &Network, peer_id: PeerId) {
+        if self.is_probe_peer(network, peer_id).await {
+            return;
+        }
+        self.load_peer_metadata(network, peer_id).await;
+        if let AuthDecision::Reject(reason) = self.authorize_peer(peer_id).await {
+            warn!("拒绝节点 {} 的连接: {}", peer_id, reason);
+            self.peer_metadata.write().await.remove(&peer_id);
+            self.forget_peer(peer_id).await;
+            self.disconnect_with_reason(network, peer_id, DisconnectReason::Unauthorized(reason))
+                .await;
+            return;
+        }
+        self.identify_peer(network, peer_id).await;
+        // 认证期间连接可能已经断开
+        if network.peer(peer_id).is_none() {
+            return;
+        }
+
+        // 新连接从建立时开始计算空闲时间
+        let now = Instant::now();
+        self.peer_activity.write().await.insert(peer_id, now);
+        self.connected_at
+            .write()
+            .await
+            .entry(peer_id)
+            .or_insert(now);
+        self.peer_auth_changed.notify_waiters();
+
+        let remote_addr = network.peer(peer_id).map(|peer| peer.address());
+        let event = self.node_connected_event(peer_id, remote_addr).await;
+        self.event_bus.publish(event).await;
+
+        // 重发上次运行中发给该节点、尚未确认的消息
+        if self.wal.is_some() {
+            let node_id = self.peer_id_to_known_node_id(peer_id).await;
+            self.replay_wal_matching(|entry| entry.target == node_id)
+                .await;
+        }
+    }
+
+    /// 连接是否由探测建立，探测连接由 [`probe_server`](Self::probe_server) 自行断开
+    async fn is_probe_peer(&self, network: &Network, peer_id: PeerId) -> bool {
+        if self.probe_peers.read().await.contains(&peer_id) {
+            return true;
+        }
+        match network.peer(peer_id) {
+            Some(peer) => {
+                let probing = self.probing_addrs.read().await.contains(&peer.address());
+                if probing {
+                    self.probe_peers.write().await.insert(peer_id);
+                }
+                probing
+            }
+            None => false,
+        }
+    }
 
-        let network = self.network.read().await;
-        if let Some(network) = network.as_ref() {
-            let servers = self.known_servers.read().await.clone();
+    /// 根据记录的握手元数据构建节点连接事件
+    async fn node_connected_event(
+        &self,
+        peer_id: PeerId,
+        remote_addr: Option<SocketAddr>,
+    ) -> crate::NetworkEvent {
+        let metadata = self
+            .peer_metadata
+            .read()
+            .await
+            .get(&peer_id)
+            .cloned()
+            .unwrap_or_default();
+        crate::NetworkEvent::NodeConnected {
+            node_id: self.peer_id_to_known_node_id(peer_id).await,
+            connection: ConnectionMetadata::from_handshake(remote_addr, &metadata),
+            metadata,
+        }
+    }
 
-            if servers.is_empty() {
-                info!("没有已知的服务器地址，跳过连接");
+    /// 启动连接事件任务：认证新连接，并发布节点连接和断开事件
+    ///
+    /// 在启动任务前订阅，任务开始运行前发生的连接和断开也不会遗漏。
+    async fn spawn_auth_watcher(&self, network: Network) {
+        let (mut events, existing) = match network.subscribe() {
+            Ok(subscription) => subscription,
+            Err(e) => {
+                warn!("订阅连接事件失败: {}", e);
                 return;
             }
+        };
+        let service = self.clone();
+        let handle = tokio::spawn(async move {
+            for peer_id in existing {
+                service.spawn_peer_auth(&network, peer_id);
+            }
 
-            for server_addr in servers {
-                match server_addr.parse::<SocketAddr>() {
-                    Ok(addr) => {
-                        info!("尝试连接到服务器: {}", server_addr);
-                        match network.connect(addr).await {
-                            Ok(peer_id) => {
-                                info!("成功连接到服务器: {} -> {}", server_addr, peer_id);
-
-                                // 注册到全局节点表
-                                if let Some(local_id) = self.local_node_id.read().await.as_ref() {
-                                    let mut global_nodes = GLOBAL_NODES.write().await;
-                                    global_nodes.insert(local_id.clone(), peer_id);
-                                    info!("节点 {} 已注册到全局节点表", local_id);
-                                }
-                            }
-                            Err(e) => {
-                                warn!("连接到服务器 {} 失败: {}", server_addr, e);
-                            }
-                        }
+            // 认证回调可能较慢，每个连接单独认证，避免阻塞后续连接事件
+            loop {
+                match events.recv().await {
+                    Ok(PeerEvent::NewPeer(peer_id)) => {
+                        service.spawn_peer_auth(&network, peer_id);
                     }
-                    Err(e) => {
-                        warn!("解析服务器地址 {} 失败: {}", server_addr, e);
+                    Ok(PeerEvent::LostPeer(peer_id, reason)) => {
+                        service
+                            .handle_lost_peer(peer_id, format!("{:?}", reason))
+                            .await;
                     }
+                    Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                        // 跳过的事件中可能有未认证的新连接，重新核对所有连接
+                        warn!("连接事件积压，跳过 {} 个事件，重新核对连接", skipped);
+                        let service = service.clone();
+                        let network = network.clone();
+                        tokio::spawn(async move { service.resync_peers(&network).await });
+                    }
+                    Err(broadcast::error::RecvError::Closed) => break,
                 }
             }
+        });
+        *self.auth_watcher.lock().await = Some(handle);
+    }
+
+    /// 在后台认证单个连接
+    fn spawn_peer_auth(&self, network: &Network, peer_id: PeerId) {
+        let service = self.clone();
+        let network = network.clone();
+        tokio::spawn(async move { service.enforce_peer_auth(&network, peer_id).await });
+    }
+
+    /// 清理已断开连接的状态并发布节点断开事件
+    async fn handle_lost_peer(&self, peer_id: PeerId, reason: String) {
+        self.peer_metadata.write().await.remove(&peer_id);
+        self.peer_activity.write().await.remove(&peer_id);
+        self.connected_at.write().await.remove(&peer_id);
+        self.disconnecting.write().await.remove(&peer_id);
+        self.peer_auth_changed.notify_waiters();
+        // 探测连接没有登记到节点表，也没有发布过连接事件
+        if self.probe_peers.write().await.remove(&peer_id) {
+            return;
         }
+        let node_id = self.peer_id_to_known_node_id(peer_id).await;
+        self.forget_peer(peer_id).await;
+        self.event_bus
+            .publish(crate::NetworkEvent::NodeDisconnected { node_id, reason })
+            .await;
     }
-}
 
-#[async_trait]
-impl NetworkServiceTrait for AnemoNetworkService {
-    async fn start(&self, config: NetworkServiceConfig) -> Result<()> {
-        let mut is_running = self.is_running.write().await;
-        if *is_running {
-            return Err(crate::NetworkError::config_error("服务已启动"));
+    /// 按当前连接重新核对状态：认证尚未认证的连接，清理已经断开的连接
+    async fn resync_peers(&self, network: &Network) {
+        let current: HashSet<PeerId> = network.peers().into_iter().collect();
+        let authenticated: HashSet<PeerId> =
+            self.connected_at.read().await.keys().copied().collect();
+        let disconnecting = self.disconnecting.read().await.clone();
+
+        let unauthenticated = current
+            .iter()
+            .filter(|peer_id| !authenticated.contains(peer_id) && !disconnecting.contains(peer_id))
+            .map(|peer_id| self.enforce_peer_auth(network, *peer_id));
+        futures::future::join_all(unauthenticated).await;
+
+        for peer_id in authenticated.difference(&current) {
+            self.handle_lost_peer(*peer_id, "连接已断开".to_string())
+                .await;
         }
+    }
 
-        // 创建路由器
-        let router = Router::new();
+    /// 启动过期节点清理任务：定期移除节点表中已断开连接的节点，并断开空闲连接
+    async fn spawn_stale_sweeper(&self, network: Network, interval_ms: u64) {
+        let service = self.clone();
+        let handle = tokio::spawn(async move {
+            let mut interval = tokio::time::interval(Duration::from_millis(interval_ms.max(1)));
+            // 跳过立即触发的第一次
+            interval.tick().await;
+            loop {
+                interval.tick().await;
+                let candidates: Vec<_> = service
+                    .nodes
+                    .read()
+                    .await
+                    .iter()
+                    .map(|(node_id, peer_id)| (node_id.clone(), *peer_id))
+                    .collect();
+                service
+                    .prune_unreachable(candidates, |peer_id| network.peer(peer_id).is_some())
+                    .await;
+                service.disconnect_idle_peers(&network).await;
+            }
+        });
+        *self.stale_sweeper.lock().await = Some(handle);
+    }
 
-        // 启动网络服务
-        let network = Network::bind(config.bind_address)
-            .server_name(config.server_name.clone())
-            .private_key(config.private_key)
-            .start(router)
-            .map_err(|e| crate::NetworkError::connection_error(format!("启动网络失败: {}", e)))?;
+    /// 记录连接上的应用消息，系统消息（心跳、探测等）不计入
+    async fn record_peer_activity(&self, peer_id: PeerId, message: &NetworkMessage) {
+        if message.message_type != MessageType::system() {
+            self.peer_activity
+                .write()
+                .await
+                .insert(peer_id, Instant::now());
+        }
+    }
 
-        info!("网络服务启动在地址: {}", network.local_addr());
+    /// 超过 `timeout` 没有应用消息的连接，已知服务器的连接除外
+    async fn idle_peers(&self, timeout: Duration) -> Vec<PeerId> {
+        let persistent = self.persistent_peers.read().await;
+        self.peer_activity
+            .read()
+            .await
+            .iter()
+            .filter(|(peer_id, last)| last.elapsed() >= timeout && !persistent.contains(peer_id))
+            .map(|(peer_id, _)| *peer_id)
+            .collect()
+    }
 
-        // 生成本地节点ID（基于地址和服务名）
-        let local_id = format!("{}:{}", config.server_name, network.local_addr());
+    /// 按配置断开空闲连接
+    async fn disconnect_idle_peers(&self, network: &Network) {
+        let timeout_ms = self
+            .config
+            .read()
+            .await
+            .as_ref()
+            .and_then(|config| config.idle_connection_timeout_ms);
+        let Some(timeout_ms) = timeout_ms else {
+            return;
+        };
 
-        // 注册到全局节点表
-        {
-            let mut global_nodes = GLOBAL_NODES.write().await;
-            global_nodes.insert(local_id.clone(), network.peer_id());
+        for peer_id in self.idle_peers(Duration::from_millis(timeout_ms)).await {
+            info!("连接 {} 空闲超过 {}ms，主动断开", peer_id, timeout_ms);
+            self.peer_activity.write().await.remove(&peer_id);
+            self.disconnect_with_reason(network, peer_id, DisconnectReason::Idle)
+                .await;
         }
+    }
 
-        // 存储本地信息
-        *self.local_node_id.write().await = Some(local_id.clone());
-        *self.network.write().await = Some(network);
-        *is_running = true;
+    /// 关注单个节点的连接状态，例如客户端只关心与服务器的连接
+    ///
+    /// 初始状态以底层网络中是否存在该连接为准，节点表中残留的记录不算已连接。
+    pub async fn watch_peer(&self, node_id: NodeId) -> tokio::sync::watch::Receiver<PeerState> {
+        let peer_id = self
+            .nodes
+            .read()
+            .await
+            .get(&node_id)
+            .copied()
+            .or_else(|| Self::parse_peer_id(&node_id));
+        let connected = match peer_id {
+            Some(peer_id) => self
+                .network
+                .read()
+                .await
+                .as_ref()
+                .is_some_and(|network| network.peer(peer_id).is_some()),
+            None => false,
+        };
+        let initial = if connected {
+            PeerState::Connected
+        } else {
+            PeerState::Disconnected
+        };
+        self.event_bus.watch_peer(node_id, initial)
+    }
 
-        info!("网络服务启动完成，节点ID: {}", local_id);
+    /// 断开与指定节点的连接（例如踢出或屏蔽），断开前通知对端原因
+    pub async fn disconnect_node(&self, node_id: &NodeId, reason: DisconnectReason) -> Result<()> {
+        let network = self
+            .network
+            .read()
+            .await
+            .clone()
+            .ok_or_else(|| crate::NetworkError::config_error("服务未启动"))?;
+        let peer_id = self.node_id_to_peer_id(node_id).await?;
+        info!("断开节点 {}: {}", node_id, reason);
+        self.disconnect_with_reason(&network, peer_id, reason).await;
         Ok(())
     }
 
-    async fn stop(&self) -> Result<()> {
-        let mut is_running = self.is_running.write().await;
-        if !*is_running {
-            return Ok(());
+    /// 断开连接并告知对端原因，通知失败不影响断开
+    ///
+    /// 对端立即被视为已断开，不再处理其消息；`Goodbye` 在后台发送，发送完成或超时后关闭连接，
+    /// 调用方无需等待。
+    async fn disconnect_with_reason(
+        &self,
+        network: &Network,
+        peer_id: PeerId,
+        reason: DisconnectReason,
+    ) {
+        self.disconnecting.write().await.insert(peer_id);
+        self.peer_auth_changed.notify_waiters();
+        let local_id = self
+            .local_node_id
+            .read()
+            .await
+            .clone()
+            .unwrap_or_else(|| Self::peer_id_to_node_id(network.peer_id()));
+        let goodbye = SystemMessageType::Goodbye { reason }.to_network_message(local_id);
+
+        let service = self.clone();
+        let network = network.clone();
+        tokio::spawn(async move {
+            let notified = match goodbye {
+                Ok(goodbye) => service
+                    .send_to_peer(&network, peer_id, &goodbye, GOODBYE_TIMEOUT_MS)
+                    .await
+                    .map(|_| ()),
+                Err(e) => Err(e),
+            };
+            if let Err(e) = notified {
+                warn!("向节点 {} 发送断开原因失败: {}", peer_id, e);
+            }
+
+            if let Err(e) = network.disconnect(peer_id) {
+                warn!("断开节点 {} 失败: {}", peer_id, e);
+            }
+        });
+    }
+
+    /// 从节点表中移除已断开连接的节点并发布断开事件，返回被移除的节点
+    async fn prune_unreachable<F>(
+        &self,
+        candidates: Vec<(NodeId, PeerId)>,
+        is_connected: F,
+    ) -> Vec<NodeId>
+    where
+        F: Fn(PeerId) -> bool,
+    {
+        let mut pruned = Vec::new();
+        {
+            let mut nodes = self.nodes.write().await;
+            for (node_id, peer_id) in candidates {
+                if is_connected(peer_id) {
+                    continue;
+                }
+                // 节点可能已经以新的连接重新注册
+                if nodes.get(&node_id) == Some(&peer_id) {
+                    nodes.remove(&node_id);
+                    pruned.push(node_id);
+                }
+            }
         }
 
-        // 从全局节点表中移除自己
-        if let Some(local_id) = self.local_node_id.read().await.as_ref() {
-            let mut global_nodes = GLOBAL_NODES.write().await;
-            global_nodes.remove(local_id);
-            info!("节点 {} 已从网络中移除", local_id);
+        for node_id in &pruned {
+            warn!("节点 {} 已断开，从节点表中移除", node_id);
+            self.event_bus
+                .publish(crate::NetworkEvent::NodeDisconnected {
+                    node_id: node_id.clone(),
+                    reason: "节点不可达".to_string(),
+                })
+                .await;
         }
+        pruned
+    }
 
-        // 清理本地状态
-        *self.local_node_id.write().await = None;
-        *self.network.write().await = None;
-        *is_running = false;
+    /// 使用指定配置替换死信队列（例如启用文件落盘）
+    pub fn with_dead_letter_config(mut self, config: DeadLetterConfig) -> Self {
+        self.dead_letters = DeadLetterQueue::new(config);
+        self
+    }
 
-        info!("网络服务已停止");
-        Ok(())
+    /// 启用出站消息预写日志
+    ///
+    /// 单播消息发送前写入日志，发送成功或移入死信队列后确认；
+    /// 上次运行中未确认的消息在目标节点连接后（或连接已知服务器后）重发。
+    pub fn with_outbound_wal(mut self, path: impl Into<PathBuf>) -> Result<Self> {
+        let path = path.into();
+        let wal = OutboundWal::open(&path).map_err(|e| {
+            crate::NetworkError::config_error(format!(
+                "打开预写日志 {} 失败: {}",
+                path.display(),
+                e
+            ))
+        })?;
+        self.wal = Some(wal);
+        Ok(self)
     }
 
-    async fn broadcast(
-        &self,
-        message: NetworkMessage,
-        options: Option<BroadcastOptions>,
-    ) -> Result<MessageId> {
-        let is_running = *self.is_running.read().await;
-        if !is_running {
-            return Err(crate::NetworkError::config_error("服务未启动"));
-        }
+    /// 使用自定义路由启动网络，可在同一网络上挂载额外的 RPC 服务
+    ///
+    /// 启动时会同时挂载 [`NETWORK_MESSAGE_ROUTE`] 和 [`NODE_INFO_ROUTE`]，
+    /// 自定义路由占用这两个路径或重复注册同一路径时 `start` 返回配置错误。
+    pub fn with_router(
+        mut self,
+        make_routes: impl Fn() -> CustomRoutes + Send + Sync + 'static,
+    ) -> Self {
+        self.router_factory = Some(Arc::new(make_routes));
+        self
+    }
 
-        let exclude_nodes = options
+    /// 构造启动网络使用的路由器，并挂载接收网络消息和查询节点信息的RPC服务
+    ///
+    /// 路由器在重复注册同一路径时会panic，挂载前先检查路径冲突。
+    fn build_router(&self) -> Result<Router> {
+        let routes = self
+            .router_factory
             .as_ref()
-            .map(|opt| opt.exclude_nodes.clone())
-            .unwrap_or_default();
+            .map_or_else(CustomRoutes::new, |make_routes| make_routes());
 
-        info!("广播消息: {:?}", message.message_type);
+        let mut paths: HashSet<&str> = HashSet::new();
+        for path in routes.paths() {
+            if path == NETWORK_MESSAGE_ROUTE || path == NODE_INFO_ROUTE {
+                return Err(crate::NetworkError::config_error(format!(
+                    "自定义路由不能使用内置路由 {}",
+                    path
+                )));
+            }
+            if !paths.insert(path) {
+                return Err(crate::NetworkError::config_error(format!(
+                    "自定义路由 {} 重复注册",
+                    path
+                )));
+            }
+        }
 
-        let mut sent_count = 0;
-        let network = self.network.read().await;
+        let router = routes
+            .routes
+            .into_iter()
+            .fold(Router::new(), |router, (_, mount)| mount(router));
+        Ok(self.mount_routes(router))
+    }
 
-        if let Some(network) = network.as_ref() {
-            let global_nodes = GLOBAL_NODES.read().await;
-            let local_id = self.local_node_id.read().await;
+    /// 在路由器上挂载内置的RPC服务
+    fn mount_routes(&self, router: Router) -> Router {
+        router
+            .route(
+                NETWORK_MESSAGE_ROUTE,
+                InboundRpc {
+                    service: self.clone(),
+                    route: NETWORK_MESSAGE_ROUTE,
+                },
+            )
+            .route(
+                NODE_INFO_ROUTE,
+                InboundRpc {
+                    service: self.clone(),
+                    route: NODE_INFO_ROUTE,
+                },
+            )
+    }
 
-            for (node_id, peer_id) in global_nodes.iter() {
-                // 跳过排除的节点
-                if exclude_nodes.contains(node_id) {
-                    continue;
-                }
+    /// 本节点对外公布的节点信息，对端连接后通过 [`NODE_INFO_ROUTE`] 查询
+    ///
+    /// 包括节点ID、服务名、协议版本、编码格式、能力列表（已注册处理器的消息类型）
+    /// 和配置的附加元数据，对端将其作为握手元数据用于认证和连接事件。
+    /// 查询方是本节点主动连接的对端时，还包括配置的认证元数据。
+    async fn node_info(&self, requester: Option<PeerId>) -> HashMap<String, String> {
+        let mut info = self
+            .config
+            .read()
+            .await
+            .as_ref()
+            .map(|config| config.node_info_metadata.clone())
+            .unwrap_or_default();
+        if self.is_dialed_peer(requester).await {
+            if let Some(config) = self.config.read().await.as_ref() {
+                info.extend(config.auth_metadata.clone());
+            }
+        }
+        if let Some(node_id) = self.local_node_id.read().await.clone() {
+            info.insert(NODE_ID_KEY.to_string(), node_id);
+        }
+        if let Some(config) = self.config.read().await.as_ref() {
+            info.insert(
+                ConnectionMetadata::SERVER_NAME_KEY.to_string(),
+                config.server_name.clone(),
+            );
+        }
+        info.insert(
+            ConnectionMetadata::PROTOCOL_VERSION_KEY.to_string(),
+            NODE_PROTOCOL_VERSION.to_string(),
+        );
+        info.insert(
+            ConnectionMetadata::WIRE_FORMAT_KEY.to_string(),
+            NODE_WIRE_FORMAT.to_string(),
+        );
+        info.insert(
+            ConnectionMetadata::CAPABILITIES_KEY.to_string(),
+            self.inbound.handled_message_types().await.join(","),
+        );
+        info
+    }
 
-                // 跳过自己
-                if let Some(ref local) = *local_id {
-                    if node_id == local {
-                        continue;
-                    }
-                }
+    /// 对端是否位于本节点主动连接过的地址上
+    async fn is_dialed_peer(&self, peer_id: Option<PeerId>) -> bool {
+        let network = self.network.read().await.clone();
+        let address = match (network, peer_id) {
+            (Some(network), Some(peer_id)) => network.peer(peer_id).map(|peer| peer.address()),
+            _ => None,
+        };
+        match address {
+            Some(address) => self.dialed_addrs.read().await.contains(&address),
+            None => false,
+        }
+    }
 
-                // 使用Anemo RPC发送消息
-                let message_bytes = serde_json::to_vec(&message).map_err(|e| {
-                    crate::NetworkError::send_error(format!("序列化消息失败: {}", e))
-                })?;
-                let request = Request::new(Bytes::from(message_bytes));
-                match network.rpc(*peer_id, request).await {
-                    Ok(_) => {
-                        sent_count += 1;
-                    }
-                    Err(e) => {
-                        warn!("发送消息到节点 {} 失败: {}", node_id, e);
-                    }
-                }
+    /// 查询对端的节点信息并并入其握手元数据，返回合并后的元数据
+    ///
+    /// 通过 [`record_peer_metadata`](Self::record_peer_metadata) 记录的值优先；
+    /// 已查询到节点ID时不再重复查询。
+    async fn load_peer_metadata(
+        &self,
+        network: &Network,
+        peer_id: PeerId,
+    ) -> HashMap<String, String> {
+        if let Some(metadata) = self.peer_metadata.read().await.get(&peer_id) {
+            if metadata.contains_key(NODE_ID_KEY) {
+                return metadata.clone();
             }
         }
+        let info = Self::fetch_node_info(network, peer_id).await;
+        let mut peer_metadata = self.peer_metadata.write().await;
+        let metadata = peer_metadata.entry(peer_id).or_default();
+        for (key, value) in info {
+            metadata.entry(key).or_insert(value);
+        }
+        metadata.clone()
+    }
 
-        info!("广播完成，成功发送到 {} 个节点", sent_count);
-        Ok(message.id)
+    /// 向对端查询其公布的节点信息，失败时返回空表
+    async fn fetch_node_info(network: &Network, peer_id: PeerId) -> HashMap<String, String> {
+        let request = Request::new(Bytes::new()).with_route(NODE_INFO_ROUTE);
+        let body = match Self::rpc_with_timeout(network.rpc(peer_id, request), NODE_INFO_TIMEOUT_MS)
+            .await
+        {
+            Ok(response) => response.into_body(),
+            Err(e) => {
+                warn!("查询节点 {} 的节点信息失败: {}", peer_id, e);
+                return HashMap::new();
+            }
+        };
+        serde_json::from_slice(&body).unwrap_or_else(|e| {
+            warn!("无法解析节点 {} 的节点信息: {}", peer_id, e);
+            HashMap::new()
+        })
     }
 
-    async fn unicast(
+    /// 确定对端的节点ID：节点表中已登记时直接使用，否则查询对端公布的节点ID并登记
+    async fn identify_peer(&self, network: &Network, peer_id: PeerId) -> NodeId {
+        if let Some(node_id) = self.known_node_id(peer_id).await {
+            return node_id;
+        }
+        let info = self.load_peer_metadata(network, peer_id).await;
+        self.register_peer(network, peer_id, info.get(NODE_ID_KEY))
+            .await
+    }
+
+    /// 在节点表中登记对端，返回登记的节点ID
+    ///
+    /// 对端没有公布节点ID、公布的ID与本节点相同或已被其他在线节点使用时，以PeerId作为节点ID。
+    async fn register_peer(
         &self,
-        target: NodeId,
-        message: NetworkMessage,
-        _options: Option<UnicastOptions>,
-    ) -> Result<MessageId> {
-        let is_running = *self.is_running.read().await;
-        if !is_running {
-            return Err(crate::NetworkError::config_error("服务未启动"));
+        network: &Network,
+        peer_id: PeerId,
+        announced: Option<&NodeId>,
+    ) -> NodeId {
+        let local_id = self.local_node_id.read().await.clone();
+        let mut nodes = self.nodes.write().await;
+        let node_id = match announced {
+            Some(node_id) if local_id.as_ref() == Some(node_id) => {
+                warn!("节点 {} 公布了与本节点相同的节点ID {}", peer_id, node_id);
+                None
+            }
+            Some(node_id) => match nodes.get(node_id) {
+                Some(existing) if *existing != peer_id && network.peer(*existing).is_some() => {
+                    warn!(
+                        "节点 {} 公布的节点ID {} 已被节点 {} 使用",
+                        peer_id, node_id, existing
+                    );
+                    None
+                }
+                _ => Some(node_id.clone()),
+            },
+            None => None,
         }
+        .unwrap_or_else(|| Self::peer_id_to_node_id(peer_id));
 
-        info!("单播消息到 {}: {:?}", target, message.message_type);
+        nodes.retain(|_, known| *known != peer_id);
+        nodes.insert(node_id.clone(), peer_id);
+        debug!("节点 {} 登记为 {}", peer_id, node_id);
+        node_id
+    }
 
-        let peer_id = Self::node_id_to_peer_id(&target)?;
-        let network = self.network.read().await;
+    /// 从节点表中移除对端
+    async fn forget_peer(&self, peer_id: PeerId) {
+        self.nodes
+            .write()
+            .await
+            .retain(|_, known| *known != peer_id);
+    }
 
-        if let Some(network) = network.as_ref() {
-            let message_bytes = serde_json::to_vec(&message)
-                .map_err(|e| crate::NetworkError::send_error(format!("序列化消息失败: {}", e)))?;
-            let request = Request::new(Bytes::from(message_bytes));
-            network
-                .rpc(peer_id, request)
-                .await
-                .map_err(|e| crate::NetworkError::send_error(format!("RPC调用失败: {}", e)))?;
-            info!("消息已发送到节点: {}", target);
-            Ok(message.id)
-        } else {
-            Err(crate::NetworkError::config_error("网络服务未启动"))
+    /// 处理对端通过RPC发来的消息，返回作为RPC响应的回复（没有回复时为空）
+    ///
+    /// 解码、重放检测、去重、工作池和处理器查找都由共享的入站处理流程完成。
+    async fn handle_inbound_rpc(&self, peer_id: PeerId, body: &[u8]) -> Bytes {
+        let network = self.network.read().await.clone();
+        let from = match &network {
+            Some(network) => {
+                // 连接建立后对端可能立即发送消息，先等待认证结果
+                if !self.wait_peer_accepted(network, peer_id).await {
+                    debug!("丢弃未通过认证的连接 {} 发来的消息", peer_id);
+                    return Bytes::new();
+                }
+                self.identify_peer(network, peer_id).await
+            }
+            None => self.peer_id_to_known_node_id(peer_id).await,
+        };
+        let ctx = self.handler_context();
+        let reply = match self.inbound.decode_frame(&from, body).await {
+            Err(crate::NetworkError::PeerBlocked(node_id)) => {
+                // 入站处理流程只负责屏蔽，连接由传输层断开
+                if let Some(network) = &network {
+                    warn!("断开被屏蔽的节点 {}", node_id);
+                    self.disconnect_with_reason(network, peer_id, DisconnectReason::Blocked)
+                        .await;
+                }
+                return Bytes::new();
+            }
+            Ok(message) => {
+                self.record_received(body.len() as u64).await;
+                self.record_peer_activity(peer_id, &message).await;
+                debug!("收到来自 {} 的消息 {}", from, message.id);
+                self.inbound
+                    .handle_incoming_request(ctx, from.clone(), message)
+                    .await
+            }
+            Err(e) => Err(e),
+        };
+        self.encode_reply(&from, reply).await
+    }
+
+    /// 等待连接通过认证；连接被拒绝、已断开或等待超时时返回 `false`
+    async fn wait_peer_accepted(&self, network: &Network, peer_id: PeerId) -> bool {
+        let deadline = tokio::time::Instant::now() + Duration::from_millis(PEER_AUTH_WAIT_MS);
+        loop {
+            // 先登记等待再检查状态，避免错过检查之后的通知
+            let notified = self.peer_auth_changed.notified();
+            tokio::pin!(notified);
+            notified.as_mut().enable();
+
+            if self.disconnecting.read().await.contains(&peer_id) {
+                return false;
+            }
+            if self.connected_at.read().await.contains_key(&peer_id) {
+                return true;
+            }
+            if network.peer(peer_id).is_none() {
+                return false;
+            }
+            if tokio::time::timeout_at(deadline, notified).await.is_err() {
+                warn!("连接 {} 等待认证超时", peer_id);
+                return false;
+            }
         }
     }
 
-    async fn get_local_node_id(&self) -> Result<NodeId> {
-        let local_id = self.local_node_id.read().await;
-        local_id
-            .clone()
-            .ok_or_else(|| crate::NetworkError::config_error("服务未启动"))
+    /// 入站消息处理流程，可查询工作池、去重和投递时延等统计
+    pub fn inbound(&self) -> &NetworkService {
+        &self.inbound
     }
 
-    async fn get_connected_nodes(&self) -> Result<Vec<NodeId>> {
-        let is_running = *self.is_running.read().await;
-        if !is_running {
-            return Err(crate::NetworkError::config_error("服务未启动"));
+    /// 入站消息处理器工作池的运行状态
+    pub async fn handler_pool_stats(&self) -> PoolStats {
+        self.inbound.handler_pool_stats().await
+    }
+
+    /// 序列化处理器的回复；处理失败或观察者模式下返回空响应
+    async fn encode_reply(&self, from: &NodeId, reply: Result<Option<NetworkMessage>>) -> Bytes {
+        let reply = match reply {
+            Ok(Some(reply)) if !self.is_observer().await => reply,
+            Ok(_) => return Bytes::new(),
+            Err(e) => {
+                warn!("处理来自 {} 的消息失败: {}", from, e);
+                self.record_error().await;
+                return Bytes::new();
+            }
+        };
+        match self.encode_message(&reply).await {
+            Ok(bytes) => Bytes::from(bytes),
+            Err(e) => {
+                warn!("序列化对 {} 的回复 {} 失败: {}", from, reply.id, e);
+                Bytes::new()
+            }
         }
+    }
 
-        let global_nodes = GLOBAL_NODES.read().await;
-        let local_id = self.local_node_id.read().await;
+    /// 预写日志中未确认的消息
+    pub async fn wal_pending(&self) -> Vec<WalEntry> {
+        match &self.wal {
+            Some(wal) => wal.pending().await,
+            None => Vec::new(),
+        }
+    }
 
-        let connected_nodes: Vec<NodeId> = global_nodes
-            .keys()
-            .filter(|&node_id| {
-                // 排除自己
-                if let Some(ref local) = *local_id {
-                    node_id != local
-                } else {
-                    true
-                }
-            })
-            .cloned()
+    /// 重发预写日志中上次运行未确认的消息，返回成功发送的数量
+    ///
+    /// 目标节点尚未连接的消息保留在日志中，等待节点连接后重发；其他原因发送失败的消息移入死信队列。
+    pub async fn replay_wal(&self) -> usize {
+        self.replay_wal_matching(|_| true).await
+    }
+
+    /// 重发满足条件的上次运行未确认的消息
+    async fn replay_wal_matching<F>(&self, filter: F) -> usize
+    where
+        F: Fn(&WalEntry) -> bool,
+    {
+        let Some(wal) = &self.wal else {
+            return 0;
+        };
+        // 在锁内读取待重发的消息，已由其他重发确认的消息不会再次发送
+        let _replaying = self.wal_replay.lock().await;
+        let pending: Vec<WalEntry> = wal
+            .recovered()
+            .await
+            .into_iter()
+            .filter(|entry| filter(entry))
             .collect();
+        if pending.is_empty() {
+            return 0;
+        }
+        info!("重发预写日志中 {} 条未确认的消息", pending.len());
 
-        info!("当前连接的节点数: {}", connected_nodes.len());
-        Ok(connected_nodes)
+        let mut replayed = 0;
+        for entry in pending {
+            let message_id = entry.message.id;
+            match self
+                .unicast_message(&entry.target, &entry.message, None)
+                .await
+            {
+                Ok(()) => replayed += 1,
+                Err(crate::NetworkError::NodeNotFound(_)) => {
+                    debug!(
+                        "节点 {} 尚未连接，消息 {} 留待重发",
+                        entry.target, message_id
+                    );
+                    continue;
+                }
+                Err(e) => {
+                    warn!("重发消息 {} 到 {} 失败: {}", message_id, entry.target, e);
+                    self.dead_letters
+                        .push(DeadLetter::new(entry.target, entry.message, e.to_string()))
+                        .await;
+                }
+            }
+            self.ack_wal(message_id).await;
+        }
+        replayed
     }
 
-    async fn register_message_handler(
+    /// 确认预写日志中的消息
+    async fn ack_wal(&self, message_id: MessageId) {
+        if let Some(wal) = &self.wal {
+            if let Err(e) = wal.ack(message_id).await {
+                warn!("确认预写日志记录 {} 失败: {}", message_id, e);
+            }
+        }
+    }
+
+    /// 获取死信队列
+    pub fn dead_letters(&self) -> &DeadLetterQueue {
+        &self.dead_letters
+    }
+
+    /// 创建供消息处理器使用的上下文
+    pub fn handler_context(&self) -> HandlerContext {
+        HandlerContext::new(self.clone())
+    }
+
+    /// 获取启动后实际生效的配置，服务未启动时返回 `None`
+    pub async fn effective_config(&self) -> Option<EffectiveConfig> {
+        let network = self.network.read().await.clone()?;
+        let config = self.config.read().await.clone()?;
+        let node_id = self.local_node_id.read().await.clone()?;
+        Some(EffectiveConfig::new(
+            config,
+            network.local_addr(),
+            network.peer_id(),
+            node_id,
+        ))
+    }
+
+    /// 获取带时间戳的统计快照
+    pub async fn stats_snapshot(&self) -> StatsSnapshot {
+        let mut stats = self.stats.read().await.clone();
+        stats.connection_count = self.nodes.read().await.len();
+
+        StatsSnapshot {
+            stats,
+            taken_at: Instant::now(),
+        }
+    }
+
+    /// 记录一次接收
+    async fn record_received(&self, bytes: u64) {
+        let mut stats = self.stats.write().await;
+        stats.messages_received += 1;
+        stats.bytes_received += bytes;
+    }
+
+    /// 记录一次成功发送
+    async fn record_sent(&self, bytes: u64) {
+        let mut stats = self.stats.write().await;
+        stats.messages_sent += 1;
+        stats.bytes_sent += bytes;
+    }
+
+    /// 序列化出站消息并检查大小限制，启用统计时按消息类型记录大小和耗时
+    async fn encode_message(&self, message: &NetworkMessage) -> Result<Vec<u8>> {
+        let (limits, record_stats) = self
+            .config
+            .read()
+            .await
+            .as_ref()
+            .map(|config| (config.message_limits, config.record_serialization_stats))
+            .unwrap_or_default();
+        limits.check_metadata(message)?;
+
+        let started = Instant::now();
+        let message_bytes = serde_json::to_vec(message)
+            .map_err(|e| crate::NetworkError::send_error(format!("序列化消息失败: {}", e)))?;
+        let elapsed = started.elapsed();
+        limits.check_encoded_len(message_bytes.len())?;
+
+        if record_stats {
+            self.stats
+                .write()
+                .await
+                .serialization
+                .entry(message.message_type.clone())
+                .or_default()
+                .record(message_bytes.len() as u64, elapsed);
+        }
+        Ok(message_bytes)
+    }
+
+    /// 记录一次发送错误
+    async fn record_error(&self) {
+        self.stats.write().await.error_count += 1;
+    }
+
+    /// 记录一次被拦截的回环消息
+    async fn record_loop_prevented(&self) {
+        self.stats.write().await.loop_prevented += 1;
+    }
+
+    /// 拆除网络并清理本地状态，由 `stop` 保证只执行一次
+    async fn teardown(&self) -> ShutdownReport {
+        if !*self.is_running.read().await {
+            return ShutdownReport::default();
+        }
+
+        // 先停止入站处理；宽限期内运行中的处理器仍可通过网络回复，因此此时不持有运行状态锁
+        let grace_ms = self.config.read().await.as_ref().map_or_else(
+            || NetworkServiceConfig::default().shutdown_grace_ms,
+            |config| config.shutdown_grace_ms,
+        );
+        let mut report = self.inbound.shutdown(Duration::from_millis(grace_ms)).await;
+
+        let mut is_running = self.is_running.write().await;
+
+        // 从全局节点表中移除自己
+        if let Some(local_id) = self.local_node_id.read().await.as_ref() {
+            let mut global_nodes = GLOBAL_NODES.write().await;
+            global_nodes.remove(local_id);
+            info!("节点 {} 已从网络中移除", local_id);
+        }
+        self.nodes.write().await.clear();
+
+        if let Some(handle) = self.auth_watcher.lock().await.take() {
+            handle.abort();
+        }
+        if let Some(handle) = self.stale_sweeper.lock().await.take() {
+            handle.abort();
+        }
+        if let Some(network) = self.network.write().await.take() {
+            report.peers_disconnected = network.peers().len();
+            if let Err(e) = network.shutdown().await {
+                warn!("关闭网络失败: {}", e);
+            }
+        }
+        self.peer_activity.write().await.clear();
+        self.connected_at.write().await.clear();
+        self.dialed_addrs.write().await.clear();
+        self.probe_peers.write().await.clear();
+        self.persistent_peers.write().await.clear();
+        self.disconnecting.write().await.clear();
+
+        // 清理本地状态
+        *self.local_node_id.write().await = None;
+        *is_running = false;
+        drop(is_running);
+
+        self.event_bus
+            .publish(crate::NetworkEvent::ServiceStopped)
+            .await;
+        info!("网络服务已停止，断开 {} 个连接", report.peers_disconnected);
+        report
+    }
+
+    /// 添加已知的服务器地址，支持 `IP:端口` 和 `主机名:端口`，主机名在连接时解析
+    pub async fn add_known_server(&self, server_addr: String) {
+        let mut servers = self.known_servers.write().await;
+        if !servers.contains(&server_addr) {
+            servers.push(server_addr.clone());
+            info!("添加已知服务器: {}", server_addr);
+        }
+    }
+
+    /// 计算发送超时：消息选项优先，否则使用配置中的默认值
+    async fn resolve_send_timeout_ms(&self, override_ms: Option<u64>) -> u64 {
+        match override_ms {
+            Some(timeout_ms) => timeout_ms,
+            None => self
+                .config
+                .read()
+                .await
+                .as_ref()
+                .map(|config| config.send_timeout_ms)
+                .unwrap_or_else(|| NetworkServiceConfig::default().send_timeout_ms),
+        }
+    }
+
+    /// 在超时限制内执行一次RPC调用
+    async fn rpc_with_timeout<F, T, E>(rpc: F, timeout_ms: u64) -> Result<T>
+    where
+        F: Future<Output = std::result::Result<T, E>>,
+        E: std::fmt::Display,
+    {
+        match tokio::time::timeout(Duration::from_millis(timeout_ms), rpc).await {
+            Ok(Ok(response)) => Ok(response),
+            Ok(Err(e)) => Err(crate::NetworkError::send_error(format!(
+                "RPC调用失败: {}",
+                e
+            ))),
+            Err(_) => Err(crate::NetworkError::RpcTimeout(timeout_ms)),
+        }
+    }
+
+    /// 将本节点发给自己的消息直接交给注册的处理器
+    ///
+    /// 本地投递不是入站流量，不经过入站处理流程（其回环检测会丢弃本节点发出的消息）。
+    async fn deliver_to_self(&self, message: NetworkMessage) -> Result<Option<NetworkMessage>> {
+        let local_id = self.get_local_node_id().await?;
+        match self.inbound.get_handler_for(&message).await {
+            Some(handler) => {
+                handler
+                    .handle_message(&self.handler_context(), local_id, message)
+                    .await
+            }
+            None => {
+                warn!("本节点未注册消息类型 {:?} 的处理器", message.message_type);
+                Ok(None)
+            }
+        }
+    }
+
+    /// 将PeerId转换为NodeId（PeerId的十六进制形式）
+    fn peer_id_to_node_id(peer_id: PeerId) -> NodeId {
+        use std::fmt::Write;
+        peer_id
+            .0
+            .iter()
+            .fold(String::with_capacity(64), |mut node_id, byte| {
+                let _ = write!(node_id, "{:02x}", byte);
+                node_id
+            })
+    }
+
+    /// 解析 [`Self::peer_id_to_node_id`] 生成的节点ID
+    fn parse_peer_id(node_id: &str) -> Option<PeerId> {
+        if node_id.len() != 64 || !node_id.is_ascii() {
+            return None;
+        }
+        let mut bytes = [0u8; 32];
+        for (i, byte) in bytes.iter_mut().enumerate() {
+            *byte = u8::from_str_radix(&node_id[i * 2..i * 2 + 2], 16).ok()?;
+        }
+        Some(PeerId(bytes))
+    }
+
+    /// 在节点表中查找PeerId对应的NodeId
+    async fn known_node_id(&self, peer_id: PeerId) -> Option<NodeId> {
+        self.nodes
+            .read()
+            .await
+            .iter()
+            .find(|(_, known)| **known == peer_id)
+            .map(|(node_id, _)| node_id.clone())
+    }
+
+    /// 在节点表中查找PeerId对应的NodeId，找不到时使用PeerId本身
+    async fn peer_id_to_known_node_id(&self, peer_id: PeerId) -> NodeId {
+        self.known_node_id(peer_id)
+            .await
+            .unwrap_or_else(|| Self::peer_id_to_node_id(peer_id))
+    }
+
+    /// 在全局节点表中登记显式指定的节点ID，已被其他节点使用时返回错误
+    async fn claim_node_id(node_id: &NodeId, peer_id: PeerId) -> Result<()> {
+        let mut global_nodes = GLOBAL_NODES.write().await;
+        match global_nodes.get(node_id) {
+            Some(existing) if *existing != peer_id => Err(crate::NetworkError::config_error(
+                format!("节点ID {} 已被节点 {} 使用", node_id, existing),
+            )),
+            _ => {
+                global_nodes.insert(node_id.clone(), peer_id);
+                Ok(())
+            }
+        }
+    }
+
+    /// 将NodeId转换为PeerId：先查本实例的节点表，再按PeerId形式的节点ID解析
+    async fn node_id_to_peer_id(&self, node_id: &NodeId) -> Result<PeerId> {
+        if let Some(peer_id) = self.nodes.read().await.get(node_id) {
+            return Ok(*peer_id);
+        }
+        if let Some(peer_id) = Self::parse_peer_id(node_id) {
+            let connected = self
+                .network
+                .read()
+                .await
+                .as_ref()
+                .is_some_and(|network| network.peer(peer_id).is_some());
+            if connected {
+                return Ok(peer_id);
+            }
+        }
+
+        Err(crate::NetworkError::node_not_found(node_id.clone()))
+    }
+
+    /// 连接到指定地址，失败时返回归类后的诊断信息
+    pub async fn connect(&self, addr: SocketAddr) -> std::result::Result<PeerId, ConnectError> {
+        let network = self
+            .network
+            .read()
+            .await
+            .clone()
+            .ok_or_else(|| ConnectError::Other("网络服务未启动".to_string()))?;
+
+        // 对端可能在连接返回前就查询节点信息，先登记地址
+        self.dialed_addrs.write().await.insert(addr);
+        let peer_id = network
+            .connect(addr)
+            .await
+            .map_err(|e| ConnectError::classify(&e))?;
+        // 连接返回后即可按对端公布的节点ID寻址
+        let node_id = self.identify_peer(&network, peer_id).await;
+        self.address_book.write().await.insert(addr, node_id);
+        Ok(peer_id)
+    }
+
+    /// 按退避策略重连指定地址，每次尝试发布 `NodeReconnecting` 事件
+    ///
+    /// 事件中的节点ID是之前在该地址上连接到的节点；从未连接过的地址没有节点ID，使用地址本身。
+    /// 多个重连循环共享同一个 `limiter` 时，同时进行的连接尝试不超过其上限。
+    pub async fn reconnect(
         &self,
-        message_type: MessageType,
-        handler: Box<dyn MessageHandler>,
+        addr: SocketAddr,
+        backoff: &BackoffConfig,
+        limiter: Option<&ReconnectLimiter>,
+        max_attempts: Option<u32>,
+    ) -> std::result::Result<PeerId, ConnectError> {
+        let node_id = self
+            .address_book
+            .read()
+            .await
+            .get(&addr)
+            .cloned()
+            .unwrap_or_else(|| addr.to_string());
+        let node_id = &node_id;
+        retry_with_backoff(backoff, limiter, max_attempts, |attempt| async move {
+            self.event_bus
+                .publish(crate::NetworkEvent::NodeReconnecting {
+                    node_id: node_id.clone(),
+                    attempt,
+                })
+                .await;
+            self.connect(addr).await
+        })
+        .await
+    }
+
+    /// 通过RPC向指定Peer发送消息，并记录统计
+    ///
+    /// 对端在同一RPC上返回的响应消息会被解码返回，没有响应时为 `None`。
+    async fn send_to_peer(
+        &self,
+        network: &Network,
+        peer_id: PeerId,
+        message: &NetworkMessage,
+        timeout_ms: u64,
+    ) -> Result<Option<NetworkMessage>> {
+        let stamped = message.clone().with_nonce(self.nonces.next_nonce());
+        let message_bytes = self.encode_message(&stamped).await?;
+        let byte_len = message_bytes.len() as u64;
+        let request = Request::new(Bytes::from(message_bytes)).with_route(NETWORK_MESSAGE_ROUTE);
+        let response = match Self::rpc_with_timeout(network.rpc(peer_id, request), timeout_ms).await
+        {
+            Ok(response) => response,
+            Err(e) => {
+                self.record_error().await;
+                return Err(e);
+            }
+        };
+        self.record_sent(byte_len).await;
+        self.record_peer_activity(peer_id, message).await;
+        Ok(Self::decode_reply(peer_id, &response.into_body()))
+    }
+
+    /// 解码RPC响应中的回复消息，空响应表示没有回复
+    fn decode_reply(peer_id: PeerId, body: &[u8]) -> Option<NetworkMessage> {
+        if body.is_empty() {
+            return None;
+        }
+        NetworkMessage::decode(body)
+            .map_err(|e| warn!("无法解码来自 {} 的响应: {}", peer_id, e))
+            .ok()
+    }
+
+    /// 将通过RPC响应收到的回复交给本节点注册的处理器
+    ///
+    /// 消息此时已经送达，处理回复失败只记录日志，不影响发送结果。
+    async fn deliver_reply(&self, from: NodeId, reply: NetworkMessage) {
+        let Some(handler) = self.inbound.get_handler_for(&reply).await else {
+            // 确认消息等没有处理器的回复只用于结束等待
+            return;
+        };
+        let reply_id = reply.id;
+        match handler
+            .handle_message(&self.handler_context(), from.clone(), reply)
+            .await
+        {
+            Ok(Some(response)) => {
+                warn!("丢弃对 {} 的回复所产生的响应 {}", from, response.id)
+            }
+            Ok(None) => {}
+            Err(e) => {
+                warn!("处理来自 {} 的回复 {} 失败: {}", from, reply_id, e);
+                self.record_error().await;
+            }
+        }
+    }
+
+    /// 观察者模式下拒绝发送
+    async fn ensure_can_send(&self) -> Result<()> {
+        if self.is_observer().await {
+            return Err(crate::NetworkError::ObserverMode("发送消息".to_string()));
+        }
+        Ok(())
+    }
+
+    /// 是否以观察者模式运行
+    async fn is_observer(&self) -> bool {
+        self.config
+            .read()
+            .await
+            .as_ref()
+            .is_some_and(|config| config.observer)
+    }
+
+    /// 按地址单播消息，无需预先注册节点ID
+    ///
+    /// 必要时先建立连接以获取对端的PeerId，再发送消息。
+    pub async fn unicast_addr(
+        &self,
+        addr: SocketAddr,
+        message: NetworkMessage,
+    ) -> Result<MessageId> {
+        let is_running = *self.is_running.read().await;
+        if !is_running {
+            return Err(crate::NetworkError::config_error("服务未启动"));
+        }
+        self.ensure_can_send().await?;
+
+        info!("单播消息到地址 {}: {:?}", addr, message.message_type);
+
+        let peer_id = self.connect(addr).await?;
+        let timeout_ms = self.resolve_send_timeout_ms(None).await;
+        let network = self
+            .network
+            .read()
+            .await
+            .clone()
+            .ok_or_else(|| crate::NetworkError::config_error("网络服务未启动"))?;
+
+        self.send_to_peer(&network, peer_id, &message, timeout_ms)
+            .await?;
+        info!("消息已发送到地址: {} ({})", addr, peer_id);
+        Ok(message.id)
+    }
+
+    /// 解析目标节点并发送单播消息
+    ///
+    /// 要求回复的消息必须得到回复：接收方处理器没有响应时会自动回复确认消息，
+    /// 确认在这里消费，不交给处理器；没有任何回复说明对端没有处理成功，按发送失败处理。
+    async fn unicast_message(
+        &self,
+        target: &NodeId,
+        message: &NetworkMessage,
+        options: Option<UnicastOptions>,
     ) -> Result<()> {
-        let mut handlers = self.message_handlers.write().await;
-        handlers.insert(message_type.clone(), handler);
-        info!("注册消息处理器: {:?}", message_type);
+        let peer_id = self.node_id_to_peer_id(target).await?;
+        let timeout_ms = self
+            .resolve_send_timeout_ms(options.and_then(|opt| opt.timeout_ms))
+            .await;
+        let network = self
+            .network
+            .read()
+            .await
+            .clone()
+            .ok_or_else(|| crate::NetworkError::config_error("网络服务未启动"))?;
+
+        let reply = self
+            .send_to_peer(&network, peer_id, message, timeout_ms)
+            .await?;
+        info!("消息已发送到节点: {}", target);
+
+        if !message.response_required {
+            return Ok(());
+        }
+        // 回复随请求所在的连接返回，无需对端重新连接本节点
+        match reply {
+            Some(reply) if reply.is_ack_for(message.id) => {
+                debug!("节点 {} 已确认消息 {}", target, message.id);
+            }
+            Some(reply) => self.deliver_reply(target.clone(), reply).await,
+            None => {
+                return Err(crate::NetworkError::receive_error(format!(
+                    "节点 {} 没有确认消息 {}",
+                    target, message.id
+                )))
+            }
+        }
         Ok(())
     }
 
-    async fn register_event_handler(&self, _handler: Box<dyn EventHandler>) -> Result<()> {
-        // 暂时不实现事件处理器
+    /// 运行时轮换节点私钥
+    ///
+    /// 使用新私钥在原地址上重新绑定网络，并重新连接已知服务器。
+    /// 新私钥会产生新的 `PeerId`，现有连接全部断开，对端需要重新建立连接。
+    /// 已设置的连接认证回调在重新绑定后继续生效。
+    /// 使用新私钥启动失败时恢复原私钥并返回错误。
+    pub async fn rotate_key(&self, new_key: [u8; 32]) -> Result<()> {
+        if !*self.is_running.read().await {
+            return Err(crate::NetworkError::config_error("服务未启动"));
+        }
+        let mut config = self
+            .config
+            .read()
+            .await
+            .clone()
+            .ok_or_else(|| crate::NetworkError::config_error("缺少启动配置"))?;
+
+        // 绑定端口为0时沿用当前实际地址，保持对外地址不变
+        if let Some(network) = self.network.read().await.as_ref() {
+            config.bind_address = network.local_addr();
+        }
+        // 启动时已把私钥保存为固定私钥
+        let previous = config.clone();
+        config.private_key = KeySource::Fixed(new_key);
+
+        warn!("轮换节点私钥，现有连接将全部断开");
+        self.stop().await?;
+        if let Err(e) = self.restart(config).await {
+            error!("使用新私钥启动失败，恢复原私钥: {}", e);
+            self.restart(previous).await.map_err(|rollback| {
+                crate::NetworkError::config_error(format!(
+                    "使用新私钥启动失败（{}），恢复原私钥也失败: {}",
+                    e, rollback
+                ))
+            })?;
+            self.connect_to_known_servers().await;
+            return Err(e);
+        }
+        self.connect_to_known_servers().await;
+
+        if let Some(network) = self.network.read().await.as_ref() {
+            info!("私钥轮换完成，新的PeerId: {}", network.peer_id());
+        }
         Ok(())
     }
+
+    /// 停止后使用给定配置重新启动，并确认显式节点ID仍归本节点所有
+    async fn restart(&self, config: NetworkServiceConfig) -> Result<()> {
+        let node_id = config.node_id.clone();
+        self.start(config).await?;
+        let Some(node_id) = node_id else {
+            return Ok(());
+        };
+
+        let peer_id = self.network.read().await.as_ref().map(|n| n.peer_id());
+        let owner = GLOBAL_NODES.read().await.get(&node_id).copied();
+        if owner.is_some() && owner == peer_id {
+            return Ok(());
+        }
+        self.stop().await?;
+        Err(crate::NetworkError::config_error(format!(
+            "重启后节点ID {} 已不属于本节点",
+            node_id
+        )))
+    }
+
+    /// 连接到已知的服务器（延迟执行）
+    pub async fn connect_to_known_servers_delayed(&self) {
+        // 等待一段时间让网络服务完全启动
+        tokio::time::sleep(tokio::time::Duration::from_secs(1)).await;
+        self.connect_to_known_servers().await;
+    }
+
+    /// 连接到已知的服务器
+    pub async fn connect_to_known_servers(&self) {
+        if self.network.read().await.is_none() {
+            return;
+        }
+
+        let servers = self.known_servers.read().await.clone();
+
+        if servers.is_empty() {
+            info!("没有已知的服务器地址，跳过连接");
+            return;
+        }
+
+        let limit = self
+            .config
+            .read()
+            .await
+            .as_ref()
+            .map(|config| config.max_concurrent_connects)
+            .unwrap_or_else(|| NetworkServiceConfig::default().max_concurrent_connects);
+
+        let connected = Self::connect_concurrently(servers, limit, |addr| self.connect(addr)).await;
+        let any_connected = !connected.is_empty();
+
+        for (server_addr, peer_id) in connected {
+            info!("成功连接到服务器: {} -> {}", server_addr, peer_id);
+            self.persistent_peers.write().await.insert(peer_id);
+        }
+
+        if any_connected {
+            self.replay_wal().await;
+        }
+    }
+
+    /// 探测所有已知服务器是否可达，不保留连接，也不加入任何聊天室
+    ///
+    /// 对每个服务器依次连接、Ping、断开；探测前已经建立的连接保持不变。
+    /// 结果顺序与已知服务器列表一致。
+    pub async fn probe_known_servers(&self, timeout: Duration) -> Vec<(String, ProbeResult)> {
+        let servers = self.known_servers.read().await.clone();
+        let limit = self
+            .config
+            .read()
+            .await
+            .as_ref()
+            .map(|config| config.max_concurrent_connects)
+            .unwrap_or_else(|| NetworkServiceConfig::default().max_concurrent_connects);
+
+        Self::probe_concurrently(servers, limit, timeout, |addr, remaining| {
+            self.probe_server(addr, remaining)
+        })
+        .await
+    }
+
+    /// 在 `timeout` 内连接指定地址并发送Ping，探测建立的连接在返回前断开
+    ///
+    /// 超时在内部处理而不是由调用方取消，保证无论成功、失败还是超时，探测连接都会被断开。
+    /// 探测直接使用底层连接，不登记到节点表，也不发布节点连接和断开事件。
+    async fn probe_server(
+        &self,
+        addr: SocketAddr,
+        timeout: Duration,
+    ) -> std::result::Result<(), ConnectError> {
+        let deadline = Instant::now() + timeout;
+        let network = self
+            .network
+            .read()
+            .await
+            .clone()
+            .ok_or_else(|| ConnectError::Other("网络服务未启动".to_string()))?;
+        let existing: HashSet<PeerId> = network.peers().into_iter().collect();
+
+        // 在连接前登记地址，连接事件任务据此跳过探测连接
+        self.probing_addrs.write().await.insert(addr);
+        let connected = tokio::time::timeout(timeout, network.connect(addr)).await;
+        self.probing_addrs.write().await.remove(&addr);
+        let peer_id = match connected {
+            Ok(Ok(peer_id)) => peer_id,
+            Ok(Err(e)) => return Err(ConnectError::classify(&e)),
+            Err(_) => {
+                // 超时时连接可能已经建立
+                for peer_id in network.peers() {
+                    let at_addr = network.peer(peer_id).map(|peer| peer.address()) == Some(addr);
+                    if at_addr && !existing.contains(&peer_id) {
+                        self.probe_peers.write().await.insert(peer_id);
+                        let _ = network.disconnect(peer_id);
+                    }
+                }
+                return Err(ConnectError::Timeout(format!("连接 {} 超时", addr)));
+            }
+        };
+        if !existing.contains(&peer_id) {
+            self.probe_peers.write().await.insert(peer_id);
+        }
+        let result = async {
+            let local_id = self
+                .local_node_id
+                .read()
+                .await
+                .clone()
+                .unwrap_or_else(|| Self::peer_id_to_node_id(network.peer_id()));
+            let ping = SystemMessageType::Ping.to_network_message(local_id)?;
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            let timeout_ms = (remaining.as_millis().min(u64::MAX as u128) as u64).max(1);
+            self.send_to_peer(&network, peer_id, &ping, timeout_ms)
+                .await?
+                .ok_or_else(|| crate::NetworkError::receive_error("对端没有回应Ping"))
+        }
+        .await;
+
+        if !existing.contains(&peer_id) {
+            if let Err(e) = network.disconnect(peer_id) {
+                warn!("断开探测连接 {} 失败: {}", addr, e);
+            }
+        }
+        result.map(|_| ()).map_err(|e| match e {
+            crate::NetworkError::RpcTimeout(_) => ConnectError::Timeout(e.to_string()),
+            e => ConnectError::Other(e.to_string()),
+        })
+    }
+
+    /// 并发探测多个服务器，同时进行的探测不超过 `limit`，结果保持输入顺序
+    ///
+    /// 每个服务器的探测总时长不超过 `timeout`；`probe` 收到剩余时间并自行在其内完成，
+    /// 以便超时后仍能清理探测连接。
+    async fn probe_concurrently<F, Fut>(
+        servers: Vec<String>,
+        limit: usize,
+        timeout: Duration,
+        probe: F,
+    ) -> Vec<(String, ProbeResult)>
+    where
+        F: Fn(SocketAddr, Duration) -> Fut,
+        Fut: Future<Output = std::result::Result<(), ConnectError>>,
+    {
+        let probe = &probe;
+        stream::iter(servers)
+            .map(|server_addr| async move {
+                let deadline = Instant::now() + timeout;
+                let addrs = match Self::resolve_server_addr(&server_addr).await {
+                    Ok(addrs) => addrs,
+                    Err(e) => {
+                        let result = ProbeResult::Failed(format!("无效的地址: {}", e));
+                        return (server_addr, result);
+                    }
+                };
+                // 往返时间不包括DNS解析的耗时
+                let started = Instant::now();
+                // 依次尝试解析到的每个地址，任一地址可达即视为可达
+                let mut last_error = None;
+                for addr in addrs {
+                    let remaining = deadline.saturating_duration_since(Instant::now());
+                    if remaining.is_zero() {
+                        last_error = Some(ConnectError::Timeout(format!("探测 {} 超时", addr)));
+                        break;
+                    }
+                    match probe(addr, remaining).await {
+                        Ok(()) => {
+                            last_error = None;
+                            break;
+                        }
+                        Err(e) => last_error = Some(e),
+                    }
+                }
+                let result = match last_error {
+                    None => ProbeResult::Reachable {
+                        rtt: started.elapsed(),
+                    },
+                    Some(e) => {
+                        warn!("探测服务器 {} 失败: {}", server_addr, e);
+                        e.into()
+                    }
+                };
+                (server_addr, result)
+            })
+            .buffered(limit.max(1))
+            .collect()
+            .await
+    }
+
+    /// 并发连接多个服务器，同时进行的连接数不超过 `limit`，返回成功的地址和PeerId
+    async fn connect_concurrently<F, Fut>(
+        servers: Vec<String>,
+        limit: usize,
+        connect: F,
+    ) -> Vec<(String, PeerId)>
+    where
+        F: Fn(SocketAddr) -> Fut,
+        Fut: Future<Output = std::result::Result<PeerId, ConnectError>>,
+    {
+        let connect = &connect;
+        stream::iter(servers)
+            .map(|server_addr| async move {
+                let addrs = match Self::resolve_server_addr(&server_addr).await {
+                    Ok(addrs) => addrs,
+                    Err(e) => {
+                        warn!("解析服务器地址 {} 失败: {}", server_addr, e);
+                        return None;
+                    }
+                };
+                info!("尝试连接到服务器: {}", server_addr);
+                // 主机名可能解析到多个地址（例如IPv6和IPv4），依次尝试直到连接成功
+                for addr in addrs {
+                    match connect(addr).await {
+                        Ok(peer_id) => return Some((server_addr, peer_id)),
+                        Err(e) => warn!("连接到服务器 {} ({}) 失败: {}", server_addr, addr, e),
+                    }
+                }
+                None
+            })
+            .buffer_unordered(limit.max(1))
+            .filter_map(|result| async move { result })
+            .collect()
+            .await
+    }
+
+    /// 解析服务器地址，`IP:端口` 直接使用，`主机名:端口` 通过DNS解析
+    async fn resolve_server_addr(server_addr: &str) -> std::io::Result<Vec<SocketAddr>> {
+        let addrs: Vec<SocketAddr> = tokio::net::lookup_host(server_addr).await?.collect();
+        if addrs.is_empty() {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::NotFound,
+                format!("{} 没有解析到任何地址", server_addr),
+            ));
+        }
+        Ok(addrs)
+    }
+
+    /// 创建并启动网络服务，返回持有服务的句柄
+    pub async fn spawn(config: NetworkServiceConfig) -> Result<ServiceHandle> {
+        let service = Self::new();
+        service.start(config).await?;
+        Ok(ServiceHandle { service })
+    }
+}
+
+/// 本服务挂载的RPC服务：[`NETWORK_MESSAGE_ROUTE`] 把收到的消息交给注册的处理器，
+/// [`NODE_INFO_ROUTE`] 返回本节点的节点信息
+#[derive(Clone)]
+struct InboundRpc {
+    service: AnemoNetworkService,
+    route: &'static str,
+}
+
+impl Service<Request<Bytes>> for InboundRpc {
+    type Response = Response<Bytes>;
+    type Error = Infallible;
+    type Future = BoxFuture<'static, std::result::Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<std::result::Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, request: Request<Bytes>) -> Self::Future {
+        let service = self.service.clone();
+        let route = self.route;
+        Box::pin(async move {
+            if route == NODE_INFO_ROUTE {
+                let requester = request.peer_id().copied();
+                let info =
+                    serde_json::to_vec(&service.node_info(requester).await).unwrap_or_default();
+                return Ok(Response::new(Bytes::from(info)));
+            }
+            let Some(peer_id) = request.peer_id().copied() else {
+                warn!("丢弃缺少对端身份的RPC请求");
+                return Ok(Response::new(Bytes::new()));
+            };
+            let reply = service.handle_inbound_rpc(peer_id, request.body()).await;
+            Ok(Response::new(reply))
+        })
+    }
+}
+
+/// 运行中的网络服务句柄，由 [`AnemoNetworkService::spawn`] 返回
+///
+/// 通过解引用直接使用服务的全部方法，`shutdown` 停止服务。
+pub struct ServiceHandle {
+    service: AnemoNetworkService,
+}
+
+impl ServiceHandle {
+    /// 获取服务的克隆句柄，例如交给聊天或授时模块
+    pub fn service(&self) -> AnemoNetworkService {
+        self.service.clone()
+    }
+
+    /// 停止服务
+    pub async fn shutdown(self) -> Result<ShutdownReport> {
+        self.service.stop().await
+    }
+}
+
+impl std::ops::Deref for ServiceHandle {
+    type Target = AnemoNetworkService;
+
+    fn deref(&self) -> &Self::Target {
+        &self.service
+    }
+}
+
+#[async_trait]
+impl NetworkServiceTrait for AnemoNetworkService {
+    async fn start(&self, mut config: NetworkServiceConfig) -> Result<()> {
+        let mut is_running = self.is_running.write().await;
+        if *is_running {
+            return Err(crate::NetworkError::config_error("服务已启动"));
+        }
+
+        config.validate()?;
+        // 启动前已订阅事件时沿用原容量
+        self.event_bus.set_capacity(config.event_bus_capacity);
+        if config.observer && self.inbound.has_handlers().await {
+            return Err(crate::NetworkError::ObserverMode(
+                "启动前注册的消息处理器".to_string(),
+            ));
+        }
+
+        // 同名服务会生成难以区分的节点ID
+        let name_prefix = format!("{}:", config.server_name);
+        if GLOBAL_NODES
+            .read()
+            .await
+            .keys()
+            .any(|node_id| node_id.starts_with(&name_prefix))
+        {
+            warn!("已有同名服务在运行: {}", config.server_name);
+        }
+
+        // 解析私钥；保存为固定私钥，重启和轮换时沿用同一身份
+        let private_key = config.private_key.resolve()?;
+        config.private_key = KeySource::Fixed(private_key);
+
+        // 创建路由器
+        let router = self.build_router()?;
+
+        // 启动网络服务
+        let network = Network::bind(config.bind_address)
+            .server_name(config.server_name.clone())
+            .private_key(private_key)
+            .start(router)
+            .map_err(|e| crate::NetworkError::connection_error(format!("启动网络失败: {}", e)))?;
+
+        info!("网络服务启动在地址: {}", network.local_addr());
+
+        // 使用显式指定的节点ID，否则基于地址和服务名生成
+        let local_id = match &config.node_id {
+            Some(node_id) => {
+                if let Err(e) = Self::claim_node_id(node_id, network.peer_id()).await {
+                    if let Err(shutdown_error) = network.shutdown().await {
+                        warn!("关闭网络失败: {}", shutdown_error);
+                    }
+                    return Err(e);
+                }
+                node_id.clone()
+            }
+            None => {
+                let local_id = format!("{}:{}", config.server_name, network.local_addr());
+                GLOBAL_NODES
+                    .write()
+                    .await
+                    .insert(local_id.clone(), network.peer_id());
+                local_id
+            }
+        };
+
+        self.spawn_auth_watcher(network.clone()).await;
+        // 启用空闲断开时，清理间隔不超过空闲超时的一半
+        let sweep_interval_ms = config
+            .idle_connection_timeout_ms
+            .map_or(config.heartbeat_interval_ms, |idle_ms| {
+                config.heartbeat_interval_ms.min(idle_ms / 2)
+            });
+        self.spawn_stale_sweeper(network.clone(), sweep_interval_ms)
+            .await;
+
+        // 注册默认系统消息处理器（已注册的处理器会作为其后备）；
+        // 观察者不回复任何消息，因此不注册
+        self.inbound.set_config(config.clone()).await;
+        if !config.observer {
+            self.inbound
+                .register_default_handler(
+                    MessageType::system(),
+                    Arc::new(SystemMessageHandler::new(self.event_bus.clone(), None)),
+                )
+                .await;
+        }
+
+        // 存储本地信息
+        *self.local_node_id.write().await = Some(local_id.clone());
+        *self.network.write().await = Some(network);
+        *self.config.write().await = Some(config);
+        *is_running = true;
+        drop(is_running);
+
+        info!("网络服务启动完成，节点ID: {}", local_id);
+        Ok(())
+    }
+
+    async fn stop(&self) -> Result<ShutdownReport> {
+        // 第一个调用者发起停止，其余调用者等待同一次拆除完成并得到同一份报告
+        let shutdown = {
+            let mut stopping = self.stopping.lock().await;
+            match stopping.as_ref() {
+                Some(shutdown) => shutdown.clone(),
+                None => {
+                    if !*self.is_running.read().await {
+                        return Ok(ShutdownReport::default());
+                    }
+                    let shutdown = Arc::new(OnceCell::new());
+                    *stopping = Some(shutdown.clone());
+                    shutdown
+                }
+            }
+        };
+
+        let report = *shutdown.get_or_init(|| self.teardown()).await;
+
+        let mut stopping = self.stopping.lock().await;
+        if stopping
+            .as_ref()
+            .is_some_and(|current| Arc::ptr_eq(current, &shutdown))
+        {
+            *stopping = None;
+        }
+        Ok(report)
+    }
+
+    async fn broadcast(
+        &self,
+        message: NetworkMessage,
+        options: Option<BroadcastOptions>,
+    ) -> Result<MessageId> {
+        let is_running = *self.is_running.read().await;
+        if !is_running {
+            return Err(crate::NetworkError::config_error("服务未启动"));
+        }
+        self.ensure_can_send().await?;
+
+        let exclude_nodes = options
+            .as_ref()
+            .map(|opt| opt.exclude_nodes.clone())
+            .unwrap_or_default();
+        let include_nodes = options.as_ref().and_then(|opt| opt.include_nodes.clone());
+        let include_self = options.as_ref().is_some_and(|opt| opt.include_self);
+        let require_recipients = options.as_ref().is_some_and(|opt| opt.require_recipients);
+        let timeout_ms = self
+            .resolve_send_timeout_ms(options.as_ref().and_then(|opt| opt.timeout_ms))
+            .await;
+
+        info!("广播消息: {:?}", message.message_type);
+
+        let mut sent_count = 0;
+        // 克隆出网络句柄，逐个节点发送期间不持有读锁，`stop` 不会被慢节点阻塞
+        let network = self.network.read().await.clone();
+
+        // 确定接收节点
+        let mut targets = Vec::new();
+        if network.is_some() {
+            let nodes = self.nodes.read().await;
+            let local_id = self.local_node_id.read().await;
+
+            for (node_id, peer_id) in nodes.iter() {
+                // 跳过排除的节点
+                if exclude_nodes.contains(node_id) {
+                    continue;
+                }
+
+                // 跳过不在白名单中的节点
+                if let Some(ref include) = include_nodes {
+                    if !include.contains(node_id) {
+                        continue;
+                    }
+                }
+
+                // 跳过自己
+                if let Some(ref local) = *local_id {
+                    if node_id == local {
+                        continue;
+                    }
+                }
+
+                // 本节点以其他名称出现在节点表中时，发出的消息会回到自己
+                if network
+                    .as_ref()
+                    .is_some_and(|network| *peer_id == network.peer_id())
+                {
+                    warn!("节点 {} 指向本节点，跳过以避免消息回环", node_id);
+                    self.record_loop_prevented().await;
+                    continue;
+                }
+
+                targets.push((node_id.clone(), *peer_id));
+            }
+        }
+
+        if require_recipients && targets.is_empty() && !include_self {
+            return Err(crate::NetworkError::NoRecipients);
+        }
+
+        if let Some(network) = network.as_ref().filter(|_| !targets.is_empty()) {
+            // 只序列化一次，发送给每个节点时共享同一份数据
+            let stamped = message.clone().with_nonce(self.nonces.next_nonce());
+            let message_bytes = Bytes::from(self.encode_message(&stamped).await?);
+            let byte_len = message_bytes.len() as u64;
+            let mut failed = Vec::new();
+            for (node_id, peer_id) in targets {
+                // 使用Anemo RPC发送消息
+                let request = Request::new(message_bytes.clone()).with_route(NETWORK_MESSAGE_ROUTE);
+                match Self::rpc_with_timeout(network.rpc(peer_id, request), timeout_ms).await {
+                    Ok(_) => {
+                        sent_count += 1;
+                        self.record_sent(byte_len).await;
+                        self.record_peer_activity(peer_id, &message).await;
+                    }
+                    Err(e) => {
+                        warn!("发送消息到节点 {} 失败: {}", node_id, e);
+                        self.record_error().await;
+                        self.dead_letters
+                            .push(DeadLetter::new(
+                                node_id.clone(),
+                                message.clone(),
+                                e.to_string(),
+                            ))
+                            .await;
+                        failed.push((node_id, peer_id));
+                    }
+                }
+            }
+
+            // 发送失败且已断开连接的节点是节点表中的过期条目
+            self.prune_unreachable(failed, |peer_id| network.peer(peer_id).is_some())
+                .await;
+        }
+
+        if include_self {
+            if let Err(e) = self.deliver_to_self(message.clone()).await {
+                warn!("本节点处理广播消息失败: {}", e);
+            }
+        }
+
+        info!("广播完成，成功发送到 {} 个节点", sent_count);
+        Ok(message.id)
+    }
+
+    async fn unicast(
+        &self,
+        target: NodeId,
+        message: NetworkMessage,
+        options: Option<UnicastOptions>,
+    ) -> Result<MessageId> {
+        let is_running = *self.is_running.read().await;
+        if !is_running {
+            return Err(crate::NetworkError::config_error("服务未启动"));
+        }
+        self.ensure_can_send().await?;
+
+        info!("单播消息到 {}: {:?}", target, message.message_type);
+
+        // 拒绝把本节点发出的消息再发回本节点
+        let is_self_target = self
+            .local_node_id
+            .read()
+            .await
+            .as_ref()
+            .is_some_and(|local| *local == target && message.sender == *local);
+        if is_self_target {
+            warn!("拒绝发送回环消息 {} 到本节点", message.id);
+            self.record_loop_prevented().await;
+            return Err(crate::NetworkError::send_error(
+                "不能将本节点的消息发回本节点",
+            ));
+        }
+
+        let mut message = message;
+        if options.as_ref().is_some_and(|opt| opt.wait_for_response) {
+            message.response_required = true;
+        }
+
+        // 发送前写入预写日志，写入失败时不发送，避免出现无法恢复的消息
+        if let Some(wal) = &self.wal {
+            wal.append(target.clone(), message.clone())
+                .await
+                .map_err(|e| crate::NetworkError::send_error(format!("写入预写日志失败: {}", e)))?;
+        }
+
+        // 发送失败的消息移入死信队列，不再留在预写日志中
+        let result = self.unicast_message(&target, &message, options).await;
+        let message_id = message.id;
+        if let Err(e) = &result {
+            self.dead_letters
+                .push(DeadLetter::new(target, message, e.to_string()))
+                .await;
+        }
+        self.ack_wal(message_id).await;
+        result.map(|()| message_id)
+    }
+
+    fn subscribe_events(&self) -> Option<broadcast::Receiver<crate::NetworkEvent>> {
+        Some(self.event_bus.subscribe())
+    }
+
+    async fn get_local_node_id(&self) -> Result<NodeId> {
+        let local_id = self.local_node_id.read().await;
+        local_id
+            .clone()
+            .ok_or_else(|| crate::NetworkError::config_error("服务未启动"))
+    }
+
+    async fn get_connected_nodes(&self) -> Result<Vec<NodeId>> {
+        let is_running = *self.is_running.read().await;
+        if !is_running {
+            return Err(crate::NetworkError::config_error("服务未启动"));
+        }
+
+        let local_id = self.local_node_id.read().await.clone();
+        let mut nodes: Vec<(NodeId, PeerId)> = self
+            .nodes
+            .read()
+            .await
+            .iter()
+            // 排除自己
+            .filter(|(node_id, _)| local_id.as_ref() != Some(*node_id))
+            .map(|(node_id, peer_id)| (node_id.clone(), *peer_id))
+            .collect();
+
+        // 节点表是 HashMap，排序保证多次调用的顺序一致
+        let order = self
+            .config
+            .read()
+            .await
+            .as_ref()
+            .map(|config| config.connected_nodes_order)
+            .unwrap_or_default();
+        match order {
+            NodeOrder::NodeId => nodes.sort_by(|a, b| a.0.cmp(&b.0)),
+            NodeOrder::ConnectedAt => {
+                let connected_at = self.connected_at.read().await;
+                nodes.sort_by_cached_key(|(node_id, peer_id)| {
+                    let at = connected_at.get(peer_id).copied();
+                    (at.is_none(), at, node_id.clone())
+                });
+            }
+        }
+
+        let connected_nodes: Vec<NodeId> = nodes.into_iter().map(|(node_id, _)| node_id).collect();
+
+        info!("当前连接的节点数: {}", connected_nodes.len());
+        Ok(connected_nodes)
+    }
+
+    async fn register_message_handler(
+        &self,
+        message_type: MessageType,
+        handler: Box<dyn MessageHandler>,
+    ) -> Result<()> {
+        let handler: Arc<dyn MessageHandler> = if message_type == MessageType::system() {
+            // 系统消息始终先由默认处理器处理，注册的处理器作为后备
+            Arc::new(SystemMessageHandler::new(
+                self.event_bus.clone(),
+                Some(Arc::from(handler)),
+            ))
+        } else {
+            Arc::from(handler)
+        };
+        self.inbound
+            .register_message_handler_internal(message_type.clone(), handler)
+            .await?;
+        info!("注册消息处理器: {:?}", message_type);
+        Ok(())
+    }
+
+    async fn register_topic_handler(
+        &self,
+        message_type: MessageType,
+        topic: String,
+        handler: Box<dyn MessageHandler>,
+    ) -> Result<()> {
+        info!("注册消息处理器: {:?} (主题: {})", message_type, topic);
+        self.inbound
+            .register_topic_handler_internal(message_type, topic, Arc::from(handler))
+            .await
+    }
+
+    async fn register_event_handler(&self, _handler: Box<dyn EventHandler>) -> Result<()> {
+        // 暂时不实现事件处理器
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::{NetworkError, StatsRates};
+
+    #[test]
+    fn test_custom_router_used_on_every_start() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        let built = Arc::new(AtomicUsize::new(0));
+        let counter = built.clone();
+        let service = AnemoNetworkService::new().with_router(move || {
+            counter.fetch_add(1, Ordering::SeqCst);
+            CustomRoutes::new()
+        });
+
+        // 克隆出的句柄共享同一个路由器构造回调，重启时重新构造
+        service.clone().build_router().unwrap();
+        service.build_router().unwrap();
+        assert_eq!(built.load(Ordering::SeqCst), 2);
+
+        AnemoNetworkService::new().build_router().unwrap();
+        assert_eq!(built.load(Ordering::SeqCst), 2);
+    }
+
+    /// 原样返回请求内容的RPC服务
+    #[derive(Clone)]
+    struct EchoRpc;
+
+    impl Service<Request<Bytes>> for EchoRpc {
+        type Response = Response<Bytes>;
+        type Error = Infallible;
+        type Future = BoxFuture<'static, std::result::Result<Self::Response, Self::Error>>;
+
+        fn poll_ready(
+            &mut self,
+            _cx: &mut Context<'_>,
+        ) -> Poll<std::result::Result<(), Self::Error>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn call(&mut self, request: Request<Bytes>) -> Self::Future {
+            let body = request.body().clone();
+            Box::pin(async move { Ok(Response::new(body)) })
+        }
+    }
+
+    #[tokio::test]
+    async fn test_custom_router_serves_extra_rpc() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        let receiver = AnemoNetworkService::new()
+            .with_router(|| CustomRoutes::new().route("/test/echo", EchoRpc));
+        let sender = AnemoNetworkService::new();
+        let calls = Arc::new(AtomicUsize::new(0));
+        receiver
+            .register_message_handler(
+                MessageType::chat(),
+                Box::new(CountingHandler {
+                    calls: calls.clone(),
+                }),
+            )
+            .await
+            .unwrap();
+        connect_pair(
+            &receiver,
+            loopback_config("echo-receiver"),
+            &sender,
+            loopback_config("echo-sender"),
+        )
+        .await;
+
+        // 自定义路由和内置的消息路由挂载在同一网络上
+        let network = sender.network.read().await.clone().unwrap();
+        let receiver_peer = receiver.network.read().await.as_ref().unwrap().peer_id();
+        let request = Request::new(Bytes::from_static(b"ping")).with_route("/test/echo");
+        let response = network.rpc(receiver_peer, request).await.unwrap();
+        assert_eq!(response.body().as_ref(), b"ping");
+
+        let sender_id = sender.get_local_node_id().await.unwrap();
+        let receiver_id = receiver.get_local_node_id().await.unwrap();
+        sender
+            .unicast(receiver_id, text_message(&sender_id), None)
+            .await
+            .unwrap();
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+
+        sender.stop().await.unwrap();
+        receiver.stop().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_custom_router_cannot_take_builtin_routes() {
+        for route in [NETWORK_MESSAGE_ROUTE, NODE_INFO_ROUTE] {
+            let service = AnemoNetworkService::new()
+                .with_router(move || CustomRoutes::new().route(route, EchoRpc));
+            let result = service.start(loopback_config("router-collision")).await;
+            assert!(matches!(result, Err(NetworkError::ConfigError(_))));
+            assert!(service.effective_config().await.is_none());
+        }
+
+        // 自定义路由之间重复注册同一路径同样是配置错误
+        let service = AnemoNetworkService::new().with_router(|| {
+            CustomRoutes::new()
+                .route("/test/echo", EchoRpc)
+                .route("/test/echo", EchoRpc)
+        });
+        let result = service.start(loopback_config("router-duplicate")).await;
+        assert!(matches!(result, Err(NetworkError::ConfigError(_))));
+    }
+
+    #[tokio::test]
+    async fn test_rpc_times_out_on_stalled_peer() {
+        // 永不返回的RPC模拟一个在传输中途停滞的对端
+        let stalled = std::future::pending::<std::result::Result<(), anyhow::Error>>();
+
+        let start = Instant::now();
+        let result = AnemoNetworkService::rpc_with_timeout(stalled, 50).await;
+
+        assert!(matches!(result, Err(NetworkError::RpcTimeout(50))));
+        assert!(start.elapsed() >= Duration::from_millis(50));
+    }
+
+    #[tokio::test]
+    async fn test_send_timeout_resolution() {
+        let service = AnemoNetworkService::new();
+        let default_timeout = NetworkServiceConfig::default().send_timeout_ms;
+
+        assert_eq!(service.resolve_send_timeout_ms(None).await, default_timeout);
+        assert_eq!(service.resolve_send_timeout_ms(Some(200)).await, 200);
+
+        *service.config.write().await = Some(NetworkServiceConfig {
+            send_timeout_ms: 1500,
+            ..Default::default()
+        });
+        assert_eq!(service.resolve_send_timeout_ms(None).await, 1500);
+    }
+
+    /// 记录调用次数的处理器
+    struct CountingHandler {
+        calls: Arc<std::sync::atomic::AtomicUsize>,
+    }
+
+    #[async_trait]
+    impl MessageHandler for CountingHandler {
+        async fn handle_message(
+            &self,
+            _ctx: &HandlerContext,
+            _from: NodeId,
+            _message: NetworkMessage,
+        ) -> Result<Option<NetworkMessage>> {
+            self.calls.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+            Ok(None)
+        }
+    }
+
+    /// 记录收到的消息并回复确认的处理器
+    struct ReplyingHandler {
+        received: Arc<std::sync::Mutex<Vec<(NodeId, NetworkMessage)>>>,
+    }
+
+    #[async_trait]
+    impl MessageHandler for ReplyingHandler {
+        async fn handle_message(
+            &self,
+            _ctx: &HandlerContext,
+            from: NodeId,
+            message: NetworkMessage,
+        ) -> Result<Option<NetworkMessage>> {
+            let reply = NetworkMessage::new(
+                message.message_type.clone(),
+                "receiver".to_string(),
+                serde_json::json!({ "ack": message.id }),
+            );
+            self.received.lock().unwrap().push((from, message));
+            Ok(Some(reply))
+        }
+    }
+
+    fn text_message(sender: &str) -> NetworkMessage {
+        NetworkMessage::new(
+            MessageType::chat(),
+            sender.to_string(),
+            serde_json::json!({
+                "TextMessage": { "room_id": "general", "content": "hello" }
+            }),
+        )
+    }
+
+    #[tokio::test]
+    async fn test_inbound_frame_dispatched_and_reply_returned() {
+        let service = AnemoNetworkService::new();
+        let received = Arc::new(std::sync::Mutex::new(Vec::new()));
+        service
+            .register_message_handler(
+                MessageType::chat(),
+                Box::new(ReplyingHandler {
+                    received: received.clone(),
+                }),
+            )
+            .await
+            .unwrap();
+
+        let peer_id = PeerId([91u8; 32]);
+        let message = text_message("sender");
+        let body = service
+            .handle_inbound_rpc(peer_id, &message.to_bytes().unwrap())
+            .await;
+
+        let handled = received.lock().unwrap().clone();
+        assert_eq!(handled.len(), 1);
+        assert_eq!(
+            handled[0].0,
+            AnemoNetworkService::peer_id_to_node_id(peer_id)
+        );
+        assert_eq!(handled[0].1.id, message.id);
+        let reply = AnemoNetworkService::decode_reply(peer_id, &body).unwrap();
+        assert_eq!(reply.payload["ack"], serde_json::json!(message.id));
+        assert_eq!(service.stats.read().await.messages_received, 1);
+
+        // 同一消息再次到达时由入站处理流程去重，不再运行处理器
+        let body = service
+            .handle_inbound_rpc(peer_id, &message.to_bytes().unwrap())
+            .await;
+        assert!(body.is_empty());
+        assert_eq!(service.inbound().duplicates_dropped_count().await, 1);
+
+        // 无法解码的数据不会到达处理器，响应为空
+        assert!(service
+            .handle_inbound_rpc(peer_id, b"garbage")
+            .await
+            .is_empty());
+        assert_eq!(received.lock().unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_text_message_reaches_remote_handler() {
+        let receiver = AnemoNetworkService::new();
+        let received = Arc::new(std::sync::Mutex::new(Vec::new()));
+        receiver
+            .register_message_handler(
+                MessageType::chat(),
+                Box::new(ReplyingHandler {
+                    received: received.clone(),
+                }),
+            )
+            .await
+            .unwrap();
+        receiver
+            .start(loopback_config("rpc-receiver"))
+            .await
+            .unwrap();
+        let sender = AnemoNetworkService::new();
+        sender.start(loopback_config("rpc-sender")).await.unwrap();
+
+        let receiver_addr = receiver.effective_config().await.unwrap().local_addr;
+        sender.connect(receiver_addr).await.unwrap();
+        let receiver_id = receiver.get_local_node_id().await.unwrap();
+        let sender_id = sender.get_local_node_id().await.unwrap();
+
+        let message = text_message(&sender_id);
+        sender
+            .unicast(
+                receiver_id,
+                message.clone(),
+                Some(UnicastOptions {
+                    wait_for_response: true,
+                    ..Default::default()
+                }),
+            )
+            .await
+            .unwrap();
+
+        let received = received.lock().unwrap().clone();
+        assert_eq!(received.len(), 1);
+        assert_eq!(received[0].0, sender_id);
+        assert_eq!(received[0].1.id, message.id);
+
+        sender.stop().await.unwrap();
+        receiver.stop().await.unwrap();
+    }
+
+    /// 绑定在本机随机端口上的配置
+    fn loopback_config(name: &str) -> NetworkServiceConfig {
+        NetworkServiceConfig {
+            bind_address: "127.0.0.1:0".parse().unwrap(),
+            server_name: name.to_string(),
+            ..Default::default()
+        }
+    }
+
+    /// 启动两个服务并由 `sender` 连接 `receiver`
+    async fn connect_pair(
+        receiver: &AnemoNetworkService,
+        receiver_config: NetworkServiceConfig,
+        sender: &AnemoNetworkService,
+        sender_config: NetworkServiceConfig,
+    ) {
+        receiver.start(receiver_config).await.unwrap();
+        sender.start(sender_config).await.unwrap();
+        let receiver_addr = receiver.effective_config().await.unwrap().local_addr;
+        sender.connect(receiver_addr).await.unwrap();
+    }
+
+    /// 等待第一个满足条件的事件，超时时测试失败
+    async fn wait_for_event<F>(
+        events: &mut broadcast::Receiver<crate::NetworkEvent>,
+        mut matches: F,
+    ) -> crate::NetworkEvent
+    where
+        F: FnMut(&crate::NetworkEvent) -> bool,
+    {
+        tokio::time::timeout(Duration::from_secs(2), async {
+            loop {
+                let event = events.recv().await.unwrap();
+                if matches(&event) {
+                    break event;
+                }
+            }
+        })
+        .await
+        .expect("等待事件超时")
+    }
+
+    #[tokio::test]
+    async fn test_disconnect_node_notifies_real_peer() {
+        let receiver = AnemoNetworkService::new();
+        let mut receiver_events = receiver.subscribe_events().unwrap();
+        let sender = AnemoNetworkService::new();
+        let mut sender_events = sender.subscribe_events().unwrap();
+        connect_pair(
+            &receiver,
+            loopback_config("block-receiver"),
+            &sender,
+            loopback_config("block-sender"),
+        )
+        .await;
+        let receiver_id = receiver.get_local_node_id().await.unwrap();
+        let sender_id = sender.get_local_node_id().await.unwrap();
+        wait_for_event(&mut receiver_events, |event| {
+            matches!(event, crate::NetworkEvent::NodeConnected { node_id, .. } if *node_id == sender_id)
+        })
+        .await;
+
+        receiver
+            .disconnect_node(&sender_id, DisconnectReason::Blocked)
+            .await
+            .unwrap();
+
+        // 被断开的一方先收到原因，再收到断开事件
+        let goodbye = wait_for_event(&mut sender_events, |event| {
+            matches!(event, crate::NetworkEvent::SystemMessage { .. })
+        })
+        .await;
+        assert!(matches!(
+            goodbye,
+            crate::NetworkEvent::SystemMessage {
+                message: SystemMessageType::Goodbye {
+                    reason: DisconnectReason::Blocked
+                },
+                ..
+            }
+        ));
+        let disconnected = wait_for_event(&mut sender_events, |event| {
+            matches!(event, crate::NetworkEvent::NodeDisconnected { .. })
+        })
+        .await;
+        assert!(matches!(
+            disconnected,
+            crate::NetworkEvent::NodeDisconnected { node_id, .. } if node_id == receiver_id
+        ));
+        tokio::time::timeout(Duration::from_secs(2), async {
+            while !receiver.get_connected_nodes().await.unwrap().is_empty() {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .expect("接收方应移除被断开的节点");
+
+        sender.stop().await.unwrap();
+        receiver.stop().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_peer_sending_garbage_is_blocked_and_disconnected() {
+        let receiver = AnemoNetworkService::new();
+        let sender = AnemoNetworkService::new();
+        let mut sender_events = sender.subscribe_events().unwrap();
+        connect_pair(
+            &receiver,
+            NetworkServiceConfig {
+                on_decode_error: crate::DecodeErrorPolicy::DisconnectAfter(2),
+                ..loopback_config("garbage-receiver")
+            },
+            &sender,
+            loopback_config("garbage-sender"),
+        )
+        .await;
+        let receiver_id = receiver.get_local_node_id().await.unwrap();
+        let sender_id = sender.get_local_node_id().await.unwrap();
+
+        let network = sender.network.read().await.clone().unwrap();
+        let receiver_peer = receiver.network.read().await.as_ref().unwrap().peer_id();
+        for _ in 0..2 {
+            let request =
+                Request::new(Bytes::from_static(b"garbage")).with_route(NETWORK_MESSAGE_ROUTE);
+            network.rpc(receiver_peer, request).await.unwrap();
+        }
+        assert!(receiver.inbound().is_peer_blocked(&sender_id).await);
+
+        // 对端收到屏蔽原因后连接被真正断开
+        let goodbye = wait_for_event(&mut sender_events, |event| {
+            matches!(event, crate::NetworkEvent::SystemMessage { .. })
+        })
+        .await;
+        assert!(matches!(
+            goodbye,
+            crate::NetworkEvent::SystemMessage {
+                message: SystemMessageType::Goodbye {
+                    reason: DisconnectReason::Blocked
+                },
+                ..
+            }
+        ));
+        let disconnected = wait_for_event(&mut sender_events, |event| {
+            matches!(event, crate::NetworkEvent::NodeDisconnected { .. })
+        })
+        .await;
+        assert!(matches!(
+            disconnected,
+            crate::NetworkEvent::NodeDisconnected { node_id, .. } if node_id == receiver_id
+        ));
+        assert!(network.peer(receiver_peer).is_none());
+
+        sender.stop().await.unwrap();
+        receiver.stop().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_replay_guard_keyed_on_connected_peer() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        let receiver = AnemoNetworkService::new();
+        let sender = AnemoNetworkService::new();
+        let calls = Arc::new(AtomicUsize::new(0));
+        receiver
+            .register_message_handler(
+                MessageType::chat(),
+                Box::new(CountingHandler {
+                    calls: calls.clone(),
+                }),
+            )
+            .await
+            .unwrap();
+        let mut receiver_events = receiver.subscribe_events().unwrap();
+        connect_pair(
+            &receiver,
+            NetworkServiceConfig {
+                require_nonce: true,
+                ..loopback_config("nonce-receiver")
+            },
+            &sender,
+            loopback_config("nonce-sender"),
+        )
+        .await;
+        let receiver_id = receiver.get_local_node_id().await.unwrap();
+        let sender_id = sender.get_local_node_id().await.unwrap();
+        let chat = |from: &str| {
+            NetworkMessage::new(
+                MessageType::chat(),
+                from.to_string(),
+                serde_json::json!({"content": "hi"}),
+            )
+        };
+
+        // 正常发送的消息由发送方附带序号
+        sender
+            .unicast(receiver_id, chat(&sender_id), None)
+            .await
+            .unwrap();
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+
+        // 绕过发送流程直接发送：重用序号时改写发送者无效，未带序号的消息被拒绝
+        let network = sender.network.read().await.clone().unwrap();
+        let receiver_peer = receiver.network.read().await.as_ref().unwrap().peer_id();
+        let nonce = u64::MAX / 2;
+        for message in [
+            chat(&sender_id).with_nonce(nonce),
+            chat("someone-else").with_nonce(nonce),
+            chat(&sender_id),
+        ] {
+            let bytes = sender.encode_message(&message).await.unwrap();
+            let request = Request::new(Bytes::from(bytes)).with_route(NETWORK_MESSAGE_ROUTE);
+            network.rpc(receiver_peer, request).await.unwrap();
+        }
+
+        let replayed = wait_for_event(&mut receiver_events, |event| {
+            matches!(event, crate::NetworkEvent::Error { .. })
+        })
+        .await;
+        assert!(matches!(
+            replayed,
+            crate::NetworkEvent::Error { error } if error.contains("重放")
+        ));
+        let missing = wait_for_event(&mut receiver_events, |event| {
+            matches!(event, crate::NetworkEvent::Error { .. })
+        })
+        .await;
+        assert!(matches!(
+            missing,
+            crate::NetworkEvent::Error { error } if error.contains("缺少")
+        ));
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+
+        sender.stop().await.unwrap();
+        receiver.stop().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_resync_authenticates_peers_missed_by_watcher() {
+        let receiver = AnemoNetworkService::new();
+        receiver
+            .set_connection_authenticator(Arc::new(|_peer_id, _metadata| {
+                Box::pin(async { AuthDecision::Reject("不接受任何连接".to_string()) })
+            }))
+            .await;
+        receiver
+            .start(loopback_config("resync-receiver"))
+            .await
+            .unwrap();
+        // 模拟连接事件积压时丢失的事件
+        receiver.auth_watcher.lock().await.take().unwrap().abort();
+
+        let sender = AnemoNetworkService::new();
+        sender
+            .start(loopback_config("resync-sender"))
+            .await
+            .unwrap();
+        let receiver_addr = receiver.effective_config().await.unwrap().local_addr;
+        sender.connect(receiver_addr).await.unwrap();
+        let sender_peer = sender.network.read().await.as_ref().unwrap().peer_id();
+
+        let network = receiver.network.read().await.clone().unwrap();
+        assert_eq!(network.peers(), vec![sender_peer]);
+        receiver.resync_peers(&network).await;
+        assert!(receiver.connected_at.read().await.is_empty());
+        assert!(receiver.disconnecting.read().await.contains(&sender_peer));
+
+        tokio::time::timeout(Duration::from_secs(2), async {
+            while !network.peers().is_empty() {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .expect("未通过认证的连接应被断开");
+
+        sender.stop().await.unwrap();
+        receiver.stop().await.unwrap();
+    }
+
+    /// 处理失败的处理器
+    struct FailingHandler {
+        calls: Arc<std::sync::atomic::AtomicUsize>,
+    }
+
+    #[async_trait]
+    impl MessageHandler for FailingHandler {
+        async fn handle_message(
+            &self,
+            _ctx: &HandlerContext,
+            _from: NodeId,
+            _message: NetworkMessage,
+        ) -> Result<Option<NetworkMessage>> {
+            self.calls.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+            Err(NetworkError::receive_error("无法处理回复"))
+        }
+    }
+
+    /// 第一次处理失败、之后成功的处理器
+    struct FailOnceHandler {
+        calls: Arc<std::sync::atomic::AtomicUsize>,
+    }
+
+    #[async_trait]
+    impl MessageHandler for FailOnceHandler {
+        async fn handle_message(
+            &self,
+            _ctx: &HandlerContext,
+            _from: NodeId,
+            _message: NetworkMessage,
+        ) -> Result<Option<NetworkMessage>> {
+            if self.calls.fetch_add(1, std::sync::atomic::Ordering::SeqCst) == 0 {
+                return Err(NetworkError::receive_error("暂时无法处理"));
+            }
+            Ok(None)
+        }
+    }
+
+    #[tokio::test]
+    async fn test_remote_duplicates_dropped_unless_handler_failed() {
+        use std::sync::atomic::Ordering;
+
+        let receiver = AnemoNetworkService::new();
+        let sender = AnemoNetworkService::new();
+        let calls = Arc::new(std::sync::atomic::AtomicUsize::new(0));
+        receiver
+            .register_message_handler(
+                MessageType::chat(),
+                Box::new(FailOnceHandler {
+                    calls: calls.clone(),
+                }),
+            )
+            .await
+            .unwrap();
+        connect_pair(
+            &receiver,
+            loopback_config("dedup-receiver"),
+            &sender,
+            loopback_config("dedup-sender"),
+        )
+        .await;
+        let receiver_id = receiver.get_local_node_id().await.unwrap();
+        let sender_id = sender.get_local_node_id().await.unwrap();
+
+        // 同一消息发送三次：第一次处理失败，重试时重新处理，之后的重复被丢弃
+        let message = NetworkMessage::new(
+            MessageType::chat(),
+            sender_id,
+            serde_json::json!({ "text": "once" }),
+        );
+        for _ in 0..3 {
+            sender
+                .unicast(receiver_id.clone(), message.clone(), None)
+                .await
+                .unwrap();
+        }
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+        assert_eq!(receiver.inbound().duplicates_dropped_count().await, 1);
+
+        sender.stop().await.unwrap();
+        receiver.stop().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_failing_reply_handler_does_not_fail_send() {
+        use std::sync::atomic::Ordering;
+
+        let server = AnemoNetworkService::new();
+        let received = Arc::new(std::sync::Mutex::new(Vec::new()));
+        server
+            .register_message_handler(
+                MessageType::chat(),
+                Box::new(ReplyingHandler {
+                    received: received.clone(),
+                }),
+            )
+            .await
+            .unwrap();
+        let client = AnemoNetworkService::new();
+        let reply_calls = Arc::new(std::sync::atomic::AtomicUsize::new(0));
+        client
+            .register_message_handler(
+                MessageType::chat(),
+                Box::new(FailingHandler {
+                    calls: reply_calls.clone(),
+                }),
+            )
+            .await
+            .unwrap();
+        connect_pair(
+            &server,
+            loopback_config("reply-server"),
+            &client,
+            loopback_config("reply-client"),
+        )
+        .await;
+        let server_id = server.get_local_node_id().await.unwrap();
+        let client_id = client.get_local_node_id().await.unwrap();
+
+        let message = text_message(&client_id);
+        let sent = client
+            .unicast(
+                server_id,
+                message.clone(),
+                Some(UnicastOptions {
+                    wait_for_response: true,
+                    ..Default::default()
+                }),
+            )
+            .await
+            .unwrap();
+
+        // 消息已送达并收到回复，回复处理失败不影响发送结果
+        assert_eq!(sent, message.id);
+        assert_eq!(received.lock().unwrap().len(), 1);
+        assert_eq!(reply_calls.load(Ordering::SeqCst), 1);
+        assert!(client.dead_letters().is_empty().await);
+        assert_eq!(client.stats.read().await.error_count, 1);
+
+        client.stop().await.unwrap();
+        server.stop().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_remote_handler_without_response_acks() {
+        use std::sync::atomic::Ordering;
+
+        let receiver = AnemoNetworkService::new();
+        let sender = AnemoNetworkService::new();
+        let calls = Arc::new(std::sync::atomic::AtomicUsize::new(0));
+        receiver
+            .register_message_handler(
+                MessageType::chat(),
+                Box::new(CountingHandler {
+                    calls: calls.clone(),
+                }),
+            )
+            .await
+            .unwrap();
+        // 发送方的确认消息处理器不应收到自动确认
+        let acks = Arc::new(std::sync::atomic::AtomicUsize::new(0));
+        sender
+            .register_message_handler(
+                MessageType::ack(),
+                Box::new(CountingHandler {
+                    calls: acks.clone(),
+                }),
+            )
+            .await
+            .unwrap();
+        connect_pair(
+            &receiver,
+            NetworkServiceConfig {
+                unhandled_grace_ms: 0,
+                ..loopback_config("ack-receiver")
+            },
+            &sender,
+            loopback_config("ack-sender"),
+        )
+        .await;
+        let receiver_id = receiver.get_local_node_id().await.unwrap();
+        let sender_id = sender.get_local_node_id().await.unwrap();
+        let options = || {
+            Some(UnicastOptions {
+                wait_for_response: true,
+                ..Default::default()
+            })
+        };
+
+        // 处理器没有返回响应，接收方自动确认，发送方消费确认后返回成功
+        sender
+            .unicast(receiver_id.clone(), text_message(&sender_id), options())
+            .await
+            .unwrap();
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+        assert_eq!(acks.load(Ordering::SeqCst), 0);
+
+        // 没有处理器的消息得不到确认，按发送失败处理
+        let unhandled = NetworkMessage::new(
+            MessageType::timesync(),
+            sender_id.clone(),
+            serde_json::json!({}),
+        );
+        assert!(sender
+            .unicast(receiver_id, unhandled.clone(), options())
+            .await
+            .is_err());
+        assert_eq!(
+            sender.dead_letters().recent().await[0].message.id,
+            unhandled.id
+        );
+
+        sender.stop().await.unwrap();
+        receiver.stop().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_early_remote_request_waits_for_handler() {
+        let receiver = AnemoNetworkService::new();
+        let sender = AnemoNetworkService::new();
+        connect_pair(
+            &receiver,
+            NetworkServiceConfig {
+                unhandled_grace_ms: 200,
+                ..loopback_config("early-receiver")
+            },
+            &sender,
+            loopback_config("early-sender"),
+        )
+        .await;
+        let receiver_id = receiver.get_local_node_id().await.unwrap();
+        let sender_id = sender.get_local_node_id().await.unwrap();
+        let request = |message: NetworkMessage| {
+            let sender = sender.clone();
+            let receiver_id = receiver_id.clone();
+            tokio::spawn(async move {
+                let options = UnicastOptions {
+                    wait_for_response: true,
+                    ..Default::default()
+                };
+                sender.unicast(receiver_id, message, Some(options)).await
+            })
+        };
+
+        // 处理器注册前到达的请求暂存，注册后在同一RPC上得到回复
+        let early = request(text_message(&sender_id));
+        tokio::time::timeout(Duration::from_secs(2), async {
+            while receiver.inbound().pending_unhandled_count().await == 0 {
+                tokio::task::yield_now().await;
+            }
+        })
+        .await
+        .expect("请求应被暂存");
+        let received = Arc::new(std::sync::Mutex::new(Vec::new()));
+        receiver
+            .register_message_handler(
+                MessageType::chat(),
+                Box::new(ReplyingHandler {
+                    received: received.clone(),
+                }),
+            )
+            .await
+            .unwrap();
+        early.await.unwrap().unwrap();
+        assert_eq!(received.lock().unwrap().len(), 1);
+
+        // 一直没有处理器的请求到期后按时得到空回复，发送方没有收到确认
+        let mut events = receiver.subscribe_events().unwrap();
+        let started = Instant::now();
+        let unknown = NetworkMessage::new(
+            MessageType::new("unknown"),
+            sender_id.clone(),
+            serde_json::json!({}),
+        );
+        assert!(request(unknown).await.unwrap().is_err());
+        assert!(started.elapsed() >= Duration::from_millis(200));
+        assert!(started.elapsed() < Duration::from_secs(2));
+        wait_for_event(&mut events, |event| {
+            matches!(event, crate::NetworkEvent::UnhandledMessage { .. })
+        })
+        .await;
+
+        sender.stop().await.unwrap();
+        receiver.stop().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_remote_messages_go_through_inbound_pipeline() {
+        use std::sync::atomic::Ordering;
+
+        let receiver = AnemoNetworkService::new();
+        let calls = Arc::new(std::sync::atomic::AtomicUsize::new(0));
+        receiver
+            .register_message_handler(
+                MessageType::chat(),
+                Box::new(CountingHandler {
+                    calls: calls.clone(),
+                }),
+            )
+            .await
+            .unwrap();
+        let mut events = receiver.subscribe_events().unwrap();
+        let sender = AnemoNetworkService::new();
+        connect_pair(
+            &receiver,
+            loopback_config("pipeline-receiver"),
+            &sender,
+            loopback_config("pipeline-sender"),
+        )
+        .await;
+        let receiver_id = receiver.get_local_node_id().await.unwrap();
+        let sender_id = sender.get_local_node_id().await.unwrap();
+
+        // 同一消息重复到达时只处理一次
+        let message = text_message(&sender_id);
+        for _ in 0..2 {
+            sender
+                .unicast(receiver_id.clone(), message.clone(), None)
+                .await
+                .unwrap();
+        }
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+        assert_eq!(receiver.inbound().duplicates_dropped_count().await, 1);
+
+        // 没有处理器的消息类型发布未处理事件
+        let unknown = NetworkMessage::new(
+            MessageType::new("unknown"),
+            sender_id.clone(),
+            serde_json::json!({}),
+        );
+        sender.unicast(receiver_id, unknown, None).await.unwrap();
+        let (received, unhandled) = tokio::time::timeout(Duration::from_secs(2), async {
+            let mut received = 0;
+            loop {
+                match events.recv().await.unwrap() {
+                    crate::NetworkEvent::MessageReceived { .. } => received += 1,
+                    crate::NetworkEvent::UnhandledMessage { from, message_type } => {
+                        break (received, (from, message_type))
+                    }
+                    _ => {}
+                }
+            }
+        })
+        .await
+        .expect("应收到未处理事件");
+        assert_eq!(received, 2);
+        assert_eq!(unhandled, (sender_id, MessageType::new("unknown")));
+
+        sender.stop().await.unwrap();
+        receiver.stop().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_peers_addressed_through_own_node_tables() {
+        use std::sync::atomic::Ordering;
+
+        let receiver = AnemoNetworkService::new();
+        let received = Arc::new(std::sync::Mutex::new(Vec::new()));
+        receiver
+            .register_message_handler(
+                MessageType::chat(),
+                Box::new(ReplyingHandler {
+                    received: received.clone(),
+                }),
+            )
+            .await
+            .unwrap();
+        let sender = AnemoNetworkService::new();
+        let replies = Arc::new(std::sync::atomic::AtomicUsize::new(0));
+        sender
+            .register_message_handler(
+                MessageType::new("pong"),
+                Box::new(CountingHandler {
+                    calls: replies.clone(),
+                }),
+            )
+            .await
+            .unwrap();
+        connect_pair(
+            &receiver,
+            loopback_config("table-receiver"),
+            &sender,
+            loopback_config("table-sender"),
+        )
+        .await;
+        let receiver_id = receiver.get_local_node_id().await.unwrap();
+        let sender_id = sender.get_local_node_id().await.unwrap();
+
+        // 与不同进程中的节点一样，寻址不依赖进程内的节点登记
+        {
+            let mut global_nodes = GLOBAL_NODES.write().await;
+            global_nodes.remove(&receiver_id);
+            global_nodes.remove(&sender_id);
+        }
+
+        sender
+            .unicast(receiver_id.clone(), text_message(&sender_id), None)
+            .await
+            .unwrap();
+        let from = received.lock().unwrap()[0].0.clone();
+        assert_eq!(from, sender_id);
+        assert_eq!(
+            receiver.get_connected_nodes().await.unwrap(),
+            vec![sender_id]
+        );
+
+        // 接收方可以直接向看到的发送方单播
+        let pong = NetworkMessage::new(
+            MessageType::new("pong"),
+            receiver_id.clone(),
+            serde_json::json!({}),
+        );
+        receiver.unicast(from, pong, None).await.unwrap();
+        assert_eq!(replies.load(Ordering::SeqCst), 1);
+
+        // 没有公布节点ID的对端以PeerId寻址
+        let peer_id = PeerId([92u8; 32]);
+        let hex_id = AnemoNetworkService::peer_id_to_node_id(peer_id);
+        assert_eq!(AnemoNetworkService::parse_peer_id(&hex_id), Some(peer_id));
+        assert!(AnemoNetworkService::parse_peer_id(&receiver_id).is_none());
+        // 只有已连接的对端可以按PeerId寻址
+        assert!(matches!(
+            receiver.node_id_to_peer_id(&hex_id).await,
+            Err(NetworkError::NodeNotFound(_))
+        ));
+        let sender_peer = sender.network.read().await.as_ref().unwrap().peer_id();
+        let sender_hex_id = AnemoNetworkService::peer_id_to_node_id(sender_peer);
+        assert_eq!(
+            receiver.node_id_to_peer_id(&sender_hex_id).await.unwrap(),
+            sender_peer
+        );
+
+        sender.stop().await.unwrap();
+        receiver.stop().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_broadcast_include_self_runs_local_handler() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        let service = AnemoNetworkService::new();
+        let calls = Arc::new(AtomicUsize::new(0));
+        service
+            .register_message_handler(
+                MessageType::chat(),
+                Box::new(CountingHandler {
+                    calls: calls.clone(),
+                }),
+            )
+            .await
+            .unwrap();
+
+        // 模拟已启动但没有远端节点的服务
+        *service.is_running.write().await = true;
+        *service.local_node_id.write().await = Some("include-self-test-node".to_string());
+
+        let message = NetworkMessage::new(
+            MessageType::chat(),
+            "include-self-test-node".to_string(),
+            serde_json::json!({}),
+        );
+        service.broadcast(message.clone(), None).await.unwrap();
+        assert_eq!(calls.load(Ordering::SeqCst), 0);
+
+        let options = BroadcastOptions {
+            include_self: true,
+            ..Default::default()
+        };
+        service.broadcast(message, Some(options)).await.unwrap();
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn test_broadcast_without_recipients() {
+        let service = AnemoNetworkService::new();
+        *service.is_running.write().await = true;
+        let message = NetworkMessage::new(
+            MessageType::chat(),
+            "lonely-node".to_string(),
+            serde_json::json!({}),
+        );
+
+        // 未要求接收者时仍然成功
+        assert!(service.broadcast(message.clone(), None).await.is_ok());
+
+        let options = BroadcastOptions {
+            require_recipients: true,
+            ..Default::default()
+        };
+        let result = service.broadcast(message, Some(options)).await;
+        assert!(matches!(result, Err(NetworkError::NoRecipients)));
+    }
+
+    #[tokio::test]
+    async fn test_empty_server_name_rejected() {
+        let service = AnemoNetworkService::new();
+        let config = NetworkServiceConfig {
+            server_name: "  ".to_string(),
+            ..Default::default()
+        };
+
+        let result = service.start(config).await;
+        assert!(matches!(result, Err(NetworkError::ConfigError(_))));
+        assert!(!*service.is_running.read().await);
+    }
+
+    #[tokio::test]
+    async fn test_failed_broadcast_recorded_as_dead_letter() {
+        let service = AnemoNetworkService::new();
+        service
+            .start(loopback_config("broadcast-dead"))
+            .await
+            .unwrap();
+        // 节点表中未连接的节点，发送必然失败
+        service
+            .nodes
+            .write()
+            .await
+            .insert("ghost".to_string(), PeerId([93u8; 32]));
+
+        let message = NetworkMessage::new(
+            MessageType::chat(),
+            "tester".to_string(),
+            serde_json::json!({ "content": "hello" }),
+        );
+        service.broadcast(message.clone(), None).await.unwrap();
+
+        let letters = service.dead_letters().recent().await;
+        assert_eq!(letters.len(), 1);
+        assert_eq!(letters[0].peer, "ghost");
+        assert_eq!(letters[0].message.id, message.id);
+
+        service.stop().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_failed_unicast_written_to_dead_letter_file() {
+        let dir = std::env::temp_dir().join(format!("dead-letters-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("dead_letters.jsonl");
+
+        let service = AnemoNetworkService::new().with_dead_letter_config(DeadLetterConfig {
+            file_path: Some(path.clone()),
+            ..Default::default()
+        });
+        *service.is_running.write().await = true;
+
+        let message = NetworkMessage::new(
+            MessageType::chat(),
+            "tester".to_string(),
+            serde_json::json!({ "content": "hello" }),
+        );
+        let message_id = message.id;
+        let result = service
+            .unicast("unknown-node".to_string(), message, None)
+            .await;
+        assert!(result.is_err());
+
+        assert_eq!(service.dead_letters().len().await, 1);
+        let contents = std::fs::read_to_string(&path).unwrap();
+        let letter: DeadLetter = serde_json::from_str(contents.lines().next().unwrap()).unwrap();
+        assert_eq!(letter.peer, "unknown-node");
+        assert_eq!(letter.message.id, message_id);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_authenticator_rejects_peer_without_token() {
+        let service = AnemoNetworkService::new();
+        service
+            .set_connection_authenticator(Arc::new(|_peer_id, metadata| {
+                Box::pin(async move {
+                    match metadata.get("token").map(String::as_str) {
+                        Some("secret") => AuthDecision::Accept,
+                        _ => AuthDecision::Reject("缺少有效令牌".to_string()),
+                    }
+                })
+            }))
+            .await;
+
+        let trusted = PeerId([1u8; 32]);
+        let anonymous = PeerId([2u8; 32]);
+        service
+            .record_peer_metadata(
+                trusted,
+                HashMap::from([("token".to_string(), "secret".to_string())]),
+            )
+            .await;
+
+        assert_eq!(service.authorize_peer(trusted).await, AuthDecision::Accept);
+        assert!(matches!(
+            service.authorize_peer(anonymous).await,
+            AuthDecision::Reject(_)
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_messages_from_rejected_peer_not_handled() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        let receiver = AnemoNetworkService::new();
+        let calls = Arc::new(AtomicUsize::new(0));
+        receiver
+            .register_message_handler(
+                MessageType::chat(),
+                Box::new(CountingHandler {
+                    calls: calls.clone(),
+                }),
+            )
+            .await
+            .unwrap();
+        // 认证较慢，对端在认证结果出来前就发送消息
+        receiver
+            .set_connection_authenticator(Arc::new(|_peer_id, _metadata| {
+                Box::pin(async {
+                    tokio::time::sleep(Duration::from_millis(200)).await;
+                    AuthDecision::Reject("不接受任何连接".to_string())
+                })
+            }))
+            .await;
+        let sender = AnemoNetworkService::new();
+        connect_pair(
+            &receiver,
+            loopback_config("rejecting-receiver"),
+            &sender,
+            loopback_config("rejected-sender"),
+        )
+        .await;
+
+        let sender_id = sender.get_local_node_id().await.unwrap();
+        let network = sender.network.read().await.clone().unwrap();
+        let receiver_peer = receiver.network.read().await.as_ref().unwrap().peer_id();
+        let bytes = sender
+            .encode_message(&text_message(&sender_id))
+            .await
+            .unwrap();
+        let request = Request::new(Bytes::from(bytes)).with_route(NETWORK_MESSAGE_ROUTE);
+        let response = network.rpc(receiver_peer, request).await.unwrap();
+        assert!(response.into_body().is_empty());
+        assert_eq!(calls.load(Ordering::SeqCst), 0);
+
+        sender.stop().await.unwrap();
+        receiver.stop().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_authenticator_uses_announced_node_info() {
+        use std::sync::atomic::AtomicUsize;
+
+        let receiver = AnemoNetworkService::new();
+        receiver
+            .set_connection_authenticator(Arc::new(|_peer_id, metadata| {
+                Box::pin(async move {
+                    match metadata.get("token").map(String::as_str) {
+                        Some("secret") => AuthDecision::Accept,
+                        _ => AuthDecision::Reject("缺少有效令牌".to_string()),
+                    }
+                })
+            }))
+            .await;
+        receiver
+            .start(loopback_config("token-receiver"))
+            .await
+            .unwrap();
+        let mut events = receiver.subscribe_events().unwrap();
+        let receiver_addr = receiver.effective_config().await.unwrap().local_addr;
+
+        // 令牌随节点信息公布，认证前已并入握手元数据
+        let trusted = AnemoNetworkService::new();
+        trusted
+            .register_message_handler(
+                MessageType::chat(),
+                Box::new(CountingHandler {
+                    calls: Arc::new(AtomicUsize::new(0)),
+                }),
+            )
+            .await
+            .unwrap();
+        trusted
+            .start(NetworkServiceConfig {
+                node_info_metadata: HashMap::from([(
+                    NODE_ID_KEY.to_string(),
+                    "forged-id".to_string(),
+                )]),
+                auth_metadata: HashMap::from([("token".to_string(), "secret".to_string())]),
+                ..loopback_config("token-trusted")
+            })
+            .await
+            .unwrap();
+        trusted.connect(receiver_addr).await.unwrap();
+        let trusted_id = trusted.get_local_node_id().await.unwrap();
+
+        let event = wait_for_event(&mut events, |event| {
+            matches!(event, crate::NetworkEvent::NodeConnected { .. })
+        })
+        .await;
+        let crate::NetworkEvent::NodeConnected {
+            node_id,
+            connection,
+            metadata,
+        } = event
+        else {
+            panic!("应为节点连接事件");
+        };
+        // 附加元数据不能覆盖内置字段
+        assert_eq!(node_id, trusted_id);
+        assert_eq!(connection.server_name.as_deref(), Some("token-trusted"));
+        assert_eq!(
+            connection.protocol_version.as_deref(),
+            Some(NODE_PROTOCOL_VERSION)
+        );
+        assert_eq!(connection.wire_format.as_deref(), Some(NODE_WIRE_FORMAT));
+        assert!(connection.capabilities.contains(&"chat".to_string()));
+        assert_eq!(metadata.get("token").map(String::as_str), Some("secret"));
+
+        // 没有公布令牌的节点被拒绝并断开
+        let anonymous = AnemoNetworkService::new();
+        anonymous
+            .start(loopback_config("token-anonymous"))
+            .await
+            .unwrap();
+        let mut anonymous_events = anonymous.subscribe_events().unwrap();
+        anonymous.connect(receiver_addr).await.unwrap();
+        wait_for_event(&mut anonymous_events, |event| {
+            matches!(event, crate::NetworkEvent::NodeDisconnected { .. })
+        })
+        .await;
+        assert_eq!(
+            receiver.get_connected_nodes().await.unwrap(),
+            vec![trusted_id]
+        );
+
+        // 认证令牌只出示给主动连接的节点，连入的节点查询不到
+        let trusted_addr = trusted.effective_config().await.unwrap().local_addr;
+        anonymous.connect(trusted_addr).await.unwrap();
+        let event = wait_for_event(&mut anonymous_events, |event| {
+            matches!(event, crate::NetworkEvent::NodeConnected { .. })
+        })
+        .await;
+        let crate::NetworkEvent::NodeConnected { metadata, .. } = event else {
+            panic!("应为节点连接事件");
+        };
+        assert_eq!(
+            metadata
+                .get(ConnectionMetadata::SERVER_NAME_KEY)
+                .map(String::as_str),
+            Some("token-trusted")
+        );
+        assert!(!metadata.contains_key("token"));
+
+        anonymous.stop().await.unwrap();
+        trusted.stop().await.unwrap();
+        receiver.stop().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_node_connected_event_carries_typed_metadata() {
+        let service = AnemoNetworkService::new();
+        let peer_id = PeerId([7u8; 32]);
+        let remote_addr: SocketAddr = "10.0.0.2:9000".parse().unwrap();
+        service
+            .record_peer_metadata(
+                peer_id,
+                HashMap::from([
+                    ("server_name".to_string(), "chat-server".to_string()),
+                    ("wire_format".to_string(), "json".to_string()),
+                    ("capabilities".to_string(), "chat, timesync".to_string()),
+                    ("region".to_string(), "eu".to_string()),
+                ]),
+            )
+            .await;
+
+        let event = service
+            .node_connected_event(peer_id, Some(remote_addr))
+            .await;
+        let crate::NetworkEvent::NodeConnected {
+            connection,
+            metadata,
+            ..
+        } = event
+        else {
+            panic!("应为节点连接事件");
+        };
+
+        assert_eq!(connection.remote_addr, Some(remote_addr));
+        assert_eq!(connection.server_name.as_deref(), Some("chat-server"));
+        assert_eq!(connection.wire_format.as_deref(), Some("json"));
+        assert_eq!(connection.protocol_version, None);
+        assert_eq!(connection.capabilities, vec!["chat", "timesync"]);
+        // 未约定的键仍保留在原始元数据中
+        assert_eq!(metadata.get("region").map(String::as_str), Some("eu"));
+    }
+
+    #[tokio::test]
+    async fn test_node_connected_event_from_real_connection() {
+        let receiver = AnemoNetworkService::new();
+        let sender = AnemoNetworkService::new();
+        receiver
+            .start(loopback_config("connected-receiver"))
+            .await
+            .unwrap();
+        sender
+            .start(loopback_config("connected-sender"))
+            .await
+            .unwrap();
+        let mut events = receiver.subscribe_events().unwrap();
+        let receiver_addr = receiver.effective_config().await.unwrap().local_addr;
+        let sender_addr = sender.effective_config().await.unwrap().local_addr;
+        sender.connect(receiver_addr).await.unwrap();
+        let sender_id = sender.get_local_node_id().await.unwrap();
+
+        let event = wait_for_event(&mut events, |event| {
+            matches!(event, crate::NetworkEvent::NodeConnected { .. })
+        })
+        .await;
+        let crate::NetworkEvent::NodeConnected {
+            node_id,
+            connection,
+            ..
+        } = event
+        else {
+            panic!("应为节点连接事件");
+        };
+        // 事件使用对端公布的节点ID和实际的连接地址
+        assert_eq!(node_id, sender_id);
+        assert_eq!(connection.remote_addr, Some(sender_addr));
+
+        sender.stop().await.unwrap();
+        receiver.stop().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_unacked_unicast_recovered_from_wal() {
+        let dir = std::env::temp_dir().join(format!("outbound-wal-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("outbound.wal");
+
+        // 发送失败的消息只进入死信队列
+        let service = AnemoNetworkService::new().with_outbound_wal(&path).unwrap();
+        *service.is_running.write().await = true;
+        let failed =
+            NetworkMessage::new(MessageType::chat(), "me".to_string(), serde_json::json!({}));
+        assert!(service
+            .unicast("offline-node".to_string(), failed.clone(), None)
+            .await
+            .is_err());
+        assert!(service.wal_pending().await.is_empty());
+        assert_eq!(service.dead_letters().len().await, 1);
+
+        // 模拟发送途中崩溃：消息已写入日志但没有确认
+        let in_flight =
+            NetworkMessage::new(MessageType::chat(), "me".to_string(), serde_json::json!({}));
+        OutboundWal::open(&path)
+            .unwrap()
+            .append("offline-node".to_string(), in_flight.clone())
+            .await
+            .unwrap();
+        drop(service);
+
+        let recovered = AnemoNetworkService::new().with_outbound_wal(&path).unwrap();
+        let pending = recovered.wal_pending().await;
+        assert_eq!(pending.len(), 1);
+        assert_eq!(pending[0].target, "offline-node");
+        assert_eq!(pending[0].message.id, in_flight.id);
+
+        // 目标尚未连接时消息继续保留在日志中
+        assert_eq!(recovered.replay_wal().await, 0);
+        assert_eq!(recovered.wal_pending().await.len(), 1);
+        assert!(recovered.dead_letters().is_empty().await);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_wal_replayed_after_known_server_connects() {
+        use std::sync::atomic::Ordering;
+
+        let dir = std::env::temp_dir().join(format!("outbound-wal-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("outbound.wal");
+
+        let receiver = AnemoNetworkService::new();
+        let calls = Arc::new(std::sync::atomic::AtomicUsize::new(0));
+        receiver
+            .register_message_handler(
+                MessageType::chat(),
+                Box::new(CountingHandler {
+                    calls: calls.clone(),
+                }),
+            )
+            .await
+            .unwrap();
+        receiver
+            .start(loopback_config("wal-receiver"))
+            .await
+            .unwrap();
+        let receiver_id = receiver.get_local_node_id().await.unwrap();
+        let receiver_addr = receiver.effective_config().await.unwrap().local_addr;
+
+        // 上次运行崩溃前留下两条未确认的消息，其中一条在崩溃前已经送达
+        let delivered = text_message("wal-sender");
+        let undelivered = text_message("wal-sender");
+        {
+            let wal = OutboundWal::open(&path).unwrap();
+            wal.append(receiver_id.clone(), delivered.clone())
+                .await
+                .unwrap();
+            wal.append(receiver_id.clone(), undelivered.clone())
+                .await
+                .unwrap();
+        }
+        let earlier = AnemoNetworkService::new();
+        earlier.start(loopback_config("wal-earlier")).await.unwrap();
+        earlier.connect(receiver_addr).await.unwrap();
+        earlier
+            .unicast(receiver_id.clone(), delivered.clone(), None)
+            .await
+            .unwrap();
+        earlier.stop().await.unwrap();
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+
+        // 启动时目标尚未连接，连接已知服务器后重发
+        let sender = AnemoNetworkService::new().with_outbound_wal(&path).unwrap();
+        sender.start(loopback_config("wal-sender")).await.unwrap();
+        assert_eq!(sender.wal_pending().await.len(), 2);
+        sender.add_known_server(receiver_addr.to_string()).await;
+        sender.connect_to_known_servers().await;
+
+        // 两条都已确认；已送达的消息由接收端去重，处理器只多运行一次
+        assert!(sender.wal_pending().await.is_empty());
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+        assert_eq!(receiver.inbound().duplicates_dropped_count().await, 1);
+        assert!(sender.dead_letters().is_empty().await);
+
+        sender.stop().await.unwrap();
+        receiver.stop().await.unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_observer_cannot_send_or_register() {
+        let service = AnemoNetworkService::new();
+        *service.is_running.write().await = true;
+        let config = NetworkServiceConfig {
+            observer: true,
+            ..Default::default()
+        };
+        service.inbound.set_config(config.clone()).await;
+        *service.config.write().await = Some(config);
+
+        let message =
+            NetworkMessage::new(MessageType::chat(), "me".to_string(), serde_json::json!({}));
+        assert!(matches!(
+            service.broadcast(message.clone(), None).await,
+            Err(crate::NetworkError::ObserverMode(_))
+        ));
+        assert!(matches!(
+            service.unicast("node-b".to_string(), message, None).await,
+            Err(crate::NetworkError::ObserverMode(_))
+        ));
+        assert!(matches!(
+            service
+                .register_message_handler(
+                    MessageType::chat(),
+                    Box::new(CountingHandler {
+                        calls: Arc::default(),
+                    }),
+                )
+                .await,
+            Err(crate::NetworkError::ObserverMode(_))
+        ));
+        // 观察者仍可订阅事件
+        assert!(service.subscribe_events().is_some());
+    }
+
+    #[tokio::test]
+    async fn test_observer_publishes_remote_messages_without_replying() {
+        let observer = AnemoNetworkService::new();
+        let sender = AnemoNetworkService::new();
+        connect_pair(
+            &observer,
+            NetworkServiceConfig {
+                observer: true,
+                ..loopback_config("observer")
+            },
+            &sender,
+            loopback_config("observer-sender"),
+        )
+        .await;
+        let observer_id = observer.get_local_node_id().await.unwrap();
+        let sender_id = sender.get_local_node_id().await.unwrap();
+        let mut events = observer.subscribe_events().unwrap();
+
+        let message = NetworkMessage::new(
+            MessageType::chat(),
+            sender_id.clone(),
+            serde_json::json!({ "text": "hello" }),
+        );
+        sender
+            .unicast(observer_id.clone(), message.clone(), None)
+            .await
+            .unwrap();
+        let received = wait_for_event(&mut events, |event| {
+            matches!(event, crate::NetworkEvent::MessageReceived { .. })
+        })
+        .await;
+        assert!(matches!(
+            received,
+            crate::NetworkEvent::MessageReceived { from, message: got }
+                if from == sender_id && got.id == message.id
+        ));
+
+        // 观察者没有注册系统处理器，也不回复Ping
+        let network = sender.network.read().await.clone().unwrap();
+        let peer_id = sender.node_id_to_peer_id(&observer_id).await.unwrap();
+        let ping = SystemMessageType::Ping
+            .to_network_message(sender_id)
+            .unwrap();
+        assert!(sender
+            .send_to_peer(&network, peer_id, &ping, 1000)
+            .await
+            .unwrap()
+            .is_none());
+
+        sender.stop().await.unwrap();
+        observer.stop().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_spawn_rejects_invalid_config() {
+        let result = AnemoNetworkService::spawn(NetworkServiceConfig {
+            server_name: "  ".to_string(),
+            ..Default::default()
+        })
+        .await;
+        assert!(matches!(result, Err(crate::NetworkError::ConfigError(_))));
+    }
+
+    #[tokio::test]
+    async fn test_service_handle_sends_and_shuts_down() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        let handle = AnemoNetworkService::spawn(loopback_config("handle-node"))
+            .await
+            .unwrap();
+        assert!(*handle.is_running.read().await);
+        assert_ne!(
+            handle.effective_config().await.unwrap().local_addr.port(),
+            0
+        );
+
+        let calls = Arc::new(AtomicUsize::new(0));
+        handle
+            .register_message_handler(
+                MessageType::chat(),
+                Box::new(CountingHandler {
+                    calls: calls.clone(),
+                }),
+            )
+            .await
+            .unwrap();
+
+        // 通过句柄直接使用服务：没有远端节点时只投递给本地处理器
+        let local_id = handle.get_local_node_id().await.unwrap();
+        let message = NetworkMessage::new(MessageType::chat(), local_id, serde_json::json!({}));
+        let options = BroadcastOptions {
+            include_self: true,
+            ..Default::default()
+        };
+        handle.broadcast(message, Some(options)).await.unwrap();
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+
+        let service = handle.service();
+        handle.shutdown().await.unwrap();
+        assert!(!*service.is_running.read().await);
+    }
+
+    #[tokio::test]
+    async fn test_prune_unreachable_removes_stale_entries() {
+        let service = AnemoNetworkService::new();
+        let mut events = service.event_bus.subscribe();
+        let stale = ("stale-node:127.0.0.1:1".to_string(), PeerId([31u8; 32]));
+        let live = ("live-node:127.0.0.1:2".to_string(), PeerId([32u8; 32]));
+        let reregistered = ("rejoined-node:127.0.0.1:3".to_string(), PeerId([33u8; 32]));
+        {
+            let mut nodes = service.nodes.write().await;
+            nodes.insert(stale.0.clone(), stale.1);
+            nodes.insert(live.0.clone(), live.1);
+            // 已用新的连接重新注册
+            nodes.insert(reregistered.0.clone(), PeerId([34u8; 32]));
+        }
+
+        let pruned = service
+            .prune_unreachable(
+                vec![stale.clone(), live.clone(), reregistered.clone()],
+                |peer_id| peer_id == live.1,
+            )
+            .await;
+        assert_eq!(pruned, vec![stale.0.clone()]);
+
+        {
+            let nodes = service.nodes.read().await;
+            assert!(!nodes.contains_key(&stale.0));
+            assert!(nodes.contains_key(&live.0));
+            assert!(nodes.contains_key(&reregistered.0));
+        }
+        match events.try_recv().unwrap() {
+            crate::NetworkEvent::NodeDisconnected { node_id, .. } => assert_eq!(node_id, stale.0),
+            other => panic!("意外的事件: {:?}", other),
+        }
+        assert!(events.try_recv().is_err());
+    }
+
+    /// 阻塞到 `gate` 放行的处理器
+    struct GatedHandler {
+        gate: Arc<tokio::sync::Semaphore>,
+    }
+
+    #[async_trait]
+    impl MessageHandler for GatedHandler {
+        async fn handle_message(
+            &self,
+            _ctx: &HandlerContext,
+            _from: NodeId,
+            _message: NetworkMessage,
+        ) -> Result<Option<NetworkMessage>> {
+            self.gate.acquire().await.unwrap().forget();
+            Ok(None)
+        }
+    }
+
+    #[tokio::test]
+    async fn test_hung_handler_of_remote_message_cancelled() {
+        let receiver = AnemoNetworkService::new();
+        let sender = AnemoNetworkService::new();
+        // 处理器等待的信号量永不放行，处理器一直挂起
+        receiver
+            .register_message_handler(
+                MessageType::chat(),
+                Box::new(GatedHandler {
+                    gate: Arc::new(tokio::sync::Semaphore::new(0)),
+                }),
+            )
+            .await
+            .unwrap();
+        connect_pair(
+            &receiver,
+            NetworkServiceConfig {
+                handler_timeout_ms: 50,
+                handler_workers: Some(1),
+                ..loopback_config("timeout-receiver")
+            },
+            &sender,
+            loopback_config("timeout-sender"),
+        )
+        .await;
+        let receiver_id = receiver.get_local_node_id().await.unwrap();
+        let sender_id = sender.get_local_node_id().await.unwrap();
+        let mut events = receiver.subscribe_events().unwrap();
+
+        // 超时释放唯一的工作位，第二条消息也能得到处理
+        for n in 0..2 {
+            let message = NetworkMessage::new(
+                MessageType::chat(),
+                sender_id.clone(),
+                serde_json::json!({ "n": n }),
+            );
+            tokio::time::timeout(
+                Duration::from_secs(2),
+                sender.unicast(receiver_id.clone(), message, None),
+            )
+            .await
+            .expect("挂起的处理器没有被取消")
+            .unwrap();
+            let completed = wait_for_event(&mut events, |event| {
+                matches!(event, crate::NetworkEvent::HandlerCompleted { .. })
+            })
+            .await;
+            assert!(matches!(
+                completed,
+                crate::NetworkEvent::HandlerCompleted { ok: false, duration_ms, .. }
+                    if duration_ms >= 50
+            ));
+        }
+
+        sender.stop().await.unwrap();
+        receiver.stop().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_handler_pool_limits_remote_messages() {
+        let receiver = AnemoNetworkService::new();
+        let sender = AnemoNetworkService::new();
+        let gate = Arc::new(tokio::sync::Semaphore::new(0));
+        receiver
+            .register_message_handler(
+                MessageType::chat(),
+                Box::new(GatedHandler { gate: gate.clone() }),
+            )
+            .await
+            .unwrap();
+        connect_pair(
+            &receiver,
+            NetworkServiceConfig {
+                handler_workers: Some(1),
+                ..loopback_config("pool-receiver")
+            },
+            &sender,
+            loopback_config("pool-sender"),
+        )
+        .await;
+        let receiver_id = receiver.get_local_node_id().await.unwrap();
+        let sender_id = sender.get_local_node_id().await.unwrap();
+
+        let sends: Vec<_> = (0..3)
+            .map(|i| {
+                let sender = sender.clone();
+                let message = NetworkMessage::new(
+                    MessageType::chat(),
+                    sender_id.clone(),
+                    serde_json::json!({ "n": i }),
+                );
+                let receiver_id = receiver_id.clone();
+                tokio::spawn(async move { sender.unicast(receiver_id, message, None).await })
+            })
+            .collect();
+
+        // 远端消息经过同一个工作池：一个处理器运行，其余排队
+        let expect_pool = |active: usize, queued: usize| {
+            let receiver = receiver.clone();
+            async move {
+                tokio::time::timeout(Duration::from_secs(2), async {
+                    loop {
+                        let stats = receiver.handler_pool_stats().await;
+                        if (stats.active, stats.queued) == (active, queued) {
+                            break;
+                        }
+                        tokio::task::yield_now().await;
+                    }
+                })
+                .await
+                .expect("工作池应达到预期状态");
+            }
+        };
+        expect_pool(1, 2).await;
+        assert_eq!(receiver.handler_pool_stats().await.workers, 1);
+
+        gate.add_permits(3);
+        for send in sends {
+            send.await.unwrap().unwrap();
+        }
+        expect_pool(0, 0).await;
+
+        sender.stop().await.unwrap();
+        receiver.stop().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_event_bus_capacity_applied_on_start() {
+        let receiver = AnemoNetworkService::new();
+        let sender = AnemoNetworkService::new();
+        connect_pair(
+            &receiver,
+            NetworkServiceConfig {
+                event_bus_capacity: 4096,
+                ..loopback_config("capacity-receiver")
+            },
+            &sender,
+            loopback_config("capacity-sender"),
+        )
+        .await;
+        assert_eq!(receiver.event_bus.capacity(), 4096);
+        let receiver_id = receiver.get_local_node_id().await.unwrap();
+        let sender_id = sender.get_local_node_id().await.unwrap();
+
+        // 订阅者在突发期间不读取，容量内的事件不会丢失
+        let mut slow_subscriber = receiver.subscribe_events().unwrap();
+        for i in 0..2000 {
+            let message = NetworkMessage::new(
+                MessageType::chat(),
+                sender_id.clone(),
+                serde_json::json!({ "n": i }),
+            );
+            sender
+                .unicast(receiver_id.clone(), message, None)
+                .await
+                .unwrap();
+        }
+        let mut received = 0;
+        loop {
+            match slow_subscriber.try_recv() {
+                Ok(crate::NetworkEvent::MessageReceived { .. }) => received += 1,
+                Ok(_) => {}
+                Err(broadcast::error::TryRecvError::Empty) => break,
+                Err(e) => panic!("订阅者落后: {:?}", e),
+            }
+        }
+        assert_eq!(received, 2000);
+
+        sender.stop().await.unwrap();
+        receiver.stop().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_reconnect_event_names_previous_node() {
+        let receiver = AnemoNetworkService::new();
+        let sender = AnemoNetworkService::new();
+        connect_pair(
+            &receiver,
+            loopback_config("reconnect-receiver"),
+            &sender,
+            loopback_config("reconnect-sender"),
+        )
+        .await;
+        let receiver_id = receiver.get_local_node_id().await.unwrap();
+        let receiver_addr = receiver.effective_config().await.unwrap().local_addr;
+        receiver.stop().await.unwrap();
+
+        let mut events = sender.subscribe_events().unwrap();
+        let backoff = BackoffConfig {
+            initial_ms: 1,
+            ..Default::default()
+        };
+        assert!(sender
+            .reconnect(receiver_addr, &backoff, None, Some(1))
+            .await
+            .is_err());
+        let reconnecting = wait_for_event(&mut events, |event| {
+            matches!(event, crate::NetworkEvent::NodeReconnecting { .. })
+        })
+        .await;
+        assert!(matches!(
+            reconnecting,
+            crate::NetworkEvent::NodeReconnecting { node_id, attempt: 1 } if node_id == receiver_id
+        ));
+
+        sender.stop().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_stale_sweeper_only_prunes_own_nodes() {
+        let sweeping = |name: &str| NetworkServiceConfig {
+            heartbeat_interval_ms: 10,
+            ..loopback_config(name)
+        };
+        let receiver = AnemoNetworkService::new();
+        let sender = AnemoNetworkService::new();
+        connect_pair(
+            &receiver,
+            sweeping("sweep-receiver"),
+            &sender,
+            sweeping("sweep-sender"),
+        )
+        .await;
+        let receiver_id = receiver.get_local_node_id().await.unwrap();
+        let mut sender_events = sender.subscribe_events().unwrap();
+
+        // 同一进程中没有连接任何节点的服务，其清理任务不应影响其他服务的节点
+        let bystander = AnemoNetworkService::new();
+        let mut bystander_events = bystander.subscribe_events().unwrap();
+        bystander.start(sweeping("sweep-bystander")).await.unwrap();
+
+        // 等待各服务的清理任务运行若干轮
+        tokio::time::sleep(Duration::from_millis(100)).await;
+
+        assert_eq!(
+            sender.get_connected_nodes().await.unwrap(),
+            vec![receiver_id]
+        );
+        assert!(bystander.get_connected_nodes().await.unwrap().is_empty());
+        for events in [&mut sender_events, &mut bystander_events] {
+            while let Ok(event) = events.try_recv() {
+                assert!(
+                    !matches!(event, crate::NetworkEvent::NodeDisconnected { .. }),
+                    "意外的断开事件: {:?}",
+                    event
+                );
+            }
+        }
+
+        bystander.stop().await.unwrap();
+        sender.stop().await.unwrap();
+        receiver.stop().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_explicit_node_id_claimed_once() {
+        let node_id = "pod-chat-0".to_string();
+        let peer_id = PeerId([41u8; 32]);
+        AnemoNetworkService::claim_node_id(&node_id, peer_id)
+            .await
+            .unwrap();
+
+        assert_eq!(GLOBAL_NODES.read().await.get(&node_id), Some(&peer_id));
+        let service = AnemoNetworkService::new();
+        *service.local_node_id.write().await = Some(node_id.clone());
+        assert_eq!(service.get_local_node_id().await.unwrap(), node_id);
+
+        // 其他节点不能使用同一ID
+        let result = AnemoNetworkService::claim_node_id(&node_id, PeerId([42u8; 32])).await;
+        assert!(matches!(result, Err(NetworkError::ConfigError(_))));
+        assert_eq!(GLOBAL_NODES.read().await.get(&node_id), Some(&peer_id));
+
+        assert!(NetworkServiceConfig {
+            node_id: Some("  ".to_string()),
+            ..Default::default()
+        }
+        .validate()
+        .is_err());
+
+        GLOBAL_NODES.write().await.remove(&node_id);
+    }
+
+    #[tokio::test]
+    async fn test_unicast_to_explicit_node_id() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        let server = AnemoNetworkService::new();
+        let client = AnemoNetworkService::new();
+        let calls = Arc::new(AtomicUsize::new(0));
+        client
+            .register_message_handler(
+                MessageType::chat(),
+                Box::new(CountingHandler {
+                    calls: calls.clone(),
+                }),
+            )
+            .await
+            .unwrap();
+        server
+            .start(loopback_config("explicit-id-server"))
+            .await
+            .unwrap();
+        let node_id = "pod-chat-1".to_string();
+        client
+            .start(NetworkServiceConfig {
+                node_id: Some(node_id.clone()),
+                ..loopback_config("explicit-id-client")
+            })
+            .await
+            .unwrap();
+        let mut server_events = server.subscribe_events().unwrap();
+        let server_addr = server.effective_config().await.unwrap().local_addr;
+        client.add_known_server(server_addr.to_string()).await;
+        client.connect_to_known_servers().await;
+        wait_for_event(&mut server_events, |event| {
+            matches!(event, crate::NetworkEvent::NodeConnected { node_id: id, .. } if *id == node_id)
+        })
+        .await;
+
+        // 连接已知服务器后显式节点ID仍指向本节点
+        let client_peer = client.effective_config().await.unwrap().peer_id;
+        assert_eq!(client.get_local_node_id().await.unwrap(), node_id);
+        assert_eq!(GLOBAL_NODES.read().await.get(&node_id), Some(&client_peer));
+
+        let server_id = server.get_local_node_id().await.unwrap();
+        server
+            .unicast(node_id.clone(), text_message(&server_id), None)
+            .await
+            .unwrap();
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+
+        client.stop().await.unwrap();
+        server.stop().await.unwrap();
+        assert!(GLOBAL_NODES.read().await.get(&node_id).is_none());
+    }
+
+    #[tokio::test]
+    async fn test_watch_peer_starts_from_live_connection() {
+        let server = AnemoNetworkService::new();
+        let client = AnemoNetworkService::new();
+        connect_pair(
+            &server,
+            loopback_config("watch-server"),
+            &client,
+            loopback_config("watch-client"),
+        )
+        .await;
+        let server_id = server.get_local_node_id().await.unwrap();
+
+        let mut watched = client.watch_peer(server_id.clone()).await;
+        assert_eq!(*watched.borrow(), PeerState::Connected);
+        assert_eq!(
+            *client
+                .watch_peer("watch-test:other".to_string())
+                .await
+                .borrow(),
+            PeerState::Disconnected
+        );
+
+        // 节点表中残留、但底层没有连接的记录不算已连接
+        let stale_id = "watch-test:stale".to_string();
+        client
+            .nodes
+            .write()
+            .await
+            .insert(stale_id.clone(), PeerId([81u8; 32]));
+        assert_eq!(
+            *client.watch_peer(stale_id).await.borrow(),
+            PeerState::Disconnected
+        );
+
+        server.stop().await.unwrap();
+        tokio::time::timeout(Duration::from_secs(5), watched.changed())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(*watched.borrow(), PeerState::Disconnected);
+
+        client.stop().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_connected_nodes_order_is_deterministic() {
+        let service = AnemoNetworkService::new();
+        *service.is_running.write().await = true;
+        let prefix = "order-test:";
+        let nodes = [("c", 71u8), ("a", 72), ("b", 73)];
+        {
+            let mut table = service.nodes.write().await;
+            for (name, byte) in nodes {
+                table.insert(format!("{}{}", prefix, name), PeerId([byte; 32]));
+            }
+        }
+        let listed = |nodes: Vec<NodeId>| -> Vec<NodeId> {
+            nodes
+                .into_iter()
+                .filter(|node_id| node_id.starts_with(prefix))
+                .map(|node_id| node_id[prefix.len()..].to_string())
+                .collect()
+        };
+
+        // 默认按节点ID排序，多次调用顺序一致
+        let first = listed(service.get_connected_nodes().await.unwrap());
+        assert_eq!(first, vec!["a", "b", "c"]);
+        assert_eq!(listed(service.get_connected_nodes().await.unwrap()), first);
+
+        // 按连接时间排序，时间未知的节点排在最后
+        *service.config.write().await = Some(NetworkServiceConfig {
+            connected_nodes_order: NodeOrder::ConnectedAt,
+            ..Default::default()
+        });
+        {
+            let now = Instant::now();
+            let mut connected_at = service.connected_at.write().await;
+            connected_at.insert(PeerId([71u8; 32]), now - Duration::from_secs(2));
+            connected_at.insert(PeerId([73u8; 32]), now - Duration::from_secs(1));
+        }
+        assert_eq!(
+            listed(service.get_connected_nodes().await.unwrap()),
+            vec!["c", "b", "a"]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_idle_peers_exclude_active_and_known_servers() {
+        let service = AnemoNetworkService::new();
+        let timeout = Duration::from_millis(50);
+        let quiet = PeerId([51u8; 32]);
+        let active = PeerId([52u8; 32]);
+        let server = PeerId([53u8; 32]);
+        let system_only = PeerId([54u8; 32]);
+        {
+            let long_ago = Instant::now() - timeout * 2;
+            let mut activity = service.peer_activity.write().await;
+            for peer_id in [quiet, active, server, system_only] {
+                activity.insert(peer_id, long_ago);
+            }
+        }
+        service.persistent_peers.write().await.insert(server);
+
+        // 应用消息刷新空闲时间，系统消息不刷新
+        let chat = NetworkMessage::new(
+            MessageType::chat(),
+            "node-a".to_string(),
+            serde_json::json!({}),
+        );
+        let ping = NetworkMessage::new(
+            MessageType::system(),
+            "node-a".to_string(),
+            serde_json::json!({}),
+        );
+        service.record_peer_activity(active, &chat).await;
+        service.record_peer_activity(system_only, &ping).await;
+
+        let mut idle = service.idle_peers(timeout).await;
+        idle.sort_by_key(|peer_id| peer_id.0);
+        assert_eq!(idle, vec![quiet, system_only]);
+    }
+
+    #[tokio::test]
+    async fn test_effective_config_reports_bound_address() {
+        let service = AnemoNetworkService::new();
+        assert!(service.effective_config().await.is_none());
+
+        let key = [61u8; 32];
+        service
+            .start(NetworkServiceConfig {
+                private_key: KeySource::Fixed(key),
+                ..loopback_config("effective-config")
+            })
+            .await
+            .unwrap();
+        let effective = service.effective_config().await.unwrap();
+        assert_ne!(effective.local_addr.port(), 0);
+        assert_eq!(effective.config.bind_address, effective.local_addr);
+        assert_eq!(
+            effective.node_id,
+            service.get_local_node_id().await.unwrap()
+        );
+        // 私钥不随生效配置暴露
+        assert_eq!(effective.config.private_key, KeySource::Random);
+
+        service.stop().await.unwrap();
+        assert!(service.effective_config().await.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_concurrent_stop_tears_down_once() {
+        let service = AnemoNetworkService::new();
+        service.start(loopback_config("stop-test")).await.unwrap();
+        let addr = service.effective_config().await.unwrap().local_addr;
+        let mut peers = Vec::new();
+        for name in ["stop-peer-a", "stop-peer-b"] {
+            let peer = AnemoNetworkService::new();
+            peer.start(loopback_config(name)).await.unwrap();
+            peer.connect(addr).await.unwrap();
+            peers.push(peer);
+        }
+        let mut events = service.event_bus.subscribe();
+
+        let stops: Vec<_> = (0..8)
+            .map(|_| {
+                let service = service.clone();
+                tokio::spawn(async move { service.stop().await })
+            })
+            .collect();
+        // 参与同一次拆除的调用者得到同一份报告，拆除完成后才到达的调用者得到空报告
+        let mut disconnected = Vec::new();
+        for stop in stops {
+            disconnected.push(stop.await.unwrap().unwrap().peers_disconnected);
+        }
+        assert!(disconnected.contains(&2));
+        assert!(disconnected.iter().all(|count| *count == 2 || *count == 0));
+
+        assert!(!*service.is_running.read().await);
+        assert!(service.local_node_id.read().await.is_none());
+
+        let mut stopped_events = 0;
+        while let Ok(event) = events.try_recv() {
+            if matches!(event, crate::NetworkEvent::ServiceStopped) {
+                stopped_events += 1;
+            }
+        }
+        assert_eq!(stopped_events, 1);
+
+        // 网络已关闭，对端看到连接断开
+        for peer in &peers {
+            tokio::time::timeout(Duration::from_secs(2), async {
+                while !peer.get_connected_nodes().await.unwrap().is_empty() {
+                    tokio::time::sleep(Duration::from_millis(10)).await;
+                }
+            })
+            .await
+            .expect("对端应看到连接断开");
+            peer.stop().await.unwrap();
+        }
+    }
+
+    #[tokio::test]
+    async fn test_stop_reports_handlers_of_remote_messages() {
+        let receiver = AnemoNetworkService::new();
+        let sender = AnemoNetworkService::new();
+        let gate = Arc::new(tokio::sync::Semaphore::new(0));
+        receiver
+            .register_message_handler(
+                MessageType::chat(),
+                Box::new(GatedHandler { gate: gate.clone() }),
+            )
+            .await
+            .unwrap();
+        connect_pair(
+            &receiver,
+            NetworkServiceConfig {
+                shutdown_grace_ms: 50,
+                ..loopback_config("report-receiver")
+            },
+            &sender,
+            loopback_config("report-sender"),
+        )
+        .await;
+        let receiver_id = receiver.get_local_node_id().await.unwrap();
+        let sender_id = sender.get_local_node_id().await.unwrap();
+
+        let send = {
+            let sender = sender.clone();
+            let message =
+                NetworkMessage::new(MessageType::chat(), sender_id, serde_json::json!({}));
+            tokio::spawn(async move { sender.unicast(receiver_id, message, None).await })
+        };
+        tokio::time::timeout(Duration::from_secs(2), async {
+            while receiver.handler_pool_stats().await.active == 0 {
+                tokio::task::yield_now().await;
+            }
+        })
+        .await
+        .expect("处理器应开始运行");
+
+        // 卡住的处理器在宽限期后被中止
+        let report = receiver.stop().await.unwrap();
+        assert_eq!(report.handlers_aborted, 1);
+        assert_eq!(report.peers_disconnected, 1);
+        let _ = send.await.unwrap();
+
+        sender.stop().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_rotate_key_requires_running_service() {
+        let service = AnemoNetworkService::new();
+        let result = service.rotate_key([7u8; 32]).await;
+        assert!(matches!(result, Err(NetworkError::ConfigError(_))));
+    }
+
+    #[tokio::test]
+    async fn test_rotate_key_on_running_service() {
+        let server = AnemoNetworkService::new();
+        server
+            .start(loopback_config("rotate-server"))
+            .await
+            .unwrap();
+        let server_addr = server.effective_config().await.unwrap().local_addr;
+
+        let service = AnemoNetworkService::new();
+        let node_id = "rotate-node".to_string();
+        service
+            .start(NetworkServiceConfig {
+                node_id: Some(node_id.clone()),
+                private_key: KeySource::Fixed([71u8; 32]),
+                ..loopback_config("rotate-node")
+            })
+            .await
+            .unwrap();
+        service.add_known_server(server_addr.to_string()).await;
+        service.connect_to_known_servers().await;
+        let before = service.effective_config().await.unwrap();
+
+        service.rotate_key([72u8; 32]).await.unwrap();
+
+        // 新私钥产生新的PeerId，地址和显式节点ID保持不变，并重新连接已知服务器
+        let after = service.effective_config().await.unwrap();
+        assert_ne!(after.peer_id, before.peer_id);
+        assert_eq!(after.local_addr, before.local_addr);
+        assert_eq!(after.node_id, node_id);
+        assert_eq!(
+            GLOBAL_NODES.read().await.get(&node_id),
+            Some(&after.peer_id)
+        );
+        assert_eq!(service.get_connected_nodes().await.unwrap().len(), 1);
+
+        service.stop().await.unwrap();
+        server.stop().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_rotate_key_rolls_back_when_restart_fails() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        // 第二次构造路由器（使用新私钥启动）时占用内置路由，使启动失败
+        let built = Arc::new(AtomicUsize::new(0));
+        let counter = built.clone();
+        let service = AnemoNetworkService::new().with_router(move || {
+            if counter.fetch_add(1, Ordering::SeqCst) == 1 {
+                CustomRoutes::new().route(NODE_INFO_ROUTE, EchoRpc)
+            } else {
+                CustomRoutes::new()
+            }
+        });
+        let node_id = "rotate-rollback-node".to_string();
+        service
+            .start(NetworkServiceConfig {
+                node_id: Some(node_id.clone()),
+                private_key: KeySource::Fixed([73u8; 32]),
+                ..loopback_config("rotate-rollback")
+            })
+            .await
+            .unwrap();
+        let before = service.effective_config().await.unwrap();
+
+        let result = service.rotate_key([74u8; 32]).await;
+        assert!(matches!(result, Err(NetworkError::ConfigError(_))));
+        assert_eq!(built.load(Ordering::SeqCst), 3);
+
+        // 恢复原私钥：服务仍在运行，身份不变
+        let after = service.effective_config().await.unwrap();
+        assert_eq!(after.peer_id, before.peer_id);
+        assert_eq!(after.local_addr, before.local_addr);
+        assert_eq!(
+            GLOBAL_NODES.read().await.get(&node_id),
+            Some(&before.peer_id)
+        );
+
+        service.stop().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_self_addressed_unicast_refused() {
+        let service = AnemoNetworkService::new();
+        let local_id = "loop-test:127.0.0.1:0".to_string();
+        *service.is_running.write().await = true;
+        *service.local_node_id.write().await = Some(local_id.clone());
+
+        let message =
+            NetworkMessage::new(MessageType::chat(), local_id.clone(), serde_json::json!({}));
+        let result = service.unicast(local_id, message, None).await;
+
+        assert!(matches!(result, Err(NetworkError::SendError(_))));
+        assert_eq!(service.stats_snapshot().await.stats.loop_prevented, 1);
+        // 回环消息不进入死信队列
+        assert!(service.dead_letters().is_empty().await);
+    }
+
+    #[tokio::test]
+    async fn test_known_server_connects_bounded() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        let current = AtomicUsize::new(0);
+        let max_seen = AtomicUsize::new(0);
+        let servers: Vec<String> = (1..=6).map(|port| format!("127.0.0.1:{}", port)).collect();
+
+        let connected = AnemoNetworkService::connect_concurrently(servers, 3, |addr| {
+            let current = &current;
+            let max_seen = &max_seen;
+            async move {
+                let now = current.fetch_add(1, Ordering::SeqCst) + 1;
+                max_seen.fetch_max(now, Ordering::SeqCst);
+                tokio::time::sleep(Duration::from_millis(20)).await;
+                current.fetch_sub(1, Ordering::SeqCst);
+                Ok(PeerId([addr.port() as u8; 32]))
+            }
+        })
+        .await;
+
+        assert_eq!(connected.len(), 6);
+        assert_eq!(max_seen.load(Ordering::SeqCst), 3);
+    }
+
+    #[tokio::test]
+    async fn test_known_server_hostname_resolved() {
+        let attempted = std::sync::Mutex::new(Vec::new());
+        let servers = vec!["localhost:7100".to_string()];
+
+        // 只有IPv4地址可以连接，解析到的其他地址失败后继续尝试
+        let connected = AnemoNetworkService::connect_concurrently(servers, 1, |addr| {
+            attempted.lock().unwrap().push(addr);
+            async move {
+                if addr.is_ipv4() {
+                    Ok(PeerId([7; 32]))
+                } else {
+                    Err(ConnectError::Refused("connection refused".to_string()))
+                }
+            }
+        })
+        .await;
+
+        assert_eq!(
+            connected,
+            vec![("localhost:7100".to_string(), PeerId([7; 32]))]
+        );
+        let attempted = attempted.into_inner().unwrap();
+        assert!(!attempted.is_empty());
+        assert!(attempted
+            .iter()
+            .all(|addr| addr.ip().is_loopback() && addr.port() == 7100));
+
+        let unresolved = AnemoNetworkService::connect_concurrently(
+            vec!["missing-port".to_string()],
+            1,
+            |_| async { Ok(PeerId([7; 32])) },
+        )
+        .await;
+        assert!(unresolved.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_probe_reports_distinct_results() {
+        let servers = vec![
+            "127.0.0.1:7001".to_string(),
+            "127.0.0.1:7002".to_string(),
+            "127.0.0.1:7003".to_string(),
+            "not-an-address".to_string(),
+        ];
+
+        let results = AnemoNetworkService::probe_concurrently(
+            servers,
+            2,
+            Duration::from_millis(100),
+            |addr, remaining| async move {
+                match addr.port() {
+                    7001 => {
+                        tokio::time::sleep(Duration::from_millis(5)).await;
+                        Ok(())
+                    }
+                    7002 => Err(ConnectError::Refused("connection refused".to_string())),
+                    _ => {
+                        tokio::time::sleep(remaining).await;
+                        Err(ConnectError::Timeout("probe timed out".to_string()))
+                    }
+                }
+            },
+        )
+        .await;
+
+        let addrs: Vec<&str> = results.iter().map(|(addr, _)| addr.as_str()).collect();
+        assert_eq!(
+            addrs,
+            vec![
+                "127.0.0.1:7001",
+                "127.0.0.1:7002",
+                "127.0.0.1:7003",
+                "not-an-address"
+            ]
+        );
+        match &results[0].1 {
+            ProbeResult::Reachable { rtt } => assert!(*rtt >= Duration::from_millis(5)),
+            other => panic!("意外的结果: {:?}", other),
+        }
+        assert_eq!(results[1].1, ProbeResult::Refused);
+        assert_eq!(results[2].1, ProbeResult::Timeout);
+        assert!(matches!(results[3].1, ProbeResult::Failed(_)));
+    }
+
+    #[tokio::test]
+    async fn test_probe_real_servers_leaves_no_connections() {
+        let target = AnemoNetworkService::new();
+        target.start(loopback_config("probe-target")).await.unwrap();
+        let target_addr = target.effective_config().await.unwrap().local_addr;
+
+        // 依次为：运行中的服务、没有服务监听的本地端口、不可路由的地址
+        let prober = AnemoNetworkService::new();
+        for addr in [
+            target_addr.to_string(),
+            "127.0.0.1:1".to_string(),
+            "10.255.255.1:9".to_string(),
+        ] {
+            prober.add_known_server(addr).await;
+        }
+        prober.start(loopback_config("prober")).await.unwrap();
+        let mut events = prober.subscribe_events().unwrap();
+
+        let results = prober.probe_known_servers(Duration::from_millis(300)).await;
+        assert!(matches!(results[0].1, ProbeResult::Reachable { .. }));
+        assert_eq!(results[1].1, ProbeResult::Refused);
+        assert_eq!(results[2].1, ProbeResult::Timeout);
+
+        // 无论结果如何，探测连接都已断开
+        let network = prober.network.read().await.clone().unwrap();
+        assert!(network.peers().is_empty());
+        tokio::time::timeout(Duration::from_secs(2), async {
+            while !target.get_connected_nodes().await.unwrap().is_empty() {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .expect("被探测的服务器应看到连接断开");
+
+        // 探测不登记节点，也不发布连接和断开事件
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert!(prober.nodes.read().await.is_empty());
+        while let Ok(event) = events.try_recv() {
+            assert!(
+                !matches!(
+                    event,
+                    crate::NetworkEvent::NodeConnected { .. }
+                        | crate::NetworkEvent::NodeDisconnected { .. }
+                ),
+                "探测不应发布连接事件: {:?}",
+                event
+            );
+        }
+
+        prober.stop().await.unwrap();
+        target.stop().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_known_server_hostname_connects() {
+        let server = AnemoNetworkService::new();
+        server
+            .start(loopback_config("hostname-server"))
+            .await
+            .unwrap();
+        let port = server.effective_config().await.unwrap().local_addr.port();
+
+        let client = AnemoNetworkService::new();
+        client
+            .start(loopback_config("hostname-client"))
+            .await
+            .unwrap();
+        client.add_known_server(format!("localhost:{}", port)).await;
+        client.connect_to_known_servers().await;
+
+        assert_eq!(
+            client.get_connected_nodes().await.unwrap(),
+            vec![server.get_local_node_id().await.unwrap()]
+        );
+
+        client.stop().await.unwrap();
+        server.stop().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_connect_before_start_fails() {
+        let service = AnemoNetworkService::new();
+        let result = service.connect("127.0.0.1:9".parse().unwrap()).await;
+        assert!(matches!(result, Err(ConnectError::Other(_))));
+    }
+
+    #[tokio::test]
+    async fn test_connect_failures_are_classified() {
+        let service = AnemoNetworkService::new();
+        service
+            .start(loopback_config("connect-failures"))
+            .await
+            .unwrap();
+
+        // 本机没有监听的端口：被拒绝，或对端不回应时超时
+        let closed = service.connect("127.0.0.1:1".parse().unwrap()).await;
+        assert!(
+            matches!(
+                closed,
+                Err(ConnectError::Refused(_) | ConnectError::Timeout(_))
+            ),
+            "{:?}",
+            closed
+        );
+
+        // 不可路由的地址：超时或不可达
+        let unroutable = tokio::time::timeout(
+            Duration::from_secs(30),
+            service.connect("10.255.255.1:9".parse().unwrap()),
+        )
+        .await
+        .expect("连接不可路由的地址应在超时后失败");
+        assert!(
+            matches!(
+                unroutable,
+                Err(ConnectError::Timeout(_) | ConnectError::AddressUnreachable(_))
+            ),
+            "{:?}",
+            unroutable
+        );
+
+        service.stop().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_unicast_addr_requires_running_service() {
+        let service = AnemoNetworkService::new();
+        let message = NetworkMessage::new(
+            MessageType::system(),
+            "tester".to_string(),
+            serde_json::json!({}),
+        );
+
+        let result = service
+            .unicast_addr("127.0.0.1:9".parse().unwrap(), message)
+            .await;
+        assert!(matches!(result, Err(NetworkError::ConfigError(_))));
+    }
+
+    #[tokio::test]
+    async fn test_unicast_addr_delivers_over_loopback() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        let receiver = AnemoNetworkService::new();
+        let sender = AnemoNetworkService::new();
+        let calls = Arc::new(AtomicUsize::new(0));
+        receiver
+            .register_message_handler(
+                MessageType::chat(),
+                Box::new(CountingHandler {
+                    calls: calls.clone(),
+                }),
+            )
+            .await
+            .unwrap();
+        receiver
+            .start(loopback_config("addr-receiver"))
+            .await
+            .unwrap();
+        sender.start(loopback_config("addr-sender")).await.unwrap();
+        let receiver_addr = receiver.effective_config().await.unwrap().local_addr;
+        let sender_id = sender.get_local_node_id().await.unwrap();
+
+        // 未连接时按地址建立连接并发送，再次发送复用同一连接
+        for expected in 1..=2 {
+            sender
+                .unicast_addr(receiver_addr, text_message(&sender_id))
+                .await
+                .unwrap();
+            assert_eq!(calls.load(Ordering::SeqCst), expected);
+        }
+        assert_eq!(
+            sender.get_connected_nodes().await.unwrap(),
+            vec![receiver.get_local_node_id().await.unwrap()]
+        );
+
+        sender.stop().await.unwrap();
+        receiver.stop().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_serialization_stats_per_message_type() {
+        let service = AnemoNetworkService::new();
+        let small = NetworkMessage::new(
+            MessageType::chat(),
+            "node-a".to_string(),
+            serde_json::json!({ "text": "hi" }),
+        );
+        let large = NetworkMessage::new(
+            MessageType::timesync(),
+            "node-a".to_string(),
+            serde_json::json!({ "text": "x".repeat(1000) }),
+        );
+
+        // 未启用时不记录
+        service.encode_message(&small).await.unwrap();
+        assert!(service
+            .stats_snapshot()
+            .await
+            .stats
+            .serialization
+            .is_empty());
+
+        *service.config.write().await = Some(NetworkServiceConfig {
+            record_serialization_stats: true,
+            ..Default::default()
+        });
+        let small_len = service.encode_message(&small).await.unwrap().len() as u64;
+        service.encode_message(&small).await.unwrap();
+        let large_len = service.encode_message(&large).await.unwrap().len() as u64;
+
+        let stats = service.stats_snapshot().await.stats.serialization;
+        let chat = &stats[&MessageType::chat()];
+        assert_eq!(chat.count, 2);
+        assert_eq!(chat.total_bytes, small_len * 2);
+        assert_eq!(chat.max_bytes, small_len);
+        let timesync = &stats[&MessageType::timesync()];
+        assert_eq!(timesync.count, 1);
+        assert_eq!(timesync.max_bytes, large_len);
+        assert!(timesync.mean_bytes() > chat.mean_bytes());
+    }
+
+    #[tokio::test]
+    async fn test_stats_snapshot_rates() {
+        let service = AnemoNetworkService::new();
+        let before = service.stats_snapshot().await;
+
+        for _ in 0..10 {
+            service.record_sent(100).await;
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+
+        let after = service.stats_snapshot().await;
+        let rates = after.rate_since(&before);
+
+        assert_eq!(after.stats.messages_sent - before.stats.messages_sent, 10);
+        assert!(rates.messages_sent_per_sec > 0.0);
+        // 10条消息在100ms以上完成，速率不应超过100条/秒
+        assert!(rates.messages_sent_per_sec <= 100.0);
+        assert!((rates.bytes_sent_per_sec - rates.messages_sent_per_sec * 100.0).abs() < 1e-6);
+        assert_eq!(rates.errors_per_sec, 0.0);
+
+        // 同一快照之间的速率为零
+        assert_eq!(after.rate_since(&after), StatsRates::default());
+    }
 }
@@ -0,0 +1,224 @@
+//! 重连退避
+//!
+//! 服务器重启时，所有客户端会几乎同时发现连接断开。如果它们按固定间隔重连，
+//! 每一轮尝试都会同时到达服务器。退避延迟中加入随机抖动，把尝试分散到整个
+//! 退避窗口；可选的并发上限进一步限制同时进行的重连数量。
+
+use rand::Rng;
+use std::future::Future;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+use tracing::warn;
+
+/// 指数退避配置
+#[derive(Debug, Clone, PartialEq)]
+pub struct BackoffConfig {
+    /// 第一次重试前的基础延迟（毫秒）
+    pub initial_ms: u64,
+    /// 基础延迟上限（毫秒）
+    pub max_ms: u64,
+    /// 每次失败后基础延迟的放大倍数
+    pub multiplier: f64,
+    /// 抖动比例（0.0-1.0）：实际延迟在 `[基础延迟 × (1 - jitter), 基础延迟]` 内均匀分布，
+    /// 1.0 表示完全抖动
+    pub jitter: f64,
+}
+
+impl Default for BackoffConfig {
+    fn default() -> Self {
+        Self {
+            initial_ms: 500,
+            max_ms: 30_000,
+            multiplier: 2.0,
+            jitter: 1.0,
+        }
+    }
+}
+
+impl BackoffConfig {
+    /// 第 `attempt` 次失败后（从0开始）不含抖动的基础延迟
+    pub fn base_delay(&self, attempt: u32) -> Duration {
+        let factor = self
+            .multiplier
+            .max(1.0)
+            .powi(attempt.min(i32::MAX as u32) as i32);
+        let delay_ms = (self.initial_ms as f64 * factor).min(self.max_ms as f64);
+        Duration::from_millis(delay_ms as u64)
+    }
+
+    /// 第 `attempt` 次失败后的延迟，随机抖动
+    pub fn delay(&self, attempt: u32) -> Duration {
+        self.delay_with(attempt, rand::rng().random::<f64>())
+    }
+
+    /// 按给定的随机采样（0.0-1.0）计算延迟
+    pub fn delay_with(&self, attempt: u32, sample: f64) -> Duration {
+        let jitter = self.jitter.clamp(0.0, 1.0);
+        let scale = 1.0 - jitter * sample.clamp(0.0, 1.0);
+        self.base_delay(attempt).mul_f64(scale)
+    }
+}
+
+/// 重连并发限制，在多个重连循环之间共享
+#[derive(Debug, Clone)]
+pub struct ReconnectLimiter {
+    slots: Arc<Semaphore>,
+}
+
+impl ReconnectLimiter {
+    /// 创建限制器，最多同时进行 `max_concurrent` 个重连尝试
+    pub fn new(max_concurrent: usize) -> Self {
+        Self {
+            slots: Arc::new(Semaphore::new(max_concurrent.max(1))),
+        }
+    }
+
+    /// 等待一个重连名额，名额在返回值释放时归还
+    pub async fn acquire(&self) -> OwnedSemaphorePermit {
+        self.slots
+            .clone()
+            .acquire_owned()
+            .await
+            .expect("重连限制器不会被关闭")
+    }
+}
+
+/// 按退避策略重试，直到成功或达到 `max_attempts`（`None` 表示不限次数）
+///
+/// `attempt_fn` 收到尝试序号（从1开始）；提供 `limiter` 时，每次尝试期间占用一个名额。
+/// 返回最后一次失败的错误。
+pub async fn retry_with_backoff<T, E, F, Fut>(
+    backoff: &BackoffConfig,
+    limiter: Option<&ReconnectLimiter>,
+    max_attempts: Option<u32>,
+    mut attempt_fn: F,
+) -> Result<T, E>
+where
+    E: std::fmt::Display,
+    F: FnMut(u32) -> Fut,
+    Fut: Future<Output = Result<T, E>>,
+{
+    let mut attempt = 0u32;
+    loop {
+        attempt += 1;
+
+        let result = {
+            let _permit = match limiter {
+                Some(limiter) => Some(limiter.acquire().await),
+                None => None,
+            };
+            attempt_fn(attempt).await
+        };
+
+        match result {
+            Ok(value) => return Ok(value),
+            Err(e) if max_attempts.is_some_and(|max| attempt >= max) => return Err(e),
+            Err(e) => {
+                let delay = backoff.delay(attempt - 1);
+                warn!(
+                    "第 {} 次重连失败: {}，{}ms 后重试",
+                    attempt,
+                    e,
+                    delay.as_millis()
+                );
+                tokio::time::sleep(delay).await;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Mutex;
+    use std::time::Instant;
+
+    #[test]
+    fn test_delay_grows_and_jitters_within_window() {
+        let backoff = BackoffConfig {
+            initial_ms: 100,
+            max_ms: 1000,
+            multiplier: 2.0,
+            jitter: 0.5,
+        };
+        assert_eq!(backoff.base_delay(0), Duration::from_millis(100));
+        assert_eq!(backoff.base_delay(3), Duration::from_millis(800));
+        assert_eq!(backoff.base_delay(10), Duration::from_millis(1000));
+
+        assert_eq!(backoff.delay_with(1, 0.0), Duration::from_millis(200));
+        assert_eq!(backoff.delay_with(1, 1.0), Duration::from_millis(100));
+    }
+
+    #[tokio::test]
+    async fn test_many_clients_reconnect_spread_out() {
+        const CLIENTS: usize = 40;
+        let backoff = BackoffConfig {
+            initial_ms: 200,
+            max_ms: 200,
+            multiplier: 2.0,
+            jitter: 1.0,
+        };
+        let limiter = ReconnectLimiter::new(4);
+        let started = Instant::now();
+        let retry_times = Arc::new(Mutex::new(Vec::new()));
+        let in_flight = Arc::new(AtomicUsize::new(0));
+        let max_in_flight = Arc::new(AtomicUsize::new(0));
+
+        // 服务器重启：每个客户端的第一次尝试失败，第二次成功
+        let clients: Vec<_> = (0..CLIENTS)
+            .map(|_| {
+                let backoff = backoff.clone();
+                let limiter = limiter.clone();
+                let retry_times = retry_times.clone();
+                let in_flight = in_flight.clone();
+                let max_in_flight = max_in_flight.clone();
+                tokio::spawn(async move {
+                    retry_with_backoff(&backoff, Some(&limiter), Some(3), |attempt| {
+                        let retry_times = retry_times.clone();
+                        let in_flight = in_flight.clone();
+                        let max_in_flight = max_in_flight.clone();
+                        async move {
+                            let current = in_flight.fetch_add(1, Ordering::SeqCst) + 1;
+                            max_in_flight.fetch_max(current, Ordering::SeqCst);
+                            tokio::time::sleep(Duration::from_millis(1)).await;
+                            in_flight.fetch_sub(1, Ordering::SeqCst);
+                            if attempt == 1 {
+                                return Err("服务器不可用");
+                            }
+                            retry_times.lock().unwrap().push(started.elapsed());
+                            Ok(())
+                        }
+                    })
+                    .await
+                })
+            })
+            .collect();
+        for client in clients {
+            client.await.unwrap().unwrap();
+        }
+
+        assert!(max_in_flight.load(Ordering::SeqCst) <= 4);
+
+        // 重试分布在整个退避窗口内，而不是集中在同一时刻
+        let retry_times = retry_times.lock().unwrap().clone();
+        assert_eq!(retry_times.len(), CLIENTS);
+        let buckets: std::collections::HashSet<u128> = retry_times
+            .iter()
+            .map(|elapsed| elapsed.as_millis() / 50)
+            .collect();
+        assert!(buckets.len() >= 3, "重试集中在 {:?}", buckets);
+        let max_per_bucket = buckets
+            .iter()
+            .map(|bucket| {
+                retry_times
+                    .iter()
+                    .filter(|elapsed| elapsed.as_millis() / 50 == *bucket)
+                    .count()
+            })
+            .max()
+            .unwrap();
+        assert!(max_per_bucket < CLIENTS * 3 / 4);
+    }
+}
@@ -0,0 +1,22 @@
+//! 连接认证
+//!
+//! 新连接建立后调用用户提供的异步认证回调，根据节点的 `PeerId`
+//! 和握手元数据（例如携带的访问令牌）决定接受或拒绝该连接。
+
+use anemo::PeerId;
+use futures::future::BoxFuture;
+use std::collections::HashMap;
+use std::sync::Arc;
+
+/// 认证结果
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AuthDecision {
+    /// 接受连接
+    Accept,
+    /// 拒绝连接，附带原因
+    Reject(String),
+}
+
+/// 连接认证回调，参数为对端 `PeerId` 和握手元数据
+pub type ConnectionAuthenticator =
+    Arc<dyn Fn(PeerId, HashMap<String, String>) -> BoxFuture<'static, AuthDecision> + Send + Sync>;
@@ -0,0 +1,202 @@
+//! 死信队列
+//!
+//! 记录未能投递的消息。内存中保留最近的若干条以便快速查询，
+//! 可选地将每条死信以JSON行的形式追加写入文件，按大小轮转，
+//! 便于崩溃后进行消息丢失分析。文件写入在阻塞线程池中执行。
+
+use crate::{NetworkMessage, NodeId};
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, PoisonError};
+use tokio::sync::Mutex;
+use tracing::warn;
+
+/// 死信：未能投递的消息
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DeadLetter {
+    /// 目标节点
+    pub peer: NodeId,
+    /// 未投递的消息
+    pub message: NetworkMessage,
+    /// 投递失败原因
+    pub reason: String,
+    /// 记录时间（毫秒）
+    pub timestamp: u64,
+}
+
+impl DeadLetter {
+    /// 创建新的死信
+    pub fn new(peer: NodeId, message: NetworkMessage, reason: impl Into<String>) -> Self {
+        Self {
+            peer,
+            message,
+            reason: reason.into(),
+            timestamp: crate::now_millis(),
+        }
+    }
+}
+
+/// 死信队列配置
+#[derive(Debug, Clone)]
+pub struct DeadLetterConfig {
+    /// 内存中保留的最大死信数量
+    pub capacity: usize,
+    /// 死信文件路径，`None` 表示只保存在内存中
+    pub file_path: Option<PathBuf>,
+    /// 单个死信文件的最大字节数，超过后轮转为 `<file>.1`
+    pub max_file_bytes: u64,
+}
+
+impl Default for DeadLetterConfig {
+    fn default() -> Self {
+        Self {
+            capacity: 1000,
+            file_path: None,
+            max_file_bytes: 10 * 1024 * 1024,
+        }
+    }
+}
+
+/// 死信队列
+#[derive(Clone)]
+pub struct DeadLetterQueue {
+    /// 配置
+    config: DeadLetterConfig,
+    /// 内存中最近的死信
+    letters: Arc<Mutex<VecDeque<DeadLetter>>>,
+    /// 串行化文件写入和轮转（只在阻塞线程池中持有）
+    file_lock: Arc<std::sync::Mutex<()>>,
+}
+
+impl DeadLetterQueue {
+    /// 创建新的死信队列
+    pub fn new(config: DeadLetterConfig) -> Self {
+        Self {
+            config,
+            letters: Arc::new(Mutex::new(VecDeque::new())),
+            file_lock: Arc::new(std::sync::Mutex::new(())),
+        }
+    }
+
+    /// 记录一条死信
+    pub async fn push(&self, letter: DeadLetter) {
+        if let Some(path) = self.config.file_path.clone() {
+            let max_bytes = self.config.max_file_bytes;
+            let file_lock = self.file_lock.clone();
+            let record = letter.clone();
+            let file = path.clone();
+            let result = tokio::task::spawn_blocking(move || {
+                let _guard = file_lock.lock().unwrap_or_else(PoisonError::into_inner);
+                Self::append_to_file(&file, max_bytes, &record)
+            })
+            .await
+            .map_err(std::io::Error::other)
+            .and_then(|result| result);
+            if let Err(e) = result {
+                warn!("写入死信文件 {} 失败: {}", path.display(), e);
+            }
+        }
+
+        let mut letters = self.letters.lock().await;
+        if letters.len() >= self.config.capacity.max(1) {
+            letters.pop_front();
+        }
+        letters.push_back(letter);
+    }
+
+    /// 追加写入死信文件，超过大小上限时先轮转
+    fn append_to_file(path: &Path, max_bytes: u64, letter: &DeadLetter) -> std::io::Result<()> {
+        let mut line = serde_json::to_vec(letter)?;
+        line.push(b'\n');
+
+        let current_len = fs::metadata(path).map(|meta| meta.len()).unwrap_or(0);
+        if current_len > 0 && current_len + line.len() as u64 > max_bytes {
+            let mut rotated = path.as_os_str().to_owned();
+            rotated.push(".1");
+            fs::rename(path, rotated)?;
+        }
+
+        let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+        file.write_all(&line)
+    }
+
+    /// 获取内存中的死信（从旧到新）
+    pub async fn recent(&self) -> Vec<DeadLetter> {
+        self.letters.lock().await.iter().cloned().collect()
+    }
+
+    /// 取出内存中的所有死信
+    pub async fn take(&self) -> Vec<DeadLetter> {
+        self.letters.lock().await.drain(..).collect()
+    }
+
+    /// 内存中的死信数量
+    pub async fn len(&self) -> usize {
+        self.letters.lock().await.len()
+    }
+
+    /// 内存中是否没有死信
+    pub async fn is_empty(&self) -> bool {
+        self.letters.lock().await.is_empty()
+    }
+}
+
+impl Default for DeadLetterQueue {
+    fn default() -> Self {
+        Self::new(DeadLetterConfig::default())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::MessageType;
+
+    fn test_letter(n: u64) -> DeadLetter {
+        let message = NetworkMessage::new(
+            MessageType::chat(),
+            "node-a".to_string(),
+            serde_json::json!({ "n": n }),
+        );
+        DeadLetter::new("node-b".to_string(), message, "测试")
+    }
+
+    #[tokio::test]
+    async fn test_file_sink_rotation() {
+        let dir = std::env::temp_dir().join(format!("dead-letters-{}", uuid::Uuid::new_v4()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("dead_letters.jsonl");
+
+        let line_len = serde_json::to_vec(&test_letter(0)).unwrap().len() as u64 + 1;
+        let queue = DeadLetterQueue::new(DeadLetterConfig {
+            capacity: 2,
+            file_path: Some(path.clone()),
+            max_file_bytes: line_len * 2,
+        });
+
+        for n in 0..3 {
+            queue.push(test_letter(n)).await;
+        }
+
+        // 内存中只保留最近的两条
+        let recent: Vec<_> = queue
+            .recent()
+            .await
+            .iter()
+            .map(|letter| letter.message.payload["n"].as_u64().unwrap())
+            .collect();
+        assert_eq!(recent, vec![1, 2]);
+
+        // 前两条轮转到 .1 文件，第三条写入新文件
+        let rotated = fs::read_to_string(dir.join("dead_letters.jsonl.1")).unwrap();
+        assert_eq!(rotated.lines().count(), 2);
+        let current = fs::read_to_string(&path).unwrap();
+        let letter: DeadLetter = serde_json::from_str(current.lines().next().unwrap()).unwrap();
+        assert_eq!(letter.message.payload["n"], 2);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,61 @@
+//! 消息投递时延统计
+//!
+//! 入站消息被接受时计算 `当前时间 - 消息时间戳`，反映包括发送端排队、
+//! 网络传输和接收端排队在内的端到端时延。发送端与接收端时钟存在偏差时，
+//! 负值按0记录。
+//!
+//! 消息类型由对端决定，内置类型和本地注册了处理器的类型单独统计，其他类型合并到
+//! [`OTHER_MESSAGE_TYPE`] 中，统计表的大小不随对端发送的类型增长。
+
+use serde::{Deserialize, Serialize};
+
+/// 合并统计未知消息类型时使用的类型名
+pub const OTHER_MESSAGE_TYPE: &str = "other";
+
+/// 直方图各桶的上界（毫秒），超过最后一个上界的计入溢出桶
+pub const DELIVERY_AGE_BUCKETS_MS: [u64; 8] = [1, 5, 10, 50, 100, 500, 1000, 5000];
+
+/// 单个消息类型的投递时延统计
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DeliveryAgeStats {
+    /// 记录次数
+    pub count: u64,
+    /// 时延总和（毫秒）
+    pub sum_ms: u64,
+    /// 最大时延（毫秒）
+    pub max_ms: u64,
+    /// 最近一次时延（毫秒）
+    pub last_ms: u64,
+    /// 各桶计数，最后一个元素为溢出桶
+    pub buckets: [u64; DELIVERY_AGE_BUCKETS_MS.len() + 1],
+}
+
+impl DeliveryAgeStats {
+    /// 记录一次投递时延
+    pub fn record(&mut self, age_ms: u64) {
+        self.count += 1;
+        self.sum_ms = self.sum_ms.saturating_add(age_ms);
+        self.max_ms = self.max_ms.max(age_ms);
+        self.last_ms = age_ms;
+
+        let bucket = DELIVERY_AGE_BUCKETS_MS
+            .iter()
+            .position(|&bound| age_ms <= bound)
+            .unwrap_or(DELIVERY_AGE_BUCKETS_MS.len());
+        self.buckets[bucket] += 1;
+    }
+
+    /// 平均时延（毫秒）
+    pub fn mean_ms(&self) -> f64 {
+        if self.count == 0 {
+            0.0
+        } else {
+            self.sum_ms as f64 / self.count as f64
+        }
+    }
+}
+
+/// 根据消息时间戳计算投递时延（毫秒）
+pub fn delivery_age_ms(sent_at_ms: u64, now_ms: u64) -> u64 {
+    now_ms.saturating_sub(sent_at_ms)
+}
@@ -2,6 +2,58 @@
 
 use thiserror::Error;
 
+/// 连接失败的归类诊断，携带底层原因
+#[derive(Debug, Error)]
+pub enum ConnectError {
+    /// 地址不可达
+    #[error("地址不可达: {0}")]
+    AddressUnreachable(String),
+
+    /// TLS握手失败（对端可能不是Anemo节点或证书不匹配）
+    #[error("TLS握手失败: {0}")]
+    TlsHandshakeFailed(String),
+
+    /// 连接超时（对端未响应）
+    #[error("连接超时: {0}")]
+    Timeout(String),
+
+    /// 连接被拒绝或重置
+    #[error("连接被拒绝: {0}")]
+    Refused(String),
+
+    /// 其他原因
+    #[error("连接失败: {0}")]
+    Other(String),
+}
+
+impl ConnectError {
+    /// 根据底层错误链归类连接失败原因
+    pub fn classify(cause: &anyhow::Error) -> Self {
+        let detail = format!("{:#}", cause);
+        let lower = detail.to_lowercase();
+
+        if lower.contains("timed out") || lower.contains("timeout") {
+            ConnectError::Timeout(detail)
+        } else if lower.contains("refused") || lower.contains("reset") {
+            ConnectError::Refused(detail)
+        } else if lower.contains("unreachable")
+            || lower.contains("no route")
+            || lower.contains("invalid remote address")
+            || lower.contains("address not available")
+        {
+            ConnectError::AddressUnreachable(detail)
+        } else if lower.contains("tls")
+            || lower.contains("crypto")
+            || lower.contains("certificate")
+            || lower.contains("handshake")
+        {
+            ConnectError::TlsHandshakeFailed(detail)
+        } else {
+            ConnectError::Other(detail)
+        }
+    }
+}
+
 /// 网络服务错误类型
 #[derive(Debug, Error)]
 pub enum NetworkError {
@@ -13,6 +65,10 @@ pub enum NetworkError {
     #[error("连接错误: {0}")]
     ConnectionError(String),
 
+    /// 连接失败（已归类）
+    #[error("{0}")]
+    ConnectFailed(#[from] ConnectError),
+
     /// 消息发送错误
     #[error("消息发送错误: {0}")]
     SendError(String),
@@ -33,14 +89,50 @@ pub enum NetworkError {
     #[error("操作超时")]
     TimeoutError,
 
+    /// 观察者模式下不允许的操作
+    #[error("观察者模式下不允许{0}")]
+    ObserverMode(String),
+
+    /// RPC调用超时
+    #[error("RPC调用超时: {0}ms")]
+    RpcTimeout(u64),
+
+    /// 检测到重放消息
+    #[error("检测到重放消息: 发送者 {sender}, 序号 {nonce}")]
+    ReplayDetected { sender: String, nonce: u64 },
+
+    /// 要求重放保护时收到未携带序号的消息
+    #[error("消息缺少重放保护序号: 发送者 {0}")]
+    MissingNonce(String),
+
     /// 节点不存在
     #[error("节点不存在: {0}")]
     NodeNotFound(String),
 
+    /// 节点因持续发送无法解码的数据被屏蔽
+    #[error("节点 {0} 已被屏蔽")]
+    PeerBlocked(String),
+
+    /// 广播没有任何接收者
+    #[error("没有可接收消息的节点")]
+    NoRecipients,
+
+    /// 消息超出大小限制
+    #[error("消息超出大小限制: {0}")]
+    MessageTooLarge(String),
+
+    /// 消息信封版本高于本节点支持的版本
+    #[error("不支持的消息信封版本 {version}（最高支持 {supported}）")]
+    UnsupportedEnvelopeVersion { version: u8, supported: u8 },
+
     /// 内部错误
     #[error("内部错误: {0}")]
     InternalError(String),
 
+    /// 停止服务时部分组件失败，按停止顺序列出（组件名称，错误）
+    #[error("停止服务失败: {}", format_failures(.0))]
+    ShutdownFailed(Vec<(String, String)>),
+
     /// Anemo网络错误
     #[error("Anemo错误: {0:?}")]
     AnemoError(anemo::types::PeerEvent),
@@ -55,6 +147,15 @@ impl From<anemo::types::PeerEvent> for NetworkError {
     }
 }
 
+/// 格式化各组件的停止失败
+fn format_failures(failures: &[(String, String)]) -> String {
+    failures
+        .iter()
+        .map(|(component, error)| format!("{}: {}", component, error))
+        .collect::<Vec<_>>()
+        .join("; ")
+}
+
 /// 网络服务结果类型
 pub type Result<T> = std::result::Result<T, NetworkError>;
 
@@ -94,3 +195,53 @@ impl NetworkError {
         NetworkError::Other(msg.into())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_connect_error_classification() {
+        let cases = [
+            (anyhow::anyhow!("timed out"), "timeout"),
+            (
+                anyhow::anyhow!("connection refused").context("failed to connect"),
+                "refused",
+            ),
+            (anyhow::anyhow!("closed by peer: reset by peer"), "refused"),
+            (
+                anyhow::anyhow!("Network is unreachable (os error 101)"),
+                "unreachable",
+            ),
+            (
+                anyhow::anyhow!("invalid remote address: [::]:0"),
+                "unreachable",
+            ),
+            (
+                anyhow::anyhow!("the cryptographic handshake failed: error 42"),
+                "tls",
+            ),
+            (anyhow::anyhow!("something else"), "other"),
+        ];
+
+        for (cause, expected) in cases {
+            let classified = ConnectError::classify(&cause);
+            let kind = match classified {
+                ConnectError::Timeout(_) => "timeout",
+                ConnectError::Refused(_) => "refused",
+                ConnectError::AddressUnreachable(_) => "unreachable",
+                ConnectError::TlsHandshakeFailed(_) => "tls",
+                ConnectError::Other(_) => "other",
+            };
+            assert_eq!(kind, expected, "错误 {:#} 归类不正确", cause);
+        }
+    }
+
+    #[test]
+    fn test_connect_error_keeps_cause() {
+        let cause = anyhow::anyhow!("connection refused").context("failed to connect");
+        let classified = ConnectError::classify(&cause);
+        assert!(classified.to_string().contains("failed to connect"));
+        assert!(classified.to_string().contains("connection refused"));
+    }
+}
@@ -1,27 +1,97 @@
 //! 网络事件总线
 
-use crate::{NetworkMessage, NodeId};
+use crate::{MessageType, NetworkMessage, NodeId};
 use async_trait::async_trait;
 use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::Arc;
-use tokio::sync::{broadcast, RwLock};
+use tokio::sync::{broadcast, watch, RwLock};
 use tracing::{error, info, warn};
 
+/// 连接元数据，由握手元数据中约定的键解析而来
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ConnectionMetadata {
+    /// 对端地址
+    pub remote_addr: Option<SocketAddr>,
+    /// 对端服务器名称
+    pub server_name: Option<String>,
+    /// 对端协议版本
+    pub protocol_version: Option<String>,
+    /// 对端使用的消息编码格式
+    pub wire_format: Option<String>,
+    /// 对端声明的能力列表
+    pub capabilities: Vec<String>,
+}
+
+impl ConnectionMetadata {
+    /// 握手元数据中服务器名称的键
+    pub const SERVER_NAME_KEY: &'static str = "server_name";
+    /// 握手元数据中协议版本的键
+    pub const PROTOCOL_VERSION_KEY: &'static str = "protocol_version";
+    /// 握手元数据中编码格式的键
+    pub const WIRE_FORMAT_KEY: &'static str = "wire_format";
+    /// 握手元数据中能力列表的键，多个能力以逗号分隔
+    pub const CAPABILITIES_KEY: &'static str = "capabilities";
+
+    /// 从对端地址和握手元数据构建
+    pub fn from_handshake(
+        remote_addr: Option<SocketAddr>,
+        metadata: &HashMap<String, String>,
+    ) -> Self {
+        let capabilities = metadata
+            .get(Self::CAPABILITIES_KEY)
+            .map(|value| {
+                value
+                    .split(',')
+                    .map(str::trim)
+                    .filter(|c| !c.is_empty())
+                    .map(str::to_string)
+                    .collect()
+            })
+            .unwrap_or_default();
+
+        Self {
+            remote_addr,
+            server_name: metadata.get(Self::SERVER_NAME_KEY).cloned(),
+            protocol_version: metadata.get(Self::PROTOCOL_VERSION_KEY).cloned(),
+            wire_format: metadata.get(Self::WIRE_FORMAT_KEY).cloned(),
+            capabilities,
+        }
+    }
+}
+
 /// 网络事件类型
 #[derive(Debug, Clone)]
 pub enum NetworkEvent {
     /// 节点连接事件
+    ///
+    /// `connection` 为解析后的常用字段，`metadata` 保留完整的握手元数据以便扩展。
     NodeConnected {
         node_id: NodeId,
+        connection: ConnectionMetadata,
         metadata: HashMap<String, String>,
     },
     /// 节点断开事件
     NodeDisconnected { node_id: NodeId, reason: String },
+    /// 正在重新连接节点，`attempt` 从 1 开始
+    NodeReconnecting { node_id: NodeId, attempt: u32 },
     /// 消息接收事件
     MessageReceived {
         from: NodeId,
         message: NetworkMessage,
     },
+    /// 收到未注册处理器的消息类型
+    UnhandledMessage {
+        from: NodeId,
+        message_type: MessageType,
+    },
+    /// 消息处理器执行完成
+    HandlerCompleted {
+        message_type: MessageType,
+        duration_ms: u64,
+        ok: bool,
+    },
     /// 消息发送成功事件
     MessageSent { to: NodeId, message_id: uuid::Uuid },
     /// 消息发送失败事件
@@ -30,6 +100,11 @@ pub enum NetworkEvent {
         message_id: uuid::Uuid,
         error: String,
     },
+    /// 收到需要上层处理的系统消息
+    SystemMessage {
+        from: NodeId,
+        message: crate::SystemMessageType,
+    },
     /// 服务启动事件
     ServiceStarted,
     /// 服务停止事件
@@ -38,6 +113,27 @@ pub enum NetworkEvent {
     Error { error: String },
 }
 
+/// 单个节点的连接状态
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum PeerState {
+    /// 已连接
+    Connected,
+    /// 连接断开后正在重连
+    Reconnecting,
+    /// 未连接
+    #[default]
+    Disconnected,
+}
+
+/// 事件监听者统计
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ListenerStats {
+    /// 注册的事件处理器数量
+    pub handlers: usize,
+    /// 活跃的订阅者（`subscribe()` 接收端）数量
+    pub subscribers: usize,
+}
+
 /// 事件处理器trait
 #[async_trait]
 pub trait EventHandler: Send + Sync {
@@ -51,29 +147,37 @@ pub trait EventHandler: Send + Sync {
 /// 事件总线
 #[derive(Clone)]
 pub struct EventBus {
-    /// 事件广播通道
-    sender: broadcast::Sender<NetworkEvent>,
+    /// 事件广播通道，调整容量时整体替换
+    sender: Arc<std::sync::RwLock<broadcast::Sender<NetworkEvent>>>,
+    /// 事件广播通道容量
+    capacity: Arc<AtomicUsize>,
     /// 事件处理器注册表
     handlers: Arc<RwLock<HashMap<String, Arc<dyn EventHandler>>>>,
+    /// 被关注节点的连接状态
+    peer_states: Arc<std::sync::Mutex<HashMap<NodeId, watch::Sender<PeerState>>>>,
 }
 
 impl EventBus {
     /// 创建新的事件总线
     pub fn new(capacity: usize) -> Self {
+        let capacity = capacity.max(1);
         let (sender, _) = broadcast::channel(capacity);
 
         Self {
-            sender,
+            sender: Arc::new(std::sync::RwLock::new(sender)),
+            capacity: Arc::new(AtomicUsize::new(capacity)),
             handlers: Arc::new(RwLock::new(HashMap::new())),
+            peer_states: Arc::new(std::sync::Mutex::new(HashMap::new())),
         }
     }
 
     /// 发布事件
     pub async fn publish(&self, event: NetworkEvent) {
         info!("发布网络事件: {:?}", event);
+        self.update_peer_states(&event);
 
         // 广播事件
-        if let Err(e) = self.sender.send(event.clone()) {
+        if let Err(e) = self.sender.read().unwrap().send(event.clone()) {
             warn!("事件广播失败: {}", e);
         }
 
@@ -97,6 +201,50 @@ impl EventBus {
         }
     }
 
+    /// 关注单个节点的连接状态，只在该节点连接、重连或断开时变化
+    ///
+    /// 首次关注时以 `initial` 作为当前状态，已被关注的节点沿用已有状态。
+    pub fn watch_peer(&self, node_id: NodeId, initial: PeerState) -> watch::Receiver<PeerState> {
+        let mut states = self.peer_states.lock().unwrap();
+        states
+            .entry(node_id)
+            .or_insert_with(|| watch::channel(initial).0)
+            .subscribe()
+    }
+
+    /// 根据事件更新被关注节点的状态，并清理已无人关注的节点
+    fn update_peer_states(&self, event: &NetworkEvent) {
+        let mut states = self.peer_states.lock().unwrap();
+        match event {
+            NetworkEvent::NodeConnected { node_id, .. } => {
+                Self::set_peer_state(&states, node_id, PeerState::Connected);
+            }
+            NetworkEvent::NodeReconnecting { node_id, .. } => {
+                Self::set_peer_state(&states, node_id, PeerState::Reconnecting);
+            }
+            NetworkEvent::NodeDisconnected { node_id, .. } => {
+                Self::set_peer_state(&states, node_id, PeerState::Disconnected);
+            }
+            NetworkEvent::ServiceStopped => {
+                for sender in states.values() {
+                    sender.send_replace(PeerState::Disconnected);
+                }
+            }
+            _ => {}
+        }
+        states.retain(|_, sender| sender.receiver_count() > 0);
+    }
+
+    fn set_peer_state(
+        states: &HashMap<NodeId, watch::Sender<PeerState>>,
+        node_id: &NodeId,
+        state: PeerState,
+    ) {
+        if let Some(sender) = states.get(node_id) {
+            sender.send_if_modified(|current| std::mem::replace(current, state) != state);
+        }
+    }
+
     /// 注册事件处理器
     pub async fn register_handler(&self, handler: Arc<dyn EventHandler>) {
         let name = handler.name().to_string();
@@ -116,13 +264,55 @@ impl EventBus {
 
     /// 创建事件订阅者
     pub fn subscribe(&self) -> broadcast::Receiver<NetworkEvent> {
-        self.sender.subscribe()
+        self.sender.read().unwrap().subscribe()
+    }
+
+    /// 事件广播通道容量：订阅者落后超过该数量的事件时会丢失最早的事件
+    pub fn capacity(&self) -> usize {
+        self.capacity.load(Ordering::SeqCst)
+    }
+
+    /// 调整事件广播通道容量
+    ///
+    /// 广播通道创建后无法扩容，只能替换为新通道；已有订阅者时替换会使其
+    /// 收不到后续事件，因此保持原容量并返回 `false`。
+    pub fn set_capacity(&self, capacity: usize) -> bool {
+        let capacity = capacity.max(1);
+        let mut sender = self.sender.write().unwrap();
+        if self.capacity() == capacity {
+            return true;
+        }
+        if sender.receiver_count() > 0 {
+            warn!(
+                "已有 {} 个事件订阅者，事件通道容量保持为 {}（请求 {}）",
+                sender.receiver_count(),
+                self.capacity(),
+                capacity
+            );
+            return false;
+        }
+        *sender = broadcast::channel(capacity).0;
+        self.capacity.store(capacity, Ordering::SeqCst);
+        true
     }
 
     /// 获取当前注册的处理器数量
     pub async fn handler_count(&self) -> usize {
         self.handlers.read().await.len()
     }
+
+    /// 获取当前活跃的订阅者数量
+    pub fn subscriber_count(&self) -> usize {
+        self.sender.read().unwrap().receiver_count()
+    }
+
+    /// 获取监听者统计，用于排查事件无人接收的问题
+    pub async fn listener_stats(&self) -> ListenerStats {
+        ListenerStats {
+            handlers: self.handler_count().await,
+            subscribers: self.subscriber_count(),
+        }
+    }
 }
 
 /// 默认日志事件处理器
@@ -142,15 +332,36 @@ impl LogEventHandler {
 impl EventHandler for LogEventHandler {
     async fn handle_event(&self, event: NetworkEvent) {
         match event {
-            NetworkEvent::NodeConnected { node_id, .. } => {
-                info!("节点已连接: {}", node_id);
-            }
+            NetworkEvent::NodeConnected {
+                node_id,
+                connection,
+                ..
+            } => match connection.remote_addr {
+                Some(addr) => info!("节点已连接: {} ({})", node_id, addr),
+                None => info!("节点已连接: {}", node_id),
+            },
             NetworkEvent::NodeDisconnected { node_id, reason } => {
                 info!("节点已断开: {} (原因: {})", node_id, reason);
             }
+            NetworkEvent::NodeReconnecting { node_id, attempt } => {
+                info!("正在重新连接节点 {} (第 {} 次)", node_id, attempt);
+            }
             NetworkEvent::MessageReceived { from, message } => {
                 info!("收到来自 {} 的消息: {:?}", from, message.message_type);
             }
+            NetworkEvent::UnhandledMessage { from, message_type } => {
+                warn!("收到来自 {} 的未处理消息类型: {:?}", from, message_type);
+            }
+            NetworkEvent::HandlerCompleted {
+                message_type,
+                duration_ms,
+                ok,
+            } => {
+                info!(
+                    "消息处理器 {:?} 执行完成: {}ms (成功: {})",
+                    message_type, duration_ms, ok
+                );
+            }
             NetworkEvent::MessageSent { to, message_id } => {
                 info!("成功发送消息 {} 到 {}", message_id, to);
             }
@@ -161,6 +372,9 @@ impl EventHandler for LogEventHandler {
             } => {
                 warn!("发送消息 {} 到 {} 失败: {}", message_id, to, error);
             }
+            NetworkEvent::SystemMessage { from, message } => {
+                info!("收到来自 {} 的系统消息: {:?}", from, message);
+            }
             NetworkEvent::ServiceStarted => {
                 info!("网络服务已启动");
             }
@@ -196,4 +410,76 @@ mod tests {
         // 等待处理完成
         tokio::time::sleep(std::time::Duration::from_millis(100)).await;
     }
+
+    #[tokio::test]
+    async fn test_listener_stats() {
+        let event_bus = EventBus::new(100);
+        assert_eq!(
+            event_bus.listener_stats().await,
+            ListenerStats {
+                handlers: 0,
+                subscribers: 0
+            }
+        );
+
+        event_bus
+            .register_handler(Arc::new(LogEventHandler::new()))
+            .await;
+        let first = event_bus.subscribe();
+        let _second = event_bus.subscribe();
+
+        assert_eq!(
+            event_bus.listener_stats().await,
+            ListenerStats {
+                handlers: 1,
+                subscribers: 2
+            }
+        );
+
+        drop(first);
+        assert_eq!(event_bus.subscriber_count(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_watch_peer_tracks_single_peer() {
+        let event_bus = EventBus::new(100);
+        let mut server = event_bus.watch_peer("server".to_string(), PeerState::Disconnected);
+
+        let connected = |node_id: &str| NetworkEvent::NodeConnected {
+            node_id: node_id.to_string(),
+            connection: ConnectionMetadata::default(),
+            metadata: HashMap::new(),
+        };
+
+        // 其他节点的事件不影响关注的节点
+        event_bus.publish(connected("other")).await;
+        assert!(!server.has_changed().unwrap());
+
+        event_bus.publish(connected("server")).await;
+        server.changed().await.unwrap();
+        assert_eq!(*server.borrow_and_update(), PeerState::Connected);
+
+        event_bus
+            .publish(NetworkEvent::NodeReconnecting {
+                node_id: "server".to_string(),
+                attempt: 1,
+            })
+            .await;
+        server.changed().await.unwrap();
+        assert_eq!(*server.borrow_and_update(), PeerState::Reconnecting);
+
+        event_bus
+            .publish(NetworkEvent::NodeDisconnected {
+                node_id: "server".to_string(),
+                reason: "连接关闭".to_string(),
+            })
+            .await;
+        server.changed().await.unwrap();
+        assert_eq!(*server.borrow_and_update(), PeerState::Disconnected);
+
+        // 无人关注后不再保留状态
+        drop(server);
+        event_bus.publish(connected("server")).await;
+        assert!(event_bus.peer_states.lock().unwrap().is_empty());
+    }
 }
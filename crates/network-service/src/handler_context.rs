@@ -0,0 +1,99 @@
+//! 消息处理器上下文
+//!
+//! 处理器通过上下文主动发起发送（广播、单播给第三方等），
+//! 而不仅限于向发送者返回一条响应消息。
+
+use crate::{
+    BroadcastOptions, MessageId, NetworkMessage, NetworkServiceTrait, NodeId, Result,
+    UnicastOptions,
+};
+use async_trait::async_trait;
+use std::sync::Arc;
+
+/// 消息发送接口
+///
+/// `NetworkServiceTrait` 要求 `Clone`，无法作为trait对象使用，
+/// 这里提供一个对象安全的发送子集。
+#[async_trait]
+pub trait MessageSender: Send + Sync {
+    /// 广播消息
+    async fn broadcast(
+        &self,
+        message: NetworkMessage,
+        options: Option<BroadcastOptions>,
+    ) -> Result<MessageId>;
+
+    /// 单播消息
+    async fn unicast(
+        &self,
+        target: NodeId,
+        message: NetworkMessage,
+        options: Option<UnicastOptions>,
+    ) -> Result<MessageId>;
+
+    /// 获取本地节点ID
+    async fn get_local_node_id(&self) -> Result<NodeId>;
+}
+
+#[async_trait]
+impl<T: NetworkServiceTrait + 'static> MessageSender for T {
+    async fn broadcast(
+        &self,
+        message: NetworkMessage,
+        options: Option<BroadcastOptions>,
+    ) -> Result<MessageId> {
+        NetworkServiceTrait::broadcast(self, message, options).await
+    }
+
+    async fn unicast(
+        &self,
+        target: NodeId,
+        message: NetworkMessage,
+        options: Option<UnicastOptions>,
+    ) -> Result<MessageId> {
+        NetworkServiceTrait::unicast(self, target, message, options).await
+    }
+
+    async fn get_local_node_id(&self) -> Result<NodeId> {
+        NetworkServiceTrait::get_local_node_id(self).await
+    }
+}
+
+/// 消息处理器上下文，持有网络服务的可克隆句柄
+#[derive(Clone)]
+pub struct HandlerContext {
+    sender: Arc<dyn MessageSender>,
+}
+
+impl HandlerContext {
+    /// 创建新的处理器上下文
+    pub fn new<S: MessageSender + 'static>(sender: S) -> Self {
+        Self {
+            sender: Arc::new(sender),
+        }
+    }
+
+    /// 广播消息
+    pub async fn broadcast(
+        &self,
+        message: NetworkMessage,
+        options: Option<BroadcastOptions>,
+    ) -> Result<MessageId> {
+        self.sender.broadcast(message, options).await
+    }
+
+    /// 单播消息
+    pub async fn unicast(
+        &self,
+        target: NodeId,
+        message: NetworkMessage,
+        options: Option<UnicastOptions>,
+    ) -> Result<MessageId> {
+        self.sender.unicast(target, message, options).await
+    }
+
+    /// 获取本地节点ID
+    pub async fn local_node_id(&self) -> Result<NodeId> {
+        self.sender.get_local_node_id().await
+    }
+}
@@ -0,0 +1,163 @@
+//! 节点私钥来源
+//!
+//! 配置中声明私钥从何处获得，启动网络服务时才解析为实际的私钥。
+//! 文件和环境变量中的私钥使用64位十六进制文本；文件也可以直接保存32字节的原始私钥。
+
+use crate::{NetworkError, Result};
+use rand::RngCore;
+use std::path::PathBuf;
+
+/// 私钥来源
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum KeySource {
+    /// 每次启动随机生成（节点身份不固定）
+    #[default]
+    Random,
+    /// 固定私钥
+    Fixed([u8; 32]),
+    /// 从文件读取
+    File(PathBuf),
+    /// 从环境变量读取
+    Env(String),
+}
+
+impl KeySource {
+    /// 解析为实际的私钥
+    pub fn resolve(&self) -> Result<[u8; 32]> {
+        match self {
+            KeySource::Random => {
+                let mut key = [0u8; 32];
+                rand::rng().fill_bytes(&mut key);
+                Ok(key)
+            }
+            KeySource::Fixed(key) => Ok(*key),
+            KeySource::File(path) => {
+                let bytes = std::fs::read(path).map_err(|e| {
+                    NetworkError::config_error(format!(
+                        "读取私钥文件 {} 失败: {}",
+                        path.display(),
+                        e
+                    ))
+                })?;
+                if let Ok(key) = <[u8; 32]>::try_from(bytes.as_slice()) {
+                    return Ok(key);
+                }
+                let text = String::from_utf8(bytes).map_err(|_| {
+                    NetworkError::config_error(format!("私钥文件 {} 格式无效", path.display()))
+                })?;
+                parse_hex_key(&text)
+            }
+            KeySource::Env(name) => {
+                let value = std::env::var(name).map_err(|e| {
+                    NetworkError::config_error(format!("读取私钥环境变量 {} 失败: {}", name, e))
+                })?;
+                parse_hex_key(&value)
+            }
+        }
+    }
+}
+
+/// 解析64位十六进制文本形式的私钥
+fn parse_hex_key(text: &str) -> Result<[u8; 32]> {
+    let text = text.trim();
+    if text.len() != 64 || !text.is_ascii() {
+        return Err(NetworkError::config_error("私钥应为64位十六进制字符串"));
+    }
+
+    let mut key = [0u8; 32];
+    for (i, byte) in key.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(&text[i * 2..i * 2 + 2], 16)
+            .map_err(|_| NetworkError::config_error("私钥包含非十六进制字符"))?;
+    }
+    Ok(key)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 测试用私钥：字节依次为 0..32
+    fn sample_key() -> ([u8; 32], String) {
+        let key: [u8; 32] = std::array::from_fn(|i| i as u8);
+        let hex = key.iter().fold(String::new(), |mut hex, b| {
+            use std::fmt::Write;
+            let _ = write!(hex, "{:02x}", b);
+            hex
+        });
+        (key, hex)
+    }
+
+    /// 以指定私钥来源启动网络服务，返回节点的PeerId
+    async fn started_peer_id(source: KeySource) -> anemo::PeerId {
+        use crate::{AnemoNetworkService, NetworkServiceConfig, NetworkServiceTrait};
+
+        let service = AnemoNetworkService::new();
+        service
+            .start(NetworkServiceConfig {
+                bind_address: "127.0.0.1:0".parse().unwrap(),
+                private_key: source,
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+        let peer_id = service.effective_config().await.unwrap().peer_id;
+        service.stop().await.unwrap();
+        peer_id
+    }
+
+    #[tokio::test]
+    async fn test_env_key_source() {
+        let (key, hex) = sample_key();
+        // 同一测试二进制中的测试并行运行，变量名只属于本测试
+        let name = format!(
+            "KEY_SOURCE_TEST_PRIVATE_KEY_{}",
+            uuid::Uuid::new_v4().simple()
+        );
+        std::env::set_var(&name, &hex);
+
+        let source = KeySource::Env(name.clone());
+        assert_eq!(source.resolve().unwrap(), key);
+        assert_ne!(
+            source.resolve().unwrap(),
+            KeySource::Random.resolve().unwrap()
+        );
+        // 从环境变量读取的私钥得到与固定私钥相同的节点身份
+        assert_eq!(
+            started_peer_id(source).await,
+            started_peer_id(KeySource::Fixed(key)).await
+        );
+        std::env::remove_var(&name);
+
+        let missing = format!("KEY_SOURCE_TEST_MISSING_{}", uuid::Uuid::new_v4().simple());
+        assert!(KeySource::Env(missing).resolve().is_err());
+    }
+
+    #[test]
+    fn test_file_key_source() {
+        let (key, hex) = sample_key();
+        let dir = std::env::temp_dir().join(format!("key-source-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+
+        let hex_path = dir.join("node.key");
+        std::fs::write(&hex_path, format!("{}\n", hex)).unwrap();
+        assert_eq!(KeySource::File(hex_path).resolve().unwrap(), key);
+
+        let raw_path = dir.join("node.bin");
+        std::fs::write(&raw_path, key).unwrap();
+        assert_eq!(KeySource::File(raw_path).resolve().unwrap(), key);
+
+        let bad_path = dir.join("bad.key");
+        std::fs::write(&bad_path, "not a key").unwrap();
+        assert!(KeySource::File(bad_path).resolve().is_err());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_random_keys_differ() {
+        assert_ne!(
+            KeySource::Random.resolve().unwrap(),
+            KeySource::Random.resolve().unwrap()
+        );
+    }
+}
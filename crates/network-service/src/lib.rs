@@ -4,19 +4,54 @@
 //! 同时保持与具体网络实现的解耦。
 
 pub mod anemo_impl;
+pub mod backoff;
+pub mod connection_auth;
+pub mod dead_letter;
+pub mod delivery_age;
 pub mod error;
 pub mod event_bus;
+pub mod handler_context;
+pub mod key_source;
 pub mod message;
+pub mod orchestrator;
+pub mod relay;
+pub mod replay;
 pub mod service;
+pub mod system;
+#[cfg(feature = "test-utils")]
+pub mod test_utils;
+pub mod time;
+pub mod wal;
 
 // 重新导出主要接口
-pub use anemo_impl::AnemoNetworkService;
-pub use error::{NetworkError, Result};
-pub use event_bus::{EventBus, EventHandler, NetworkEvent};
-pub use message::{BroadcastOptions, MessageType, NetworkMessage, UnicastOptions};
-pub use service::{NetworkService, NetworkServiceConfig};
+pub use anemo_impl::{
+    AnemoNetworkService, CustomRoutes, EffectiveConfig, ProbeResult, RouterFactory, ServiceHandle,
+    NETWORK_MESSAGE_ROUTE, NODE_INFO_ROUTE,
+};
+pub use backoff::{retry_with_backoff, BackoffConfig, ReconnectLimiter};
+pub use connection_auth::{AuthDecision, ConnectionAuthenticator};
+pub use dead_letter::{DeadLetter, DeadLetterConfig, DeadLetterQueue};
+pub use delivery_age::{DeliveryAgeStats, OTHER_MESSAGE_TYPE};
+pub use error::{ConnectError, NetworkError, Result};
+pub use event_bus::{
+    ConnectionMetadata, EventBus, EventHandler, ListenerStats, NetworkEvent, PeerState,
+};
+pub use handler_context::{HandlerContext, MessageSender};
+pub use key_source::KeySource;
+pub use message::{
+    BroadcastOptions, ChatPayload, ChatType, HandlerKey, MessageLimits, MessageType,
+    NetworkMessage, UnicastOptions,
+};
+pub use orchestrator::{ServiceOrchestrator, ShutdownComponent};
+pub use relay::SeenMessages;
+pub use replay::{NonceGenerator, ReplayGuard};
+pub use service::{DecodeErrorPolicy, NetworkService, NetworkServiceConfig, NodeOrder, PoolStats};
+pub use system::{DisconnectReason, SystemMessageHandler, SystemMessageType};
+pub use time::{now_millis, now_nanos};
+pub use wal::{OutboundWal, WalEntry};
 
 use async_trait::async_trait;
+use std::collections::HashMap;
 use uuid::Uuid;
 
 /// 网络节点ID类型
@@ -31,8 +66,8 @@ pub trait NetworkServiceTrait: Send + Sync + Clone {
     /// 启动网络服务
     async fn start(&self, config: NetworkServiceConfig) -> Result<()>;
 
-    /// 停止网络服务
-    async fn stop(&self) -> Result<()>;
+    /// 停止网络服务，返回停止时清理的内容
+    async fn stop(&self) -> Result<ShutdownReport>;
 
     /// 广播消息给所有连接的节点
     async fn broadcast(
@@ -52,6 +87,11 @@ pub trait NetworkServiceTrait: Send + Sync + Clone {
     /// 获取当前连接的节点列表
     async fn get_connected_nodes(&self) -> Result<Vec<NodeId>>;
 
+    /// 订阅网络事件，不支持事件的实现返回 `None`
+    fn subscribe_events(&self) -> Option<tokio::sync::broadcast::Receiver<NetworkEvent>> {
+        None
+    }
+
     /// 获取本地节点ID
     async fn get_local_node_id(&self) -> Result<NodeId>;
 
@@ -62,6 +102,18 @@ pub trait NetworkServiceTrait: Send + Sync + Clone {
         handler: Box<dyn MessageHandler>,
     ) -> Result<()>;
 
+    /// 注册只处理指定主题消息的处理器
+    async fn register_topic_handler(
+        &self,
+        _message_type: MessageType,
+        _topic: String,
+        _handler: Box<dyn MessageHandler>,
+    ) -> Result<()> {
+        Err(NetworkError::config_error(
+            "该网络服务不支持按主题注册处理器",
+        ))
+    }
+
     /// 注册事件处理器
     async fn register_event_handler(&self, handler: Box<dyn EventHandler>) -> Result<()>;
 }
@@ -70,8 +122,11 @@ pub trait NetworkServiceTrait: Send + Sync + Clone {
 #[async_trait]
 pub trait MessageHandler: Send + Sync {
     /// 处理接收到的消息
+    ///
+    /// 返回的响应消息会回复给发送者；需要发给其他节点时使用 `ctx` 主动发送。
     async fn handle_message(
         &self,
+        ctx: &HandlerContext,
         from: NodeId,
         message: NetworkMessage,
     ) -> Result<Option<NetworkMessage>>;
@@ -87,8 +142,21 @@ pub struct ServiceHealth {
     pub last_activity: Option<std::time::SystemTime>,
 }
 
+/// 停止服务时的清理情况，用于排查停止期间丢失的消息
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ShutdownReport {
+    /// 断开的对端连接数
+    pub peers_disconnected: usize,
+    /// 在宽限期内等待完成的处理器数量
+    pub handlers_awaited: usize,
+    /// 超过宽限期被中止的处理器数量
+    pub handlers_aborted: usize,
+    /// 尚未处理就被丢弃的入站消息数量
+    pub queued_dropped: usize,
+}
+
 /// 网络统计信息
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Default)]
 pub struct NetworkStats {
     pub bytes_sent: u64,
     pub bytes_received: u64,
@@ -96,4 +164,85 @@ pub struct NetworkStats {
     pub messages_received: u64,
     pub connection_count: usize,
     pub error_count: u64,
+    /// 因会发回本节点而被拦截的消息数量
+    pub loop_prevented: u64,
+    /// 各消息类型的序列化开销，启用 `record_serialization_stats` 后记录
+    pub serialization: HashMap<MessageType, SerializationStats>,
+}
+
+/// 单个消息类型的序列化开销
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SerializationStats {
+    /// 序列化次数
+    pub count: u64,
+    /// 序列化后的总字节数
+    pub total_bytes: u64,
+    /// 最大的序列化结果（字节）
+    pub max_bytes: u64,
+    /// 序列化总耗时（纳秒）
+    pub total_nanos: u64,
+}
+
+impl SerializationStats {
+    /// 记录一次序列化
+    pub fn record(&mut self, bytes: u64, elapsed: std::time::Duration) {
+        self.count += 1;
+        self.total_bytes = self.total_bytes.saturating_add(bytes);
+        self.max_bytes = self.max_bytes.max(bytes);
+        self.total_nanos = self
+            .total_nanos
+            .saturating_add(elapsed.as_nanos().min(u64::MAX as u128) as u64);
+    }
+
+    /// 平均序列化大小（字节）
+    pub fn mean_bytes(&self) -> f64 {
+        if self.count == 0 {
+            0.0
+        } else {
+            self.total_bytes as f64 / self.count as f64
+        }
+    }
+}
+
+/// 带时间戳的统计快照，用于计算速率
+#[derive(Debug, Clone)]
+pub struct StatsSnapshot {
+    pub stats: NetworkStats,
+    pub taken_at: std::time::Instant,
+}
+
+/// 两个快照之间的每秒速率
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct StatsRates {
+    pub bytes_sent_per_sec: f64,
+    pub bytes_received_per_sec: f64,
+    pub messages_sent_per_sec: f64,
+    pub messages_received_per_sec: f64,
+    pub errors_per_sec: f64,
+}
+
+impl StatsSnapshot {
+    /// 计算自上一个快照以来的每秒速率
+    pub fn rate_since(&self, prev: &StatsSnapshot) -> StatsRates {
+        let elapsed = self
+            .taken_at
+            .saturating_duration_since(prev.taken_at)
+            .as_secs_f64();
+        if elapsed <= 0.0 {
+            return StatsRates::default();
+        }
+
+        let rate = |current: u64, previous: u64| current.saturating_sub(previous) as f64 / elapsed;
+
+        StatsRates {
+            bytes_sent_per_sec: rate(self.stats.bytes_sent, prev.stats.bytes_sent),
+            bytes_received_per_sec: rate(self.stats.bytes_received, prev.stats.bytes_received),
+            messages_sent_per_sec: rate(self.stats.messages_sent, prev.stats.messages_sent),
+            messages_received_per_sec: rate(
+                self.stats.messages_received,
+                prev.stats.messages_received,
+            ),
+            errors_per_sec: rate(self.stats.error_count, prev.stats.error_count),
+        }
+    }
 }
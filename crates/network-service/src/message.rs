@@ -2,7 +2,6 @@
 
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
-use std::time::{SystemTime, UNIX_EPOCH};
 use uuid::Uuid;
 
 /// 消息类型标识符
@@ -28,11 +27,36 @@ impl MessageType {
     pub fn system() -> Self {
         Self("system".to_string())
     }
+
+    /// 确认消息类型
+    pub fn ack() -> Self {
+        Self("ack".to_string())
+    }
 }
 
+/// 处理器注册键：消息类型和可选的应用主题
+pub type HandlerKey = (MessageType, Option<String>);
+
+/// 确认消息中记录被确认消息ID的元数据键
+pub const ACK_FOR_METADATA_KEY: &str = "ack_for";
+
+/// 当前消息信封版本
+///
+/// - 1: 基础字段（ID、类型、发送者、负载、时间戳、元数据）
+/// - 2: 增加 `response_required`、`topic`
+///
+/// 新增字段必须带默认值，使本节点能解码所有不高于当前版本的消息。
+pub const ENVELOPE_VERSION: u8 = 2;
+
+/// 日志中负载片段的最大字符数
+pub const PAYLOAD_SNIPPET_MAX_CHARS: usize = 200;
+
 /// 网络消息结构
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct NetworkMessage {
+    /// 信封版本，未携带版本的消息来自引入版本号之前的节点，视为版本1
+    #[serde(default = "default_envelope_version")]
+    pub envelope_version: u8,
     /// 唯一消息ID
     pub id: Uuid,
     /// 消息类型
@@ -41,25 +65,71 @@ pub struct NetworkMessage {
     pub sender: String,
     /// 消息内容（JSON格式）
     pub payload: serde_json::Value,
-    /// 时间戳
+    /// 时间戳（毫秒）
     pub timestamp: u64,
     /// 元数据
+    #[serde(default)]
     pub metadata: HashMap<String, String>,
+    /// 是否要求接收方回复
+    ///
+    /// 处理器没有返回响应时，接收方会自动回复一条空的确认消息。
+    #[serde(default)]
+    pub response_required: bool,
+    /// 应用主题，用于在共享网络上隔离同类型的多个应用实例
+    #[serde(default)]
+    pub topic: Option<String>,
+}
+
+/// 未携带信封版本的消息视为版本1
+fn default_envelope_version() -> u8 {
+    1
 }
 
 impl NetworkMessage {
     /// 创建新消息
     pub fn new(message_type: MessageType, sender: String, payload: serde_json::Value) -> Self {
         Self {
+            envelope_version: ENVELOPE_VERSION,
             id: Uuid::new_v4(),
             message_type,
             sender,
             payload,
-            timestamp: current_timestamp(),
+            timestamp: crate::now_millis(),
             metadata: HashMap::new(),
+            response_required: false,
+            topic: None,
         }
     }
 
+    /// 设置应用主题
+    pub fn with_topic(mut self, topic: impl Into<String>) -> Self {
+        self.topic = Some(topic.into());
+        self
+    }
+
+    /// 查找处理器使用的键：消息类型和主题；没有该主题的处理器时回退到只按类型注册的处理器
+    pub fn handler_key(&self) -> HandlerKey {
+        (self.message_type.clone(), self.topic.clone())
+    }
+
+    /// 要求接收方回复
+    pub fn with_response_required(mut self) -> Self {
+        self.response_required = true;
+        self
+    }
+
+    /// 为本消息生成空的确认消息
+    pub fn ack(&self, sender: String) -> Self {
+        Self::new(MessageType::ack(), sender, serde_json::Value::Null)
+            .with_metadata(ACK_FOR_METADATA_KEY.to_string(), self.id.to_string())
+    }
+
+    /// 是否为指定消息的确认
+    pub fn is_ack_for(&self, message_id: Uuid) -> bool {
+        self.message_type == MessageType::ack()
+            && self.get_metadata(ACK_FOR_METADATA_KEY) == Some(&message_id.to_string())
+    }
+
     /// 添加元数据
     pub fn with_metadata(mut self, key: String, value: String) -> Self {
         self.metadata.insert(key, value);
@@ -71,6 +141,36 @@ impl NetworkMessage {
         self.metadata.get(key)
     }
 
+    /// 元数据键和值的总字节数
+    pub fn metadata_bytes(&self) -> usize {
+        self.metadata
+            .iter()
+            .map(|(key, value)| key.len() + value.len())
+            .sum()
+    }
+
+    /// 负载的截断片段，用于日志
+    pub fn payload_snippet(&self, max_chars: usize) -> String {
+        let raw = self.payload.to_string();
+        if raw.chars().count() <= max_chars {
+            return raw;
+        }
+        let truncated: String = raw.chars().take(max_chars).collect();
+        format!("{}…", truncated)
+    }
+
+    /// 负载解析失败时的日志描述，包含来源节点和负载片段
+    pub fn parse_failure_summary(&self, from: &str, error: &serde_json::Error) -> String {
+        format!(
+            "无法解析来自 {} 的 {} 消息 {}: {}，负载片段: {}",
+            from,
+            self.message_type.0,
+            self.id,
+            error,
+            self.payload_snippet(PAYLOAD_SNIPPET_MAX_CHARS)
+        )
+    }
+
     /// 序列化为字节
     pub fn to_bytes(&self) -> Result<Vec<u8>, serde_json::Error> {
         serde_json::to_vec(self)
@@ -80,6 +180,80 @@ impl NetworkMessage {
     pub fn from_bytes(bytes: &[u8]) -> Result<Self, serde_json::Error> {
         serde_json::from_slice(bytes)
     }
+
+    /// 从字节解码并校验信封版本
+    pub fn decode(bytes: &[u8]) -> crate::Result<Self> {
+        let message = Self::from_bytes(bytes)?;
+        message.check_envelope_version()?;
+        Ok(message)
+    }
+
+    /// 拒绝高于本节点支持版本的信封，旧版本缺少的字段已按默认值补齐
+    pub fn check_envelope_version(&self) -> crate::Result<()> {
+        if self.envelope_version > ENVELOPE_VERSION {
+            return Err(crate::NetworkError::UnsupportedEnvelopeVersion {
+                version: self.envelope_version,
+                supported: ENVELOPE_VERSION,
+            });
+        }
+        Ok(())
+    }
+}
+
+/// 消息大小限制，发送和接收时都会检查
+///
+/// 整体大小按序列化后的完整消息计算，元数据另有条数和字节数上限，
+/// 防止对端借元数据绕过负载大小的限制。
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MessageLimits {
+    /// 序列化后整条消息的最大字节数
+    pub max_message_bytes: usize,
+    /// 元数据最大条数
+    pub max_metadata_entries: usize,
+    /// 元数据键和值的最大总字节数
+    pub max_metadata_bytes: usize,
+}
+
+impl Default for MessageLimits {
+    fn default() -> Self {
+        Self {
+            max_message_bytes: 4 * 1024 * 1024,
+            max_metadata_entries: 64,
+            max_metadata_bytes: 16 * 1024,
+        }
+    }
+}
+
+impl MessageLimits {
+    /// 检查序列化后的消息大小
+    pub fn check_encoded_len(&self, len: usize) -> crate::Result<()> {
+        if len > self.max_message_bytes {
+            return Err(crate::NetworkError::MessageTooLarge(format!(
+                "消息 {} 字节，上限 {} 字节",
+                len, self.max_message_bytes
+            )));
+        }
+        Ok(())
+    }
+
+    /// 检查元数据的条数和总字节数
+    pub fn check_metadata(&self, message: &NetworkMessage) -> crate::Result<()> {
+        let entries = message.metadata.len();
+        if entries > self.max_metadata_entries {
+            return Err(crate::NetworkError::MessageTooLarge(format!(
+                "消息 {} 的元数据 {} 条，上限 {} 条",
+                message.id, entries, self.max_metadata_entries
+            )));
+        }
+        let bytes = message.metadata_bytes();
+        if bytes > self.max_metadata_bytes {
+            return Err(crate::NetworkError::MessageTooLarge(format!(
+                "消息 {} 的元数据 {} 字节，上限 {} 字节",
+                message.id, bytes, self.max_metadata_bytes
+            )));
+        }
+        Ok(())
+    }
 }
 
 /// 广播选项
@@ -87,21 +261,32 @@ impl NetworkMessage {
 pub struct BroadcastOptions {
     /// 排除的节点列表
     pub exclude_nodes: Vec<String>,
+    /// 接收节点白名单，`None` 表示所有已知节点
+    ///
+    /// 与 `exclude_nodes` 同时生效：只发送给在白名单中且未被排除的节点。
+    pub include_nodes: Option<Vec<String>>,
     /// 是否等待响应
     pub wait_for_response: bool,
     /// 超时时间（毫秒）
     pub timeout_ms: Option<u64>,
     /// 重试次数
     pub retry_count: u32,
+    /// 是否同时投递给本节点注册的处理器
+    pub include_self: bool,
+    /// 没有任何接收者时是否返回 `NetworkError::NoRecipients`
+    pub require_recipients: bool,
 }
 
 impl Default for BroadcastOptions {
     fn default() -> Self {
         Self {
             exclude_nodes: Vec::new(),
+            include_nodes: None,
             wait_for_response: false,
             timeout_ms: Some(5000),
             retry_count: 0,
+            include_self: false,
+            require_recipients: false,
         }
     }
 }
@@ -134,14 +319,26 @@ pub struct ChatPayload {
     pub chat_type: ChatType,
 }
 
-/// 聊天类型
-#[derive(Debug, Clone, Serialize, Deserialize)]
+/// 聊天类型（消息内容类型）
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default, Serialize, Deserialize)]
 pub enum ChatType {
+    #[default]
     Text,
     Image,
     File,
 }
 
+impl ChatType {
+    /// 类型名称，用于消息历史和错误信息
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ChatType::Text => "text",
+            ChatType::Image => "image",
+            ChatType::File => "file",
+        }
+    }
+}
+
 /// 授时消息负载
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct TimeSyncPayload {
@@ -156,10 +353,68 @@ pub enum TimeSyncRequestType {
     SyncTime { timestamp: u64 },
 }
 
-/// 获取当前时间戳
-pub fn current_timestamp() -> u64 {
-    SystemTime::now()
-        .duration_since(UNIX_EPOCH)
-        .unwrap()
-        .as_secs()
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_metadata_limits_apply_with_tiny_payload() {
+        let limits = MessageLimits::default();
+        let message = NetworkMessage::new(
+            MessageType::chat(),
+            "node1".to_string(),
+            serde_json::json!("hi"),
+        );
+        assert!(limits.check_metadata(&message).is_ok());
+
+        let stuffed = message
+            .clone()
+            .with_metadata("padding".to_string(), "x".repeat(64 * 1024));
+        assert!(matches!(
+            limits.check_metadata(&stuffed),
+            Err(crate::NetworkError::MessageTooLarge(_))
+        ));
+
+        let many = (0..=limits.max_metadata_entries).fold(message, |message, i| {
+            message.with_metadata(format!("k{}", i), String::new())
+        });
+        assert!(matches!(
+            limits.check_metadata(&many),
+            Err(crate::NetworkError::MessageTooLarge(_))
+        ));
+    }
+
+    #[test]
+    fn test_decode_v1_envelope_applies_defaults() {
+        let v1 = serde_json::json!({
+            "id": Uuid::new_v4(),
+            "message_type": "chat",
+            "sender": "old-node",
+            "payload": {"content": "hi"},
+            "timestamp": 1_700_000_000_000u64,
+        });
+        let message = NetworkMessage::decode(&serde_json::to_vec(&v1).unwrap()).unwrap();
+        assert_eq!(message.envelope_version, 1);
+        assert_eq!(message.message_type, MessageType::chat());
+        assert!(message.metadata.is_empty());
+        assert!(!message.response_required);
+        assert!(message.topic.is_none());
+    }
+
+    #[test]
+    fn test_decode_rejects_newer_envelope() {
+        let mut message = NetworkMessage::new(
+            MessageType::chat(),
+            "new-node".to_string(),
+            serde_json::json!({}),
+        );
+        message.envelope_version = ENVELOPE_VERSION + 1;
+        match NetworkMessage::decode(&message.to_bytes().unwrap()) {
+            Err(crate::NetworkError::UnsupportedEnvelopeVersion { version, supported }) => {
+                assert_eq!(version, ENVELOPE_VERSION + 1);
+                assert_eq!(supported, ENVELOPE_VERSION);
+            }
+            other => panic!("意外的结果: {:?}", other),
+        }
+    }
 }
@@ -0,0 +1,147 @@
+//! 服务编排
+//!
+//! 业务模块依赖网络服务，停止时需要先停业务模块再停网络，
+//! 否则业务模块的收尾消息（例如离开聊天室、最后一次心跳）无法发出。
+
+use crate::{NetworkError, NetworkServiceTrait, Result, ShutdownReport};
+use async_trait::async_trait;
+use std::sync::Arc;
+use tracing::{error, info};
+
+/// 网络服务在停止失败汇总中的组件名称
+const NETWORK_COMPONENT: &str = "network";
+
+/// 可由编排器统一停止的业务组件
+#[async_trait]
+pub trait ShutdownComponent: Send + Sync {
+    /// 组件名称，用于日志和错误汇总
+    fn name(&self) -> &str;
+
+    /// 停止组件，释放后台任务等资源
+    async fn shutdown(&self) -> Result<()>;
+}
+
+/// 按依赖顺序停止业务组件和网络服务
+pub struct ServiceOrchestrator<N: NetworkServiceTrait> {
+    network: N,
+    components: Vec<Arc<dyn ShutdownComponent>>,
+}
+
+impl<N: NetworkServiceTrait> ServiceOrchestrator<N> {
+    /// 创建编排器，网络服务总是最后停止
+    pub fn new(network: N) -> Self {
+        Self {
+            network,
+            components: Vec::new(),
+        }
+    }
+
+    /// 添加业务组件，按启动顺序添加
+    pub fn with_component(mut self, component: Arc<dyn ShutdownComponent>) -> Self {
+        self.add_component(component);
+        self
+    }
+
+    /// 添加业务组件，按启动顺序添加
+    pub fn add_component(&mut self, component: Arc<dyn ShutdownComponent>) {
+        self.components.push(component);
+    }
+
+    /// 编排的网络服务
+    pub fn network(&self) -> &N {
+        &self.network
+    }
+
+    /// 按启动的相反顺序停止业务组件，最后停止网络服务，返回网络服务的清理情况
+    ///
+    /// 某个组件停止失败不会中断后续组件的停止，所有失败汇总在
+    /// `NetworkError::ShutdownFailed` 中返回。
+    pub async fn shutdown(self) -> Result<ShutdownReport> {
+        let mut failures = Vec::new();
+
+        for component in self.components.iter().rev() {
+            info!("停止组件 {}", component.name());
+            if let Err(e) = component.shutdown().await {
+                error!("停止组件 {} 失败: {}", component.name(), e);
+                failures.push((component.name().to_string(), e.to_string()));
+            }
+        }
+
+        info!("停止网络服务");
+        let report = match self.network.stop().await {
+            Ok(report) => report,
+            Err(e) => {
+                error!("停止网络服务失败: {}", e);
+                failures.push((NETWORK_COMPONENT.to_string(), e.to_string()));
+                ShutdownReport::default()
+            }
+        };
+
+        if failures.is_empty() {
+            Ok(report)
+        } else {
+            Err(NetworkError::ShutdownFailed(failures))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::AnemoNetworkService;
+    use std::sync::Mutex;
+
+    /// 记录停止顺序的组件
+    struct RecordingComponent {
+        name: &'static str,
+        fail: bool,
+        stopped: Arc<Mutex<Vec<&'static str>>>,
+    }
+
+    #[async_trait]
+    impl ShutdownComponent for RecordingComponent {
+        fn name(&self) -> &str {
+            self.name
+        }
+
+        async fn shutdown(&self) -> Result<()> {
+            self.stopped.lock().unwrap().push(self.name);
+            if self.fail {
+                return Err(NetworkError::internal_error("仍有未完成的任务"));
+            }
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_shutdown_stops_components_in_reverse_order() {
+        let stopped = Arc::new(Mutex::new(Vec::new()));
+        let component = |name, fail| -> Arc<dyn ShutdownComponent> {
+            Arc::new(RecordingComponent {
+                name,
+                fail,
+                stopped: stopped.clone(),
+            })
+        };
+
+        let orchestrator = ServiceOrchestrator::new(AnemoNetworkService::new())
+            .with_component(component("timesync", false))
+            .with_component(component("chat", false));
+        orchestrator.shutdown().await.unwrap();
+        assert_eq!(*stopped.lock().unwrap(), vec!["chat", "timesync"]);
+
+        // 失败的组件不影响其余组件停止，错误按组件汇总
+        stopped.lock().unwrap().clear();
+        let orchestrator = ServiceOrchestrator::new(AnemoNetworkService::new())
+            .with_component(component("timesync", false))
+            .with_component(component("chat", true));
+        match orchestrator.shutdown().await {
+            Err(NetworkError::ShutdownFailed(failures)) => {
+                assert_eq!(failures.len(), 1);
+                assert_eq!(failures[0].0, "chat");
+            }
+            other => panic!("意外的结果: {:?}", other),
+        }
+        assert_eq!(*stopped.lock().unwrap(), vec!["chat", "timesync"]);
+    }
+}
@@ -0,0 +1,109 @@
+//! 中继转发与消息去重
+//!
+//! 中继节点转发广播时保留原始消息ID，并在元数据中累积经过的节点列表；
+//! 转发时跳过路径上已有的节点。网状拓扑中同一条消息仍可能经不同路径
+//! 多次到达，接收端按消息ID去重，保证每个节点只处理一次。
+
+use crate::{BroadcastOptions, HandlerContext, MessageId, NetworkMessage, NodeId, Result};
+use std::collections::{HashSet, VecDeque};
+use std::sync::Arc;
+use tokio::sync::Mutex;
+
+/// 消息元数据中记录中继路径的键，值为节点ID的JSON数组
+pub const RELAY_PATH_METADATA_KEY: &str = "relay_path";
+
+/// 默认记住的消息ID数量
+pub const DEFAULT_SEEN_CAPACITY: usize = 4096;
+
+impl NetworkMessage {
+    /// 已经过的中继节点（不含原始发送者）
+    pub fn relay_path(&self) -> Vec<NodeId> {
+        self.get_metadata(RELAY_PATH_METADATA_KEY)
+            .and_then(|value| serde_json::from_str(value).ok())
+            .unwrap_or_default()
+    }
+
+    /// 追加一个中继节点，消息ID保持不变
+    pub fn with_relay_hop(self, node_id: NodeId) -> Self {
+        let mut path = self.relay_path();
+        path.push(node_id);
+        let value = serde_json::to_string(&path).unwrap_or_default();
+        self.with_metadata(RELAY_PATH_METADATA_KEY.to_string(), value)
+    }
+}
+
+impl HandlerContext {
+    /// 将收到的广播转发给其他节点
+    ///
+    /// 保留原始消息ID，跳过原始发送者、上一跳和路径上已有的节点。
+    pub async fn relay_broadcast(
+        &self,
+        from: NodeId,
+        message: NetworkMessage,
+    ) -> Result<MessageId> {
+        let local_id = self.local_node_id().await?;
+
+        let mut exclude_nodes = message.relay_path();
+        exclude_nodes.push(message.sender.clone());
+        exclude_nodes.push(from);
+
+        let relayed = message.with_relay_hop(local_id);
+        let options = BroadcastOptions {
+            exclude_nodes,
+            ..Default::default()
+        };
+        self.broadcast(relayed, Some(options)).await
+    }
+}
+
+/// 按消息ID去重，只记住最近的若干条
+#[derive(Debug, Clone)]
+pub struct SeenMessages {
+    capacity: usize,
+    inner: Arc<Mutex<SeenInner>>,
+}
+
+#[derive(Debug, Default)]
+struct SeenInner {
+    ids: HashSet<MessageId>,
+    order: VecDeque<MessageId>,
+}
+
+impl SeenMessages {
+    /// 创建去重器
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity: capacity.max(1),
+            inner: Arc::new(Mutex::new(SeenInner::default())),
+        }
+    }
+
+    /// 记录消息ID，第一次见到时返回 `true`
+    pub async fn insert(&self, id: MessageId) -> bool {
+        let mut inner = self.inner.lock().await;
+        if !inner.ids.insert(id) {
+            return false;
+        }
+        inner.order.push_back(id);
+        if inner.order.len() > self.capacity {
+            if let Some(oldest) = inner.order.pop_front() {
+                inner.ids.remove(&oldest);
+            }
+        }
+        true
+    }
+
+    /// 忘记消息ID，例如消息没有处理成功，发送方重试时应重新处理
+    pub async fn remove(&self, id: MessageId) {
+        let mut inner = self.inner.lock().await;
+        if inner.ids.remove(&id) {
+            inner.order.retain(|seen| *seen != id);
+        }
+    }
+}
+
+impl Default for SeenMessages {
+    fn default() -> Self {
+        Self::new(DEFAULT_SEEN_CAPACITY)
+    }
+}
@@ -0,0 +1,192 @@
+//! 消息重放保护
+//!
+//! 发送方为每条消息附带单调递增的序号（nonce），接收方按发送节点记录
+//! 已见过的序号：重复的序号，或落后于当前最大序号超过重排窗口的序号，
+//! 都视为重放并拒绝。与基于消息ID的去重互为补充。
+//!
+//! 发送节点取传输层认证的对端，而不是消息中自报的 `sender` 字段，
+//! 否则改写 `sender` 即可绕过检测。
+
+use crate::{NetworkError, NetworkMessage, Result};
+use std::collections::{HashMap, HashSet};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use tokio::sync::RwLock;
+
+/// 消息元数据中携带序号的键
+pub const NONCE_METADATA_KEY: &str = "nonce";
+
+/// 默认的重排窗口大小
+pub const DEFAULT_REORDER_WINDOW: u64 = 64;
+
+impl NetworkMessage {
+    /// 附带重放保护序号
+    pub fn with_nonce(self, nonce: u64) -> Self {
+        self.with_metadata(NONCE_METADATA_KEY.to_string(), nonce.to_string())
+    }
+
+    /// 获取重放保护序号
+    pub fn nonce(&self) -> Option<u64> {
+        self.get_metadata(NONCE_METADATA_KEY)
+            .and_then(|value| value.parse().ok())
+    }
+}
+
+/// 发送端序号生成器
+#[derive(Debug, Clone, Default)]
+pub struct NonceGenerator {
+    next: Arc<AtomicU64>,
+}
+
+impl NonceGenerator {
+    /// 创建新的序号生成器
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// 创建从 `start` 之后开始生成序号的生成器
+    ///
+    /// 以当前时间为起点时，进程重启后生成的序号仍大于接收端记录的最大序号。
+    pub fn starting_at(start: u64) -> Self {
+        Self {
+            next: Arc::new(AtomicU64::new(start)),
+        }
+    }
+
+    /// 获取下一个序号
+    pub fn next_nonce(&self) -> u64 {
+        self.next.fetch_add(1, Ordering::SeqCst) + 1
+    }
+}
+
+/// 单个发送者的序号窗口
+#[derive(Debug, Default)]
+struct SenderWindow {
+    /// 已接受的最大序号
+    highest: u64,
+    /// 窗口内已接受的序号
+    seen: HashSet<u64>,
+}
+
+/// 接收端重放检测器
+#[derive(Debug, Clone)]
+pub struct ReplayGuard {
+    /// 允许乱序到达的窗口大小
+    window: u64,
+    /// 按发送者记录的序号窗口
+    senders: Arc<RwLock<HashMap<String, SenderWindow>>>,
+}
+
+impl ReplayGuard {
+    /// 创建新的重放检测器
+    pub fn new(window: u64) -> Self {
+        Self {
+            window,
+            senders: Arc::new(RwLock::new(HashMap::new())),
+        }
+    }
+
+    /// 检查序号是否为重放，未重放时记录该序号
+    pub async fn check_and_record(&self, sender: &str, nonce: u64) -> Result<()> {
+        let mut senders = self.senders.write().await;
+        let entry = senders.entry(sender.to_string()).or_default();
+
+        let too_old = nonce.saturating_add(self.window) <= entry.highest;
+        if too_old || entry.seen.contains(&nonce) {
+            return Err(NetworkError::ReplayDetected {
+                sender: sender.to_string(),
+                nonce,
+            });
+        }
+
+        entry.seen.insert(nonce);
+        if nonce > entry.highest {
+            entry.highest = nonce;
+            let floor = entry.highest.saturating_sub(self.window);
+            entry.seen.retain(|&n| n > floor);
+        }
+
+        Ok(())
+    }
+
+    /// 检查来自 `sender` 节点的消息
+    ///
+    /// `require_nonce` 为假时未携带序号的消息不做检查，为真时拒绝这类消息。
+    pub async fn check_message(
+        &self,
+        sender: &str,
+        message: &NetworkMessage,
+        require_nonce: bool,
+    ) -> Result<()> {
+        match message.nonce() {
+            Some(nonce) => self.check_and_record(sender, nonce).await,
+            None if require_nonce => Err(NetworkError::MissingNonce(sender.to_string())),
+            None => Ok(()),
+        }
+    }
+}
+
+impl Default for ReplayGuard {
+    fn default() -> Self {
+        Self::new(DEFAULT_REORDER_WINDOW)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::MessageType;
+
+    #[tokio::test]
+    async fn test_replayed_message_rejected() {
+        let guard = ReplayGuard::default();
+        let nonces = NonceGenerator::new();
+        let message = NetworkMessage::new(
+            MessageType::chat(),
+            "node-a".to_string(),
+            serde_json::json!({"command": "kick"}),
+        )
+        .with_nonce(nonces.next_nonce());
+
+        assert!(guard.check_message("node-a", &message, false).await.is_ok());
+        let replay = guard.check_message("node-a", &message.clone(), false).await;
+        assert!(matches!(
+            replay,
+            Err(NetworkError::ReplayDetected { nonce: 1, .. })
+        ));
+
+        // 改写自报的发送者不能绕过检测
+        let mut forged = message.clone();
+        forged.sender = "node-z".to_string();
+        assert!(guard.check_message("node-a", &forged, false).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_missing_nonce_rejected_when_required() {
+        let guard = ReplayGuard::default();
+        let message = NetworkMessage::new(
+            MessageType::chat(),
+            "node-a".to_string(),
+            serde_json::json!({"command": "kick"}),
+        );
+
+        assert!(guard.check_message("node-a", &message, false).await.is_ok());
+        assert!(matches!(
+            guard.check_message("node-a", &message, true).await,
+            Err(NetworkError::MissingNonce(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_reorder_window() {
+        let guard = ReplayGuard::new(4);
+
+        guard.check_and_record("node-a", 10).await.unwrap();
+        // 窗口内乱序到达的序号可以接受
+        guard.check_and_record("node-a", 8).await.unwrap();
+        // 超出窗口的旧序号被拒绝
+        assert!(guard.check_and_record("node-a", 6).await.is_err());
+        // 其他发送者互不影响
+        guard.check_and_record("node-b", 1).await.unwrap();
+    }
+}
@@ -1,12 +1,21 @@
 //! 网络服务核心实现
 
+use crate::delivery_age::{delivery_age_ms, OTHER_MESSAGE_TYPE};
 use crate::MessageHandler;
-use crate::{EventBus, MessageType, NetworkMessage, NodeId, Result};
-use rand::RngCore;
-use std::collections::HashMap;
+use crate::{
+    DeliveryAgeStats, EventBus, HandlerContext, HandlerKey, KeySource, MessageId, MessageLimits,
+    MessageType, NetworkMessage, NodeId, ReplayGuard, Result, SeenMessages, ShutdownReport,
+};
+use std::collections::{HashMap, HashSet, VecDeque};
 use std::net::SocketAddr;
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::Arc;
-use tokio::sync::RwLock;
+use std::time::Duration;
+use tokio::sync::{oneshot, Mutex, RwLock, Semaphore};
+use tokio::task::JoinSet;
+
+/// 服务器名称的最大长度
+pub const MAX_SERVER_NAME_LEN: usize = 64;
 
 /// 网络服务配置
 #[derive(Debug, Clone)]
@@ -15,33 +24,181 @@ pub struct NetworkServiceConfig {
     pub bind_address: SocketAddr,
     /// 服务器名称
     pub server_name: String,
-    /// 私钥（用于TLS）
-    pub private_key: [u8; 32],
+    /// 私钥来源（用于TLS），启动时解析
+    pub private_key: KeySource,
     /// 最大连接数
     pub max_connections: usize,
     /// 心跳间隔（毫秒）
     pub heartbeat_interval_ms: u64,
     /// 消息缓冲区大小
+    ///
+    /// 未设置 `handler_workers` 时作为同时处理中的入站消息上限。缓冲区满时，
+    /// 新的入站消息在 `handle_incoming_message` 中等待空位，从而向接收端施加背压。
     pub message_buffer_size: usize,
-    /// 事件总线容量
+    /// 处理器工作池大小（同时运行的消息处理器数量），`None` 时使用 `message_buffer_size`
+    pub handler_workers: Option<usize>,
+    /// 消息大小限制（整条消息和元数据），发送和接收时都会检查
+    pub message_limits: MessageLimits,
+    /// 事件总线容量：订阅者最多可以落后的事件数量，超出时丢失最早的事件
+    ///
+    /// 启动时生效；启动前已有订阅者时无法调整，沿用原容量。
     pub event_bus_capacity: usize,
+    /// 单次发送（RPC）超时（毫秒），消息选项未指定超时时使用
+    pub send_timeout_ms: u64,
+    /// 慢处理器告警阈值（毫秒），消息处理耗时超过该值时记录警告
+    pub slow_handler_threshold_ms: u64,
+    /// 消息处理器最长执行时间（毫秒），超时的处理器会被取消
+    pub handler_timeout_ms: u64,
+    /// 停止时等待运行中的消息处理器完成的最长时间（毫秒），超时后中止
+    pub shutdown_grace_ms: u64,
+    /// 连接已知服务器时同时进行的最大连接数
+    pub max_concurrent_connects: usize,
+    /// 入站数据解码失败时对来源节点的处理策略
+    pub on_decode_error: DecodeErrorPolicy,
+    /// 观察者模式：只接收消息并发布事件，不发送消息，也不运行消息处理器
+    pub observer: bool,
+    /// 是否按消息类型记录出站消息的序列化大小和耗时
+    pub record_serialization_stats: bool,
+    /// 显式指定的本地节点ID，`None` 时由服务名和监听地址生成
+    pub node_id: Option<NodeId>,
+    /// 空闲连接超时（毫秒）：超过该时间没有应用消息（系统消息不计）的连接会被断开，
+    /// 已知服务器的连接除外；`None` 表示不断开空闲连接
+    pub idle_connection_timeout_ms: Option<u64>,
+    /// 没有处理器的消息暂存多久（毫秒）等待处理器注册，0 表示直接丢弃
+    ///
+    /// 用于覆盖启动后、处理器注册前到达的消息。
+    pub unhandled_grace_ms: u64,
+    /// 暂存的无处理器消息数量上限
+    pub unhandled_buffer_capacity: usize,
+    /// `get_connected_nodes` 返回节点的排列顺序
+    pub connected_nodes_order: NodeOrder,
+    /// 是否拒绝未携带重放保护序号的入站消息
+    pub require_nonce: bool,
+    /// 在节点信息中公布的附加元数据，不能覆盖节点ID等内置字段
+    ///
+    /// 任何连接到本节点的对端都能查询，不能包含令牌等机密，认证凭据使用 `auth_metadata`。
+    pub node_info_metadata: HashMap<String, String>,
+    /// 出示给本节点主动连接的对端的认证元数据（例如令牌）
+    ///
+    /// 只随节点信息提供给连接地址由本节点拨出的对端，连入本节点的节点查询不到。
+    pub auth_metadata: HashMap<String, String>,
+}
+
+/// 入站数据解码失败时的处理策略
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum DecodeErrorPolicy {
+    /// 只丢弃无法解码的数据
+    #[default]
+    Drop,
+    /// 同一节点连续解码失败达到指定次数后断开并屏蔽该节点
+    DisconnectAfter(u32),
+}
+
+/// 已连接节点列表的排序方式
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum NodeOrder {
+    /// 按节点ID字典序
+    #[default]
+    NodeId,
+    /// 按本节点观察到的连接建立时间，先连接的在前；时间未知的节点排在最后并按节点ID排序
+    ConnectedAt,
 }
 
 impl Default for NetworkServiceConfig {
     fn default() -> Self {
-        // 生成随机私钥
-        let mut private_key = [0u8; 32];
-        rand::rng().fill_bytes(&mut private_key);
-
         Self {
             bind_address: "127.0.0.1:8080".parse().unwrap(),
             server_name: "anemo-network-service".to_string(),
-            private_key,
+            private_key: KeySource::default(),
             max_connections: 1000,
             heartbeat_interval_ms: 30000,
             message_buffer_size: 1000,
+            handler_workers: None,
+            message_limits: MessageLimits::default(),
             event_bus_capacity: 1000,
+            send_timeout_ms: 5000,
+            slow_handler_threshold_ms: 1000,
+            handler_timeout_ms: 30000,
+            shutdown_grace_ms: 5000,
+            max_concurrent_connects: 8,
+            on_decode_error: DecodeErrorPolicy::default(),
+            observer: false,
+            record_serialization_stats: false,
+            node_id: None,
+            idle_connection_timeout_ms: None,
+            unhandled_grace_ms: 0,
+            unhandled_buffer_capacity: 64,
+            connected_nodes_order: NodeOrder::default(),
+            require_nonce: false,
+            node_info_metadata: HashMap::new(),
+            auth_metadata: HashMap::new(),
+        }
+    }
+}
+
+impl NetworkServiceConfig {
+    /// 实际生效的处理器工作池大小，至少为1
+    pub fn effective_handler_workers(&self) -> usize {
+        self.handler_workers
+            .unwrap_or(self.message_buffer_size)
+            .max(1)
+    }
+
+    /// 校验配置
+    pub fn validate(&self) -> Result<()> {
+        let name = self.server_name.trim();
+        if name.is_empty() {
+            return Err(crate::NetworkError::config_error("服务器名称不能为空"));
         }
+        if name.chars().count() > MAX_SERVER_NAME_LEN {
+            return Err(crate::NetworkError::config_error(format!(
+                "服务器名称过长（上限 {} 字符）: {}",
+                MAX_SERVER_NAME_LEN, name
+            )));
+        }
+        if self
+            .node_id
+            .as_ref()
+            .is_some_and(|node_id| node_id.trim().is_empty())
+        {
+            return Err(crate::NetworkError::config_error("节点ID不能为空"));
+        }
+        Ok(())
+    }
+}
+
+/// 等待处理器注册的入站消息
+struct PendingMessage {
+    ctx: HandlerContext,
+    from: NodeId,
+    message: NetworkMessage,
+    reply: Option<oneshot::Sender<Option<NetworkMessage>>>,
+}
+
+/// 处理器工作池的运行状态
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct PoolStats {
+    /// 工作池大小
+    pub workers: usize,
+    /// 正在运行的处理器数量
+    pub active: usize,
+    /// 等待空闲工作位的入站消息数量
+    pub queued: usize,
+}
+
+/// 等待工作位期间计入排队数量，离开作用域时扣除
+struct QueuedGuard(Arc<AtomicUsize>);
+
+impl QueuedGuard {
+    fn new(queued: &Arc<AtomicUsize>) -> Self {
+        queued.fetch_add(1, Ordering::SeqCst);
+        Self(queued.clone())
+    }
+}
+
+impl Drop for QueuedGuard {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
     }
 }
 
@@ -51,23 +208,63 @@ pub struct NetworkService {
     /// 事件总线
     event_bus: EventBus,
     /// 消息处理器注册表
-    message_handlers: Arc<RwLock<HashMap<MessageType, Arc<dyn MessageHandler>>>>,
+    message_handlers: Arc<RwLock<HashMap<HandlerKey, Arc<dyn MessageHandler>>>>,
     /// 服务状态
     is_running: Arc<RwLock<bool>>,
     /// 配置
     config: Arc<RwLock<Option<NetworkServiceConfig>>>,
+    /// 处理器工作池（处理中消息的空位）
+    inbound_slots: Arc<RwLock<Arc<Semaphore>>>,
+    /// 等待工作位的入站消息数量
+    handler_queued: Arc<AtomicUsize>,
+    /// 运行中的处理器任务，停止时等待或中止；`None` 表示已停止，不再接受新任务
+    handler_tasks: Arc<Mutex<Option<JoinSet<()>>>>,
+    /// 重放检测
+    replay_guard: ReplayGuard,
+    /// 被丢弃的回环消息数量
+    loop_prevented: Arc<RwLock<u64>>,
+    /// 已处理的消息ID，用于丢弃经不同中继路径重复到达的消息
+    seen_messages: SeenMessages,
+    /// 被丢弃的重复消息数量
+    duplicates_dropped: Arc<RwLock<u64>>,
+    /// 各节点连续解码失败次数
+    decode_failures: Arc<RwLock<HashMap<NodeId, u32>>>,
+    /// 因持续发送无法解码的数据而被屏蔽的节点
+    blocked_peers: Arc<RwLock<HashSet<NodeId>>>,
+    /// 按消息类型统计的投递时延
+    delivery_ages: Arc<RwLock<HashMap<MessageType, DeliveryAgeStats>>>,
+    /// 暂存的无处理器消息（按到达顺序）
+    pending_unhandled: Arc<Mutex<VecDeque<PendingMessage>>>,
 }
 
 impl NetworkService {
     /// 创建新的网络服务
     pub fn new() -> Self {
-        let event_bus = EventBus::new(1000);
+        Self::with_event_bus(EventBus::new(
+            NetworkServiceConfig::default().event_bus_capacity,
+        ))
+    }
+
+    /// 创建使用指定事件总线的网络服务，例如作为具体传输实现的入站处理流程
+    pub fn with_event_bus(event_bus: EventBus) -> Self {
+        let workers = NetworkServiceConfig::default().effective_handler_workers();
 
         Self {
             event_bus,
             message_handlers: Arc::new(RwLock::new(HashMap::new())),
             is_running: Arc::new(RwLock::new(false)),
             config: Arc::new(RwLock::new(None)),
+            inbound_slots: Arc::new(RwLock::new(Arc::new(Semaphore::new(workers)))),
+            handler_queued: Arc::new(AtomicUsize::new(0)),
+            handler_tasks: Arc::new(Mutex::new(Some(JoinSet::new()))),
+            replay_guard: ReplayGuard::default(),
+            loop_prevented: Arc::new(RwLock::new(0)),
+            seen_messages: SeenMessages::default(),
+            duplicates_dropped: Arc::new(RwLock::new(0)),
+            decode_failures: Arc::new(RwLock::new(HashMap::new())),
+            blocked_peers: Arc::new(RwLock::new(HashSet::new())),
+            delivery_ages: Arc::new(RwLock::new(HashMap::new())),
+            pending_unhandled: Arc::new(Mutex::new(VecDeque::new())),
         }
     }
 
@@ -81,6 +278,165 @@ impl NetworkService {
         *self.is_running.read().await
     }
 
+    /// 被丢弃的回环消息数量
+    pub async fn loop_prevented_count(&self) -> u64 {
+        *self.loop_prevented.read().await
+    }
+
+    /// 被丢弃的重复消息数量
+    pub async fn duplicates_dropped_count(&self) -> u64 {
+        *self.duplicates_dropped.read().await
+    }
+
+    /// 暂存中等待处理器注册的消息数量
+    pub async fn pending_unhandled_count(&self) -> usize {
+        self.pending_unhandled.lock().await.len()
+    }
+
+    /// 节点是否因解码失败被屏蔽
+    pub async fn is_peer_blocked(&self, node_id: &NodeId) -> bool {
+        self.blocked_peers.read().await.contains(node_id)
+    }
+
+    /// 解除对节点的屏蔽
+    pub async fn unblock_peer(&self, node_id: &NodeId) -> bool {
+        self.decode_failures.write().await.remove(node_id);
+        self.blocked_peers.write().await.remove(node_id)
+    }
+
+    /// 处理入站的原始数据：解码后交给 `handle_incoming_message`
+    ///
+    /// 解码失败按 `on_decode_error` 策略处理，连续失败达到阈值的节点会被断开并屏蔽。
+    pub async fn handle_incoming_frame(
+        &self,
+        ctx: HandlerContext,
+        from: NodeId,
+        bytes: &[u8],
+    ) -> Result<()> {
+        let message = self.decode_frame(&from, bytes).await?;
+        self.handle_incoming_message(ctx, from, message).await
+    }
+
+    /// 检查并解码入站的原始数据，拒绝被屏蔽节点的数据
+    ///
+    /// 解码失败按 `on_decode_error` 策略处理，连续失败达到阈值的节点会被屏蔽。
+    /// 被屏蔽节点的数据返回 [`NetworkError::PeerBlocked`](crate::NetworkError::PeerBlocked)，
+    /// 由传输层通知对端并断开连接。
+    pub async fn decode_frame(&self, from: &NodeId, bytes: &[u8]) -> Result<NetworkMessage> {
+        if self.is_peer_blocked(from).await {
+            return Err(crate::NetworkError::PeerBlocked(from.clone()));
+        }
+
+        // 解码前先按整体大小拒绝，避免为超大数据分配内存
+        let limits = self
+            .get_config()
+            .await
+            .map(|config| config.message_limits)
+            .unwrap_or_default();
+        if let Err(e) = limits.check_encoded_len(bytes.len()) {
+            tracing::warn!("拒绝来自 {} 的数据: {}", from, e);
+            return Err(e);
+        }
+
+        let message = match NetworkMessage::from_bytes(bytes) {
+            Ok(message) => {
+                self.decode_failures.write().await.remove(from);
+                message
+            }
+            Err(e) => {
+                if self.record_decode_failure(from, &e).await {
+                    return Err(crate::NetworkError::PeerBlocked(from.clone()));
+                }
+                return Err(e.into());
+            }
+        };
+        if let Err(e) = message
+            .check_envelope_version()
+            .and_then(|()| limits.check_metadata(&message))
+        {
+            tracing::warn!("拒绝来自 {} 的消息 {}: {}", from, message.id, e);
+            return Err(e);
+        }
+        Ok(message)
+    }
+
+    /// 记录一次解码失败，达到阈值时屏蔽节点；返回节点是否因此被屏蔽
+    async fn record_decode_failure(&self, from: &NodeId, error: &serde_json::Error) -> bool {
+        let failures = {
+            let mut counts = self.decode_failures.write().await;
+            let count = counts.entry(from.clone()).or_insert(0);
+            *count += 1;
+            *count
+        };
+        tracing::warn!(
+            "无法解码来自 {} 的数据（连续 {} 次）: {}",
+            from,
+            failures,
+            error
+        );
+        self.event_bus
+            .publish(crate::event_bus::NetworkEvent::Error {
+                error: format!("无法解码来自 {} 的数据: {}", from, error),
+            })
+            .await;
+
+        let policy = self
+            .get_config()
+            .await
+            .map(|config| config.on_decode_error)
+            .unwrap_or_default();
+        match policy {
+            DecodeErrorPolicy::DisconnectAfter(limit)
+                if failures >= limit.max(1)
+                    && self.blocked_peers.write().await.insert(from.clone()) =>
+            {
+                tracing::warn!(
+                    "节点 {} 连续 {} 次发送无法解码的数据，屏蔽该节点",
+                    from,
+                    failures
+                );
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// 各消息类型的投递时延统计
+    pub async fn delivery_age_stats(&self) -> HashMap<MessageType, DeliveryAgeStats> {
+        self.delivery_ages.read().await.clone()
+    }
+
+    /// 投递时延统计中的消息类型：内置类型和已注册处理器的类型单独统计，其他类型合并
+    async fn delivery_age_key(&self, message_type: &MessageType) -> MessageType {
+        let builtin = [
+            MessageType::chat(),
+            MessageType::timesync(),
+            MessageType::system(),
+            MessageType::ack(),
+        ];
+        let known = builtin.contains(message_type)
+            || self
+                .message_handlers
+                .read()
+                .await
+                .keys()
+                .any(|(registered, _)| registered == message_type);
+        if known {
+            message_type.clone()
+        } else {
+            MessageType::new(OTHER_MESSAGE_TYPE)
+        }
+    }
+
+    /// 指定消息类型最近一次的投递时延（毫秒）
+    pub async fn last_delivery_age_ms(&self, message_type: &MessageType) -> Option<u64> {
+        self.delivery_ages
+            .read()
+            .await
+            .get(message_type)
+            .map(|stats| stats.last_ms)
+    }
+
     /// 获取配置
     pub async fn get_config(&self) -> Option<NetworkServiceConfig> {
         self.config.read().await.clone()
@@ -88,35 +444,253 @@ impl NetworkService {
 
     /// 设置配置
     pub async fn set_config(&self, config: NetworkServiceConfig) {
+        self.event_bus.set_capacity(config.event_bus_capacity);
+        self.resize_pool(config.effective_handler_workers()).await;
+        // 停止后重新启动时恢复接受处理器任务
+        self.handler_tasks
+            .lock()
+            .await
+            .get_or_insert_with(JoinSet::new);
         *self.config.write().await = Some(config);
     }
 
+    /// 按新的大小调整工作池，等待中的消息留在同一个工作池中，不会因替换而卡住或越过上限
+    ///
+    /// 缩小时先收回空闲的工作位，不足的部分在运行中的处理器结束后收回；
+    /// 停止时工作池已关闭，重新启动时按新大小重建。
+    async fn resize_pool(&self, workers: usize) {
+        let current = self
+            .get_config()
+            .await
+            .unwrap_or_default()
+            .effective_handler_workers();
+        let mut slots = self.inbound_slots.write().await;
+        if slots.is_closed() {
+            *slots = Arc::new(Semaphore::new(workers));
+        } else if workers > current {
+            slots.add_permits(workers - current);
+        } else if workers < current {
+            let excess = current - workers;
+            let remaining = excess - slots.forget_permits(excess);
+            if remaining > 0 {
+                let slots = slots.clone();
+                tokio::spawn(async move {
+                    if let Ok(permits) = slots.acquire_many_owned(remaining as u32).await {
+                        permits.forget();
+                    }
+                });
+            }
+        }
+    }
+
+    /// 当前处理中的入站消息数量
+    pub async fn inbound_in_flight(&self) -> usize {
+        self.handler_pool_stats().await.active
+    }
+
+    /// 处理器工作池的运行状态，`queued` 持续增长说明处理器跟不上入站速度
+    pub async fn handler_pool_stats(&self) -> PoolStats {
+        let workers = self
+            .get_config()
+            .await
+            .unwrap_or_default()
+            .effective_handler_workers();
+        let available = self.inbound_slots.read().await.available_permits();
+        PoolStats {
+            workers,
+            active: workers.saturating_sub(available),
+            queued: self.handler_queued.load(Ordering::SeqCst),
+        }
+    }
+
+    /// 是否处于观察者模式
+    pub async fn is_observer(&self) -> bool {
+        self.get_config()
+            .await
+            .is_some_and(|config| config.observer)
+    }
+
     /// 注册消息处理器
     pub async fn register_message_handler_internal(
         &self,
         message_type: MessageType,
         handler: Arc<dyn MessageHandler>,
     ) -> Result<()> {
-        let mut handlers = self.message_handlers.write().await;
-        handlers.insert(message_type, handler);
+        if self.is_observer().await {
+            return Err(crate::NetworkError::ObserverMode(
+                "注册消息处理器".to_string(),
+            ));
+        }
+        self.message_handlers
+            .write()
+            .await
+            .insert((message_type, None), handler);
+        self.deliver_pending().await;
+        Ok(())
+    }
+
+    /// 注册默认处理器，已有处理器时保留已有的处理器
+    pub(crate) async fn register_default_handler(
+        &self,
+        message_type: MessageType,
+        handler: Arc<dyn MessageHandler>,
+    ) {
+        self.message_handlers
+            .write()
+            .await
+            .entry((message_type, None))
+            .or_insert(handler);
+        self.deliver_pending().await;
+    }
+
+    /// 是否注册了任何消息处理器
+    pub async fn has_handlers(&self) -> bool {
+        !self.message_handlers.read().await.is_empty()
+    }
+
+    /// 已注册处理器的消息类型名称（排序、去重）
+    pub async fn handled_message_types(&self) -> Vec<String> {
+        let handlers = self.message_handlers.read().await;
+        let mut types: Vec<String> = handlers
+            .keys()
+            .map(|(message_type, _)| message_type.0.clone())
+            .collect();
+        types.sort();
+        types.dedup();
+        types
+    }
+
+    /// 注册只处理指定主题消息的处理器
+    pub async fn register_topic_handler_internal(
+        &self,
+        message_type: MessageType,
+        topic: String,
+        handler: Arc<dyn MessageHandler>,
+    ) -> Result<()> {
+        if self.is_observer().await {
+            return Err(crate::NetworkError::ObserverMode(
+                "注册消息处理器".to_string(),
+            ));
+        }
+        self.message_handlers
+            .write()
+            .await
+            .insert((message_type, Some(topic)), handler);
+        self.deliver_pending().await;
         Ok(())
     }
 
-    /// 获取消息处理器
+    /// 获取消息处理器（不带主题）
     pub async fn get_message_handler(
         &self,
         message_type: &MessageType,
     ) -> Option<Arc<dyn MessageHandler>> {
         let handlers = self.message_handlers.read().await;
-        handlers.get(message_type).cloned()
+        handlers.get(&(message_type.clone(), None)).cloned()
+    }
+
+    /// 按消息类型和主题查找处理器
+    ///
+    /// 优先使用该主题的处理器；没有时回退到只按类型注册的处理器，
+    /// 因此只按类型注册的处理器也能收到带主题的消息。
+    pub async fn get_handler_for(
+        &self,
+        message: &NetworkMessage,
+    ) -> Option<Arc<dyn MessageHandler>> {
+        let handlers = self.message_handlers.read().await;
+        handlers
+            .get(&message.handler_key())
+            .or_else(|| handlers.get(&(message.message_type.clone(), None)))
+            .cloned()
     }
 
     /// 处理接收到的消息
+    ///
+    /// 入站缓冲区已满时会等待，直到有处理中的消息完成。
+    ///
+    /// 处理器的响应通过单播发回发送者，适用于没有响应通道的传输方式；
+    /// 收到的是RPC请求时应使用 [`handle_incoming_request`](Self::handle_incoming_request)。
     pub async fn handle_incoming_message(
         &self,
+        ctx: HandlerContext,
+        from: NodeId,
+        message: NetworkMessage,
+    ) -> Result<()> {
+        self.dispatch_incoming(ctx, from, message, None).await
+    }
+
+    /// 处理入站请求，返回处理器的响应，由调用方在同一连接上回复
+    ///
+    /// 不会按发送者的节点ID重新建立连接，因此发送者不可被连接
+    /// （例如绑定在 `0.0.0.0:0` 的客户端）时也能收到响应。
+    pub async fn handle_incoming_request(
+        &self,
+        ctx: HandlerContext,
+        from: NodeId,
+        message: NetworkMessage,
+    ) -> Result<Option<NetworkMessage>> {
+        let (reply_tx, reply_rx) = oneshot::channel();
+        self.dispatch_incoming(ctx, from, message, Some(reply_tx))
+            .await?;
+        // 消息被丢弃、没有处理器或处理失败时不回复
+        Ok(reply_rx.await.ok().flatten())
+    }
+
+    /// 入站消息处理流程，`reply` 为 `None` 时通过单播回复
+    async fn dispatch_incoming(
+        &self,
+        ctx: HandlerContext,
         from: NodeId,
         message: NetworkMessage,
+        reply: Option<oneshot::Sender<Option<NetworkMessage>>>,
     ) -> Result<()> {
+        // 丢弃本节点自己发出的消息，避免处理器反复转发形成回环
+        if let Ok(local_id) = ctx.local_node_id().await {
+            if message.sender == local_id {
+                tracing::warn!("丢弃来自本节点的回环消息 {}", message.id);
+                *self.loop_prevented.write().await += 1;
+                return Ok(());
+            }
+        }
+
+        // 拒绝重放的消息，按发送节点而不是消息自报的发送者记录序号
+        let require_nonce = self
+            .config
+            .read()
+            .await
+            .as_ref()
+            .is_some_and(|config| config.require_nonce);
+        if let Err(e) = self
+            .replay_guard
+            .check_message(&from, &message, require_nonce)
+            .await
+        {
+            tracing::warn!("拒绝来自 {} 的消息: {}", from, e);
+            self.event_bus
+                .publish(crate::event_bus::NetworkEvent::Error {
+                    error: e.to_string(),
+                })
+                .await;
+            return Err(e);
+        }
+
+        // 同一消息经多条中继路径到达时只处理一次；处理失败的消息会被忘记，重试时重新处理
+        if !self.seen_messages.insert(message.id).await {
+            tracing::debug!("丢弃来自 {} 的重复消息 {}", from, message.id);
+            *self.duplicates_dropped.write().await += 1;
+            return Ok(());
+        }
+
+        // 记录投递时延（含发送端和接收端的排队时间）
+        let age_ms = delivery_age_ms(message.timestamp, crate::now_millis());
+        let age_key = self.delivery_age_key(&message.message_type).await;
+        self.delivery_ages
+            .write()
+            .await
+            .entry(age_key)
+            .or_default()
+            .record(age_ms);
+
         // 发布消息接收事件
         self.event_bus
             .publish(crate::event_bus::NetworkEvent::MessageReceived {
@@ -125,40 +699,281 @@ impl NetworkService {
             })
             .await;
 
+        // 观察者只发布事件，不运行处理器，也就不会产生任何响应
+        if self.is_observer().await {
+            return Ok(());
+        }
+
         // 查找消息处理器
-        if let Some(handler) = self.get_message_handler(&message.message_type).await {
-            // 异步处理消息
-            let handler_clone = handler.clone();
-            let from_clone = from.clone();
-            let message_clone = message.clone();
-            let event_bus = self.event_bus.clone();
+        if let Some(handler) = self.get_handler_for(&message).await {
+            self.spawn_handler(handler, ctx, from, message, reply)
+                .await?;
+        } else {
+            self.buffer_unhandled(PendingMessage {
+                ctx,
+                from,
+                message,
+                reply,
+            })
+            .await;
+        }
 
-            tokio::spawn(async move {
-                match handler_clone
-                    .handle_message(from_clone.clone(), message_clone)
-                    .await
-                {
-                    Ok(response) => {
-                        if let Some(response_msg) = response {
-                            // 如果有响应消息，可以在这里处理发送逻辑
-                            tracing::info!("消息处理器返回响应: {:?}", response_msg);
+        Ok(())
+    }
+
+    /// 暂存没有处理器的消息；未启用暂存或暂存已满时丢弃
+    ///
+    /// 暂存的消息到期时由定时任务取出，不依赖后续消息的到达。
+    async fn buffer_unhandled(&self, entry: PendingMessage) {
+        let config = self.get_config().await.unwrap_or_default();
+        let grace = Duration::from_millis(config.unhandled_grace_ms);
+
+        let mut pending = self.pending_unhandled.lock().await;
+        // 加锁期间处理器可能刚刚注册
+        if let Some(handler) = self.get_handler_for(&entry.message).await {
+            drop(pending);
+            self.run_pending(handler, entry).await;
+            return;
+        }
+
+        if grace.is_zero() || pending.len() >= config.unhandled_buffer_capacity {
+            drop(pending);
+            self.report_unhandled(entry.from, entry.message.message_type)
+                .await;
+            return;
+        }
+        tracing::debug!(
+            "暂存消息类型 {:?} 的消息 {}，等待处理器注册",
+            entry.message.message_type,
+            entry.message.id
+        );
+        self.schedule_expiry(entry.message.id, grace);
+        pending.push_back(entry);
+    }
+
+    /// `grace` 后仍在暂存区的消息按无处理器上报；请求方的回复通道随之关闭，得到空回复
+    fn schedule_expiry(&self, message_id: MessageId, grace: Duration) {
+        let service = self.clone();
+        tokio::spawn(async move {
+            tokio::time::sleep(grace).await;
+            let expired = {
+                let mut pending = service.pending_unhandled.lock().await;
+                pending
+                    .iter()
+                    .position(|entry| entry.message.id == message_id)
+                    .and_then(|index| pending.remove(index))
+            };
+            if let Some(entry) = expired {
+                service
+                    .report_unhandled(entry.from, entry.message.message_type)
+                    .await;
+            }
+        });
+    }
+
+    /// 投递已有处理器的暂存消息
+    async fn deliver_pending(&self) {
+        let mut ready = Vec::new();
+        {
+            let mut pending = self.pending_unhandled.lock().await;
+            let mut remaining = VecDeque::new();
+            for entry in pending.drain(..) {
+                match self.get_handler_for(&entry.message).await {
+                    Some(handler) => ready.push((handler, entry)),
+                    None => remaining.push_back(entry),
+                }
+            }
+            *pending = remaining;
+        }
+
+        for (handler, entry) in ready {
+            self.run_pending(handler, entry).await;
+        }
+    }
+
+    /// 运行暂存消息的处理器
+    async fn run_pending(&self, handler: Arc<dyn MessageHandler>, entry: PendingMessage) {
+        let message_id = entry.message.id;
+        if let Err(e) = self
+            .spawn_handler(handler, entry.ctx, entry.from, entry.message, entry.reply)
+            .await
+        {
+            tracing::warn!("投递暂存消息 {} 失败: {}", message_id, e);
+        }
+    }
+
+    /// 记录并发布没有处理器的消息
+    async fn report_unhandled(&self, from: NodeId, message_type: MessageType) {
+        tracing::warn!("未找到消息类型 {:?} 的处理器", message_type);
+        self.event_bus
+            .publish(crate::event_bus::NetworkEvent::UnhandledMessage { from, message_type })
+            .await;
+    }
+
+    /// 在后台运行消息处理器，`reply` 为 `None` 时通过单播回复
+    ///
+    /// 处理器失败、超时或未能启动时从去重记录中移除该消息。
+    async fn spawn_handler(
+        &self,
+        handler: Arc<dyn MessageHandler>,
+        ctx: HandlerContext,
+        from: NodeId,
+        message: NetworkMessage,
+        reply: Option<oneshot::Sender<Option<NetworkMessage>>>,
+    ) -> Result<()> {
+        // 异步处理消息
+        let handler_clone = handler.clone();
+        let from_clone = from.clone();
+        let message_clone = message.clone();
+        let event_bus = self.event_bus.clone();
+        let config = self.get_config().await.unwrap_or_default();
+        let slow_threshold_ms = config.slow_handler_threshold_ms;
+        let handler_timeout = std::time::Duration::from_millis(config.handler_timeout_ms);
+        let seen_messages = self.seen_messages.clone();
+        let message_id = message.id;
+
+        // 占用一个工作位，处理完成后释放
+        let slots = self.inbound_slots.read().await.clone();
+        let queued = QueuedGuard::new(&self.handler_queued);
+        let permit = match slots.acquire_owned().await {
+            Ok(permit) => permit,
+            Err(e) => {
+                self.seen_messages.remove(message_id).await;
+                return Err(crate::NetworkError::internal_error(format!(
+                    "入站缓冲区已关闭: {}",
+                    e
+                )));
+            }
+        };
+        drop(queued);
+
+        let mut tasks = self.handler_tasks.lock().await;
+        // 在同一把锁下检查：停止流程取走任务集合后获得工作位的消息不再启动处理器
+        let Some(tasks) = tasks.as_mut() else {
+            tracing::warn!("入站处理已停止，丢弃来自 {} 的消息 {}", from, message.id);
+            self.seen_messages.remove(message_id).await;
+            return Err(crate::NetworkError::internal_error("入站处理已停止"));
+        };
+        // 回收已结束的任务，避免集合无限增长
+        while tasks.try_join_next().is_some() {}
+        tasks.spawn(async move {
+            let _permit = permit;
+            let message_type = message_clone.message_type.clone();
+            let request = message_clone
+                .response_required
+                .then(|| message_clone.clone());
+            let started = std::time::Instant::now();
+            // 超时后丢弃处理器的future，避免卡住的处理器一直占用缓冲区空位
+            let result = tokio::time::timeout(
+                handler_timeout,
+                handler_clone.handle_message(&ctx, from_clone.clone(), message_clone),
+            )
+            .await
+            .unwrap_or_else(|_| {
+                tracing::error!(
+                    "消息处理器 {:?} 执行超过 {}ms，已取消",
+                    message_type,
+                    handler_timeout.as_millis()
+                );
+                Err(crate::NetworkError::TimeoutError)
+            });
+            let duration_ms = started.elapsed().as_millis() as u64;
+
+            if duration_ms > slow_threshold_ms {
+                tracing::warn!(
+                    "消息处理器 {:?} 处理耗时 {}ms，超过阈值 {}ms",
+                    message_type,
+                    duration_ms,
+                    slow_threshold_ms
+                );
+            }
+            event_bus
+                .publish(crate::event_bus::NetworkEvent::HandlerCompleted {
+                    message_type,
+                    duration_ms,
+                    ok: result.is_ok(),
+                })
+                .await;
+
+            match result {
+                Ok(response) => {
+                    // 发送方要求回复但处理器没有响应时，补发确认消息，避免发送方等到超时
+                    let response = match (response, request) {
+                        (None, Some(request)) => match ctx.local_node_id().await {
+                            Ok(local_id) => Some(request.ack(local_id)),
+                            Err(e) => {
+                                tracing::warn!("无法生成确认消息: {}", e);
+                                None
+                            }
+                        },
+                        (response, _) => response,
+                    };
+                    if let Some(reply) = reply {
+                        // 交给调用方在请求所在的连接上回复
+                        let _ = reply.send(response);
+                    } else if let Some(response_msg) = response {
+                        // 将响应消息回复给发送者
+                        tracing::info!("消息处理器返回响应: {:?}", response_msg);
+                        if let Err(e) = ctx.unicast(from_clone.clone(), response_msg, None).await {
+                            tracing::warn!("回复 {} 失败: {}", from_clone, e);
                         }
                     }
-                    Err(e) => {
-                        tracing::error!("消息处理器处理消息失败: {}", e);
-                        event_bus
-                            .publish(crate::event_bus::NetworkEvent::Error {
-                                error: format!("处理来自 {} 的消息失败: {}", from_clone, e),
-                            })
-                            .await;
-                    }
                 }
-            });
-        } else {
-            tracing::warn!("未找到消息类型 {:?} 的处理器", message.message_type);
+                Err(e) => {
+                    tracing::error!("消息处理器处理消息失败: {}", e);
+                    seen_messages.remove(message_id).await;
+                    event_bus
+                        .publish(crate::event_bus::NetworkEvent::Error {
+                            error: format!("处理来自 {} 的消息失败: {}", from_clone, e),
+                        })
+                        .await;
+                }
+            }
+        });
+        Ok(())
+    }
+
+    /// 停止入站处理并报告清理情况
+    ///
+    /// 不再接受新的处理器任务；等待工作位和等待处理器注册的消息直接丢弃，
+    /// 运行中的处理器最多等待 `grace`，超时后中止。
+    pub async fn shutdown(&self, grace: Duration) -> ShutdownReport {
+        self.set_running(false).await;
+
+        let mut report = ShutdownReport {
+            queued_dropped: self.handler_queued.load(Ordering::SeqCst),
+            ..Default::default()
+        };
+        // 关闭工作池，等待工作位的消息随即返回错误
+        self.inbound_slots.read().await.close();
+
+        let unhandled = std::mem::take(&mut *self.pending_unhandled.lock().await);
+        report.queued_dropped += unhandled.len();
+
+        let mut tasks = self.handler_tasks.lock().await.take().unwrap_or_default();
+        // 已经结束的任务不计入
+        while tasks.try_join_next().is_some() {}
+        let deadline = tokio::time::Instant::now() + grace;
+        loop {
+            match tokio::time::timeout_at(deadline, tasks.join_next()).await {
+                Ok(Some(_)) => report.handlers_awaited += 1,
+                Ok(None) => break,
+                Err(_) => {
+                    report.handlers_aborted = tasks.len();
+                    tasks.shutdown().await;
+                    break;
+                }
+            }
         }
 
-        Ok(())
+        if report.handlers_aborted > 0 || report.queued_dropped > 0 {
+            tracing::warn!(
+                "停止入站处理: 中止 {} 个处理器，丢弃 {} 条未处理消息",
+                report.handlers_aborted,
+                report.queued_dropped
+            );
+        }
+        report
     }
 
     /// 设置运行状态
@@ -177,7 +992,9 @@ impl Default for NetworkService {
 mod tests {
     use super::*;
     use crate::message::MessageType;
+    use crate::{BroadcastOptions, MessageId, MessageSender, UnicastOptions};
     use async_trait::async_trait;
+    use std::sync::Mutex;
 
     struct TestMessageHandler;
 
@@ -185,6 +1002,7 @@ mod tests {
     impl MessageHandler for TestMessageHandler {
         async fn handle_message(
             &self,
+            _ctx: &HandlerContext,
             _from: NodeId,
             _message: NetworkMessage,
         ) -> Result<Option<NetworkMessage>> {
@@ -192,6 +1010,210 @@ mod tests {
         }
     }
 
+    /// 广播记录：消息及其排除节点
+    type BroadcastLog = Arc<Mutex<Vec<(NetworkMessage, Vec<NodeId>)>>>;
+
+    /// 记录广播和单播的发送器
+    #[derive(Clone, Default)]
+    struct RecordingSender {
+        broadcasts: BroadcastLog,
+        unicasts: Arc<Mutex<Vec<(NodeId, NetworkMessage)>>>,
+    }
+
+    #[async_trait]
+    impl MessageSender for RecordingSender {
+        async fn broadcast(
+            &self,
+            message: NetworkMessage,
+            options: Option<BroadcastOptions>,
+        ) -> Result<MessageId> {
+            let id = message.id;
+            let exclude_nodes = options.map(|opt| opt.exclude_nodes).unwrap_or_default();
+            self.broadcasts
+                .lock()
+                .unwrap()
+                .push((message, exclude_nodes));
+            Ok(id)
+        }
+
+        async fn unicast(
+            &self,
+            target: NodeId,
+            message: NetworkMessage,
+            _options: Option<UnicastOptions>,
+        ) -> Result<MessageId> {
+            let id = message.id;
+            self.unicasts.lock().unwrap().push((target, message));
+            Ok(id)
+        }
+
+        async fn get_local_node_id(&self) -> Result<NodeId> {
+            Ok("node-b".to_string())
+        }
+    }
+
+    /// 收到消息后向其他节点转发的处理器
+    struct RelayHandler;
+
+    #[async_trait]
+    impl MessageHandler for RelayHandler {
+        async fn handle_message(
+            &self,
+            ctx: &HandlerContext,
+            from: NodeId,
+            message: NetworkMessage,
+        ) -> Result<Option<NetworkMessage>> {
+            let relayed = NetworkMessage::new(
+                message.message_type.clone(),
+                ctx.local_node_id().await?,
+                message.payload.clone(),
+            );
+            let options = BroadcastOptions {
+                exclude_nodes: vec![from],
+                ..Default::default()
+            };
+            ctx.broadcast(relayed, Some(options)).await?;
+            Ok(None)
+        }
+    }
+
+    /// 内存中的全连接网状网络，广播直接投递到其他节点的入站处理
+    #[derive(Clone)]
+    struct MeshSender {
+        local: NodeId,
+        nodes: Arc<Mutex<HashMap<NodeId, NetworkService>>>,
+        /// 进行中的投递，每个投递在目标节点的处理器（包括其继续转发）完成后结束
+        deliveries: Arc<Mutex<Vec<tokio::task::JoinHandle<()>>>>,
+    }
+
+    impl MeshSender {
+        /// 等待所有投递完成，包括等待期间新产生的转发
+        async fn settle(&self) {
+            loop {
+                let Some(delivery) = self.deliveries.lock().unwrap().pop() else {
+                    break;
+                };
+                delivery.await.unwrap();
+            }
+        }
+    }
+
+    #[async_trait]
+    impl MessageSender for MeshSender {
+        async fn broadcast(
+            &self,
+            message: NetworkMessage,
+            options: Option<BroadcastOptions>,
+        ) -> Result<MessageId> {
+            let exclude_nodes = options.map(|opt| opt.exclude_nodes).unwrap_or_default();
+            let targets: Vec<(NodeId, NetworkService)> = self
+                .nodes
+                .lock()
+                .unwrap()
+                .iter()
+                .filter(|(id, _)| **id != self.local && !exclude_nodes.contains(id))
+                .map(|(id, service)| (id.clone(), service.clone()))
+                .collect();
+
+            for (target, service) in targets {
+                let ctx = HandlerContext::new(MeshSender {
+                    local: target,
+                    nodes: self.nodes.clone(),
+                    deliveries: self.deliveries.clone(),
+                });
+                let from = self.local.clone();
+                let message = message.clone();
+                let delivery = tokio::spawn(async move {
+                    let _ = service.handle_incoming_request(ctx, from, message).await;
+                });
+                self.deliveries.lock().unwrap().push(delivery);
+            }
+            Ok(message.id)
+        }
+
+        async fn unicast(
+            &self,
+            _target: NodeId,
+            message: NetworkMessage,
+            _options: Option<UnicastOptions>,
+        ) -> Result<MessageId> {
+            Ok(message.id)
+        }
+
+        async fn get_local_node_id(&self) -> Result<NodeId> {
+            Ok(self.local.clone())
+        }
+    }
+
+    /// 统计收到次数并沿中继路径继续转发的处理器
+    #[derive(Default)]
+    struct CountingRelayHandler {
+        received: std::sync::atomic::AtomicUsize,
+    }
+
+    #[async_trait]
+    impl MessageHandler for CountingRelayHandler {
+        async fn handle_message(
+            &self,
+            ctx: &HandlerContext,
+            from: NodeId,
+            message: NetworkMessage,
+        ) -> Result<Option<NetworkMessage>> {
+            self.received
+                .fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+            ctx.relay_broadcast(from, message).await?;
+            Ok(None)
+        }
+    }
+
+    #[tokio::test]
+    async fn test_relayed_broadcast_delivered_once_per_node() {
+        use std::sync::atomic::Ordering;
+
+        let nodes: Arc<Mutex<HashMap<NodeId, NetworkService>>> = Arc::default();
+        let mut handlers = HashMap::new();
+        for id in ["node-a", "node-b", "node-c", "node-d"] {
+            let service = NetworkService::new();
+            let handler = Arc::new(CountingRelayHandler::default());
+            service
+                .register_message_handler_internal(MessageType::chat(), handler.clone())
+                .await
+                .unwrap();
+            nodes.lock().unwrap().insert(id.to_string(), service);
+            handlers.insert(id, handler);
+        }
+
+        // 每个节点都是中继，消息可以沿多条路径到达并绕回
+        let origin = MeshSender {
+            local: "node-a".to_string(),
+            nodes: nodes.clone(),
+            deliveries: Arc::default(),
+        };
+        let message = NetworkMessage::new(
+            MessageType::chat(),
+            "node-a".to_string(),
+            serde_json::json!({"text": "hi"}),
+        );
+        origin.broadcast(message, None).await.unwrap();
+        origin.settle().await;
+
+        for id in ["node-b", "node-c", "node-d"] {
+            assert_eq!(handlers[id].received.load(Ordering::SeqCst), 1, "{}", id);
+        }
+        // 发起者不会处理绕回的消息
+        assert_eq!(handlers["node-a"].received.load(Ordering::SeqCst), 0);
+
+        let duplicates: u64 = {
+            let services: Vec<NetworkService> = nodes.lock().unwrap().values().cloned().collect();
+            let mut total = 0;
+            for service in services {
+                total += service.duplicates_dropped_count().await;
+            }
+            total
+        };
+        assert!(duplicates > 0);
+    }
+
     #[tokio::test]
     async fn test_network_service_creation() {
         let service = NetworkService::new();
@@ -211,4 +1233,1137 @@ mod tests {
             .unwrap();
         assert!(service.get_message_handler(&message_type).await.is_some());
     }
+
+    /// 记录并发处理数量的慢处理器
+    #[derive(Default)]
+    struct SlowHandler {
+        current: std::sync::atomic::AtomicUsize,
+        max_seen: std::sync::atomic::AtomicUsize,
+        processed: std::sync::atomic::AtomicUsize,
+    }
+
+    #[async_trait]
+    impl MessageHandler for SlowHandler {
+        async fn handle_message(
+            &self,
+            _ctx: &HandlerContext,
+            _from: NodeId,
+            _message: NetworkMessage,
+        ) -> Result<Option<NetworkMessage>> {
+            use std::sync::atomic::Ordering;
+
+            let current = self.current.fetch_add(1, Ordering::SeqCst) + 1;
+            self.max_seen.fetch_max(current, Ordering::SeqCst);
+            tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+            self.current.fetch_sub(1, Ordering::SeqCst);
+            self.processed.fetch_add(1, Ordering::SeqCst);
+            Ok(None)
+        }
+    }
+
+    #[tokio::test]
+    async fn test_message_before_handler_registration_buffered() {
+        use std::sync::atomic::Ordering;
+
+        let service = NetworkService::new();
+        service
+            .set_config(NetworkServiceConfig {
+                unhandled_grace_ms: 1000,
+                unhandled_buffer_capacity: 1,
+                ..Default::default()
+            })
+            .await;
+        let mut events = service.event_bus().subscribe();
+
+        // 启动后、注册处理器前到达的消息
+        for _ in 0..2 {
+            let message = NetworkMessage::new(
+                MessageType::chat(),
+                "node-a".to_string(),
+                serde_json::json!({}),
+            );
+            service
+                .handle_incoming_message(
+                    HandlerContext::new(RecordingSender::default()),
+                    "node-a".to_string(),
+                    message,
+                )
+                .await
+                .unwrap();
+        }
+
+        // 超出暂存上限的消息仍按无处理器上报
+        let mut unhandled = 0;
+        while let Ok(event) = events.try_recv() {
+            if matches!(event, crate::NetworkEvent::UnhandledMessage { .. }) {
+                unhandled += 1;
+            }
+        }
+        assert_eq!(unhandled, 1);
+
+        let handler = Arc::new(SlowHandler::default());
+        service
+            .register_message_handler_internal(MessageType::chat(), handler.clone())
+            .await
+            .unwrap();
+        tokio::time::sleep(std::time::Duration::from_millis(200)).await;
+        assert_eq!(handler.processed.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn test_buffered_request_expires_without_further_traffic() {
+        let service = NetworkService::new();
+        service
+            .set_config(NetworkServiceConfig {
+                unhandled_grace_ms: 50,
+                ..Default::default()
+            })
+            .await;
+        let mut events = service.event_bus().subscribe();
+
+        // 到期后请求方得到空回复，不需要等到下一条消息到达
+        let message = NetworkMessage::new(
+            MessageType::chat(),
+            "node-a".to_string(),
+            serde_json::json!({}),
+        );
+        let reply = tokio::time::timeout(
+            Duration::from_secs(2),
+            service.handle_incoming_request(
+                HandlerContext::new(RecordingSender::default()),
+                "node-a".to_string(),
+                message,
+            ),
+        )
+        .await
+        .expect("暂存的请求应按时到期")
+        .unwrap();
+        assert!(reply.is_none());
+        assert_eq!(service.pending_unhandled_count().await, 0);
+
+        let mut unhandled = 0;
+        while let Ok(event) = events.try_recv() {
+            if matches!(event, crate::NetworkEvent::UnhandledMessage { .. }) {
+                unhandled += 1;
+            }
+        }
+        assert_eq!(unhandled, 1);
+    }
+
+    #[tokio::test]
+    async fn test_message_buffer_bounds_in_flight() {
+        use std::sync::atomic::Ordering;
+
+        let service = NetworkService::new();
+        service
+            .set_config(NetworkServiceConfig {
+                message_buffer_size: 2,
+                ..Default::default()
+            })
+            .await;
+        let handler = Arc::new(SlowHandler::default());
+        service
+            .register_message_handler_internal(MessageType::chat(), handler.clone())
+            .await
+            .unwrap();
+
+        let mut tasks = Vec::new();
+        for i in 0..6 {
+            let service = service.clone();
+            tasks.push(tokio::spawn(async move {
+                let message = NetworkMessage::new(
+                    MessageType::chat(),
+                    format!("node-{}", i),
+                    serde_json::json!({}),
+                );
+                service
+                    .handle_incoming_message(
+                        HandlerContext::new(RecordingSender::default()),
+                        format!("node-{}", i),
+                        message,
+                    )
+                    .await
+            }));
+        }
+
+        tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+        assert!(service.inbound_in_flight().await <= 2);
+
+        for task in tasks {
+            task.await.unwrap().unwrap();
+        }
+        tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+
+        assert_eq!(handler.processed.load(Ordering::SeqCst), 6);
+        assert_eq!(handler.max_seen.load(Ordering::SeqCst), 2);
+        assert_eq!(service.inbound_in_flight().await, 0);
+    }
+
+    #[tokio::test]
+    async fn test_handler_pool_stats_under_load() {
+        use std::sync::atomic::Ordering;
+
+        let service = NetworkService::new();
+        service
+            .set_config(NetworkServiceConfig {
+                handler_workers: Some(2),
+                ..Default::default()
+            })
+            .await;
+        let handler = Arc::new(GatedHandler::default());
+        service
+            .register_message_handler_internal(MessageType::chat(), handler.clone())
+            .await
+            .unwrap();
+        assert_eq!(
+            service.handler_pool_stats().await,
+            PoolStats {
+                workers: 2,
+                active: 0,
+                queued: 0
+            }
+        );
+
+        let mut tasks = Vec::new();
+        for i in 0..6 {
+            let service = service.clone();
+            tasks.push(tokio::spawn(async move {
+                let message = NetworkMessage::new(
+                    MessageType::chat(),
+                    format!("node-{}", i),
+                    serde_json::json!({}),
+                );
+                service
+                    .handle_incoming_message(
+                        HandlerContext::new(RecordingSender::default()),
+                        format!("node-{}", i),
+                        message,
+                    )
+                    .await
+            }));
+        }
+
+        // 处理器被阻塞：两个工作位占满，其余消息排队
+        wait_for_pool(&service, 2, 4).await;
+        assert_eq!(handler.started.load(Ordering::SeqCst), 2);
+
+        handler.gate.add_permits(6);
+        for task in tasks {
+            task.await.unwrap().unwrap();
+        }
+        wait_for_pool(&service, 0, 0).await;
+        assert_eq!(handler.started.load(Ordering::SeqCst), 6);
+    }
+
+    #[tokio::test]
+    async fn test_pool_resized_in_place() {
+        use std::sync::atomic::Ordering;
+
+        let service = NetworkService::new();
+        let config = NetworkServiceConfig {
+            handler_workers: Some(1),
+            ..Default::default()
+        };
+        service.set_config(config.clone()).await;
+        let handler = Arc::new(GatedHandler::default());
+        service
+            .register_message_handler_internal(MessageType::chat(), handler.clone())
+            .await
+            .unwrap();
+        let send = |n: usize| {
+            let service = service.clone();
+            tokio::spawn(async move {
+                let message = NetworkMessage::new(
+                    MessageType::chat(),
+                    format!("node-{}", n),
+                    serde_json::json!({}),
+                );
+                service
+                    .handle_incoming_message(
+                        HandlerContext::new(RecordingSender::default()),
+                        format!("node-{}", n),
+                        message,
+                    )
+                    .await
+            })
+        };
+
+        let mut tasks: Vec<_> = (0..3).map(send).collect();
+        wait_for_pool(&service, 1, 2).await;
+
+        // 扩大后排队中的消息立即得到工作位
+        service
+            .set_config(NetworkServiceConfig {
+                handler_workers: Some(3),
+                ..config.clone()
+            })
+            .await;
+        wait_for_pool(&service, 3, 0).await;
+        assert_eq!(handler.started.load(Ordering::SeqCst), 3);
+
+        // 处理器全部运行时缩小：运行中的处理器结束后才收回多余的工作位
+        service.set_config(config).await;
+        tasks.extend((3..5).map(send));
+        wait_for_pool(&service, 1, 2).await;
+        handler.gate.add_permits(3);
+        wait_for_pool(&service, 1, 1).await;
+        assert_eq!(handler.started.load(Ordering::SeqCst), 4);
+
+        handler.gate.add_permits(2);
+        for task in tasks {
+            task.await.unwrap().unwrap();
+        }
+        wait_for_pool(&service, 0, 0).await;
+        assert_eq!(handler.started.load(Ordering::SeqCst), 5);
+    }
+
+    /// 阻塞到 `gate` 放行的处理器
+    struct GatedHandler {
+        started: std::sync::atomic::AtomicUsize,
+        gate: Semaphore,
+    }
+
+    impl Default for GatedHandler {
+        fn default() -> Self {
+            Self {
+                started: Default::default(),
+                gate: Semaphore::new(0),
+            }
+        }
+    }
+
+    #[async_trait]
+    impl MessageHandler for GatedHandler {
+        async fn handle_message(
+            &self,
+            _ctx: &HandlerContext,
+            _from: NodeId,
+            _message: NetworkMessage,
+        ) -> Result<Option<NetworkMessage>> {
+            self.started
+                .fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+            self.gate.acquire().await.unwrap().forget();
+            Ok(None)
+        }
+    }
+
+    /// 等待工作池达到指定的运行和排队数量，超时时测试失败
+    async fn wait_for_pool(service: &NetworkService, active: usize, queued: usize) {
+        tokio::time::timeout(Duration::from_secs(2), async {
+            loop {
+                let stats = service.handler_pool_stats().await;
+                if (stats.active, stats.queued) == (active, queued) {
+                    break;
+                }
+                tokio::task::yield_now().await;
+            }
+        })
+        .await
+        .expect("工作池应达到预期状态");
+    }
+
+    /// 按负载中的 `sleep_ms` 休眠的处理器
+    struct SleepyHandler;
+
+    #[async_trait]
+    impl MessageHandler for SleepyHandler {
+        async fn handle_message(
+            &self,
+            _ctx: &HandlerContext,
+            _from: NodeId,
+            message: NetworkMessage,
+        ) -> Result<Option<NetworkMessage>> {
+            let sleep_ms = message.payload["sleep_ms"].as_u64().unwrap_or(0);
+            tokio::time::sleep(Duration::from_millis(sleep_ms)).await;
+            Ok(None)
+        }
+    }
+
+    #[tokio::test]
+    async fn test_no_handlers_started_after_shutdown() {
+        let service = NetworkService::new();
+        let handler = Arc::new(GatedHandler::default());
+        service
+            .register_message_handler_internal(MessageType::chat(), handler.clone())
+            .await
+            .unwrap();
+        service.shutdown(Duration::from_millis(10)).await;
+
+        let message = NetworkMessage::new(
+            MessageType::chat(),
+            "node-a".to_string(),
+            serde_json::json!({}),
+        );
+        let result = service
+            .handle_incoming_message(
+                HandlerContext::new(RecordingSender::default()),
+                "node-a".to_string(),
+                message,
+            )
+            .await;
+        assert!(result.is_err());
+        assert_eq!(handler.started.load(std::sync::atomic::Ordering::SeqCst), 0);
+    }
+
+    #[tokio::test]
+    async fn test_shutdown_reports_in_flight_work() {
+        let service = NetworkService::new();
+        service
+            .set_config(NetworkServiceConfig {
+                handler_workers: Some(2),
+                unhandled_grace_ms: 60_000,
+                ..Default::default()
+            })
+            .await;
+        service
+            .register_message_handler_internal(MessageType::chat(), Arc::new(SleepyHandler))
+            .await
+            .unwrap();
+
+        // 一个在宽限期内完成、一个远超宽限期，第三个等待工作位
+        let mut tasks = Vec::new();
+        for (i, sleep_ms) in [150u64, 60_000, 10].into_iter().enumerate() {
+            let service = service.clone();
+            tasks.push(tokio::spawn(async move {
+                let message = NetworkMessage::new(
+                    MessageType::chat(),
+                    format!("node-{}", i),
+                    serde_json::json!({ "sleep_ms": sleep_ms }),
+                );
+                service
+                    .handle_incoming_message(
+                        HandlerContext::new(RecordingSender::default()),
+                        format!("node-{}", i),
+                        message,
+                    )
+                    .await
+            }));
+            tokio::time::sleep(Duration::from_millis(5)).await;
+        }
+        // 没有处理器的消息在暂存区等待
+        service
+            .handle_incoming_message(
+                HandlerContext::new(RecordingSender::default()),
+                "node-x".to_string(),
+                NetworkMessage::new(
+                    MessageType::timesync(),
+                    "node-x".to_string(),
+                    serde_json::json!({}),
+                ),
+            )
+            .await
+            .unwrap();
+        assert_eq!(service.handler_pool_stats().await.queued, 1);
+
+        let report = service.shutdown(Duration::from_millis(200)).await;
+        assert_eq!(
+            report,
+            ShutdownReport {
+                peers_disconnected: 0,
+                handlers_awaited: 1,
+                handlers_aborted: 1,
+                queued_dropped: 2,
+            }
+        );
+
+        // 等待工作位的消息在工作池关闭后返回错误
+        let results: Vec<bool> = futures::future::join_all(tasks)
+            .await
+            .into_iter()
+            .map(|result| result.unwrap().is_ok())
+            .collect();
+        assert_eq!(results, vec![true, true, false]);
+        assert!(!service.is_running().await);
+    }
+
+    #[tokio::test]
+    async fn test_event_bus_capacity_from_config() {
+        let service = NetworkService::new();
+        service
+            .set_config(NetworkServiceConfig {
+                event_bus_capacity: 4096,
+                ..Default::default()
+            })
+            .await;
+        assert_eq!(service.event_bus().capacity(), 4096);
+
+        // 订阅者在突发期间不读取，容量内的事件不会丢失
+        let mut slow_subscriber = service.event_bus().subscribe();
+        for i in 0..3000 {
+            service
+                .event_bus()
+                .publish(crate::event_bus::NetworkEvent::Error {
+                    error: format!("事件 {}", i),
+                })
+                .await;
+        }
+        let mut received = 0;
+        loop {
+            match slow_subscriber.try_recv() {
+                Ok(_) => received += 1,
+                Err(tokio::sync::broadcast::error::TryRecvError::Empty) => break,
+                Err(e) => panic!("订阅者落后: {:?}", e),
+            }
+        }
+        assert_eq!(received, 3000);
+
+        // 已有订阅者时保持原容量
+        assert!(!service.event_bus().set_capacity(16));
+        assert_eq!(service.event_bus().capacity(), 4096);
+    }
+
+    #[tokio::test]
+    async fn test_response_required_synthesizes_ack() {
+        let service = NetworkService::new();
+        service
+            .register_message_handler_internal(MessageType::chat(), Arc::new(TestMessageHandler))
+            .await
+            .unwrap();
+
+        let sender = RecordingSender::default();
+        let request = NetworkMessage::new(
+            MessageType::chat(),
+            "node-a".to_string(),
+            serde_json::json!({}),
+        )
+        .with_response_required();
+        service
+            .handle_incoming_message(
+                HandlerContext::new(sender.clone()),
+                "node-a".to_string(),
+                request.clone(),
+            )
+            .await
+            .unwrap();
+
+        // 发送方应很快收到确认，而不是等到超时
+        let ack = tokio::time::timeout(std::time::Duration::from_secs(1), async {
+            loop {
+                if let Some((target, message)) = sender.unicasts.lock().unwrap().first().cloned() {
+                    return (target, message);
+                }
+                tokio::time::sleep(std::time::Duration::from_millis(5)).await;
+            }
+        })
+        .await
+        .expect("应收到确认消息");
+
+        assert_eq!(ack.0, "node-a");
+        assert!(ack.1.is_ack_for(request.id));
+        assert_eq!(ack.1.sender, "node-b");
+    }
+
+    #[tokio::test]
+    async fn test_self_sent_message_dropped() {
+        use std::sync::atomic::Ordering;
+
+        let service = NetworkService::new();
+        let handler = Arc::new(SlowHandler::default());
+        service
+            .register_message_handler_internal(MessageType::chat(), handler.clone())
+            .await
+            .unwrap();
+
+        // RecordingSender 的本地节点ID为 "node-b"
+        let message = NetworkMessage::new(
+            MessageType::chat(),
+            "node-b".to_string(),
+            serde_json::json!({}),
+        );
+        service
+            .handle_incoming_message(
+                HandlerContext::new(RecordingSender::default()),
+                "node-a".to_string(),
+                message,
+            )
+            .await
+            .unwrap();
+        tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+
+        assert_eq!(handler.processed.load(Ordering::SeqCst), 0);
+        assert_eq!(service.loop_prevented_count().await, 1);
+    }
+
+    #[tokio::test]
+    async fn test_topic_handlers_isolated() {
+        use std::sync::atomic::Ordering;
+
+        // 两个聊天实例共享同一网络，分别使用不同主题
+        let service = NetworkService::new();
+        let lobby = Arc::new(SlowHandler::default());
+        let support = Arc::new(SlowHandler::default());
+        service
+            .register_topic_handler_internal(
+                MessageType::chat(),
+                "lobby".to_string(),
+                lobby.clone(),
+            )
+            .await
+            .unwrap();
+        service
+            .register_topic_handler_internal(
+                MessageType::chat(),
+                "support".to_string(),
+                support.clone(),
+            )
+            .await
+            .unwrap();
+
+        let ctx = HandlerContext::new(RecordingSender::default());
+        for topic in ["lobby", "lobby", "support"] {
+            let message = NetworkMessage::new(
+                MessageType::chat(),
+                "node-a".to_string(),
+                serde_json::json!({}),
+            )
+            .with_topic(topic);
+            service
+                .handle_incoming_message(ctx.clone(), "node-a".to_string(), message)
+                .await
+                .unwrap();
+        }
+
+        // 没有主题的消息不会投递给任何主题处理器
+        let untagged = NetworkMessage::new(
+            MessageType::chat(),
+            "node-a".to_string(),
+            serde_json::json!({}),
+        );
+        service
+            .handle_incoming_message(ctx, "node-a".to_string(), untagged)
+            .await
+            .unwrap();
+        tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+
+        assert_eq!(lobby.processed.load(Ordering::SeqCst), 2);
+        assert_eq!(support.processed.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn test_topic_falls_back_to_type_handler() {
+        use std::sync::atomic::Ordering;
+
+        let service = NetworkService::new();
+        let lobby = Arc::new(SlowHandler::default());
+        let any_chat = Arc::new(SlowHandler::default());
+        service
+            .register_topic_handler_internal(
+                MessageType::chat(),
+                "lobby".to_string(),
+                lobby.clone(),
+            )
+            .await
+            .unwrap();
+        service
+            .register_message_handler_internal(MessageType::chat(), any_chat.clone())
+            .await
+            .unwrap();
+
+        let ctx = HandlerContext::new(RecordingSender::default());
+        for topic in [Some("lobby"), Some("support"), None] {
+            let mut message = NetworkMessage::new(
+                MessageType::chat(),
+                "node-a".to_string(),
+                serde_json::json!({}),
+            );
+            if let Some(topic) = topic {
+                message = message.with_topic(topic);
+            }
+            service
+                .handle_incoming_message(ctx.clone(), "node-a".to_string(), message)
+                .await
+                .unwrap();
+        }
+        wait_for_pool(&service, 0, 0).await;
+
+        // 主题处理器优先；没有对应主题处理器的消息交给类型处理器
+        assert_eq!(lobby.processed.load(Ordering::SeqCst), 1);
+        assert_eq!(any_chat.processed.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn test_replayed_message_not_dispatched() {
+        use std::sync::atomic::Ordering;
+
+        let service = NetworkService::new();
+        let handler = Arc::new(SlowHandler::default());
+        service
+            .register_message_handler_internal(MessageType::chat(), handler.clone())
+            .await
+            .unwrap();
+
+        let message = NetworkMessage::new(
+            MessageType::chat(),
+            "node-a".to_string(),
+            serde_json::json!({"command": "kick"}),
+        )
+        .with_nonce(1);
+        let ctx = HandlerContext::new(RecordingSender::default());
+
+        service
+            .handle_incoming_message(ctx.clone(), "node-a".to_string(), message.clone())
+            .await
+            .unwrap();
+        let replay = service
+            .handle_incoming_message(ctx, "node-a".to_string(), message)
+            .await;
+        assert!(matches!(
+            replay,
+            Err(crate::NetworkError::ReplayDetected { .. })
+        ));
+
+        tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+        assert_eq!(handler.processed.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn test_handler_duration_event() {
+        let service = NetworkService::new();
+        service
+            .set_config(NetworkServiceConfig {
+                slow_handler_threshold_ms: 10,
+                ..Default::default()
+            })
+            .await;
+        service
+            .register_message_handler_internal(
+                MessageType::chat(),
+                Arc::new(SlowHandler::default()),
+            )
+            .await
+            .unwrap();
+        let mut events = service.event_bus().subscribe();
+
+        let message = NetworkMessage::new(
+            MessageType::chat(),
+            "node-a".to_string(),
+            serde_json::json!({}),
+        );
+        service
+            .handle_incoming_message(
+                HandlerContext::new(RecordingSender::default()),
+                "node-a".to_string(),
+                message,
+            )
+            .await
+            .unwrap();
+
+        let completed = tokio::time::timeout(std::time::Duration::from_secs(2), async {
+            loop {
+                if let crate::NetworkEvent::HandlerCompleted {
+                    message_type,
+                    duration_ms,
+                    ok,
+                } = events.recv().await.unwrap()
+                {
+                    break (message_type, duration_ms, ok);
+                }
+            }
+        })
+        .await
+        .expect("未收到处理器完成事件");
+
+        assert_eq!(completed.0, MessageType::chat());
+        assert!(completed.1 >= 50);
+        assert!(completed.2);
+    }
+
+    #[tokio::test]
+    async fn test_hung_handler_cancelled() {
+        use std::sync::atomic::Ordering;
+
+        let service = NetworkService::new();
+        service
+            .set_config(NetworkServiceConfig {
+                handler_timeout_ms: 10,
+                ..Default::default()
+            })
+            .await;
+        let handler = Arc::new(SlowHandler::default());
+        service
+            .register_message_handler_internal(MessageType::chat(), handler.clone())
+            .await
+            .unwrap();
+        let mut events = service.event_bus().subscribe();
+
+        let message = NetworkMessage::new(
+            MessageType::chat(),
+            "node-a".to_string(),
+            serde_json::json!({}),
+        );
+        service
+            .handle_incoming_message(
+                HandlerContext::new(RecordingSender::default()),
+                "node-a".to_string(),
+                message,
+            )
+            .await
+            .unwrap();
+
+        let error = tokio::time::timeout(std::time::Duration::from_secs(2), async {
+            loop {
+                if let crate::NetworkEvent::Error { error } = events.recv().await.unwrap() {
+                    break error;
+                }
+            }
+        })
+        .await
+        .expect("未收到处理器超时错误事件");
+        assert!(error.contains("超时"));
+
+        // 处理器被取消，不会执行完成，缓冲区空位也已释放
+        tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+        assert_eq!(handler.processed.load(Ordering::SeqCst), 0);
+        assert_eq!(service.inbound_in_flight().await, 0);
+    }
+
+    #[tokio::test]
+    async fn test_peer_disconnected_after_repeated_garbage() {
+        let service = NetworkService::new();
+        service
+            .set_config(NetworkServiceConfig {
+                on_decode_error: DecodeErrorPolicy::DisconnectAfter(3),
+                ..Default::default()
+            })
+            .await;
+        let mut events = service.event_bus().subscribe();
+        let ctx_sender = RecordingSender::default();
+        let ctx = HandlerContext::new(ctx_sender.clone());
+        let garbage = b"not a message".as_slice();
+
+        for _ in 0..2 {
+            let result = service
+                .handle_incoming_frame(ctx.clone(), "noisy".to_string(), garbage)
+                .await;
+            assert!(matches!(
+                result,
+                Err(crate::NetworkError::SerializationError(_))
+            ));
+        }
+        assert!(!service.is_peer_blocked(&"noisy".to_string()).await);
+
+        let result = service
+            .handle_incoming_frame(ctx.clone(), "noisy".to_string(), garbage)
+            .await;
+        assert!(matches!(result, Err(crate::NetworkError::PeerBlocked(_))));
+        assert!(service.is_peer_blocked(&"noisy".to_string()).await);
+
+        // 断开连接由传输层负责，入站处理流程不发送消息，也不发布断开事件
+        assert!(ctx_sender.unicasts.lock().unwrap().is_empty());
+        while let Ok(event) = events.try_recv() {
+            assert!(!matches!(
+                event,
+                crate::NetworkEvent::NodeDisconnected { .. }
+            ));
+        }
+
+        // 屏蔽后即使发送合法数据也会被拒绝
+        let valid = NetworkMessage::new(
+            MessageType::chat(),
+            "noisy".to_string(),
+            serde_json::json!({}),
+        )
+        .to_bytes()
+        .unwrap();
+        assert!(matches!(
+            service
+                .handle_incoming_frame(ctx, "noisy".to_string(), &valid)
+                .await,
+            Err(crate::NetworkError::PeerBlocked(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_frame_with_oversized_metadata_rejected() {
+        let service = NetworkService::new();
+        service
+            .set_config(NetworkServiceConfig {
+                message_limits: MessageLimits {
+                    max_message_bytes: 64 * 1024,
+                    max_metadata_entries: 8,
+                    max_metadata_bytes: 1024,
+                },
+                ..Default::default()
+            })
+            .await;
+        let ctx = HandlerContext::new(RecordingSender::default());
+
+        // 负载很小，但元数据超出上限
+        let stuffed = NetworkMessage::new(
+            MessageType::chat(),
+            "peer".to_string(),
+            serde_json::json!("hi"),
+        )
+        .with_metadata("padding".to_string(), "x".repeat(4096))
+        .to_bytes()
+        .unwrap();
+        assert!(matches!(
+            service
+                .handle_incoming_frame(ctx.clone(), "peer".to_string(), &stuffed)
+                .await,
+            Err(crate::NetworkError::MessageTooLarge(_))
+        ));
+
+        // 整体超出上限的数据在解码前被拒绝
+        let oversized = vec![b' '; 64 * 1024 + 1];
+        assert!(matches!(
+            service
+                .handle_incoming_frame(ctx, "peer".to_string(), &oversized)
+                .await,
+            Err(crate::NetworkError::MessageTooLarge(_))
+        ));
+        assert!(!service.is_peer_blocked(&"peer".to_string()).await);
+    }
+
+    /// 原样返回请求内容的处理器
+    struct EchoHandler;
+
+    #[async_trait]
+    impl MessageHandler for EchoHandler {
+        async fn handle_message(
+            &self,
+            ctx: &HandlerContext,
+            _from: NodeId,
+            message: NetworkMessage,
+        ) -> Result<Option<NetworkMessage>> {
+            Ok(Some(NetworkMessage::new(
+                message.message_type,
+                ctx.local_node_id().await?,
+                message.payload,
+            )))
+        }
+    }
+
+    #[tokio::test]
+    async fn test_request_reply_returned_without_dialing_sender() {
+        let service = NetworkService::new();
+        service
+            .register_message_handler_internal(MessageType::chat(), Arc::new(EchoHandler))
+            .await
+            .unwrap();
+        let sender = RecordingSender::default();
+
+        // 发送者绑定在随机端口上，节点ID无法用于建立连接
+        let client = "client:0.0.0.0:0".to_string();
+        let mut request = NetworkMessage::new(
+            MessageType::chat(),
+            client.clone(),
+            serde_json::json!({ "question": "ping" }),
+        );
+        request.response_required = true;
+
+        let reply = service
+            .handle_incoming_request(HandlerContext::new(sender.clone()), client.clone(), request)
+            .await
+            .unwrap()
+            .expect("应返回响应");
+        assert_eq!(reply.sender, "node-b");
+        assert_eq!(reply.payload["question"], "ping");
+        assert!(sender.unicasts.lock().unwrap().is_empty());
+
+        // 没有处理器时不回复
+        let unhandled = NetworkMessage::new(
+            MessageType::timesync(),
+            client.clone(),
+            serde_json::json!({}),
+        );
+        assert!(service
+            .handle_incoming_request(HandlerContext::new(sender.clone()), client, unhandled)
+            .await
+            .unwrap()
+            .is_none());
+    }
+
+    #[tokio::test]
+    async fn test_delivery_age_recorded() {
+        let service = NetworkService::new();
+        let mut message = NetworkMessage::new(
+            MessageType::chat(),
+            "node-a".to_string(),
+            serde_json::json!({}),
+        );
+        message.timestamp = crate::now_millis() - 1500;
+
+        service
+            .handle_incoming_message(
+                HandlerContext::new(RecordingSender::default()),
+                "node-a".to_string(),
+                message,
+            )
+            .await
+            .unwrap();
+
+        let age = service
+            .last_delivery_age_ms(&MessageType::chat())
+            .await
+            .unwrap();
+        assert!((1500..1600).contains(&age), "投递时延为 {}ms", age);
+
+        let stats = service.delivery_age_stats().await;
+        let chat = &stats[&MessageType::chat()];
+        assert_eq!(chat.count, 1);
+        // 1500ms 落在 (1000, 5000] 桶中
+        assert_eq!(chat.buckets[7], 1);
+        assert!(service
+            .last_delivery_age_ms(&MessageType::timesync())
+            .await
+            .is_none());
+    }
+
+    #[tokio::test]
+    async fn test_unknown_message_types_share_delivery_age_stats() {
+        let service = NetworkService::new();
+        let custom = MessageType::new("custom");
+        service
+            .register_message_handler_internal(custom.clone(), Arc::new(TestMessageHandler))
+            .await
+            .unwrap();
+
+        let mut types: Vec<MessageType> = (0..50)
+            .map(|i| MessageType::new(&format!("random-{}", i)))
+            .collect();
+        types.push(custom.clone());
+        for message_type in types {
+            let message =
+                NetworkMessage::new(message_type, "node-a".to_string(), serde_json::json!({}));
+            service
+                .handle_incoming_message(
+                    HandlerContext::new(RecordingSender::default()),
+                    "node-a".to_string(),
+                    message,
+                )
+                .await
+                .unwrap();
+        }
+
+        let stats = service.delivery_age_stats().await;
+        assert_eq!(stats.len(), 2);
+        assert_eq!(
+            stats[&MessageType::new(crate::OTHER_MESSAGE_TYPE)].count,
+            50
+        );
+        assert_eq!(stats[&custom].count, 1);
+    }
+
+    #[tokio::test]
+    async fn test_observer_receives_events_without_dispatch() {
+        use std::sync::atomic::Ordering;
+
+        let service = NetworkService::new();
+        let handler = Arc::new(SlowHandler::default());
+        service
+            .register_message_handler_internal(MessageType::chat(), handler.clone())
+            .await
+            .unwrap();
+        service
+            .set_config(NetworkServiceConfig {
+                observer: true,
+                ..Default::default()
+            })
+            .await;
+        let mut events = service.event_bus().subscribe();
+
+        let sender = RecordingSender::default();
+        let message = NetworkMessage::new(
+            MessageType::chat(),
+            "node-a".to_string(),
+            serde_json::json!({}),
+        )
+        .with_response_required();
+        service
+            .handle_incoming_message(
+                HandlerContext::new(sender.clone()),
+                "node-a".to_string(),
+                message.clone(),
+            )
+            .await
+            .unwrap();
+
+        match events.recv().await.unwrap() {
+            crate::NetworkEvent::MessageReceived {
+                from,
+                message: received,
+            } => {
+                assert_eq!(from, "node-a");
+                assert_eq!(received.id, message.id);
+            }
+            other => panic!("应为消息接收事件: {:?}", other),
+        }
+
+        // 处理器没有运行，也没有自动确认
+        tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+        assert_eq!(handler.processed.load(Ordering::SeqCst), 0);
+        assert!(sender.unicasts.lock().unwrap().is_empty());
+
+        assert!(matches!(
+            service
+                .register_message_handler_internal(MessageType::chat(), handler)
+                .await,
+            Err(crate::NetworkError::ObserverMode(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_unhandled_message_event() {
+        let service = NetworkService::new();
+        let mut events = service.event_bus().subscribe();
+
+        let message = NetworkMessage::new(
+            MessageType::new("unknown"),
+            "node-a".to_string(),
+            serde_json::json!({}),
+        );
+        service
+            .handle_incoming_message(
+                HandlerContext::new(RecordingSender::default()),
+                "node-a".to_string(),
+                message,
+            )
+            .await
+            .unwrap();
+
+        let mut unhandled = Vec::new();
+        while let Ok(event) = events.try_recv() {
+            if let crate::NetworkEvent::UnhandledMessage { from, message_type } = event {
+                unhandled.push((from, message_type));
+            }
+        }
+        assert_eq!(
+            unhandled,
+            vec![("node-a".to_string(), MessageType::new("unknown"))]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_handler_initiates_send_via_context() {
+        let service = NetworkService::new();
+        let sender = RecordingSender::default();
+        service
+            .register_message_handler_internal(MessageType::chat(), Arc::new(RelayHandler))
+            .await
+            .unwrap();
+
+        let message = NetworkMessage::new(
+            MessageType::chat(),
+            "node-a".to_string(),
+            serde_json::json!({"content": "hello"}),
+        );
+        service
+            .handle_incoming_message(
+                HandlerContext::new(sender.clone()),
+                "node-a".to_string(),
+                message,
+            )
+            .await
+            .unwrap();
+
+        // 处理器在后台任务中执行
+        tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+
+        let broadcasts = sender.broadcasts.lock().unwrap();
+        assert_eq!(broadcasts.len(), 1);
+        let (relayed, exclude_nodes) = &broadcasts[0];
+        assert_eq!(relayed.sender, "node-b");
+        assert_eq!(exclude_nodes, &vec!["node-a".to_string()]);
+    }
 }
@@ -0,0 +1,244 @@
+//! 系统消息
+//!
+//! `MessageType::system()` 对应的消息定义和默认处理器。默认处理器回应
+//! Ping 和健康检查，其余系统消息以 `NetworkEvent::SystemMessage` 事件发布；
+//! 无法解析为系统消息的负载交给注册的后备处理器（例如聊天模块的状态移交）。
+
+use crate::{
+    EventBus, HandlerContext, MessageHandler, MessageType, NetworkEvent, NetworkMessage, NodeId,
+    Result,
+};
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use tracing::{error, info};
+
+/// 系统消息类型
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SystemMessageType {
+    /// 节点即将关闭
+    Shutdown { reason: String },
+    /// 重新加载配置
+    ConfigReload,
+    /// 连通性探测
+    Ping,
+    /// Ping的回应
+    Pong,
+    /// 健康检查请求
+    HealthRequest,
+    /// 健康检查结果
+    HealthResponse {
+        node_id: NodeId,
+        healthy: bool,
+        timestamp: u64,
+    },
+    /// 连接即将被对端断开
+    Goodbye { reason: DisconnectReason },
+}
+
+/// 主动断开连接的原因，随 `Goodbye` 消息发送给被断开的节点
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum DisconnectReason {
+    /// 被管理员踢出
+    Kicked,
+    /// 已被屏蔽
+    Blocked,
+    /// 连接空闲超时
+    Idle,
+    /// 服务器过载
+    Overloaded,
+    /// 连接认证被拒绝
+    Unauthorized(String),
+}
+
+impl DisconnectReason {
+    /// 被断开的节点是否适合自动重连
+    pub fn should_reconnect(&self) -> bool {
+        matches!(self, DisconnectReason::Idle | DisconnectReason::Overloaded)
+    }
+}
+
+impl std::fmt::Display for DisconnectReason {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DisconnectReason::Kicked => write!(f, "被管理员踢出"),
+            DisconnectReason::Blocked => write!(f, "已被屏蔽"),
+            DisconnectReason::Idle => write!(f, "连接空闲超时"),
+            DisconnectReason::Overloaded => write!(f, "服务器过载"),
+            DisconnectReason::Unauthorized(reason) => write!(f, "认证被拒绝: {}", reason),
+        }
+    }
+}
+
+impl SystemMessageType {
+    /// 构造系统网络消息
+    pub fn to_network_message(&self, sender: NodeId) -> Result<NetworkMessage> {
+        Ok(NetworkMessage::new(
+            MessageType::system(),
+            sender,
+            serde_json::to_value(self)?,
+        ))
+    }
+}
+
+/// 默认系统消息处理器
+pub struct SystemMessageHandler {
+    /// 事件总线
+    event_bus: Arc<EventBus>,
+    /// 非系统消息负载的后备处理器
+    fallback: Option<Arc<dyn MessageHandler>>,
+}
+
+impl SystemMessageHandler {
+    /// 创建新的系统消息处理器
+    pub fn new(event_bus: Arc<EventBus>, fallback: Option<Arc<dyn MessageHandler>>) -> Self {
+        Self {
+            event_bus,
+            fallback,
+        }
+    }
+}
+
+#[async_trait]
+impl MessageHandler for SystemMessageHandler {
+    async fn handle_message(
+        &self,
+        ctx: &HandlerContext,
+        from: NodeId,
+        message: NetworkMessage,
+    ) -> Result<Option<NetworkMessage>> {
+        let system_message: SystemMessageType =
+            match serde_json::from_value(message.payload.clone()) {
+                Ok(msg) => msg,
+                Err(e) => {
+                    if let Some(fallback) = &self.fallback {
+                        return fallback.handle_message(ctx, from, message).await;
+                    }
+                    error!("{}", message.parse_failure_summary(&from, &e));
+                    return Err(e.into());
+                }
+            };
+
+        match system_message {
+            SystemMessageType::Ping => {
+                info!("收到来自 {} 的Ping", from);
+                let pong =
+                    SystemMessageType::Pong.to_network_message(ctx.local_node_id().await?)?;
+                Ok(Some(pong))
+            }
+            SystemMessageType::HealthRequest => {
+                info!("收到来自 {} 的健康检查请求", from);
+                let local_id = ctx.local_node_id().await?;
+                let health = SystemMessageType::HealthResponse {
+                    node_id: local_id.clone(),
+                    healthy: true,
+                    timestamp: crate::now_millis(),
+                };
+                Ok(Some(health.to_network_message(local_id)?))
+            }
+            SystemMessageType::Goodbye { reason } => {
+                info!("节点 {} 即将断开连接: {}", from, reason);
+                self.event_bus
+                    .publish(NetworkEvent::SystemMessage {
+                        from,
+                        message: SystemMessageType::Goodbye { reason },
+                    })
+                    .await;
+                Ok(None)
+            }
+            other => {
+                info!("收到来自 {} 的系统消息: {:?}", from, other);
+                self.event_bus
+                    .publish(NetworkEvent::SystemMessage {
+                        from,
+                        message: other,
+                    })
+                    .await;
+                Ok(None)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{BroadcastOptions, MessageId, MessageSender, UnicastOptions};
+
+    /// 只提供本地节点ID的发送器
+    struct LocalSender;
+
+    #[async_trait]
+    impl MessageSender for LocalSender {
+        async fn broadcast(
+            &self,
+            message: NetworkMessage,
+            _options: Option<BroadcastOptions>,
+        ) -> Result<MessageId> {
+            Ok(message.id)
+        }
+
+        async fn unicast(
+            &self,
+            _target: NodeId,
+            message: NetworkMessage,
+            _options: Option<UnicastOptions>,
+        ) -> Result<MessageId> {
+            Ok(message.id)
+        }
+
+        async fn get_local_node_id(&self) -> Result<NodeId> {
+            Ok("node-b".to_string())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_ping_answered_with_pong() {
+        let event_bus = Arc::new(EventBus::new(16));
+        let handler = SystemMessageHandler::new(event_bus.clone(), None);
+        let mut events = event_bus.subscribe();
+
+        let ping = SystemMessageType::Ping
+            .to_network_message("node-a".to_string())
+            .unwrap();
+        let reply = handler
+            .handle_message(
+                &HandlerContext::new(LocalSender),
+                "node-a".to_string(),
+                ping,
+            )
+            .await
+            .unwrap()
+            .expect("Ping应返回Pong");
+
+        assert_eq!(reply.message_type, MessageType::system());
+        assert_eq!(reply.sender, "node-b");
+        assert_eq!(
+            serde_json::from_value::<SystemMessageType>(reply.payload).unwrap(),
+            SystemMessageType::Pong
+        );
+
+        // 其他系统消息以事件发布
+        let shutdown = SystemMessageType::Shutdown {
+            reason: "维护".to_string(),
+        }
+        .to_network_message("node-a".to_string())
+        .unwrap();
+        let reply = handler
+            .handle_message(
+                &HandlerContext::new(LocalSender),
+                "node-a".to_string(),
+                shutdown,
+            )
+            .await
+            .unwrap();
+        assert!(reply.is_none());
+        assert!(matches!(
+            events.try_recv().unwrap(),
+            NetworkEvent::SystemMessage {
+                message: SystemMessageType::Shutdown { .. },
+                ..
+            }
+        ));
+    }
+}
@@ -0,0 +1,122 @@
+//! 测试辅助工具
+//!
+//! 供业务模块的测试使用，通过 `test-utils` feature 启用。
+
+use crate::{
+    BroadcastOptions, EventBus, EventHandler, MessageHandler, MessageId, MessageType, NetworkError,
+    NetworkEvent, NetworkMessage, NetworkServiceConfig, NetworkServiceTrait, NodeId, Result,
+    ShutdownReport, UnicastOptions,
+};
+use async_trait::async_trait;
+use std::collections::HashSet;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::broadcast;
+
+/// 模拟网络服务，记录所有发出的消息而不进行真实网络通信
+#[derive(Clone)]
+pub struct MockNetworkService {
+    pub broadcasts: Arc<Mutex<Vec<NetworkMessage>>>,
+    pub broadcast_options: Arc<Mutex<Vec<BroadcastOptions>>>,
+    pub unicasts: Arc<Mutex<Vec<(NodeId, NetworkMessage)>>>,
+    /// 单播到这些节点时返回 `NodeNotFound`
+    pub failing_targets: Arc<Mutex<HashSet<NodeId>>>,
+    /// `get_connected_nodes` 返回的在线节点
+    pub connected_nodes: Arc<Mutex<Vec<NodeId>>>,
+    /// 每次广播的模拟耗时（毫秒）
+    pub broadcast_delay_ms: Arc<AtomicU64>,
+    /// 已开始（不一定完成）的广播次数
+    pub broadcasts_started: Arc<AtomicUsize>,
+    /// 供测试发布网络事件
+    pub event_bus: Arc<EventBus>,
+}
+
+impl MockNetworkService {
+    pub fn new() -> Self {
+        Self {
+            broadcasts: Arc::default(),
+            broadcast_options: Arc::default(),
+            unicasts: Arc::default(),
+            failing_targets: Arc::default(),
+            connected_nodes: Arc::default(),
+            broadcast_delay_ms: Arc::default(),
+            broadcasts_started: Arc::default(),
+            event_bus: Arc::new(EventBus::new(16)),
+        }
+    }
+}
+
+impl Default for MockNetworkService {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[async_trait]
+impl NetworkServiceTrait for MockNetworkService {
+    async fn start(&self, _config: NetworkServiceConfig) -> Result<()> {
+        Ok(())
+    }
+
+    async fn stop(&self) -> Result<ShutdownReport> {
+        Ok(ShutdownReport::default())
+    }
+
+    async fn broadcast(
+        &self,
+        message: NetworkMessage,
+        options: Option<BroadcastOptions>,
+    ) -> Result<MessageId> {
+        let id = message.id;
+        self.broadcasts_started.fetch_add(1, Ordering::SeqCst);
+        let delay_ms = self.broadcast_delay_ms.load(Ordering::SeqCst);
+        if delay_ms > 0 {
+            tokio::time::sleep(Duration::from_millis(delay_ms)).await;
+        }
+        self.broadcasts.lock().unwrap().push(message);
+        self.broadcast_options
+            .lock()
+            .unwrap()
+            .push(options.unwrap_or_default());
+        Ok(id)
+    }
+
+    async fn unicast(
+        &self,
+        target: NodeId,
+        message: NetworkMessage,
+        _options: Option<UnicastOptions>,
+    ) -> Result<MessageId> {
+        if self.failing_targets.lock().unwrap().contains(&target) {
+            return Err(NetworkError::node_not_found(target));
+        }
+        let id = message.id;
+        self.unicasts.lock().unwrap().push((target, message));
+        Ok(id)
+    }
+
+    async fn get_connected_nodes(&self) -> Result<Vec<NodeId>> {
+        Ok(self.connected_nodes.lock().unwrap().clone())
+    }
+
+    fn subscribe_events(&self) -> Option<broadcast::Receiver<NetworkEvent>> {
+        Some(self.event_bus.subscribe())
+    }
+
+    async fn get_local_node_id(&self) -> Result<NodeId> {
+        Ok("mock-node".to_string())
+    }
+
+    async fn register_message_handler(
+        &self,
+        _message_type: MessageType,
+        _handler: Box<dyn MessageHandler>,
+    ) -> Result<()> {
+        Ok(())
+    }
+
+    async fn register_event_handler(&self, _handler: Box<dyn EventHandler>) -> Result<()> {
+        Ok(())
+    }
+}
@@ -0,0 +1,21 @@
+//! 时间戳工具
+//!
+//! 各模块统一使用Unix纪元以来的毫秒（或纳秒）时间戳，避免秒级与毫秒级时间戳混用。
+
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// 当前时间戳（毫秒）
+pub fn now_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_millis() as u64
+}
+
+/// 当前时间戳（纳秒）
+pub fn now_nanos() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_nanos() as u64
+}
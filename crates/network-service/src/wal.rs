@@ -0,0 +1,284 @@
+//! 出站消息预写日志
+//!
+//! 单播消息在发送前以JSON行的形式追加到日志文件，发送成功（或移入死信队列）后
+//! 追加一条确认记录。服务重启时重新打开同一日志，上次运行中未确认的消息在目标节点
+//! 连接后重新发送；崩溃前可能已经送达的消息由接收端按消息ID去重。
+//! 日志在打开时以及确认记录累积到一定数量时压缩，只保留未确认的消息。
+//!
+//! 文件读写在阻塞线程池中执行，不占用异步运行时的工作线程。
+
+use crate::{MessageId, NetworkMessage, NodeId};
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, PoisonError};
+use tracing::warn;
+
+/// 确认记录累积到该数量时压缩日志
+const COMPACT_AFTER_ACKS: usize = 256;
+
+/// 日志中的一行记录
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "op", rename_all = "snake_case")]
+enum WalRecord {
+    /// 待发送的消息
+    Append {
+        target: NodeId,
+        message: NetworkMessage,
+    },
+    /// 消息已发送成功或已移入死信队列
+    Ack { message_id: MessageId },
+}
+
+/// 未确认的出站消息
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WalEntry {
+    /// 目标节点
+    pub target: NodeId,
+    /// 待发送的消息
+    pub message: NetworkMessage,
+}
+
+/// 日志状态
+struct WalState {
+    /// 未确认的消息（按写入顺序）
+    pending: Vec<WalEntry>,
+    /// 打开日志时恢复的、上次运行中未确认的消息ID
+    recovered: HashSet<MessageId>,
+    /// 上次压缩后写入的确认记录数量
+    acks_since_compaction: usize,
+}
+
+/// 出站消息预写日志
+#[derive(Clone)]
+pub struct OutboundWal {
+    /// 日志文件路径
+    path: PathBuf,
+    /// 日志状态，同时串行化文件写入（只在阻塞线程池中持有）
+    state: Arc<Mutex<WalState>>,
+}
+
+impl OutboundWal {
+    /// 打开日志文件，恢复未确认的消息并压缩日志
+    pub fn open(path: impl Into<PathBuf>) -> std::io::Result<Self> {
+        let path = path.into();
+        let pending = Self::load(&path)?;
+        Self::rewrite(&path, &pending)?;
+
+        Ok(Self {
+            path,
+            state: Arc::new(Mutex::new(WalState {
+                recovered: pending.iter().map(|entry| entry.message.id).collect(),
+                pending,
+                acks_since_compaction: 0,
+            })),
+        })
+    }
+
+    /// 在阻塞线程池中持有日志状态执行文件操作
+    async fn with_state<T, F>(&self, op: F) -> std::io::Result<T>
+    where
+        F: FnOnce(&Path, &mut WalState) -> std::io::Result<T> + Send + 'static,
+        T: Send + 'static,
+    {
+        let path = self.path.clone();
+        let state = self.state.clone();
+        tokio::task::spawn_blocking(move || {
+            let mut state = state.lock().unwrap_or_else(PoisonError::into_inner);
+            op(&path, &mut state)
+        })
+        .await
+        .map_err(std::io::Error::other)?
+    }
+
+    /// 读取日志，返回未确认的消息；损坏的行（例如崩溃时写了一半）会被跳过
+    fn load(path: &Path) -> std::io::Result<Vec<WalEntry>> {
+        let file = match File::open(path) {
+            Ok(file) => file,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e),
+        };
+
+        let mut pending: Vec<WalEntry> = Vec::new();
+        for line in BufReader::new(file).lines() {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            match serde_json::from_str::<WalRecord>(&line) {
+                Ok(WalRecord::Append { target, message }) => {
+                    pending.push(WalEntry { target, message })
+                }
+                Ok(WalRecord::Ack { message_id }) => {
+                    pending.retain(|entry| entry.message.id != message_id)
+                }
+                Err(e) => warn!("跳过无法解析的预写日志记录 {}: {}", path.display(), e),
+            }
+        }
+        Ok(pending)
+    }
+
+    /// 用未确认的消息重写日志文件
+    fn rewrite(path: &Path, pending: &[WalEntry]) -> std::io::Result<()> {
+        let mut tmp = path.as_os_str().to_owned();
+        tmp.push(".tmp");
+        let tmp = PathBuf::from(tmp);
+
+        let mut file = File::create(&tmp)?;
+        for entry in pending {
+            let record = WalRecord::Append {
+                target: entry.target.clone(),
+                message: entry.message.clone(),
+            };
+            file.write_all(&Self::encode(&record)?)?;
+        }
+        file.sync_all()?;
+        fs::rename(&tmp, path)
+    }
+
+    /// 编码为一行JSON
+    fn encode(record: &WalRecord) -> std::io::Result<Vec<u8>> {
+        let mut line = serde_json::to_vec(record)?;
+        line.push(b'\n');
+        Ok(line)
+    }
+
+    /// 追加一条记录并落盘
+    fn append_record(path: &Path, record: &WalRecord) -> std::io::Result<()> {
+        let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+        file.write_all(&Self::encode(record)?)?;
+        file.sync_data()
+    }
+
+    /// 发送前记录消息
+    pub async fn append(&self, target: NodeId, message: NetworkMessage) -> std::io::Result<()> {
+        self.with_state(move |path, state| {
+            Self::append_record(
+                path,
+                &WalRecord::Append {
+                    target: target.clone(),
+                    message: message.clone(),
+                },
+            )?;
+            state.pending.push(WalEntry { target, message });
+            Ok(())
+        })
+        .await
+    }
+
+    /// 消息发送成功或已移入死信队列后确认，必要时压缩日志
+    pub async fn ack(&self, message_id: MessageId) -> std::io::Result<()> {
+        self.with_state(move |path, state| {
+            let before = state.pending.len();
+            state.pending.retain(|entry| entry.message.id != message_id);
+            state.recovered.remove(&message_id);
+            if state.pending.len() == before {
+                return Ok(());
+            }
+
+            state.acks_since_compaction += 1;
+            if state.pending.is_empty() || state.acks_since_compaction >= COMPACT_AFTER_ACKS {
+                Self::rewrite(path, &state.pending)?;
+                state.acks_since_compaction = 0;
+                Ok(())
+            } else {
+                Self::append_record(path, &WalRecord::Ack { message_id })
+            }
+        })
+        .await
+    }
+
+    /// 压缩日志，只保留未确认的消息
+    pub async fn compact(&self) -> std::io::Result<()> {
+        self.with_state(|path, state| {
+            Self::rewrite(path, &state.pending)?;
+            state.acks_since_compaction = 0;
+            Ok(())
+        })
+        .await
+    }
+
+    /// 未确认的消息（按写入顺序），包括本次运行中正在发送的消息
+    pub async fn pending(&self) -> Vec<WalEntry> {
+        self.with_state(|_, state| Ok(state.pending.clone()))
+            .await
+            .unwrap_or_default()
+    }
+
+    /// 上次运行中未确认、需要重新发送的消息（按写入顺序）
+    pub async fn recovered(&self) -> Vec<WalEntry> {
+        self.with_state(|_, state| {
+            Ok(state
+                .pending
+                .iter()
+                .filter(|entry| state.recovered.contains(&entry.message.id))
+                .cloned()
+                .collect())
+        })
+        .await
+        .unwrap_or_default()
+    }
+
+    /// 日志文件路径
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::MessageType;
+
+    fn test_message(n: u64) -> NetworkMessage {
+        NetworkMessage::new(
+            MessageType::chat(),
+            "node-a".to_string(),
+            serde_json::json!({ "n": n }),
+        )
+    }
+
+    #[tokio::test]
+    async fn test_unacked_entries_survive_reopen() {
+        let dir = std::env::temp_dir().join(format!("outbound-wal-{}", uuid::Uuid::new_v4()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("outbound.wal");
+
+        let wal = OutboundWal::open(&path).unwrap();
+        let acked = test_message(1);
+        let unacked = test_message(2);
+        wal.append("node-b".to_string(), acked.clone())
+            .await
+            .unwrap();
+        wal.append("node-b".to_string(), unacked.clone())
+            .await
+            .unwrap();
+        wal.ack(acked.id).await.unwrap();
+        // 本次运行中写入的消息由发送方自己确认，不需要重发
+        assert!(wal.recovered().await.is_empty());
+        // 模拟崩溃时写了一半的记录
+        OpenOptions::new()
+            .append(true)
+            .open(&path)
+            .unwrap()
+            .write_all(b"{\"op\":\"app")
+            .unwrap();
+        drop(wal);
+
+        let reopened = OutboundWal::open(&path).unwrap();
+        let pending = reopened.pending().await;
+        assert_eq!(pending.len(), 1);
+        assert_eq!(pending[0].message.id, unacked.id);
+        assert_eq!(reopened.recovered().await.len(), 1);
+
+        // 打开时已压缩：文件中只剩未确认的消息
+        assert_eq!(fs::read_to_string(&path).unwrap().lines().count(), 1);
+
+        reopened.ack(unacked.id).await.unwrap();
+        assert!(fs::read_to_string(&path).unwrap().is_empty());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
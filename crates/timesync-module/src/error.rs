@@ -11,6 +11,9 @@ pub enum TimeSyncError {
     #[error("时间请求超时")]
     RequestTimeout,
 
+    #[error("请求已取消: {0}")]
+    RequestCancelled(uuid::Uuid),
+
     #[error("无效的时间戳: {0}")]
     InvalidTimestamp(i64),
 
@@ -23,6 +26,12 @@ pub enum TimeSyncError {
     #[error("心跳服务已启动")]
     HeartbeatAlreadyStarted,
 
+    #[error("自动同步未启动")]
+    AutoSyncNotStarted,
+
+    #[error("自动同步已启动")]
+    AutoSyncAlreadyStarted,
+
     #[error("无效的同步间隔: {0}ms")]
     InvalidSyncInterval(u64),
 
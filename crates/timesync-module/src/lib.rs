@@ -12,7 +12,10 @@ pub mod timesync_service;
 
 pub use error::{Result, TimeSyncError};
 pub use message_handler::TimeSyncMessageHandler;
-pub use timesync_service::{SyncStats, TimeInfo, TimeSyncService};
+pub use timesync_service::{
+    ClockOffset, HeartbeatFailureCallback, HeartbeatStatus, PeerRestartCallback, RemoteTime,
+    SyncSessionInfo, SyncStats, TimeInfo, TimeSyncConfig, TimeSyncMetrics, TimeSyncService,
+};
 
 use async_trait::async_trait;
 use network_service::NodeId;
@@ -109,9 +112,15 @@ pub trait TimeSyncServiceTrait: Send + Sync {
     /// 获取同步统计信息
     async fn get_sync_stats(&self) -> Result<SyncStats>;
 
-    /// 启动定时心跳
-    async fn start_heartbeat(&self, interval_ms: u64) -> Result<()>;
+    /// 获取授时指标（偏移和时延的移动平均、抖动、会话、丢失心跳和超时请求）
+    async fn get_metrics(&self) -> Result<TimeSyncMetrics>;
+
+    /// 启动定时心跳，`interval_ms` 为 `None` 时使用配置的心跳间隔
+    async fn start_heartbeat(&self, interval_ms: Option<u64>) -> Result<()>;
 
     /// 停止定时心跳
     async fn stop_heartbeat(&self) -> Result<()>;
+
+    /// 记录收到的对端心跳序列号，判断是否丢失、乱序或对端已重启
+    async fn record_heartbeat(&self, from: NodeId, sequence: u64) -> HeartbeatStatus;
 }
@@ -1,27 +1,30 @@
 //! 授时消息处理器
 
-use crate::{TimeSyncError, TimeSyncMessageType, TimeSyncServiceTrait};
+use crate::{TimeSyncError, TimeSyncMessageType, TimeSyncService, TimeSyncServiceTrait};
 use async_trait::async_trait;
-use network_service::{MessageHandler, NetworkMessage, NodeId};
+use network_service::{
+    HandlerContext, MessageHandler, NetworkMessage, NetworkServiceTrait, NodeId,
+};
 use std::sync::Arc;
 use tracing::{error, info, warn};
 
 /// 授时消息处理器
-pub struct TimeSyncMessageHandler<T: TimeSyncServiceTrait> {
-    timesync_service: Arc<T>,
+pub struct TimeSyncMessageHandler<N: NetworkServiceTrait> {
+    timesync_service: Arc<TimeSyncService<N>>,
 }
 
-impl<T: TimeSyncServiceTrait> TimeSyncMessageHandler<T> {
+impl<N: NetworkServiceTrait> TimeSyncMessageHandler<N> {
     /// 创建新的授时消息处理器
-    pub fn new(timesync_service: Arc<T>) -> Self {
+    pub fn new(timesync_service: Arc<TimeSyncService<N>>) -> Self {
         Self { timesync_service }
     }
 }
 
 #[async_trait]
-impl<T: TimeSyncServiceTrait> MessageHandler for TimeSyncMessageHandler<T> {
+impl<N: NetworkServiceTrait + 'static> MessageHandler for TimeSyncMessageHandler<N> {
     async fn handle_message(
         &self,
+        _ctx: &HandlerContext,
         from: NodeId,
         message: NetworkMessage,
     ) -> network_service::Result<Option<NetworkMessage>> {
@@ -32,13 +35,13 @@ impl<T: TimeSyncServiceTrait> MessageHandler for TimeSyncMessageHandler<T> {
             match serde_json::from_value(message.payload.clone()) {
                 Ok(msg) => msg,
                 Err(e) => {
-                    error!("无法解析授时消息: {}", e);
+                    error!("{}", message.parse_failure_summary(&from, &e));
                     return Err(network_service::NetworkError::SerializationError(e));
                 }
             };
 
         // 根据消息类型处理
-        let result = match timesync_message {
+        let result = match timesync_message.clone() {
             TimeSyncMessageType::TimeRequest {
                 request_id,
                 client_timestamp,
@@ -61,13 +64,12 @@ impl<T: TimeSyncServiceTrait> MessageHandler for TimeSyncMessageHandler<T> {
                 info!("收到时间响应: request_id={}, server_time={}, client_time={}, processing_time={}ns", 
                       request_id, server_timestamp, client_timestamp, processing_time_ns);
                 // 客户端收到服务器的时间响应，可以在这里计算时间偏差
-                let current_time = std::time::SystemTime::now()
-                    .duration_since(std::time::UNIX_EPOCH)
-                    .unwrap()
-                    .as_millis() as i64;
+                let current_time = network_service::now_millis() as i64;
                 let time_offset = server_timestamp - current_time;
                 info!("计算得到的时间偏差: {}ms", time_offset);
-                Ok(())
+                self.timesync_service
+                    .complete_request(request_id, timesync_message)
+                    .await
             }
 
             TimeSyncMessageType::SyncRequest {
@@ -98,7 +100,9 @@ impl<T: TimeSyncServiceTrait> MessageHandler for TimeSyncMessageHandler<T> {
                     "应用时间偏差: {}ms，网络延迟: {}ms",
                     time_offset_ms, round_trip_time_ms
                 );
-                Ok(())
+                self.timesync_service
+                    .complete_request(request_id, timesync_message)
+                    .await
             }
 
             TimeSyncMessageType::Heartbeat {
@@ -106,7 +110,9 @@ impl<T: TimeSyncServiceTrait> MessageHandler for TimeSyncMessageHandler<T> {
                 sequence,
             } => {
                 info!("收到心跳: timestamp={}, sequence={}", timestamp, sequence);
-                // 处理心跳消息，可以用来检测网络连接状态
+                self.timesync_service
+                    .record_heartbeat(from.clone(), sequence)
+                    .await;
                 Ok(())
             }
         };
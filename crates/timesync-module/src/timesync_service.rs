@@ -4,18 +4,96 @@ use crate::{Result, TimeSyncError, TimeSyncMessageType, TimeSyncServiceTrait};
 use async_trait::async_trait;
 use chrono::Utc;
 use network_service::{
-    MessageId, MessageType, NetworkMessage, NetworkServiceTrait, NodeId, UnicastOptions,
+    BroadcastOptions, MessageId, MessageType, NetworkError, NetworkEvent, NetworkMessage,
+    NetworkServiceTrait, NodeId, ShutdownComponent, UnicastOptions,
 };
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+use std::io::Write;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
-use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
-use tokio::sync::{Mutex, RwLock};
+use std::time::{Duration, Instant};
+use tokio::sync::{broadcast, oneshot, Mutex, RwLock};
 use tokio::time::interval;
-use tracing::{info, warn};
+use tokio_util::sync::CancellationToken;
+use tracing::{error, info, warn};
 use uuid::Uuid;
 
+/// 等待响应的调用方
+type ResponseWaiter = oneshot::Sender<Result<TimeSyncMessageType>>;
+
+/// 心跳连续失败回调，参数为当前连续失败次数
+pub type HeartbeatFailureCallback = Arc<dyn Fn(u32) + Send + Sync>;
+
+/// 对端重启回调，参数为对端节点、重启前的序列号和重启后的序列号
+pub type PeerRestartCallback = Arc<dyn Fn(&NodeId, u64, u64) + Send + Sync>;
+
+/// 收到对端心跳后的判断结果
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HeartbeatStatus {
+    /// 第一次收到该对端的心跳
+    First,
+    /// 序列号连续
+    InOrder,
+    /// 中间丢失了若干心跳
+    Gap { missed: u64 },
+    /// 重复的序列号
+    Duplicate,
+    /// 序列号小幅回退，视为乱序到达
+    Reordered,
+    /// 序列号大幅回退，视为对端重启，跟踪状态已重置
+    Restarted { previous: u64 },
+}
+
+/// 运行中的后台任务（心跳、自动同步）及其取消令牌
+type BackgroundTask = (CancellationToken, tokio::task::JoinHandle<()>);
+
+/// 授时服务配置
+#[derive(Debug, Clone)]
+pub struct TimeSyncConfig {
+    /// 心跳间隔（毫秒），`start_heartbeat` 未指定间隔时使用
+    pub heartbeat_interval_ms: u64,
+    /// 心跳连续失败告警阈值
+    pub heartbeat_failure_threshold: u32,
+    /// 对外报告的时区
+    pub timezone: String,
+    /// 允许的最大时钟偏差（毫秒），超出的时间戳视为无效
+    pub max_skew_ms: i64,
+    /// 待处理时间请求的数量上限，超出时淘汰最早的记录
+    pub max_pending_requests: usize,
+    /// 发送请求的超时时间（毫秒）
+    pub request_timeout_ms: u64,
+    /// 发送响应的超时时间（毫秒）
+    pub response_timeout_ms: u64,
+    /// 对外报告的时钟精度（纳秒），`None` 表示启动时测量
+    pub precision_ns: Option<u64>,
+    /// 时钟偏移持久化文件，`None` 表示不持久化
+    pub offset_file: Option<PathBuf>,
+    /// 对端心跳序列号回退超过该值时视为对端重启，否则视为乱序
+    pub heartbeat_restart_threshold: u64,
+    /// 优先使用的授时服务器（按优先级排列），都不可达时才回退到其他已连接节点
+    pub preferred_servers: Vec<NodeId>,
+}
+
+impl Default for TimeSyncConfig {
+    fn default() -> Self {
+        Self {
+            heartbeat_interval_ms: 5000,
+            heartbeat_failure_threshold: 3,
+            timezone: Utc.to_string(),
+            max_skew_ms: 3_600_000,
+            max_pending_requests: 1024,
+            request_timeout_ms: 5000,
+            response_timeout_ms: 3000,
+            precision_ns: None,
+            offset_file: None,
+            heartbeat_restart_threshold: 16,
+            preferred_servers: Vec::new(),
+        }
+    }
+}
+
 /// 时间信息
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct TimeInfo {
@@ -25,6 +103,29 @@ pub struct TimeInfo {
     pub server_id: String,
 }
 
+/// 远端节点的时间查询结果
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RemoteTime {
+    /// 远端节点的时间（毫秒）
+    pub server_timestamp: i64,
+    /// 远端时间减本地时间（毫秒），假设往返路径对称
+    pub offset_ms: i64,
+    /// 往返时间（毫秒）
+    pub rtt_ms: u64,
+}
+
+/// 相对服务器的时钟偏移估计
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ClockOffset {
+    /// 服务器时间减本地时间（毫秒）
+    pub offset_ms: i64,
+    /// 得到该偏移的本地时间（毫秒）
+    pub updated_at: i64,
+    /// 是否为启动时从文件加载、尚未被新的同步确认的旧值
+    #[serde(skip)]
+    pub stale: bool,
+}
+
 /// 同步统计信息
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct SyncStats {
@@ -36,6 +137,62 @@ pub struct SyncStats {
     pub heartbeat_count: u64,
 }
 
+/// 指标中指数移动平均的平滑系数，越大越偏向最新的样本
+const METRICS_EMA_ALPHA: f64 = 0.2;
+
+/// 授时指标
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct TimeSyncMetrics {
+    /// 时钟偏移（服务器时间减本地时间）的指数移动平均（毫秒）
+    pub offset_ema_ms: Option<f64>,
+    /// 往返时延的指数移动平均（毫秒）
+    pub delay_ema_ms: Option<f64>,
+    /// 抖动：相邻两次往返时延之差的指数移动平均（毫秒）
+    pub jitter_ms: f64,
+    /// 完成的同步次数（时间查询和时间同步的响应）
+    pub sync_count: u64,
+    /// 各同步会话的请求次数（本节点作为服务器）
+    pub session_requests: HashMap<NodeId, u64>,
+    /// 距上次完成同步的时间（毫秒）
+    pub last_sync_age_ms: Option<u64>,
+    /// 丢失的对端心跳数
+    pub missed_heartbeats: u64,
+    /// 等待响应超时的请求数
+    pub timed_out_requests: u64,
+}
+
+/// 指标的累积状态，会话和同步时间在读取时计算
+#[derive(Debug, Default)]
+struct MetricsState {
+    offset_ema_ms: Option<f64>,
+    delay_ema_ms: Option<f64>,
+    jitter_ms: f64,
+    last_delay_ms: Option<f64>,
+    sync_count: u64,
+    last_sync_at: Option<i64>,
+    missed_heartbeats: u64,
+    timed_out_requests: u64,
+}
+
+impl MetricsState {
+    /// 记录一次同步得到的偏移和往返时延
+    fn record_sample(&mut self, offset_ms: f64, delay_ms: f64, now_ms: i64) {
+        let ema = |previous: Option<f64>, sample: f64| {
+            previous.map_or(sample, |previous| {
+                previous + METRICS_EMA_ALPHA * (sample - previous)
+            })
+        };
+        self.offset_ema_ms = Some(ema(self.offset_ema_ms, offset_ms));
+        self.delay_ema_ms = Some(ema(self.delay_ema_ms, delay_ms));
+        if let Some(last_delay) = self.last_delay_ms {
+            self.jitter_ms = ema(Some(self.jitter_ms), (delay_ms - last_delay).abs());
+        }
+        self.last_delay_ms = Some(delay_ms);
+        self.sync_count += 1;
+        self.last_sync_at = Some(now_ms);
+    }
+}
+
 /// 时间请求记录
 #[derive(Debug, Clone)]
 struct TimeRequest {
@@ -46,29 +203,55 @@ struct TimeRequest {
 }
 
 /// 同步会话信息
-#[derive(Debug, Clone)]
-struct SyncSession {
-    node_id: NodeId,
-    last_sync_time: i64,
-    time_offset_ms: i64,
-    sync_interval_ms: u64,
-    request_count: u64,
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SyncSessionInfo {
+    pub node_id: NodeId,
+    pub last_sync_time: i64,
+    pub time_offset_ms: i64,
+    pub sync_interval_ms: u64,
+    pub request_count: u64,
 }
 
 /// 授时服务实现
 pub struct TimeSyncService<N: NetworkServiceTrait> {
     /// 网络服务
     network_service: N,
+    /// 配置
+    config: TimeSyncConfig,
     /// 待处理的时间请求
     pending_requests: Arc<RwLock<HashMap<Uuid, TimeRequest>>>,
+    /// 等待响应的请求
+    response_waiters: Arc<Mutex<HashMap<Uuid, ResponseWaiter>>>,
     /// 同步会话
-    sync_sessions: Arc<RwLock<HashMap<NodeId, SyncSession>>>,
+    sync_sessions: Arc<RwLock<HashMap<NodeId, SyncSessionInfo>>>,
+    /// 连接时公布了授时能力的在线节点，心跳只发给这些节点
+    timesync_peers: Arc<RwLock<HashSet<NodeId>>>,
     /// 统计信息
     stats: Arc<RwLock<SyncStats>>,
-    /// 心跳状态
-    heartbeat_handle: Arc<Mutex<Option<tokio::task::JoinHandle<()>>>>,
+    /// 授时指标
+    metrics: Arc<RwLock<MetricsState>>,
+    /// 心跳任务及其取消令牌
+    heartbeat_handle: Arc<Mutex<Option<BackgroundTask>>>,
     /// 心跳序列号
     heartbeat_sequence: Arc<RwLock<u64>>,
+    /// 心跳连续失败告警阈值
+    heartbeat_failure_threshold: Arc<RwLock<u32>>,
+    /// 心跳连续失败回调
+    heartbeat_failure_callback: Arc<RwLock<Option<HeartbeatFailureCallback>>>,
+    /// 各对端最近的心跳序列号
+    peer_heartbeats: Arc<RwLock<HashMap<NodeId, u64>>>,
+    /// 对端重启回调
+    peer_restart_callback: Arc<RwLock<Option<PeerRestartCallback>>>,
+    /// 网络事件订阅任务
+    event_task: Arc<Mutex<Option<tokio::task::JoinHandle<()>>>>,
+    /// 时钟精度（纳秒）
+    precision_ns: u64,
+    /// 当前时钟偏移估计
+    clock_offset: Arc<RwLock<Option<ClockOffset>>>,
+    /// 最近一次成功同步的授时服务器
+    authoritative_server: Arc<RwLock<Option<NodeId>>>,
+    /// 自动同步任务及其取消令牌
+    auto_sync_handle: Arc<Mutex<Option<BackgroundTask>>>,
     /// 服务器ID
     server_id: String,
 }
@@ -76,10 +259,26 @@ pub struct TimeSyncService<N: NetworkServiceTrait> {
 impl<N: NetworkServiceTrait> TimeSyncService<N> {
     /// 创建新的授时服务
     pub fn new(network_service: N, server_id: String) -> Self {
+        Self::with_config(network_service, server_id, TimeSyncConfig::default())
+    }
+
+    /// 使用指定配置创建授时服务
+    pub fn with_config(network_service: N, server_id: String, config: TimeSyncConfig) -> Self {
+        let failure_threshold = config.heartbeat_failure_threshold.max(1);
+        let precision_ns = config
+            .precision_ns
+            .unwrap_or_else(Self::measure_clock_precision_ns);
+        let clock_offset = config
+            .offset_file
+            .as_deref()
+            .and_then(Self::load_clock_offset);
         Self {
             network_service,
+            config,
             pending_requests: Arc::new(RwLock::new(HashMap::new())),
+            response_waiters: Arc::new(Mutex::new(HashMap::new())),
             sync_sessions: Arc::new(RwLock::new(HashMap::new())),
+            timesync_peers: Arc::new(RwLock::new(HashSet::new())),
             stats: Arc::new(RwLock::new(SyncStats {
                 total_requests: 0,
                 total_responses: 0,
@@ -88,26 +287,373 @@ impl<N: NetworkServiceTrait> TimeSyncService<N> {
                 active_sessions: 0,
                 heartbeat_count: 0,
             })),
+            metrics: Arc::new(RwLock::new(MetricsState::default())),
             heartbeat_handle: Arc::new(Mutex::new(None)),
             heartbeat_sequence: Arc::new(RwLock::new(0)),
+            heartbeat_failure_threshold: Arc::new(RwLock::new(failure_threshold)),
+            heartbeat_failure_callback: Arc::new(RwLock::new(None)),
+            peer_heartbeats: Arc::new(RwLock::new(HashMap::new())),
+            peer_restart_callback: Arc::new(RwLock::new(None)),
+            event_task: Arc::new(Mutex::new(None)),
+            precision_ns,
+            clock_offset: Arc::new(RwLock::new(clock_offset)),
+            authoritative_server: Arc::new(RwLock::new(None)),
+            auto_sync_handle: Arc::new(Mutex::new(None)),
             server_id,
         }
     }
 
+    /// 从文件加载上次保存的时钟偏移，标记为待确认
+    fn load_clock_offset(path: &Path) -> Option<ClockOffset> {
+        let content = match std::fs::read_to_string(path) {
+            Ok(content) => content,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return None,
+            Err(e) => {
+                warn!("读取时钟偏移文件 {} 失败: {}", path.display(), e);
+                return None;
+            }
+        };
+        match serde_json::from_str::<ClockOffset>(&content) {
+            Ok(mut offset) => {
+                offset.stale = true;
+                info!(
+                    "从 {} 加载时钟偏移 {}ms（待同步确认）",
+                    path.display(),
+                    offset.offset_ms
+                );
+                Some(offset)
+            }
+            Err(e) => {
+                warn!("解析时钟偏移文件 {} 失败: {}", path.display(), e);
+                None
+            }
+        }
+    }
+
+    /// 记录新同步得到的时钟偏移，配置了文件时同时持久化
+    ///
+    /// 持有偏移的写锁完成落盘，并发记录时文件内容与内存中的偏移一致。
+    pub async fn record_clock_offset(&self, offset_ms: i64) {
+        let offset = ClockOffset {
+            offset_ms,
+            updated_at: Self::get_current_timestamp_ms(),
+            stale: false,
+        };
+
+        let mut current = self.clock_offset.write().await;
+        if let Some(path) = self.config.offset_file.clone() {
+            let record = offset.clone();
+            let file = path.clone();
+            let result =
+                tokio::task::spawn_blocking(move || Self::save_clock_offset(&file, &record))
+                    .await
+                    .map_err(std::io::Error::other)
+                    .and_then(|result| result);
+            if let Err(e) = result {
+                warn!("写入时钟偏移文件 {} 失败: {}", path.display(), e);
+            }
+        }
+
+        *current = Some(offset);
+    }
+
+    /// 先写入临时文件再重命名，崩溃时不会留下写了一半的偏移文件
+    fn save_clock_offset(path: &Path, offset: &ClockOffset) -> std::io::Result<()> {
+        let mut tmp = path.as_os_str().to_owned();
+        tmp.push(".tmp");
+        let tmp = PathBuf::from(tmp);
+
+        let mut file = std::fs::File::create(&tmp)?;
+        file.write_all(&serde_json::to_vec(offset)?)?;
+        file.sync_all()?;
+        std::fs::rename(&tmp, path)
+    }
+
+    /// 获取当前时钟偏移估计
+    pub async fn clock_offset(&self) -> Option<ClockOffset> {
+        self.clock_offset.read().await.clone()
+    }
+
+    /// 按时钟偏移校正后的当前时间（毫秒），没有偏移估计时返回本地时间
+    pub async fn now_adjusted(&self) -> i64 {
+        let offset_ms = self
+            .clock_offset
+            .read()
+            .await
+            .as_ref()
+            .map_or(0, |offset| offset.offset_ms);
+        Self::get_current_timestamp_ms() + offset_ms
+    }
+
+    /// 测量时钟的实际分辨率：连续采样 `Instant::now()`，取最小的非零间隔
+    fn measure_clock_precision_ns() -> u64 {
+        const SAMPLES: usize = 1000;
+
+        let mut min_delta = u64::MAX;
+        let mut last = Instant::now();
+        for _ in 0..SAMPLES {
+            let now = Instant::now();
+            let delta = now.duration_since(last).as_nanos() as u64;
+            if delta > 0 {
+                min_delta = min_delta.min(delta);
+            }
+            last = now;
+        }
+
+        if min_delta == u64::MAX {
+            // 采样期间时钟没有前进，退回毫秒精度
+            1_000_000
+        } else {
+            min_delta
+        }
+    }
+
+    /// 获取配置
+    pub fn config(&self) -> &TimeSyncConfig {
+        &self.config
+    }
+
+    /// 登记运行授时服务的节点
+    ///
+    /// `attach` 订阅网络事件后，连接时公布了授时能力的节点会自动登记，断开时自动移除。
+    pub async fn register_timesync_peer(&self, node_id: NodeId) {
+        self.timesync_peers.write().await.insert(node_id);
+    }
+
+    /// 移除授时节点
+    pub async fn unregister_timesync_peer(&self, node_id: &NodeId) {
+        self.timesync_peers.write().await.remove(node_id);
+    }
+
+    /// 发送时间查询请求
+    async fn send_time_request(&self, target: NodeId, request_id: Uuid) -> Result<()> {
+        let client_timestamp = Self::get_current_timestamp_ms();
+
+        info!("向 {} 请求时间: {}", target, client_timestamp);
+
+        let request_message = TimeSyncMessageType::TimeRequest {
+            request_id,
+            client_timestamp,
+        };
+
+        let payload = serde_json::to_value(&request_message)?;
+        let network_msg =
+            NetworkMessage::new(MessageType::timesync(), self.server_id.clone(), payload);
+
+        let options = UnicastOptions {
+            wait_for_response: true,
+            timeout_ms: Some(self.config.request_timeout_ms),
+            retry_count: 2,
+        };
+
+        self.network_service
+            .unicast(target, network_msg, Some(options))
+            .await?;
+
+        Ok(())
+    }
+
+    /// 发送时间查询请求并等待响应
+    ///
+    /// 超过 `request_timeout_ms` 未收到响应时返回超时错误，
+    /// 请求被 `cancel_request` 取消时返回取消错误。
+    pub async fn request_time_and_wait(&self, target: NodeId) -> Result<TimeSyncMessageType> {
+        let timeout = Duration::from_millis(self.config.request_timeout_ms);
+        self.wait_for_time_response(target, timeout).await
+    }
+
+    /// 查询远端节点的时间，返回远端时间、时钟偏移和往返时间
+    pub async fn query_time(&self, target: NodeId, timeout: Duration) -> Result<RemoteTime> {
+        match self.wait_for_time_response(target, timeout).await? {
+            TimeSyncMessageType::TimeResponse {
+                server_timestamp,
+                client_timestamp,
+                ..
+            } => {
+                let now = Self::get_current_timestamp_ms();
+                Ok(RemoteTime {
+                    server_timestamp,
+                    offset_ms: server_timestamp - (client_timestamp + now) / 2,
+                    rtt_ms: (now - client_timestamp).max(0) as u64,
+                })
+            }
+            other => Err(TimeSyncError::SyncFailed(format!(
+                "时间查询收到意外的响应: {:?}",
+                other
+            ))),
+        }
+    }
+
+    /// 当前的授时服务器（最近一次成功同步的节点），所有服务器都不可达时为 `None`
+    pub async fn authoritative_server(&self) -> Option<NodeId> {
+        self.authoritative_server.read().await.clone()
+    }
+
+    /// 按优先级向授时服务器查询时间，用第一个成功的结果更新时钟偏移
+    ///
+    /// 先依次尝试 `preferred_servers`，都不可达时才尝试其他已连接节点。
+    pub async fn sync_with_servers(&self, timeout: Duration) -> Result<RemoteTime> {
+        let preferred = &self.config.preferred_servers;
+        let mut candidates = preferred.clone();
+        match self.network_service.get_connected_nodes().await {
+            Ok(nodes) => {
+                candidates.extend(nodes.into_iter().filter(|node| !preferred.contains(node)))
+            }
+            Err(e) => warn!("获取已连接节点失败: {}", e),
+        }
+
+        let mut last_error = None;
+        for server in candidates {
+            match self.query_time(server.clone(), timeout).await {
+                Ok(remote) => {
+                    self.record_clock_offset(remote.offset_ms).await;
+                    let mut authoritative = self.authoritative_server.write().await;
+                    if authoritative.as_ref() != Some(&server) {
+                        info!("授时服务器切换为 {}", server);
+                        *authoritative = Some(server);
+                    }
+                    return Ok(remote);
+                }
+                Err(e) => {
+                    warn!("向 {} 同步时间失败: {}", server, e);
+                    last_error = Some(e);
+                }
+            }
+        }
+
+        *self.authoritative_server.write().await = None;
+        Err(TimeSyncError::SyncFailed(match last_error {
+            Some(e) => format!("所有授时服务器均不可达: {}", e),
+            None => "没有可用的授时服务器".to_string(),
+        }))
+    }
+
+    /// 发送时间查询请求并在 `timeout` 内等待响应
+    async fn wait_for_time_response(
+        &self,
+        target: NodeId,
+        timeout: Duration,
+    ) -> Result<TimeSyncMessageType> {
+        let request_id = Uuid::new_v4();
+        let (tx, rx) = oneshot::channel();
+        self.response_waiters.lock().await.insert(request_id, tx);
+
+        if let Err(e) = self.send_time_request(target, request_id).await {
+            self.response_waiters.lock().await.remove(&request_id);
+            return Err(e);
+        }
+
+        let result = tokio::time::timeout(timeout, rx).await;
+        self.response_waiters.lock().await.remove(&request_id);
+
+        match result {
+            Ok(Ok(response)) => response,
+            Ok(Err(_)) => Err(TimeSyncError::RequestCancelled(request_id)),
+            Err(_) => {
+                self.metrics.write().await.timed_out_requests += 1;
+                Err(TimeSyncError::RequestTimeout)
+            }
+        }
+    }
+
+    /// 列出所有处理中的请求（包括等待响应的请求和待回复的时间请求）
+    pub async fn pending_request_ids(&self) -> Vec<Uuid> {
+        let mut ids: Vec<Uuid> = self.response_waiters.lock().await.keys().copied().collect();
+        ids.extend(self.pending_requests.read().await.keys().copied());
+        ids
+    }
+
+    /// 列出所有同步会话（按节点ID排序）
+    pub async fn list_sessions(&self) -> Vec<SyncSessionInfo> {
+        let mut sessions: Vec<SyncSessionInfo> =
+            self.sync_sessions.read().await.values().cloned().collect();
+        sessions.sort_by(|a, b| a.node_id.cmp(&b.node_id));
+        sessions
+    }
+
+    /// 取消处理中的请求，等待方会收到取消错误；请求不存在时返回false
+    pub async fn cancel_request(&self, request_id: Uuid) -> bool {
+        if let Some(waiter) = self.response_waiters.lock().await.remove(&request_id) {
+            info!("取消等待响应的请求: {}", request_id);
+            let _ = waiter.send(Err(TimeSyncError::RequestCancelled(request_id)));
+            return true;
+        }
+
+        self.pending_requests
+            .write()
+            .await
+            .remove(&request_id)
+            .is_some()
+    }
+
+    /// 处理时间查询或同步的响应，唤醒等待该请求的调用方
+    pub async fn complete_request(
+        &self,
+        request_id: Uuid,
+        response: TimeSyncMessageType,
+    ) -> Result<()> {
+        // 只记录本节点发出且仍在等待的请求的响应，过期或伪造的响应不影响时钟偏移
+        let Some(waiter) = self.response_waiters.lock().await.remove(&request_id) else {
+            warn!("忽略未知或已超时请求 {} 的响应", request_id);
+            return Ok(());
+        };
+
+        let now = Self::get_current_timestamp_ms();
+        match &response {
+            TimeSyncMessageType::SyncResponse {
+                time_offset_ms,
+                round_trip_time_ms,
+                ..
+            } => {
+                self.record_clock_offset(*time_offset_ms).await;
+                self.metrics.write().await.record_sample(
+                    *time_offset_ms as f64,
+                    *round_trip_time_ms as f64,
+                    now,
+                );
+            }
+            TimeSyncMessageType::TimeResponse {
+                server_timestamp,
+                client_timestamp,
+                ..
+            } => {
+                // 假设往返路径对称，服务器时间对应往返的中点
+                let delay_ms = (now - client_timestamp).max(0) as f64;
+                let offset_ms = *server_timestamp as f64 - (*client_timestamp + now) as f64 / 2.0;
+                self.metrics
+                    .write()
+                    .await
+                    .record_sample(offset_ms, delay_ms, now);
+            }
+            _ => {}
+        }
+        let _ = waiter.send(Ok(response));
+        Ok(())
+    }
+
+    /// 设置心跳连续失败告警阈值（最小为1）
+    pub async fn set_heartbeat_failure_threshold(&self, threshold: u32) {
+        *self.heartbeat_failure_threshold.write().await = threshold.max(1);
+    }
+
+    /// 设置心跳连续失败回调，连续失败次数达到阈值时触发
+    pub async fn on_heartbeat_failure(&self, callback: HeartbeatFailureCallback) {
+        *self.heartbeat_failure_callback.write().await = Some(callback);
+    }
+
+    /// 设置对端重启回调，检测到对端心跳序列号大幅回退时触发
+    pub async fn on_peer_restart(&self, callback: PeerRestartCallback) {
+        *self.peer_restart_callback.write().await = Some(callback);
+    }
+
     /// 获取当前高精度时间戳（纳秒）
     fn get_current_timestamp_ns() -> u64 {
-        SystemTime::now()
-            .duration_since(UNIX_EPOCH)
-            .unwrap()
-            .as_nanos() as u64
+        network_service::now_nanos()
     }
 
     /// 获取当前时间戳（毫秒）
     fn get_current_timestamp_ms() -> i64 {
-        SystemTime::now()
-            .duration_since(UNIX_EPOCH)
-            .unwrap()
-            .as_millis() as i64
+        network_service::now_millis() as i64
     }
 
     /// 计算两个时间戳之间的差值（毫秒）
@@ -116,18 +662,38 @@ impl<N: NetworkServiceTrait> TimeSyncService<N> {
     }
 
     /// 验证时间戳是否合理
-    fn validate_timestamp(timestamp: i64) -> Result<()> {
+    fn validate_timestamp(timestamp: i64, max_skew_ms: i64) -> Result<()> {
         let current = Self::get_current_timestamp_ms();
         let diff = (current - timestamp).abs();
 
-        // 允许最大1小时的时间差
-        if diff > 3600000 {
+        if diff > max_skew_ms {
             return Err(TimeSyncError::InvalidTimestamp(timestamp));
         }
 
         Ok(())
     }
 
+    /// 记录待处理的时间请求，超过上限时淘汰最早的记录
+    async fn track_pending_request(&self, request: TimeRequest) {
+        let mut requests = self.pending_requests.write().await;
+
+        while requests.len() >= self.config.max_pending_requests.max(1) {
+            let oldest = requests
+                .values()
+                .min_by_key(|r| r.server_receive_time)
+                .map(|r| r.request_id);
+            match oldest {
+                Some(id) => {
+                    warn!("待处理时间请求过多，淘汰最早的请求: {}", id);
+                    requests.remove(&id);
+                }
+                None => break,
+            }
+        }
+
+        requests.insert(request.request_id, request);
+    }
+
     /// 更新统计信息
     async fn update_stats(&self, request_processed: bool, response_time_ms: Option<f64>) {
         let mut stats = self.stats.write().await;
@@ -172,7 +738,7 @@ impl<N: NetworkServiceTrait> TimeSyncService<N> {
 
         let options = UnicastOptions {
             wait_for_response: false,
-            timeout_ms: Some(3000),
+            timeout_ms: Some(self.config.response_timeout_ms),
             retry_count: 1,
         };
 
@@ -210,7 +776,7 @@ impl<N: NetworkServiceTrait> TimeSyncService<N> {
 
         let options = UnicastOptions {
             wait_for_response: false,
-            timeout_ms: Some(3000),
+            timeout_ms: Some(self.config.response_timeout_ms),
             retry_count: 1,
         };
 
@@ -224,6 +790,64 @@ impl<N: NetworkServiceTrait> TimeSyncService<N> {
     }
 }
 
+impl<N: NetworkServiceTrait + 'static> TimeSyncService<N> {
+    /// 启动自动同步，每隔 `interval_ms` 调用一次 `sync_with_servers`
+    pub async fn start_auto_sync(self: &Arc<Self>, interval_ms: u64) -> Result<()> {
+        if interval_ms == 0 {
+            return Err(TimeSyncError::InvalidSyncInterval(interval_ms));
+        }
+        let mut handle_guard = self.auto_sync_handle.lock().await;
+        if handle_guard.is_some() {
+            return Err(TimeSyncError::AutoSyncAlreadyStarted);
+        }
+
+        info!("启动自动同步，间隔: {}ms", interval_ms);
+
+        // 任务只持有弱引用，服务释放后自动退出
+        let service = Arc::downgrade(self);
+        let timeout = Duration::from_millis(self.config.request_timeout_ms);
+        let cancel = CancellationToken::new();
+        let task_cancel = cancel.clone();
+
+        let handle = tokio::spawn(async move {
+            let mut interval = interval(Duration::from_millis(interval_ms));
+            loop {
+                tokio::select! {
+                    _ = task_cancel.cancelled() => break,
+                    _ = interval.tick() => {}
+                }
+                let Some(service) = service.upgrade() else {
+                    break;
+                };
+                tokio::select! {
+                    _ = task_cancel.cancelled() => break,
+                    result = service.sync_with_servers(timeout) => {
+                        if let Err(e) = result {
+                            warn!("自动同步失败: {}", e);
+                        }
+                    }
+                }
+            }
+        });
+
+        *handle_guard = Some((cancel, handle));
+        Ok(())
+    }
+
+    /// 停止自动同步
+    pub async fn stop_auto_sync(&self) -> Result<()> {
+        let Some((cancel, handle)) = self.auto_sync_handle.lock().await.take() else {
+            return Err(TimeSyncError::AutoSyncNotStarted);
+        };
+        cancel.cancel();
+        if let Err(e) = handle.await {
+            warn!("自动同步任务异常退出: {}", e);
+        }
+        info!("自动同步已停止");
+        Ok(())
+    }
+}
+
 #[async_trait]
 impl<N: NetworkServiceTrait + 'static> TimeSyncServiceTrait for TimeSyncService<N> {
     async fn handle_time_request(
@@ -235,42 +859,40 @@ impl<N: NetworkServiceTrait + 'static> TimeSyncServiceTrait for TimeSyncService<
         info!("处理来自 {} 的时间请求: {}", from, client_timestamp);
 
         // 验证时间戳
-        Self::validate_timestamp(client_timestamp)?;
+        Self::validate_timestamp(client_timestamp, self.config.max_skew_ms)?;
 
         let start_time = Instant::now();
 
         // 记录请求信息
-        {
-            let mut requests = self.pending_requests.write().await;
-            requests.insert(
-                request_id,
-                TimeRequest {
-                    request_id,
-                    from_node: from.clone(),
-                    client_timestamp,
-                    server_receive_time: start_time,
-                },
-            );
-        }
+        self.track_pending_request(TimeRequest {
+            request_id,
+            from_node: from.clone(),
+            client_timestamp,
+            server_receive_time: start_time,
+        })
+        .await;
 
         // 计算处理时间
         let processing_time_ns = start_time.elapsed().as_nanos() as u64;
 
         // 发送响应
-        self.send_time_response(
-            from.clone(),
-            request_id,
-            client_timestamp,
-            processing_time_ns,
-        )
-        .await?;
+        let send_result = self
+            .send_time_response(
+                from.clone(),
+                request_id,
+                client_timestamp,
+                processing_time_ns,
+            )
+            .await;
 
-        // 清理请求记录
+        // 清理请求记录（发送失败时同样清理，避免泄漏）
         {
             let mut requests = self.pending_requests.write().await;
             requests.remove(&request_id);
         }
 
+        send_result?;
+
         // 更新统计
         self.update_stats(true, None).await;
 
@@ -279,29 +901,7 @@ impl<N: NetworkServiceTrait + 'static> TimeSyncServiceTrait for TimeSyncService<
 
     async fn request_time(&self, target: NodeId) -> Result<Uuid> {
         let request_id = Uuid::new_v4();
-        let client_timestamp = Self::get_current_timestamp_ms();
-
-        info!("向 {} 请求时间: {}", target, client_timestamp);
-
-        let request_message = TimeSyncMessageType::TimeRequest {
-            request_id,
-            client_timestamp,
-        };
-
-        let payload = serde_json::to_value(&request_message)?;
-        let network_msg =
-            NetworkMessage::new(MessageType::timesync(), self.server_id.clone(), payload);
-
-        let options = UnicastOptions {
-            wait_for_response: true,
-            timeout_ms: Some(5000),
-            retry_count: 2,
-        };
-
-        self.network_service
-            .unicast(target, network_msg, Some(options))
-            .await?;
-
+        self.send_time_request(target, request_id).await?;
         Ok(request_id)
     }
 
@@ -318,7 +918,7 @@ impl<N: NetworkServiceTrait + 'static> TimeSyncServiceTrait for TimeSyncService<
         );
 
         // 验证时间戳和同步间隔
-        Self::validate_timestamp(client_time)?;
+        Self::validate_timestamp(client_time, self.config.max_skew_ms)?;
         if sync_interval_ms < 1000 || sync_interval_ms > 3600000 {
             return Err(TimeSyncError::InvalidSyncInterval(sync_interval_ms));
         }
@@ -329,13 +929,15 @@ impl<N: NetworkServiceTrait + 'static> TimeSyncServiceTrait for TimeSyncService<
         // 更新或创建同步会话
         {
             let mut sessions = self.sync_sessions.write().await;
-            let session = sessions.entry(from.clone()).or_insert_with(|| SyncSession {
-                node_id: from.clone(),
-                last_sync_time: server_time,
-                time_offset_ms,
-                sync_interval_ms,
-                request_count: 0,
-            });
+            let session = sessions
+                .entry(from.clone())
+                .or_insert_with(|| SyncSessionInfo {
+                    node_id: from.clone(),
+                    last_sync_time: server_time,
+                    time_offset_ms,
+                    sync_interval_ms,
+                    request_count: 0,
+                });
 
             session.last_sync_time = server_time;
             session.time_offset_ms = time_offset_ms;
@@ -384,21 +986,42 @@ impl<N: NetworkServiceTrait + 'static> TimeSyncServiceTrait for TimeSyncService<
 
         let options = UnicastOptions {
             wait_for_response: true,
-            timeout_ms: Some(5000),
+            timeout_ms: Some(self.config.request_timeout_ms),
             retry_count: 2,
         };
+        let timeout = Duration::from_millis(
+            self.config.request_timeout_ms * (options.retry_count as u64 + 1),
+        );
 
-        self.network_service
+        // 响应由处理器交给 complete_request 记录，超时未响应时清理等待项
+        let (tx, rx) = oneshot::channel();
+        self.response_waiters.lock().await.insert(request_id, tx);
+        if let Err(e) = self
+            .network_service
             .unicast(target, network_msg, Some(options))
-            .await?;
+            .await
+        {
+            self.response_waiters.lock().await.remove(&request_id);
+            return Err(e.into());
+        }
+
+        let waiters = self.response_waiters.clone();
+        let metrics = self.metrics.clone();
+        tokio::spawn(async move {
+            if tokio::time::timeout(timeout, rx).await.is_err()
+                && waiters.lock().await.remove(&request_id).is_some()
+            {
+                metrics.write().await.timed_out_requests += 1;
+            }
+        });
 
         Ok(request_id)
     }
 
     async fn get_time_info(&self) -> Result<TimeInfo> {
         let current_time = Self::get_current_timestamp_ms();
-        let timezone = Utc::now().timezone().to_string();
-        let precision_ns = 1000000; // 毫秒精度
+        let timezone = self.config.timezone.clone();
+        let precision_ns = self.precision_ns;
 
         Ok(TimeInfo {
             current_time,
@@ -413,7 +1036,31 @@ impl<N: NetworkServiceTrait + 'static> TimeSyncServiceTrait for TimeSyncService<
         Ok(stats.clone())
     }
 
-    async fn start_heartbeat(&self, interval_ms: u64) -> Result<()> {
+    async fn get_metrics(&self) -> Result<TimeSyncMetrics> {
+        let session_requests = self
+            .sync_sessions
+            .read()
+            .await
+            .iter()
+            .map(|(node_id, session)| (node_id.clone(), session.request_count))
+            .collect();
+        let now = Self::get_current_timestamp_ms();
+
+        let state = self.metrics.read().await;
+        Ok(TimeSyncMetrics {
+            offset_ema_ms: state.offset_ema_ms,
+            delay_ema_ms: state.delay_ema_ms,
+            jitter_ms: state.jitter_ms,
+            sync_count: state.sync_count,
+            session_requests,
+            last_sync_age_ms: state.last_sync_at.map(|at| (now - at).max(0) as u64),
+            missed_heartbeats: state.missed_heartbeats,
+            timed_out_requests: state.timed_out_requests,
+        })
+    }
+
+    async fn start_heartbeat(&self, interval_ms: Option<u64>) -> Result<()> {
+        let interval_ms = interval_ms.unwrap_or(self.config.heartbeat_interval_ms);
         let mut handle_guard = self.heartbeat_handle.lock().await;
 
         if handle_guard.is_some() {
@@ -426,12 +1073,22 @@ impl<N: NetworkServiceTrait + 'static> TimeSyncServiceTrait for TimeSyncService<
         let server_id = self.server_id.clone();
         let heartbeat_sequence = self.heartbeat_sequence.clone();
         let stats = self.stats.clone();
+        let failure_threshold = self.heartbeat_failure_threshold.clone();
+        let failure_callback = self.heartbeat_failure_callback.clone();
+        let timesync_peers = self.timesync_peers.clone();
+        let cancel = CancellationToken::new();
+        let task_cancel = cancel.clone();
 
         let handle = tokio::spawn(async move {
             let mut interval = interval(Duration::from_millis(interval_ms));
+            let mut consecutive_failures: u32 = 0;
 
             loop {
-                interval.tick().await;
+                // 只在两次心跳之间响应取消，进行中的广播总能完整结束
+                tokio::select! {
+                    _ = task_cancel.cancelled() => break,
+                    _ = interval.tick() => {}
+                }
 
                 let sequence = {
                     let mut seq = heartbeat_sequence.write().await;
@@ -450,10 +1107,30 @@ impl<N: NetworkServiceTrait + 'static> TimeSyncServiceTrait for TimeSyncService<
                     let network_msg =
                         NetworkMessage::new(MessageType::timesync(), server_id.clone(), payload);
 
-                    // 广播心跳消息
-                    if let Err(e) = network_service.broadcast(network_msg, None).await {
-                        warn!("心跳广播失败: {}", e);
+                    // 只向运行授时服务的节点广播心跳，不打扰只使用聊天的节点
+                    let recipients: Vec<NodeId> =
+                        timesync_peers.read().await.iter().cloned().collect();
+                    let options = BroadcastOptions {
+                        include_nodes: Some(recipients),
+                        ..Default::default()
+                    };
+                    if let Err(e) = network_service.broadcast(network_msg, Some(options)).await {
+                        consecutive_failures += 1;
+                        warn!("心跳广播失败（连续 {} 次）: {}", consecutive_failures, e);
+
+                        // 达到阈值时告警，持续失败期间只触发一次
+                        if consecutive_failures == *failure_threshold.read().await {
+                            error!("心跳广播连续失败 {} 次", consecutive_failures);
+                            if let Some(callback) = failure_callback.read().await.as_ref() {
+                                callback(consecutive_failures);
+                            }
+                        }
                     } else {
+                        if consecutive_failures > 0 {
+                            info!("心跳广播已恢复，此前连续失败 {} 次", consecutive_failures);
+                        }
+                        consecutive_failures = 0;
+
                         // 更新心跳计数
                         let mut stats_guard = stats.write().await;
                         stats_guard.heartbeat_count += 1;
@@ -462,16 +1139,71 @@ impl<N: NetworkServiceTrait + 'static> TimeSyncServiceTrait for TimeSyncService<
             }
         });
 
-        *handle_guard = Some(handle);
+        *handle_guard = Some((cancel, handle));
 
         Ok(())
     }
 
+    async fn record_heartbeat(&self, from: NodeId, sequence: u64) -> HeartbeatStatus {
+        let status = {
+            let mut peers = self.peer_heartbeats.write().await;
+            let status = match peers.get(&from).copied() {
+                None => HeartbeatStatus::First,
+                Some(last) if sequence == last + 1 => HeartbeatStatus::InOrder,
+                Some(last) if sequence > last => HeartbeatStatus::Gap {
+                    missed: sequence - last - 1,
+                },
+                Some(last) if sequence == last => HeartbeatStatus::Duplicate,
+                // 序列号从头开始（0或1）或大幅回退，说明对端已重启
+                Some(last)
+                    if sequence <= 1
+                        || last - sequence > self.config.heartbeat_restart_threshold =>
+                {
+                    HeartbeatStatus::Restarted { previous: last }
+                }
+                Some(_) => HeartbeatStatus::Reordered,
+            };
+            // 乱序和重复的心跳不推进跟踪状态；重启时从新的序列号重新开始
+            if !matches!(
+                status,
+                HeartbeatStatus::Reordered | HeartbeatStatus::Duplicate
+            ) {
+                peers.insert(from.clone(), sequence);
+            }
+            status
+        };
+
+        match status {
+            HeartbeatStatus::Gap { missed } => {
+                self.metrics.write().await.missed_heartbeats += missed;
+                warn!(
+                    "来自 {} 的心跳丢失 {} 个（当前序列号 {}）",
+                    from, missed, sequence
+                )
+            }
+            HeartbeatStatus::Restarted { previous } => {
+                warn!(
+                    "节点 {} 心跳序列号从 {} 回退到 {}，视为节点已重启",
+                    from, previous, sequence
+                );
+                if let Some(callback) = self.peer_restart_callback.read().await.as_ref() {
+                    callback(&from, previous, sequence);
+                }
+            }
+            _ => {}
+        }
+        status
+    }
+
     async fn stop_heartbeat(&self) -> Result<()> {
         let mut handle_guard = self.heartbeat_handle.lock().await;
 
-        if let Some(handle) = handle_guard.take() {
-            handle.abort();
+        if let Some((cancel, handle)) = handle_guard.take() {
+            // 等待心跳任务自行退出，确保停止网络前没有发送中的心跳
+            cancel.cancel();
+            if let Err(e) = handle.await {
+                warn!("心跳任务异常退出: {}", e);
+            }
             info!("心跳服务已停止");
             Ok(())
         } else {
@@ -480,10 +1212,109 @@ impl<N: NetworkServiceTrait + 'static> TimeSyncServiceTrait for TimeSyncService<
     }
 }
 
+impl<N: NetworkServiceTrait + 'static> TimeSyncService<N> {
+    /// 订阅网络事件：公布了授时能力的节点连接时登记为心跳接收方，节点断开时移除并清除其心跳跟踪状态
+    ///
+    /// 节点重新连接后的第一个心跳按首次心跳处理，不会被误报为丢失或乱序。
+    /// 网络服务不支持事件订阅时返回false。重复调用不会创建新的订阅。
+    pub async fn attach(self: &Arc<Self>) -> bool {
+        let mut task_guard = self.event_task.lock().await;
+        if task_guard.is_some() {
+            return true;
+        }
+        let Some(mut events) = self.network_service.subscribe_events() else {
+            warn!("网络服务不支持事件订阅，无法清理断开节点的心跳状态");
+            return false;
+        };
+
+        // 只持有弱引用，服务销毁后任务自然结束
+        let service = Arc::downgrade(self);
+        let handle = tokio::spawn(async move {
+            loop {
+                match events.recv().await {
+                    Ok(NetworkEvent::NodeConnected {
+                        node_id,
+                        connection,
+                        ..
+                    }) => {
+                        let Some(service) = service.upgrade() else {
+                            break;
+                        };
+                        let timesync = MessageType::timesync();
+                        if connection.capabilities.contains(&timesync.0) {
+                            info!("授时节点 {} 已连接，加入心跳接收方", node_id);
+                            service.register_timesync_peer(node_id).await;
+                        }
+                    }
+                    Ok(NetworkEvent::NodeDisconnected { node_id, .. }) => {
+                        let Some(service) = service.upgrade() else {
+                            break;
+                        };
+                        service.unregister_timesync_peer(&node_id).await;
+                        if service
+                            .peer_heartbeats
+                            .write()
+                            .await
+                            .remove(&node_id)
+                            .is_some()
+                        {
+                            info!("节点 {} 已断开，清除心跳跟踪状态", node_id);
+                        }
+                    }
+                    Ok(_) => {}
+                    Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                        warn!("网络事件积压，跳过 {} 个事件", skipped);
+                    }
+                    Err(broadcast::error::RecvError::Closed) => break,
+                }
+            }
+        });
+
+        *task_guard = Some(handle);
+        true
+    }
+}
+
+impl<N: NetworkServiceTrait> Drop for TimeSyncService<N> {
+    fn drop(&mut self) {
+        if let Ok(mut task) = self.event_task.try_lock() {
+            if let Some(handle) = task.take() {
+                handle.abort();
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl<N: NetworkServiceTrait + 'static> ShutdownComponent for TimeSyncService<N> {
+    fn name(&self) -> &str {
+        "timesync"
+    }
+
+    async fn shutdown(&self) -> network_service::Result<()> {
+        if let Some(handle) = self.event_task.lock().await.take() {
+            handle.abort();
+        }
+        match self.stop_heartbeat().await {
+            Ok(()) | Err(TimeSyncError::HeartbeatNotStarted) => Ok(()),
+            Err(e) => Err(NetworkError::internal_error(e.to_string())),
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
-    use network_service::AnemoNetworkService;
+    use network_service::test_utils::MockNetworkService;
+    use network_service::{AnemoNetworkService, ConnectionMetadata};
+
+    /// 登记一个等待响应的请求，模拟本节点已发出的同步请求
+    async fn outstanding_request(service: &TimeSyncService<MockNetworkService>) -> Uuid {
+        let request_id = Uuid::new_v4();
+        let (tx, _rx) = oneshot::channel();
+        service.response_waiters.lock().await.insert(request_id, tx);
+        request_id
+    }
 
     #[tokio::test]
     async fn test_timesync_service_creation() {
@@ -497,12 +1328,777 @@ mod tests {
     #[tokio::test]
     async fn test_timestamp_validation() {
         let current = TimeSyncService::<AnemoNetworkService>::get_current_timestamp_ms();
+        let max_skew_ms = TimeSyncConfig::default().max_skew_ms;
 
         // 正常时间戳应该通过验证
-        assert!(TimeSyncService::<AnemoNetworkService>::validate_timestamp(current).is_ok());
+        assert!(
+            TimeSyncService::<AnemoNetworkService>::validate_timestamp(current, max_skew_ms)
+                .is_ok()
+        );
 
         // 过时的时间戳应该失败
         let old_timestamp = current - 7200000; // 2小时前
-        assert!(TimeSyncService::<AnemoNetworkService>::validate_timestamp(old_timestamp).is_err());
+        assert!(TimeSyncService::<AnemoNetworkService>::validate_timestamp(
+            old_timestamp,
+            max_skew_ms
+        )
+        .is_err());
+    }
+
+    #[tokio::test]
+    async fn test_heartbeat_skips_chat_only_peers() {
+        let network_service = MockNetworkService::new();
+        let timesync_service = Arc::new(TimeSyncService::new(
+            network_service.clone(),
+            "test-server".to_string(),
+        ));
+        assert!(timesync_service.attach().await);
+
+        // 只有连接时公布了授时能力的节点接收心跳
+        for (node_id, capability) in [("timesync-peer", "timesync"), ("chat-peer", "chat")] {
+            network_service
+                .event_bus
+                .publish(NetworkEvent::NodeConnected {
+                    node_id: node_id.to_string(),
+                    connection: ConnectionMetadata {
+                        capabilities: vec![capability.to_string()],
+                        ..Default::default()
+                    },
+                    metadata: HashMap::new(),
+                })
+                .await;
+        }
+        // 发送时间请求不会让只使用聊天的节点成为心跳接收方
+        let current = TimeSyncService::<MockNetworkService>::get_current_timestamp_ms();
+        timesync_service
+            .handle_time_request("chat-peer".to_string(), Uuid::new_v4(), current)
+            .await
+            .unwrap();
+
+        let recipients = || async {
+            let mut peers: Vec<NodeId> = timesync_service
+                .timesync_peers
+                .read()
+                .await
+                .iter()
+                .cloned()
+                .collect();
+            peers.sort();
+            peers
+        };
+        tokio::time::timeout(Duration::from_secs(2), async {
+            while recipients().await.is_empty() {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .expect("授时节点连接后应登记为心跳接收方");
+
+        timesync_service.start_heartbeat(Some(10)).await.unwrap();
+        let sent_to = loop {
+            let options = network_service.broadcast_options.lock().unwrap().pop();
+            if let Some(options) = options {
+                break options.include_nodes.unwrap();
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        };
+        timesync_service.stop_heartbeat().await.unwrap();
+        assert_eq!(sent_to, vec!["timesync-peer".to_string()]);
+
+        // 断开的节点不再接收心跳
+        network_service
+            .event_bus
+            .publish(NetworkEvent::NodeDisconnected {
+                node_id: "timesync-peer".to_string(),
+                reason: "test".to_string(),
+            })
+            .await;
+        tokio::time::timeout(Duration::from_secs(2), async {
+            while !recipients().await.is_empty() {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .expect("断开后应移出心跳接收方");
+    }
+
+    #[tokio::test]
+    async fn test_orchestrated_shutdown_stops_heartbeat() {
+        use network_service::ServiceOrchestrator;
+
+        let network_service = MockNetworkService::new();
+        let timesync_service = Arc::new(TimeSyncService::new(
+            network_service.clone(),
+            "test-server".to_string(),
+        ));
+        timesync_service.start_heartbeat(Some(10)).await.unwrap();
+
+        ServiceOrchestrator::new(network_service)
+            .with_component(timesync_service.clone())
+            .shutdown()
+            .await
+            .unwrap();
+        assert!(timesync_service.heartbeat_handle.lock().await.is_none());
+
+        // 心跳未运行时停止不视为失败
+        timesync_service.shutdown().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_stop_heartbeat_waits_for_in_flight_send() {
+        use std::sync::atomic::Ordering;
+
+        let network_service = MockNetworkService::new();
+        network_service
+            .broadcast_delay_ms
+            .store(50, Ordering::SeqCst);
+        let timesync_service =
+            TimeSyncService::new(network_service.clone(), "test-server".to_string());
+
+        timesync_service.start_heartbeat(Some(1)).await.unwrap();
+        while network_service.broadcasts_started.load(Ordering::SeqCst) == 0 {
+            tokio::time::sleep(Duration::from_millis(1)).await;
+        }
+        timesync_service.stop_heartbeat().await.unwrap();
+
+        // 停止返回时，已开始的广播全部完成并计入统计
+        let started = network_service.broadcasts_started.load(Ordering::SeqCst);
+        assert_eq!(network_service.broadcasts.lock().unwrap().len(), started);
+        let stats = timesync_service.get_sync_stats().await.unwrap();
+        assert_eq!(stats.heartbeat_count, started as u64);
+
+        // 停止之后不再发起新的广播
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        assert_eq!(
+            network_service.broadcasts_started.load(Ordering::SeqCst),
+            started
+        );
+    }
+
+    #[tokio::test]
+    async fn test_list_sessions_reports_offsets() {
+        let network_service = MockNetworkService::new();
+        let timesync_service = TimeSyncService::new(network_service, "test-server".to_string());
+
+        let current = TimeSyncService::<MockNetworkService>::get_current_timestamp_ms();
+        timesync_service
+            .handle_sync_request("node-a".to_string(), Uuid::new_v4(), current - 1000, 5000)
+            .await
+            .unwrap();
+        timesync_service
+            .handle_sync_request(
+                "node-b".to_string(),
+                Uuid::new_v4(),
+                current - 60_000,
+                10_000,
+            )
+            .await
+            .unwrap();
+
+        let sessions = timesync_service.list_sessions().await;
+        assert_eq!(sessions.len(), 2);
+        assert_eq!(sessions[0].node_id, "node-a");
+        assert_eq!(sessions[0].sync_interval_ms, 5000);
+        assert_eq!(sessions[0].request_count, 1);
+        assert_eq!(sessions[1].node_id, "node-b");
+        assert_eq!(sessions[1].sync_interval_ms, 10_000);
+
+        // 客户端时间相差59秒，偏移量应相应不同
+        let gap = (sessions[1].time_offset_ms - sessions[0].time_offset_ms).abs();
+        assert!((58_000..=60_000).contains(&gap));
+    }
+
+    #[tokio::test]
+    async fn test_cancel_pending_request() {
+        let network_service = MockNetworkService::new();
+        let timesync_service = Arc::new(TimeSyncService::new(
+            network_service.clone(),
+            "test-client".to_string(),
+        ));
+
+        let waiter = {
+            let timesync_service = timesync_service.clone();
+            tokio::spawn(async move {
+                timesync_service
+                    .request_time_and_wait("server".to_string())
+                    .await
+            })
+        };
+
+        // 等待请求发出
+        let request_id = loop {
+            if let Some(id) = timesync_service.pending_request_ids().await.pop() {
+                break id;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        };
+        assert_eq!(network_service.unicasts.lock().unwrap().len(), 1);
+
+        assert!(timesync_service.cancel_request(request_id).await);
+        let result = waiter.await.unwrap();
+        assert!(matches!(result, Err(TimeSyncError::RequestCancelled(id)) if id == request_id));
+
+        assert!(timesync_service.pending_request_ids().await.is_empty());
+        assert!(!timesync_service.cancel_request(request_id).await);
+    }
+
+    #[tokio::test]
+    async fn test_query_time_returns_offset_and_rtt() {
+        let network_service = MockNetworkService::new();
+        let timesync_service = Arc::new(TimeSyncService::new(
+            network_service.clone(),
+            "test-client".to_string(),
+        ));
+
+        let query = {
+            let timesync_service = timesync_service.clone();
+            tokio::spawn(async move {
+                timesync_service
+                    .query_time("server".to_string(), Duration::from_secs(2))
+                    .await
+            })
+        };
+
+        let request_id = loop {
+            if let Some(id) = timesync_service.pending_request_ids().await.pop() {
+                break id;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        };
+        let (target, request) = network_service.unicasts.lock().unwrap()[0].clone();
+        assert_eq!(target, "server");
+        let client_timestamp = match serde_json::from_value(request.payload).unwrap() {
+            TimeSyncMessageType::TimeRequest {
+                client_timestamp, ..
+            } => client_timestamp,
+            other => panic!("意外的请求: {:?}", other),
+        };
+
+        // 服务器时钟快 500ms，往返约 20ms
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        timesync_service
+            .complete_request(
+                request_id,
+                TimeSyncMessageType::TimeResponse {
+                    request_id,
+                    server_timestamp: client_timestamp + 510,
+                    client_timestamp,
+                    processing_time_ns: 0,
+                },
+            )
+            .await
+            .unwrap();
+
+        let remote = query.await.unwrap().unwrap();
+        assert_eq!(remote.server_timestamp, client_timestamp + 510);
+        assert!(
+            (20..200).contains(&remote.rtt_ms),
+            "往返时间 {}",
+            remote.rtt_ms
+        );
+        assert!(
+            (400..=500).contains(&remote.offset_ms),
+            "偏移 {}",
+            remote.offset_ms
+        );
+    }
+
+    /// 回复所有已发出的时间查询，模拟时钟快 `offset_ms` 的授时服务器
+    fn spawn_time_responder(
+        network_service: MockNetworkService,
+        timesync_service: Arc<TimeSyncService<MockNetworkService>>,
+        offset_ms: i64,
+    ) -> tokio::task::JoinHandle<()> {
+        tokio::spawn(async move {
+            loop {
+                let requests: Vec<_> = network_service.unicasts.lock().unwrap().drain(..).collect();
+                for (_, request) in requests {
+                    if let Ok(TimeSyncMessageType::TimeRequest {
+                        request_id,
+                        client_timestamp,
+                    }) = serde_json::from_value(request.payload)
+                    {
+                        timesync_service
+                            .complete_request(
+                                request_id,
+                                TimeSyncMessageType::TimeResponse {
+                                    request_id,
+                                    server_timestamp: client_timestamp + offset_ms,
+                                    client_timestamp,
+                                    processing_time_ns: 0,
+                                },
+                            )
+                            .await
+                            .unwrap();
+                    }
+                }
+                tokio::time::sleep(Duration::from_millis(5)).await;
+            }
+        })
+    }
+
+    #[tokio::test]
+    async fn test_preferred_server_used_while_reachable() {
+        let network_service = MockNetworkService::new();
+        network_service
+            .connected_nodes
+            .lock()
+            .unwrap()
+            .extend(["fallback".to_string(), "primary".to_string()]);
+        let config = TimeSyncConfig {
+            preferred_servers: vec!["primary".to_string()],
+            ..Default::default()
+        };
+        let timesync_service = Arc::new(TimeSyncService::with_config(
+            network_service.clone(),
+            "test-client".to_string(),
+            config,
+        ));
+        let responder =
+            spawn_time_responder(network_service.clone(), timesync_service.clone(), 300);
+        assert_eq!(timesync_service.authoritative_server().await, None);
+
+        timesync_service.start_auto_sync(20).await.unwrap();
+        assert!(matches!(
+            timesync_service.start_auto_sync(20).await,
+            Err(TimeSyncError::AutoSyncAlreadyStarted)
+        ));
+        tokio::time::timeout(Duration::from_secs(2), async {
+            while timesync_service.authoritative_server().await.is_none() {
+                tokio::time::sleep(Duration::from_millis(5)).await;
+            }
+        })
+        .await
+        .expect("自动同步未完成");
+        assert_eq!(
+            timesync_service.authoritative_server().await.as_deref(),
+            Some("primary")
+        );
+        assert!(timesync_service.clock_offset().await.is_some());
+        timesync_service.stop_auto_sync().await.unwrap();
+
+        // 首选服务器不可达时回退到其他已连接节点
+        network_service
+            .failing_targets
+            .lock()
+            .unwrap()
+            .insert("primary".to_string());
+        timesync_service
+            .sync_with_servers(Duration::from_secs(1))
+            .await
+            .unwrap();
+        assert_eq!(
+            timesync_service.authoritative_server().await.as_deref(),
+            Some("fallback")
+        );
+
+        // 首选服务器恢复后重新使用
+        network_service.failing_targets.lock().unwrap().clear();
+        timesync_service
+            .sync_with_servers(Duration::from_secs(1))
+            .await
+            .unwrap();
+        assert_eq!(
+            timesync_service.authoritative_server().await.as_deref(),
+            Some("primary")
+        );
+        responder.abort();
+    }
+
+    #[tokio::test]
+    async fn test_measured_precision_plausible() {
+        let precision_ns = TimeSyncService::<AnemoNetworkService>::measure_clock_precision_ns();
+        assert!(precision_ns > 0);
+        assert!(precision_ns < 1_000_000_000);
+
+        let config = TimeSyncConfig {
+            precision_ns: Some(500),
+            ..Default::default()
+        };
+        let timesync_service = TimeSyncService::with_config(
+            AnemoNetworkService::new(),
+            "test-server".to_string(),
+            config,
+        );
+        assert_eq!(
+            timesync_service.get_time_info().await.unwrap().precision_ns,
+            500
+        );
+    }
+
+    #[tokio::test]
+    async fn test_persisted_offset_used_after_restart() {
+        let path = std::env::temp_dir().join(format!("clock-offset-{}.json", Uuid::new_v4()));
+        let config = TimeSyncConfig {
+            offset_file: Some(path.clone()),
+            ..Default::default()
+        };
+
+        let first = TimeSyncService::with_config(
+            MockNetworkService::new(),
+            "client".to_string(),
+            config.clone(),
+        );
+        assert!(first.clock_offset().await.is_none());
+        first
+            .complete_request(
+                outstanding_request(&first).await,
+                TimeSyncMessageType::SyncResponse {
+                    request_id: Uuid::new_v4(),
+                    server_time: 0,
+                    client_time: 0,
+                    time_offset_ms: 90_000,
+                    round_trip_time_ms: 10,
+                },
+            )
+            .await
+            .unwrap();
+
+        // 重启后在新的同步之前使用持久化的偏移，并标记为待确认
+        let restarted =
+            TimeSyncService::with_config(MockNetworkService::new(), "client".to_string(), config);
+        let offset = restarted.clock_offset().await.unwrap();
+        assert_eq!(offset.offset_ms, 90_000);
+        assert!(offset.stale);
+
+        let local = TimeSyncService::<MockNetworkService>::get_current_timestamp_ms();
+        let adjusted = restarted.now_adjusted().await;
+        assert!((89_000..=91_000).contains(&(adjusted - local)));
+
+        restarted.record_clock_offset(500).await;
+        assert!(!restarted.clock_offset().await.unwrap().stale);
+        // 通过临时文件替换写入，不留下临时文件
+        let saved: ClockOffset =
+            serde_json::from_str(&std::fs::read_to_string(&path).unwrap()).unwrap();
+        assert_eq!(saved.offset_ms, 500);
+        let mut tmp = path.as_os_str().to_owned();
+        tmp.push(".tmp");
+        assert!(!PathBuf::from(tmp).exists());
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_custom_config_honored() {
+        let config = TimeSyncConfig {
+            timezone: "Asia/Shanghai".to_string(),
+            max_skew_ms: 1000,
+            ..Default::default()
+        };
+        let timesync_service = TimeSyncService::with_config(
+            AnemoNetworkService::new(),
+            "test-server".to_string(),
+            config,
+        );
+
+        let time_info = timesync_service.get_time_info().await.unwrap();
+        assert_eq!(time_info.timezone, "Asia/Shanghai");
+
+        // 5秒的偏差在默认配置下可以接受，在自定义配置下被拒绝
+        let skewed = TimeSyncService::<AnemoNetworkService>::get_current_timestamp_ms() - 5000;
+        let result = timesync_service
+            .handle_time_request("client".to_string(), Uuid::new_v4(), skewed)
+            .await;
+        assert!(matches!(result, Err(TimeSyncError::InvalidTimestamp(_))));
+    }
+
+    #[tokio::test]
+    async fn test_heartbeat_uses_configured_interval() {
+        let network_service = MockNetworkService::new();
+        let config = TimeSyncConfig {
+            heartbeat_interval_ms: 10,
+            ..Default::default()
+        };
+        let timesync_service = TimeSyncService::with_config(
+            network_service.clone(),
+            "test-server".to_string(),
+            config,
+        );
+
+        // 未指定间隔时按配置的10毫秒发送，默认的5秒间隔内只会发出第一个心跳
+        timesync_service.start_heartbeat(None).await.unwrap();
+        tokio::time::timeout(Duration::from_secs(1), async {
+            while network_service.broadcasts.lock().unwrap().len() < 3 {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .expect("应按配置的间隔发送心跳");
+        timesync_service.stop_heartbeat().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_heartbeat_sequence_reset_is_restart() {
+        let timesync_service =
+            TimeSyncService::new(MockNetworkService::new(), "test-server".to_string());
+        let restarts = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let recorded = restarts.clone();
+        timesync_service
+            .on_peer_restart(Arc::new(move |node, previous, sequence| {
+                recorded
+                    .lock()
+                    .unwrap()
+                    .push((node.clone(), previous, sequence));
+            }))
+            .await;
+        let peer = "peer".to_string();
+
+        assert_eq!(
+            timesync_service.record_heartbeat(peer.clone(), 99).await,
+            HeartbeatStatus::First
+        );
+        assert_eq!(
+            timesync_service.record_heartbeat(peer.clone(), 100).await,
+            HeartbeatStatus::InOrder
+        );
+        // 小幅回退是乱序，不是重启
+        assert_eq!(
+            timesync_service.record_heartbeat(peer.clone(), 98).await,
+            HeartbeatStatus::Reordered
+        );
+        assert_eq!(
+            timesync_service.record_heartbeat(peer.clone(), 1).await,
+            HeartbeatStatus::Restarted { previous: 100 }
+        );
+        // 重启后从新序列号继续跟踪，不会报告巨大的间隔
+        assert_eq!(
+            timesync_service.record_heartbeat(peer.clone(), 2).await,
+            HeartbeatStatus::InOrder
+        );
+        assert_eq!(
+            timesync_service.record_heartbeat(peer.clone(), 5).await,
+            HeartbeatStatus::Gap { missed: 2 }
+        );
+
+        assert_eq!(*restarts.lock().unwrap(), vec![(peer.clone(), 100, 1)]);
+
+        // 序列号回到起点时即使回退幅度小于阈值也是重启
+        assert_eq!(
+            timesync_service.record_heartbeat(peer.clone(), 6).await,
+            HeartbeatStatus::InOrder
+        );
+        assert_eq!(
+            timesync_service.record_heartbeat(peer.clone(), 1).await,
+            HeartbeatStatus::Restarted { previous: 6 }
+        );
+        assert_eq!(
+            timesync_service.record_heartbeat(peer.clone(), 0).await,
+            HeartbeatStatus::Restarted { previous: 1 }
+        );
+    }
+
+    #[tokio::test]
+    async fn test_disconnect_clears_heartbeat_tracking() {
+        let network = MockNetworkService::new();
+        let timesync_service = Arc::new(TimeSyncService::new(
+            network.clone(),
+            "test-server".to_string(),
+        ));
+        assert!(timesync_service.attach().await);
+        let peer = "peer".to_string();
+
+        timesync_service.record_heartbeat(peer.clone(), 5).await;
+        network
+            .event_bus
+            .publish(NetworkEvent::NodeDisconnected {
+                node_id: peer.clone(),
+                reason: "test".to_string(),
+            })
+            .await;
+        tokio::time::timeout(Duration::from_secs(2), async {
+            while timesync_service
+                .peer_heartbeats
+                .read()
+                .await
+                .contains_key(&peer)
+            {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .expect("断开后应清除心跳跟踪状态");
+
+        // 重新连接后的心跳按首次心跳处理
+        assert_eq!(
+            timesync_service.record_heartbeat(peer.clone(), 3).await,
+            HeartbeatStatus::First
+        );
+    }
+
+    #[tokio::test]
+    async fn test_heartbeat_failure_callback_after_threshold() {
+        // 未启动的网络服务广播必然失败
+        let network_service = AnemoNetworkService::new();
+        let timesync_service = TimeSyncService::new(network_service, "test-server".to_string());
+
+        let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+        timesync_service.set_heartbeat_failure_threshold(2).await;
+        timesync_service
+            .on_heartbeat_failure(Arc::new(move |failures| {
+                let _ = tx.send(failures);
+            }))
+            .await;
+
+        timesync_service.start_heartbeat(Some(10)).await.unwrap();
+        let failures = tokio::time::timeout(Duration::from_secs(2), rx.recv())
+            .await
+            .expect("心跳失败回调未触发")
+            .unwrap();
+        timesync_service.stop_heartbeat().await.unwrap();
+
+        assert_eq!(failures, 2);
+        let stats = timesync_service.get_sync_stats().await.unwrap();
+        assert_eq!(stats.heartbeat_count, 0);
+    }
+
+    #[tokio::test]
+    async fn test_pending_request_cleaned_on_send_failure() {
+        // 未启动的网络服务发送必然失败
+        let network_service = AnemoNetworkService::new();
+        let timesync_service = TimeSyncService::new(network_service, "test-server".to_string());
+
+        let current = TimeSyncService::<AnemoNetworkService>::get_current_timestamp_ms();
+        let result = timesync_service
+            .handle_time_request("client".to_string(), Uuid::new_v4(), current)
+            .await;
+
+        assert!(result.is_err());
+        assert!(timesync_service.pending_requests.read().await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_pending_requests_bounded() {
+        let network_service = AnemoNetworkService::new();
+        let timesync_service = TimeSyncService::new(network_service, "test-server".to_string());
+
+        let max_pending = timesync_service.config().max_pending_requests;
+        let first_id = Uuid::new_v4();
+        let base = Instant::now();
+        for i in 0..=max_pending {
+            let request_id = if i == 0 { first_id } else { Uuid::new_v4() };
+            timesync_service
+                .track_pending_request(TimeRequest {
+                    request_id,
+                    from_node: "client".to_string(),
+                    client_timestamp: 0,
+                    server_receive_time: base + Duration::from_millis(i as u64),
+                })
+                .await;
+        }
+
+        let requests = timesync_service.pending_requests.read().await;
+        assert_eq!(requests.len(), max_pending);
+        assert!(!requests.contains_key(&first_id));
+    }
+
+    #[tokio::test]
+    async fn test_unsolicited_response_ignored() {
+        let timesync_service =
+            TimeSyncService::new(MockNetworkService::new(), "client".to_string());
+        let response = |request_id| TimeSyncMessageType::SyncResponse {
+            request_id,
+            server_time: 0,
+            client_time: 0,
+            time_offset_ms: 90_000,
+            round_trip_time_ms: 10,
+        };
+
+        // 没有对应请求的响应不改变时钟偏移和指标
+        let unknown = Uuid::new_v4();
+        timesync_service
+            .complete_request(unknown, response(unknown))
+            .await
+            .unwrap();
+        assert!(timesync_service.clock_offset().await.is_none());
+        assert!(timesync_service
+            .get_metrics()
+            .await
+            .unwrap()
+            .offset_ema_ms
+            .is_none());
+
+        // 同一请求的重复响应只记录一次
+        let request_id = outstanding_request(&timesync_service).await;
+        for _ in 0..2 {
+            timesync_service
+                .complete_request(request_id, response(request_id))
+                .await
+                .unwrap();
+        }
+        assert!(timesync_service.clock_offset().await.is_some());
+        assert!(timesync_service.pending_request_ids().await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_metrics_populated_by_syncs() {
+        let config = TimeSyncConfig {
+            request_timeout_ms: 20,
+            ..Default::default()
+        };
+        let timesync_service =
+            TimeSyncService::with_config(MockNetworkService::new(), "client".to_string(), config);
+        let metrics = timesync_service.get_metrics().await.unwrap();
+        assert!(metrics.offset_ema_ms.is_none());
+        assert!(metrics.last_sync_age_ms.is_none());
+
+        // 偏移 100、110、120ms，往返时延 10、20、10ms
+        for (offset, delay) in [(100, 10), (110, 20), (120, 10)] {
+            timesync_service
+                .complete_request(
+                    outstanding_request(&timesync_service).await,
+                    TimeSyncMessageType::SyncResponse {
+                        request_id: Uuid::new_v4(),
+                        server_time: 0,
+                        client_time: 0,
+                        time_offset_ms: offset,
+                        round_trip_time_ms: delay,
+                    },
+                )
+                .await
+                .unwrap();
+        }
+
+        // 作为服务器处理一个同步会话，并收到一次丢失2个心跳的序列
+        let current = TimeSyncService::<MockNetworkService>::get_current_timestamp_ms();
+        timesync_service
+            .handle_sync_request("node-a".to_string(), Uuid::new_v4(), current, 5000)
+            .await
+            .unwrap();
+        timesync_service
+            .record_heartbeat("node-a".to_string(), 1)
+            .await;
+        timesync_service
+            .record_heartbeat("node-a".to_string(), 4)
+            .await;
+
+        // 没有响应的请求超时
+        assert!(matches!(
+            timesync_service
+                .request_time_and_wait("server".to_string())
+                .await,
+            Err(TimeSyncError::RequestTimeout)
+        ));
+
+        let metrics = timesync_service.get_metrics().await.unwrap();
+        let offset = metrics.offset_ema_ms.unwrap();
+        assert!((100.0..120.0).contains(&offset), "偏移均值 {}", offset);
+        let delay = metrics.delay_ema_ms.unwrap();
+        assert!((10.0..20.0).contains(&delay), "时延均值 {}", delay);
+        assert!(metrics.jitter_ms > 0.0);
+        assert_eq!(metrics.sync_count, 3);
+        assert_eq!(metrics.session_requests.get("node-a"), Some(&1));
+        assert!(metrics.last_sync_age_ms.unwrap() < 5_000);
+        assert_eq!(metrics.missed_heartbeats, 2);
+        assert_eq!(metrics.timed_out_requests, 1);
+
+        // 兼容的统计接口保持不变
+        assert_eq!(
+            timesync_service
+                .get_sync_stats()
+                .await
+                .unwrap()
+                .total_requests,
+            1
+        );
     }
 }
@@ -14,15 +14,21 @@ use clap::{Parser, Subcommand};
 use std::net::SocketAddr;
 use std::sync::Arc;
 use tokio::signal;
-use tracing::{error, info, Level};
+use tracing::{error, info, warn, Level};
 use tracing_subscriber::FmtSubscriber;
 
 // 导入各个模块
-use chat_module::{ChatMessageHandler, ChatService, ChatServiceTrait};
+use chat_module::{
+    ChatMessageHandler, ChatMessageType, ChatService, ChatServiceConfig, ChatServiceTrait,
+    ContentType,
+};
 use network_service::{
-    AnemoNetworkService, MessageType, NetworkServiceConfig, NetworkServiceTrait,
+    AnemoNetworkService, DecodeErrorPolicy, KeySource, MessageLimits, MessageType,
+    NetworkServiceConfig, NetworkServiceTrait, NodeOrder, ProbeResult, ServiceOrchestrator,
+};
+use timesync_module::{
+    TimeSyncConfig, TimeSyncMessageHandler, TimeSyncService, TimeSyncServiceTrait,
 };
-use timesync_module::{TimeSyncMessageHandler, TimeSyncService, TimeSyncServiceTrait};
 
 /// 命令行参数
 #[derive(Parser)]
@@ -52,6 +58,15 @@ enum Commands {
         /// 心跳间隔（毫秒）
         #[arg(long, default_value = "30000")]
         heartbeat_interval: u64,
+        /// 启动时连接的其他服务器地址，可指定多个
+        #[arg(long = "peer")]
+        peers: Vec<SocketAddr>,
+        /// 联邦服务器的节点ID，可指定多个；聊天室消息会转发给其中有同一聊天室成员的服务器
+        #[arg(long = "federation-peer")]
+        federation_peers: Vec<String>,
+        /// 受信任的主服务器节点ID，可指定多个；只接受这些节点发来的状态移交
+        #[arg(long = "handoff-source")]
+        handoff_sources: Vec<String>,
     },
     /// 启动聊天客户端
     ChatClient {
@@ -76,6 +91,15 @@ enum Commands {
     },
     /// 运行演示
     Demo,
+    /// 探测服务器是否可达（连接、Ping后立即断开）
+    Probe {
+        /// 服务器地址，可指定多个
+        #[arg(short, long, required = true)]
+        server: Vec<SocketAddr>,
+        /// 每个服务器的探测超时（毫秒）
+        #[arg(long, default_value = "3000")]
+        timeout: u64,
+    },
 }
 
 /// 应用程序状态
@@ -114,8 +138,25 @@ async fn main() -> Result<()> {
             enable_chat,
             enable_timesync,
             heartbeat_interval,
+            peers,
+            federation_peers,
+            handoff_sources,
         } => {
-            run_server(addr, name, enable_chat, enable_timesync, heartbeat_interval).await?;
+            let chat_config = ChatServiceConfig {
+                federation_peers,
+                handoff_sources,
+                ..Default::default()
+            };
+            run_server(
+                addr,
+                name,
+                enable_chat,
+                enable_timesync,
+                heartbeat_interval,
+                peers,
+                chat_config,
+            )
+            .await?;
         }
         Commands::ChatClient {
             server,
@@ -133,11 +174,28 @@ async fn main() -> Result<()> {
         Commands::Demo => {
             run_demo().await?;
         }
+        Commands::Probe { server, timeout } => {
+            run_probe(server, timeout).await?;
+        }
     }
 
     Ok(())
 }
 
+/// 订阅网络事件，节点断开时将其移出聊天室
+async fn attach_chat_service(chat_service: &Arc<ChatService<AnemoNetworkService>>) {
+    if !chat_service.attach().await {
+        warn!("网络服务不支持事件订阅，节点断开后不会自动移出聊天室");
+    }
+}
+
+/// 订阅网络事件，跟踪心跳接收方，节点断开时清除其心跳跟踪状态
+async fn attach_timesync_service(timesync_service: &Arc<TimeSyncService<AnemoNetworkService>>) {
+    if !timesync_service.attach().await {
+        warn!("网络服务不支持事件订阅，不会向授时节点发送心跳，节点断开后也不会清除心跳跟踪状态");
+    }
+}
+
 /// 运行服务器
 async fn run_server(
     addr: SocketAddr,
@@ -145,6 +203,8 @@ async fn run_server(
     enable_chat: bool,
     enable_timesync: bool,
     heartbeat_interval: u64,
+    peers: Vec<SocketAddr>,
+    chat_config: ChatServiceConfig,
 ) -> Result<()> {
     info!("🚀 启动网络服务器");
     info!("📍 监听地址: {}", addr);
@@ -170,7 +230,14 @@ async fn run_server(
     // 启用聊天服务
     if enable_chat {
         info!("🏗️  初始化聊天服务");
-        let chat_service = Arc::new(ChatService::new(app_state.network_service.clone()));
+        if !chat_config.federation_peers.is_empty() {
+            info!("🌐 联邦服务器: {}", chat_config.federation_peers.join(", "));
+        }
+        let chat_service = Arc::new(ChatService::with_config(
+            app_state.network_service.clone(),
+            chat_config,
+        ));
+        attach_chat_service(&chat_service).await;
         let chat_handler = ChatMessageHandler::new(chat_service.clone());
 
         app_state
@@ -178,6 +245,15 @@ async fn run_server(
             .register_message_handler(MessageType::chat(), Box::new(chat_handler))
             .await?;
 
+        // 状态移交等系统消息
+        app_state
+            .network_service
+            .register_message_handler(
+                MessageType::system(),
+                Box::new(ChatMessageHandler::new(chat_service.clone())),
+            )
+            .await?;
+
         app_state.chat_service = Some(chat_service);
         info!("✅ 聊天服务已启动");
     }
@@ -185,10 +261,16 @@ async fn run_server(
     // 启用授时服务
     if enable_timesync {
         info!("🏗️  初始化授时服务");
-        let timesync_service = Arc::new(TimeSyncService::new(
+        let timesync_config = TimeSyncConfig {
+            heartbeat_interval_ms: heartbeat_interval,
+            ..Default::default()
+        };
+        let timesync_service = Arc::new(TimeSyncService::with_config(
             app_state.network_service.clone(),
             name.clone(),
+            timesync_config,
         ));
+        attach_timesync_service(&timesync_service).await;
         let timesync_handler = TimeSyncMessageHandler::new(timesync_service.clone());
 
         app_state
@@ -196,13 +278,24 @@ async fn run_server(
             .register_message_handler(MessageType::timesync(), Box::new(timesync_handler))
             .await?;
 
-        // 启动心跳
-        timesync_service.start_heartbeat(heartbeat_interval).await?;
+        // 按配置的间隔启动心跳
+        timesync_service.start_heartbeat(None).await?;
 
         app_state.timesync_service = Some(timesync_service);
         info!("✅ 授时服务已启动");
     }
 
+    // 连接其他服务器（例如联邦服务器）
+    if !peers.is_empty() {
+        for peer in &peers {
+            app_state
+                .network_service
+                .add_known_server(peer.to_string())
+                .await;
+        }
+        app_state.network_service.connect_to_known_servers().await;
+    }
+
     info!("🎉 服务器启动完成！");
     info!("📊 服务状态:");
 
@@ -225,15 +318,17 @@ async fn run_server(
 
     info!("🛑 收到停止信号，正在关闭服务器...");
 
-    // 停止服务
+    // 先停止业务模块，最后停止网络服务
+    let mut orchestrator = ServiceOrchestrator::new(app_state.network_service);
+    if let Some(chat_service) = app_state.chat_service {
+        orchestrator.add_component(chat_service);
+    }
     if let Some(timesync_service) = app_state.timesync_service {
-        if let Err(e) = timesync_service.stop_heartbeat().await {
-            error!("停止心跳服务失败: {}", e);
-        }
+        orchestrator.add_component(timesync_service);
     }
-
-    if let Err(e) = app_state.network_service.stop().await {
-        error!("停止网络服务失败: {}", e);
+    match orchestrator.shutdown().await {
+        Ok(report) => info!("已断开 {} 个连接", report.peers_disconnected),
+        Err(e) => error!("关闭服务失败: {}", e),
     }
 
     info!("✅ 服务器已关闭");
@@ -252,6 +347,7 @@ async fn run_chat_client(server: SocketAddr, username: String, room: String) ->
 
     // 创建聊天服务
     let chat_service = Arc::new(ChatService::new(network_service.clone()));
+    attach_chat_service(&chat_service).await;
     let chat_handler = ChatMessageHandler::new(chat_service.clone());
 
     // 注册消息处理器
@@ -323,7 +419,12 @@ async fn run_chat_client(server: SocketAddr, username: String, room: String) ->
 
                 // 发送消息
                 match chat_service
-                    .send_message(local_id.clone(), room.clone(), input.to_string())
+                    .send_message(
+                        local_id.clone(),
+                        room.clone(),
+                        input.to_string(),
+                        ContentType::Text,
+                    )
                     .await
                 {
                     Ok(message_id) => {
@@ -341,10 +442,11 @@ async fn run_chat_client(server: SocketAddr, username: String, room: String) ->
         }
     }
 
-    // 离开聊天室
+    // 离开所有聊天室
     info!("🚪 离开聊天室...");
-    if let Err(e) = chat_service.leave_room(local_id, room).await {
-        error!("离开聊天室失败: {}", e);
+    match chat_service.leave_all_rooms(local_id).await {
+        Ok(rooms) => info!("已离开聊天室: {:?}", rooms),
+        Err(e) => error!("离开聊天室失败: {}", e),
     }
 
     // 停止网络服务
@@ -370,6 +472,7 @@ async fn run_timesync_client(server: SocketAddr, sync_interval: u64) -> Result<(
         network_service.clone(),
         "timesync-client".to_string(),
     ));
+    attach_timesync_service(&timesync_service).await;
     let timesync_handler = TimeSyncMessageHandler::new(timesync_service.clone());
 
     // 注册消息处理器
@@ -381,11 +484,29 @@ async fn run_timesync_client(server: SocketAddr, sync_interval: u64) -> Result<(
     let config = NetworkServiceConfig {
         bind_address: "0.0.0.0:0".parse().unwrap(),
         server_name: "timesync-client".to_string(),
-        private_key: [2u8; 32],
+        private_key: KeySource::Random,
         max_connections: 10,
         heartbeat_interval_ms: 30000,
         message_buffer_size: 100,
+        handler_workers: None,
+        message_limits: MessageLimits::default(),
         event_bus_capacity: 100,
+        send_timeout_ms: 5000,
+        slow_handler_threshold_ms: 1000,
+        handler_timeout_ms: 30000,
+        shutdown_grace_ms: 5000,
+        max_concurrent_connects: 8,
+        on_decode_error: DecodeErrorPolicy::default(),
+        observer: false,
+        record_serialization_stats: false,
+        node_id: None,
+        idle_connection_timeout_ms: None,
+        unhandled_grace_ms: 0,
+        unhandled_buffer_capacity: 64,
+        connected_nodes_order: NodeOrder::NodeId,
+        require_nonce: false,
+        node_info_metadata: Default::default(),
+        auth_metadata: Default::default(),
     };
 
     network_service.start(config).await?;
@@ -476,78 +597,198 @@ async fn run_timesync_client(server: SocketAddr, sync_interval: u64) -> Result<(
     Ok(())
 }
 
-/// 运行演示
-async fn run_demo() -> Result<()> {
-    info!("🎬 启动演示模式");
-    info!("📚 这将展示网络服务的各种功能");
+/// 演示节点：网络服务及其上的聊天和授时服务
+struct DemoNode {
+    network_service: AnemoNetworkService,
+    chat_service: Arc<ChatService<AnemoNetworkService>>,
+    timesync_service: Arc<TimeSyncService<AnemoNetworkService>>,
+}
 
-    // 创建网络服务
-    let network_service = AnemoNetworkService::new();
+impl DemoNode {
+    /// 在回环地址的随机端口启动节点，并注册聊天和授时处理器
+    async fn start(name: &str) -> Result<Self> {
+        let network_service = AnemoNetworkService::new();
+        let chat_service = Arc::new(ChatService::new(network_service.clone()));
+        attach_chat_service(&chat_service).await;
+        let timesync_service = Arc::new(TimeSyncService::new(
+            network_service.clone(),
+            name.to_string(),
+        ));
+        attach_timesync_service(&timesync_service).await;
 
-    // 创建聊天服务
-    let chat_service = Arc::new(ChatService::new(network_service.clone()));
-    let chat_handler = ChatMessageHandler::new(chat_service.clone());
+        network_service
+            .register_message_handler(
+                MessageType::chat(),
+                Box::new(ChatMessageHandler::new(chat_service.clone())),
+            )
+            .await?;
+        network_service
+            .register_message_handler(
+                MessageType::timesync(),
+                Box::new(TimeSyncMessageHandler::new(timesync_service.clone())),
+            )
+            .await?;
 
-    // 创建授时服务
-    let timesync_service = Arc::new(TimeSyncService::new(
-        network_service.clone(),
-        "demo-server".to_string(),
-    ));
-    let timesync_handler = TimeSyncMessageHandler::new(timesync_service.clone());
+        let config = NetworkServiceConfig {
+            bind_address: "127.0.0.1:0".parse().unwrap(),
+            server_name: name.to_string(),
+            ..Default::default()
+        };
+        network_service.start(config).await?;
+
+        Ok(Self {
+            network_service,
+            chat_service,
+            timesync_service,
+        })
+    }
 
-    // 注册消息处理器
-    network_service
-        .register_message_handler(MessageType::chat(), Box::new(chat_handler))
-        .await?;
+    /// 先停止业务模块，再停止网络服务
+    async fn shutdown(self) -> Result<()> {
+        ServiceOrchestrator::new(self.network_service)
+            .with_component(self.chat_service)
+            .with_component(self.timesync_service)
+            .shutdown()
+            .await?;
+        Ok(())
+    }
+}
+
+/// 探测服务器是否可达
+async fn run_probe(servers: Vec<SocketAddr>, timeout_ms: u64) -> Result<()> {
+    let network_service = AnemoNetworkService::new();
+    for server in &servers {
+        network_service.add_known_server(server.to_string()).await;
+    }
     network_service
-        .register_message_handler(MessageType::timesync(), Box::new(timesync_handler))
+        .start(NetworkServiceConfig {
+            bind_address: "0.0.0.0:0".parse().unwrap(),
+            server_name: "probe".to_string(),
+            private_key: KeySource::Random,
+            ..Default::default()
+        })
         .await?;
 
-    // 启动网络服务
-    let config = NetworkServiceConfig::default();
-    network_service.start(config).await?;
+    let results = network_service
+        .probe_known_servers(std::time::Duration::from_millis(timeout_ms))
+        .await;
+    for (server, result) in &results {
+        match result {
+            ProbeResult::Reachable { rtt } => {
+                info!("✅ {} 可达，耗时 {}ms", server, rtt.as_millis())
+            }
+            ProbeResult::Refused => error!("❌ {} 拒绝连接", server),
+            ProbeResult::Timeout => error!("⏱️  {} 超时", server),
+            ProbeResult::Failed(detail) => error!("❌ {} 探测失败: {}", server, detail),
+        }
+    }
 
-    let local_id = network_service.get_local_node_id().await?;
-    info!("🆔 本地节点ID: {}", local_id);
+    network_service.stop().await?;
+    let unreachable = results
+        .iter()
+        .filter(|(_, result)| !matches!(result, ProbeResult::Reachable { .. }))
+        .count();
+    if unreachable > 0 {
+        anyhow::bail!("{} 个服务器不可达", unreachable);
+    }
+    Ok(())
+}
+
+/// 运行演示：在进程内启动服务器和客户端两个节点，经回环地址完成聊天和授时的往返
+async fn run_demo() -> Result<()> {
+    info!("🎬 启动演示模式");
+    info!("📚 这将在本进程内启动服务器和客户端节点，展示真实的网络往返");
+
+    let server = DemoNode::start("demo-server").await?;
+    let client = DemoNode::start("demo-client").await?;
+
+    let result = run_loopback_round_trip(&server, &client).await;
+
+    // 停止服务
+    info!("🛑 停止演示");
+    client.shutdown().await?;
+    server.shutdown().await?;
 
-    // 演示聊天功能
+    result?;
+    info!("✅ 演示完成");
+    Ok(())
+}
+
+/// 客户端连接服务器，通过网络加入聊天室并同步一次时间
+async fn run_loopback_round_trip(server: &DemoNode, client: &DemoNode) -> Result<()> {
+    const ROOM: &str = "演示聊天室";
+
+    let server_addr = server
+        .network_service
+        .effective_config()
+        .await
+        .ok_or_else(|| anyhow::anyhow!("服务器节点未启动"))?
+        .local_addr;
+    let server_id = server.network_service.get_local_node_id().await?;
+    let client_id = client.network_service.get_local_node_id().await?;
+    info!(
+        "🔗 客户端 {} 连接服务器 {} ({})",
+        client_id, server_id, server_addr
+    );
+    client.network_service.connect(server_addr).await?;
+
+    // 聊天往返：客户端发出的加入请求在服务器上生效
     info!("💬 演示聊天功能");
-    chat_service
-        .join_room(
-            local_id.clone(),
-            "演示用户".to_string(),
-            "演示聊天室".to_string(),
-        )
-        .await?;
-    chat_service
-        .send_message(
-            local_id.clone(),
-            "演示聊天室".to_string(),
-            "Hello, World!".to_string(),
-        )
+    let join = ChatMessageType::UserJoin {
+        username: "演示用户".to_string(),
+        room_id: ROOM.to_string(),
+    }
+    .to_network_message(MessageType::chat(), client_id.clone())?;
+    client
+        .network_service
+        .unicast(server_id.clone(), join, None)
         .await?;
 
-    let rooms = chat_service.list_rooms().await?;
-    info!("📋 聊天室列表: {:?}", rooms);
+    let joined = tokio::time::timeout(tokio::time::Duration::from_secs(5), async {
+        while !server.chat_service.is_room_member(ROOM, &client_id).await {
+            tokio::time::sleep(tokio::time::Duration::from_millis(50)).await;
+        }
+    })
+    .await;
+    if joined.is_err() {
+        anyhow::bail!("加入聊天室的消息未到达服务器");
+    }
+    let members = server
+        .chat_service
+        .list_room_members(ROOM.to_string())
+        .await?;
+    info!("📋 服务器上的聊天室成员: {:?}", members);
 
-    // 演示授时功能
+    // 授时往返：客户端向服务器查询时间并等待响应
     info!("⏰ 演示授时功能");
-    let time_info = timesync_service.get_time_info().await?;
-    info!("📅 时间信息: {:?}", time_info);
+    let response = client
+        .timesync_service
+        .request_time_and_wait(server_id)
+        .await?;
+    info!("📅 服务器时间响应: {:?}", response);
 
-    timesync_service.start_heartbeat(5000).await?;
+    Ok(())
+}
 
-    info!("⏱️  等待 10 秒展示心跳...");
-    tokio::time::sleep(tokio::time::Duration::from_secs(10)).await;
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-    let stats = timesync_service.get_sync_stats().await?;
-    info!("📊 同步统计: {:?}", stats);
+    #[tokio::test]
+    async fn test_demo_message_crosses_nodes() {
+        let server = DemoNode::start("loopback-server").await.unwrap();
+        let client = DemoNode::start("loopback-client").await.unwrap();
 
-    // 停止服务
-    info!("🛑 停止演示");
-    timesync_service.stop_heartbeat().await?;
-    network_service.stop().await?;
+        let result = run_loopback_round_trip(&server, &client).await;
+        let client_id = client.network_service.get_local_node_id().await.unwrap();
+        let crossed = server
+            .chat_service
+            .is_room_member("演示聊天室", &client_id)
+            .await;
 
-    info!("✅ 演示完成");
-    Ok(())
+        client.shutdown().await.unwrap();
+        server.shutdown().await.unwrap();
+        result.unwrap();
+        assert!(crossed);
+    }
 }